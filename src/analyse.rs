@@ -1,6 +1,6 @@
 use std::fmt;
 
-use inkwell::values::{FloatValue, PointerValue};
+use inkwell::values::{FloatValue, IntValue, PointerValue};
 
 use crate::Span;
 
@@ -9,15 +9,17 @@ pub trait Complex<T> {
     fn im(&self) -> T;
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum SpanLength {
     Size(usize),
     ToEnd,
     None,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Location {
+    /// Which file this is in, numbered in the order the driver loaded them.
+    pub file: usize,
     pub line: usize,
     pub col: usize,
     pub len: SpanLength,
@@ -29,6 +31,7 @@ impl Location {
         match self.line.cmp(&rhs.line) {
             std::cmp::Ordering::Less    => {
                 Self {
+                    file: self.file,
                     line: self.line,
                     col: self.col,
                     len: SpanLength::ToEnd,
@@ -36,6 +39,7 @@ impl Location {
             },
             std::cmp::Ordering::Greater => {
                 Self {
+                    file: rhs.file,
                     line: rhs.line,
                     col: rhs.col,
                     len: SpanLength::ToEnd,
@@ -45,6 +49,7 @@ impl Location {
                 let min = if self.col <= rhs.col { self } else { rhs };
                 let max = if self.col == min.col { rhs } else { self };
                 Self {
+                    file: self.file,
                     line: self.line,
                     col: min.col,
                     len: SpanLength::Size(max.col - min.col),
@@ -66,6 +71,7 @@ impl fmt::Display for Location {
 impl From<&Span<'_>> for Location {
     fn from(span: &Span) -> Self {
         Self {
+            file: span.extra,
             line: span.location_line() as usize,
             col: span.get_column(),
             len: SpanLength::None,
@@ -94,36 +100,105 @@ impl<T: Clone> Clone for Located<T> {
     }
 }
 
-impl<V, T: Complex<V>> Complex<V> for Located<Typed<T>> {
-    fn re(&self) -> V {
-        self.borrow_val().val().re()
-    }
-
-    fn im(&self) -> V {
-        self.borrow_val().val().im()
-    }
-}
-
 // Value types
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Type {
-    MutScalar,
-    Scalar,
+    Complex,
+    Eisenstein,
+    Quaternion,
+    Rational,
+    Poly,
+    Bool,
+    Range,
+    Str,
+    List(Box<Type>),
+    // The types of the keys and of the values
+    Dict(Box<Type>, Box<Type>),
+    Set(Box<Type>),
+    Tuple(Vec<Type>),
+    Matrix,
+    // A record is identified by its name, and the names and types of its fields
+    Struct(String, Vec<(String, Type)>),
+    // An enum is identified by its name, since its variants only hold complex numbers
+    Enum(String),
+    // A closure is identified by where it was written, and the names and types of what it captured
+    Closure(Location, Vec<(String, Type)>),
+    // Either `some(value)` or `none`
+    Option(Box<Type>),
+    // A value that hasn't been computed yet, and the closure that will compute it
+    Lazy(Box<Type>, Box<Type>),
 }
 
-// A value with its type
-pub struct Typed<T>(T, Type);
-
-impl<T> Typed<T> {
-    pub fn new(val: T, ty: Type) -> Self { Self(val, ty) }
-    pub fn val(&self) -> &T { &self.0 }
-    pub fn ty(&self) -> Type { self.1 }
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Complex => write!(f, "complex"),
+            Self::Eisenstein => write!(f, "eisenstein"),
+            Self::Quaternion => write!(f, "quaternion"),
+            Self::Rational => write!(f, "rational"),
+            Self::Poly => write!(f, "poly"),
+            Self::Bool    => write!(f, "bool"),
+            Self::Range   => write!(f, "range"),
+            Self::Str     => write!(f, "str"),
+            Self::List(elem) => write!(f, "[{}]", elem),
+            Self::Dict(key, value) => write!(f, "{{{}: {}}}", key, value),
+            Self::Set(elem) => write!(f, "{{{}}}", elem),
+            Self::Tuple(elems) => {
+                write!(f, "(")?;
+                for (k, elem) in elems.iter().enumerate() {
+                    if k > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", elem)?;
+                }
+                if elems.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            },
+            Self::Matrix => write!(f, "matrix"),
+            Self::Struct(name, _) | Self::Enum(name) => write!(f, "{}", name),
+            Self::Closure(pos, _) => write!(f, "fn at {}", pos),
+            Self::Option(inner) => write!(f, "option({})", inner),
+            Self::Lazy(inner, _) => write!(f, "lazy {}", inner),
+        }
+    }
+}
 
-    pub fn is_mutable(&self) -> bool {
-        matches!(self.1, Type::MutScalar)
+impl Type {
+    /// The broad kind of value, without the types inside it, as given by `typeof`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Complex      => "complex",
+            Self::Eisenstein   => "eisenstein",
+            Self::Quaternion   => "quaternion",
+            Self::Rational     => "rational",
+            Self::Poly         => "poly",
+            Self::Bool         => "bool",
+            Self::Range        => "range",
+            Self::Str          => "str",
+            Self::List(_)      => "list",
+            Self::Dict(..)     => "dict",
+            Self::Set(_)       => "set",
+            Self::Tuple(_)     => "tuple",
+            Self::Matrix       => "matrix",
+            Self::Struct(..)   => "struct",
+            Self::Enum(_)      => "enum",
+            Self::Closure(..)  => "function",
+            Self::Option(_)    => "option",
+            Self::Lazy(..)     => "lazy",
+        }
     }
 }
 
+// A variable: where it lives, what it holds, and whether it can be assigned to
+#[derive(Debug, Clone)]
+pub struct Variable<'ctx> {
+    pub ptr: PointerValue<'ctx>,
+    pub ty: Type,
+    pub mutable: bool,
+}
+
 // Collects two LLVM IntValues into one object
 #[derive(Debug, Clone, Copy)]
 pub struct ComplexValue<'ctx> {
@@ -141,23 +216,80 @@ impl<'a> Complex<FloatValue<'a>> for ComplexValue<'a> {
     }
 }
 
-pub struct ComplexPointer<'ctx> {
-    pub re: PointerValue<'ctx>,
-    pub im: PointerValue<'ctx>,
-}
-
 impl<'ctx> From<(FloatValue<'ctx>, FloatValue<'ctx>)> for ComplexValue<'ctx> {
     fn from((re, im): (FloatValue<'ctx>, FloatValue<'ctx>)) -> Self {
         Self { re, im }
     }
 }
 
-impl<'a> Complex<PointerValue<'a>> for ComplexPointer<'a> {
-    fn re(&self) -> PointerValue<'a> {
-        self.re
-    }
+// A range in canonical form: it contains `start + k * step` for each integer 0 <= k < len
+#[derive(Debug, Clone, Copy)]
+pub struct RangeValue<'ctx> {
+    pub start: ComplexValue<'ctx>,
+    pub step: ComplexValue<'ctx>,
+    pub len: FloatValue<'ctx>,
+}
 
-    fn im(&self) -> PointerValue<'a> {
-        self.im
+// A compiled value of any type
+#[derive(Debug, Clone)]
+pub enum Value<'ctx> {
+    Complex(ComplexValue<'ctx>),
+    // `a + bω`, with `a` and `b` kept in `re` and `im`
+    Eisenstein(ComplexValue<'ctx>),
+    // `z + wj`, which is `a + bi + cj + dk` for `z = a + bi` and `w = c + di`
+    Quaternion(ComplexValue<'ctx>, ComplexValue<'ctx>),
+    // `p / q` for Gaussian integers `p` and `q`, in lowest terms with `q` a canonical associate
+    Rational(ComplexValue<'ctx>, ComplexValue<'ctx>),
+    // A pointer to a list of coefficients, starting from the constant term, without trailing zeroes
+    Poly(PointerValue<'ctx>),
+    Bool(IntValue<'ctx>),
+    Range(RangeValue<'ctx>),
+    // A pointer to a null-terminated string
+    Str(PointerValue<'ctx>),
+    // A pointer to the list, and the type of its elements
+    List(PointerValue<'ctx>, Type),
+    // A list of keys and a list of values at the same positions, a hash table of the keys (null if
+    // they can't be hashed), and the types of each
+    Dict(PointerValue<'ctx>, PointerValue<'ctx>, PointerValue<'ctx>, Type, Type),
+    // A pointer to a list without duplicates, and the type of its elements
+    Set(PointerValue<'ctx>, Type),
+    Tuple(Vec<Value<'ctx>>),
+    // A pointer to the matrix
+    Matrix(PointerValue<'ctx>),
+    // The name of the record, and the values of its fields in the order they were declared
+    Struct(String, Vec<(String, Value<'ctx>)>),
+    // The name of the enum, which variant this is, and its fields padded with zeroes to a fixed length
+    Enum(String, IntValue<'ctx>, Vec<ComplexValue<'ctx>>),
+    // Where the closure was written, and the values it captured
+    Closure(Location, Vec<(String, Value<'ctx>)>),
+    // Whether there's a value, and the value itself (which is zero if there isn't one)
+    Option(IntValue<'ctx>, Box<Value<'ctx>>),
+    // A pointer to where the value will be kept once it's computed, its type, and the type of the
+    // closure that computes it
+    Lazy(PointerValue<'ctx>, Box<Type>, Box<Type>),
+}
+
+impl<'ctx> Value<'ctx> {
+    pub fn ty(&self) -> Type {
+        match self {
+            Self::Complex(_) => Type::Complex,
+            Self::Eisenstein(_) => Type::Eisenstein,
+            Self::Quaternion(..) => Type::Quaternion,
+            Self::Rational(..) => Type::Rational,
+            Self::Poly(_) => Type::Poly,
+            Self::Bool(_)    => Type::Bool,
+            Self::Range(_)   => Type::Range,
+            Self::Str(_)     => Type::Str,
+            Self::List(_, elem) => Type::List(Box::new(elem.clone())),
+            Self::Dict(_, _, _, key, value) => Type::Dict(Box::new(key.clone()), Box::new(value.clone())),
+            Self::Set(_, elem) => Type::Set(Box::new(elem.clone())),
+            Self::Tuple(elems) => Type::Tuple(elems.iter().map(Value::ty).collect()),
+            Self::Matrix(_)  => Type::Matrix,
+            Self::Enum(name, ..) => Type::Enum(name.clone()),
+            Self::Struct(name, fields) => Type::Struct(name.clone(), fields.iter().map(|(field, value)| (field.clone(), value.ty())).collect()),
+            Self::Closure(pos, env) => Type::Closure(*pos, env.iter().map(|(name, value)| (name.clone(), value.ty())).collect()),
+            Self::Option(_, inner) => Type::Option(Box::new(inner.ty())),
+            Self::Lazy(_, inner, closure) => Type::Lazy(inner.clone(), closure.clone()),
+        }
     }
 }
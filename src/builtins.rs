@@ -1,6 +1,6 @@
 use std::rc::Rc;
 
-use inkwell::{builder::Builder, context::Context, module::{Linkage, Module}, values::FunctionValue};
+use inkwell::{IntPredicate, attributes::{Attribute, AttributeLoc}, builder::Builder, context::Context, module::{Linkage, Module}, values::{FloatValue, FunctionValue, PointerValue}};
 
 pub struct Builtins<'ctx> {
     ctx: &'ctx Context,
@@ -18,6 +18,36 @@ pub struct Builtins<'ctx> {
     log: Option<FunctionValue<'ctx>>,
     cos: Option<FunctionValue<'ctx>>,
     sin: Option<FunctionValue<'ctx>>,
+    exp: Option<FunctionValue<'ctx>>,
+    floor: Option<FunctionValue<'ctx>>,
+    ceil: Option<FunctionValue<'ctx>>,
+    round: Option<FunctionValue<'ctx>>,
+    trunc: Option<FunctionValue<'ctx>>,
+    atan2: Option<FunctionValue<'ctx>>,
+    powi: Option<FunctionValue<'ctx>>,
+    exit: Option<FunctionValue<'ctx>>,
+    stacksave: Option<FunctionValue<'ctx>>,
+    stackrestore: Option<FunctionValue<'ctx>>,
+    realloc: Option<FunctionValue<'ctx>>,
+    calloc: Option<FunctionValue<'ctx>>,
+    runtime_error: Option<FunctionValue<'ctx>>,
+    setjmp: Option<FunctionValue<'ctx>>,
+    longjmp: Option<FunctionValue<'ctx>>,
+    raise: Option<FunctionValue<'ctx>>,
+    strlen: Option<FunctionValue<'ctx>>,
+    strspn: Option<FunctionValue<'ctx>>,
+    strcpy: Option<FunctionValue<'ctx>>,
+    strcat: Option<FunctionValue<'ctx>>,
+    strcmp: Option<FunctionValue<'ctx>>,
+    str_concat: Option<FunctionValue<'ctx>>,
+    snprintf: Option<FunctionValue<'ctx>>,
+    sscanf: Option<FunctionValue<'ctx>>,
+    getchar: Option<FunctionValue<'ctx>>,
+    fflush: Option<FunctionValue<'ctx>>,
+    read_line: Option<FunctionValue<'ctx>>,
+    mulmod: Option<FunctionValue<'ctx>>,
+    powmod: Option<FunctionValue<'ctx>>,
+    is_prime: Option<FunctionValue<'ctx>>,
 }
 
 impl<'ctx> Builtins<'ctx> {
@@ -163,11 +193,643 @@ impl<'ctx> Builtins<'ctx> {
         *self.sin.get_or_insert(f)
     }
 
+    pub fn exp(&mut self) -> FunctionValue<'ctx> {
+        let f = self.exp.unwrap_or_else(|| {
+            let t_f64 = self.ctx.f64_type();
+            let fn_type = t_f64.fn_type(&[t_f64.into()], false);
+            self.module.add_function("llvm.exp.f64", fn_type, Some(Linkage::External))
+        });
+        *self.exp.get_or_insert(f)
+    }
+
+    pub fn floor(&mut self) -> FunctionValue<'ctx> {
+        let f = self.floor.unwrap_or_else(|| {
+            let t_f64 = self.ctx.f64_type();
+            let fn_type = t_f64.fn_type(&[t_f64.into()], false);
+            self.module.add_function("llvm.floor.f64", fn_type, Some(Linkage::External))
+        });
+        *self.floor.get_or_insert(f)
+    }
+
+    pub fn ceil(&mut self) -> FunctionValue<'ctx> {
+        let f = self.ceil.unwrap_or_else(|| {
+            let t_f64 = self.ctx.f64_type();
+            let fn_type = t_f64.fn_type(&[t_f64.into()], false);
+            self.module.add_function("llvm.ceil.f64", fn_type, Some(Linkage::External))
+        });
+        *self.ceil.get_or_insert(f)
+    }
+
+    pub fn round(&mut self) -> FunctionValue<'ctx> {
+        let f = self.round.unwrap_or_else(|| {
+            let t_f64 = self.ctx.f64_type();
+            let fn_type = t_f64.fn_type(&[t_f64.into()], false);
+            self.module.add_function("llvm.round.f64", fn_type, Some(Linkage::External))
+        });
+        *self.round.get_or_insert(f)
+    }
+
+    pub fn trunc(&mut self) -> FunctionValue<'ctx> {
+        let f = self.trunc.unwrap_or_else(|| {
+            let t_f64 = self.ctx.f64_type();
+            let fn_type = t_f64.fn_type(&[t_f64.into()], false);
+            self.module.add_function("llvm.trunc.f64", fn_type, Some(Linkage::External))
+        });
+        *self.trunc.get_or_insert(f)
+    }
+
+    /// There's no LLVM intrinsic for this, so we use libm's.
+    pub fn atan2(&mut self) -> FunctionValue<'ctx> {
+        let f = self.atan2.unwrap_or_else(|| {
+            let t_f64 = self.ctx.f64_type();
+            let fn_type = t_f64.fn_type(&[t_f64.into(), t_f64.into()], false);
+            self.module.add_function("atan2", fn_type, Some(Linkage::External))
+        });
+        *self.atan2.get_or_insert(f)
+    }
+
+    fn complex_mul(&self, (a, b): (FloatValue<'ctx>, FloatValue<'ctx>), (c, d): (FloatValue<'ctx>, FloatValue<'ctx>))
+            -> (FloatValue<'ctx>, FloatValue<'ctx>) {
+        let ac = self.builder.build_float_mul(a, c, "ac");
+        let bd = self.builder.build_float_mul(b, d, "bd");
+        let ad = self.builder.build_float_mul(a, d, "ad");
+        let bc = self.builder.build_float_mul(b, c, "bc");
+        (self.builder.build_float_sub(ac, bd, "mul_re"), self.builder.build_float_add(ad, bc, "mul_im"))
+    }
+
+    /// Raises a complex number to an integer power by repeated squaring. Negative powers are
+    /// computed as the reciprocal of the positive power.
+    pub fn powi(&mut self) -> FunctionValue<'ctx> {
+        let f = self.powi.unwrap_or_else(|| {
+            let t_f64 = self.ctx.f64_type();
+            let t_i64 = self.ctx.i64_type();
+            let t_complex = self.ctx.struct_type(&[t_f64.into(), t_f64.into()], false);
+            let fn_type = t_complex.fn_type(&[t_f64.into(), t_f64.into(), t_i64.into()], false);
+            let f = self.module.add_function(".powi", fn_type, None);
+
+            let entry_bb = self.ctx.append_basic_block(f, "entry");
+            let test_bb = self.ctx.append_basic_block(f, "test");
+            let body_bb = self.ctx.append_basic_block(f, "body");
+            let done_bb = self.ctx.append_basic_block(f, "done");
+
+            self.builder.position_at_end(entry_bb);
+            let re = f.get_nth_param(0).unwrap().into_float_value();
+            let im = f.get_nth_param(1).unwrap().into_float_value();
+            let n = f.get_nth_param(2).unwrap().into_int_value();
+            let zero = t_i64.const_zero();
+            let one = t_i64.const_int(1, false);
+            let negative = self.builder.build_int_compare(IntPredicate::SLT, n, zero, "negative");
+            let neg_n = self.builder.build_int_neg(n, "neg_n");
+            let n = self.builder.build_select(negative, neg_n, n, "abs_n").into_int_value();
+            self.builder.build_unconditional_branch(test_bb);
+
+            // Loop invariant: acc * base^k is the result
+            self.builder.position_at_end(test_bb);
+            let acc_re = self.builder.build_phi(t_f64, "acc_re");
+            let acc_im = self.builder.build_phi(t_f64, "acc_im");
+            let base_re = self.builder.build_phi(t_f64, "base_re");
+            let base_im = self.builder.build_phi(t_f64, "base_im");
+            let k = self.builder.build_phi(t_i64, "k");
+            let acc = (acc_re.as_basic_value().into_float_value(), acc_im.as_basic_value().into_float_value());
+            let base = (base_re.as_basic_value().into_float_value(), base_im.as_basic_value().into_float_value());
+            let k_val = k.as_basic_value().into_int_value();
+            let finished = self.builder.build_int_compare(IntPredicate::EQ, k_val, zero, "finished");
+            self.builder.build_conditional_branch(finished, done_bb, body_bb);
+
+            self.builder.position_at_end(body_bb);
+            let low_bit = self.builder.build_and(k_val, one, "low_bit");
+            let odd = self.builder.build_int_compare(IntPredicate::NE, low_bit, zero, "odd");
+            let prod = self.complex_mul(acc, base);
+            let next_acc_re = self.builder.build_select(odd, prod.0, acc.0, "next_acc_re");
+            let next_acc_im = self.builder.build_select(odd, prod.1, acc.1, "next_acc_im");
+            let square = self.complex_mul(base, base);
+            let next_k = self.builder.build_right_shift(k_val, one, false, "next_k");
+            self.builder.build_unconditional_branch(test_bb);
+
+            acc_re.add_incoming(&[(&t_f64.const_float(1.), entry_bb), (&next_acc_re, body_bb)]);
+            acc_im.add_incoming(&[(&t_f64.const_zero(), entry_bb), (&next_acc_im, body_bb)]);
+            base_re.add_incoming(&[(&re, entry_bb), (&square.0, body_bb)]);
+            base_im.add_incoming(&[(&im, entry_bb), (&square.1, body_bb)]);
+            k.add_incoming(&[(&n, entry_bb), (&next_k, body_bb)]);
+
+            // 1/z = z^ / |z|^2
+            self.builder.position_at_end(done_bb);
+            let re2 = self.builder.build_float_mul(acc.0, acc.0, "re2");
+            let im2 = self.builder.build_float_mul(acc.1, acc.1, "im2");
+            let norm = self.builder.build_float_add(re2, im2, "norm");
+            let inv_re = self.builder.build_float_div(acc.0, norm, "inv_re");
+            let neg_im = self.builder.build_float_neg(acc.1, "neg_im");
+            let inv_im = self.builder.build_float_div(neg_im, norm, "inv_im");
+            let res_re = self.builder.build_select(negative, inv_re, acc.0, "res_re");
+            let res_im = self.builder.build_select(negative, inv_im, acc.1, "res_im");
+
+            let res = t_complex.get_undef();
+            let res = self.builder.build_insert_value(res, res_re, 0, "ret_re").unwrap().into_struct_value();
+            let res = self.builder.build_insert_value(res, res_im, 1, "ret_im").unwrap().into_struct_value();
+            self.builder.build_return(Some(&res));
+            f
+        });
+        *self.powi.get_or_insert(f)
+    }
+
+    pub fn exit(&mut self) -> FunctionValue<'ctx> {
+        let f = self.exit.unwrap_or_else(|| {
+            let fn_type = self.ctx.void_type().fn_type(&[self.ctx.i32_type().into()], false);
+            self.module.add_function("exit", fn_type, Some(Linkage::External))
+        });
+        *self.exit.get_or_insert(f)
+    }
+
+    pub fn stacksave(&mut self) -> FunctionValue<'ctx> {
+        let f = self.stacksave.unwrap_or_else(|| {
+            let i8p_type = self.ctx.i8_type().ptr_type(inkwell::AddressSpace::Generic);
+            self.module.add_function("llvm.stacksave", i8p_type.fn_type(&[], false), Some(Linkage::External))
+        });
+        *self.stacksave.get_or_insert(f)
+    }
+
+    pub fn stackrestore(&mut self) -> FunctionValue<'ctx> {
+        let f = self.stackrestore.unwrap_or_else(|| {
+            let i8p_type = self.ctx.i8_type().ptr_type(inkwell::AddressSpace::Generic);
+            let fn_type = self.ctx.void_type().fn_type(&[i8p_type.into()], false);
+            self.module.add_function("llvm.stackrestore", fn_type, Some(Linkage::External))
+        });
+        *self.stackrestore.get_or_insert(f)
+    }
+
+    pub fn realloc(&mut self) -> FunctionValue<'ctx> {
+        let f = self.realloc.unwrap_or_else(|| {
+            let i8p_type = self.ctx.i8_type().ptr_type(inkwell::AddressSpace::Generic);
+            let fn_type = i8p_type.fn_type(&[i8p_type.into(), self.ctx.i64_type().into()], false);
+            self.module.add_function("realloc", fn_type, Some(Linkage::External))
+        });
+        *self.realloc.get_or_insert(f)
+    }
+
+    pub fn calloc(&mut self) -> FunctionValue<'ctx> {
+        let f = self.calloc.unwrap_or_else(|| {
+            let i8p_type = self.ctx.i8_type().ptr_type(inkwell::AddressSpace::Generic);
+            let fn_type = i8p_type.fn_type(&[self.ctx.i64_type().into(), self.ctx.i64_type().into()], false);
+            self.module.add_function("calloc", fn_type, Some(Linkage::External))
+        });
+        *self.calloc.get_or_insert(f)
+    }
+
+    /// Prints an error message and exits with a failure status.
+    pub fn runtime_error(&mut self) -> FunctionValue<'ctx> {
+        let printf = self.printf();
+        let exit = self.exit();
+        let f = self.runtime_error.unwrap_or_else(|| {
+            let i8p_type = self.ctx.i8_type().ptr_type(inkwell::AddressSpace::Generic);
+            let fn_type = self.ctx.void_type().fn_type(&[i8p_type.into()], false);
+            let f = self.module.add_function(".runtime_error", fn_type, None);
+            let block = self.ctx.append_basic_block(f, "entry");
+            self.builder.position_at_end(block);
+            let printf_str = self.builder.build_global_string_ptr("\nruntime error: %s\n", ".err_format");
+            let ptr = f.get_nth_param(0).unwrap().into_pointer_value();
+            self.builder.build_call(printf, &[printf_str.as_pointer_value().into(), ptr.into()], "call");
+            self.builder.build_call(exit, &[self.ctx.i32_type().const_int(1, false).into()], "call");
+            self.builder.build_unreachable();
+            f
+        });
+        *self.runtime_error.get_or_insert(f)
+    }
+
+    /// The `jmp_buf` of the innermost `try` that's running, or null if there isn't one.
+    pub fn handler(&self) -> PointerValue<'ctx> {
+        let i8p_type = self.ctx.i8_type().ptr_type(inkwell::AddressSpace::Generic);
+        let global = self.module.get_global(".handler").unwrap_or_else(|| {
+            let global = self.module.add_global(i8p_type, None, ".handler");
+            global.set_initializer(&i8p_type.const_null());
+            global
+        });
+        global.as_pointer_value()
+    }
+
+    /// The message of the error that `raise` jumped with.
+    pub fn error(&self) -> PointerValue<'ctx> {
+        let i8p_type = self.ctx.i8_type().ptr_type(inkwell::AddressSpace::Generic);
+        let global = self.module.get_global(".error").unwrap_or_else(|| {
+            let global = self.module.add_global(i8p_type, None, ".error");
+            global.set_initializer(&i8p_type.const_null());
+            global
+        });
+        global.as_pointer_value()
+    }
+
+    pub fn setjmp(&mut self) -> FunctionValue<'ctx> {
+        let f = self.setjmp.unwrap_or_else(|| {
+            let i8p_type = self.ctx.i8_type().ptr_type(inkwell::AddressSpace::Generic);
+            let fn_type = self.ctx.i32_type().fn_type(&[i8p_type.into()], false);
+            let f = self.module.add_function("_setjmp", fn_type, Some(Linkage::External));
+            let returns_twice = Attribute::get_named_enum_kind_id("returns_twice");
+            f.add_attribute(AttributeLoc::Function, self.ctx.create_enum_attribute(returns_twice, 0));
+            f
+        });
+        *self.setjmp.get_or_insert(f)
+    }
+
+    fn longjmp(&mut self) -> FunctionValue<'ctx> {
+        let f = self.longjmp.unwrap_or_else(|| {
+            let i8p_type = self.ctx.i8_type().ptr_type(inkwell::AddressSpace::Generic);
+            let fn_type = self.ctx.void_type().fn_type(&[i8p_type.into(), self.ctx.i32_type().into()], false);
+            let f = self.module.add_function("longjmp", fn_type, Some(Linkage::External));
+            let noreturn = Attribute::get_named_enum_kind_id("noreturn");
+            f.add_attribute(AttributeLoc::Function, self.ctx.create_enum_attribute(noreturn, 0));
+            f
+        });
+        *self.longjmp.get_or_insert(f)
+    }
+
+    /// Jumps to the innermost `try` that's running with an error message, or returns if there isn't
+    /// one, in which case the caller should abort.
+    pub fn raise(&mut self) -> FunctionValue<'ctx> {
+        let longjmp = self.longjmp();
+        let f = self.raise.unwrap_or_else(|| {
+            let i8p_type = self.ctx.i8_type().ptr_type(inkwell::AddressSpace::Generic);
+            let fn_type = self.ctx.void_type().fn_type(&[i8p_type.into()], false);
+            let f = self.module.add_function(".raise", fn_type, None);
+            let entry = self.ctx.append_basic_block(f, "entry");
+            let jump_bb = self.ctx.append_basic_block(f, "jump");
+            let done_bb = self.ctx.append_basic_block(f, "done");
+            self.builder.position_at_end(entry);
+            let handler = self.builder.build_load(self.handler(), "handler").into_pointer_value();
+            let running = self.builder.build_is_not_null(handler, "running");
+            self.builder.build_conditional_branch(running, jump_bb, done_bb);
+
+            self.builder.position_at_end(jump_bb);
+            let msg = f.get_nth_param(0).unwrap().into_pointer_value();
+            self.builder.build_store(self.error(), msg);
+            self.builder.build_call(longjmp, &[handler.into(), self.ctx.i32_type().const_int(1, false).into()], "call");
+            self.builder.build_unreachable();
+
+            self.builder.position_at_end(done_bb);
+            self.builder.build_return(None);
+            f
+        });
+        *self.raise.get_or_insert(f)
+    }
+
+    pub fn snprintf(&mut self) -> FunctionValue<'ctx> {
+        let f = self.snprintf.unwrap_or_else(|| {
+            let i8p_type = self.ctx.i8_type().ptr_type(inkwell::AddressSpace::Generic);
+            let fn_type = self.ctx.i32_type().fn_type(&[i8p_type.into(), self.ctx.i64_type().into(), i8p_type.into()], true);
+            self.module.add_function("snprintf", fn_type, Some(Linkage::External))
+        });
+        *self.snprintf.get_or_insert(f)
+    }
+
+    pub fn sscanf(&mut self) -> FunctionValue<'ctx> {
+        let f = self.sscanf.unwrap_or_else(|| {
+            let i8p_type = self.ctx.i8_type().ptr_type(inkwell::AddressSpace::Generic);
+            let fn_type = self.ctx.i32_type().fn_type(&[i8p_type.into(), i8p_type.into()], true);
+            self.module.add_function("sscanf", fn_type, Some(Linkage::External))
+        });
+        *self.sscanf.get_or_insert(f)
+    }
+
+    fn getchar(&mut self) -> FunctionValue<'ctx> {
+        let f = self.getchar.unwrap_or_else(|| {
+            let fn_type = self.ctx.i32_type().fn_type(&[], false);
+            self.module.add_function("getchar", fn_type, Some(Linkage::External))
+        });
+        *self.getchar.get_or_insert(f)
+    }
+
+    fn fflush(&mut self) -> FunctionValue<'ctx> {
+        let f = self.fflush.unwrap_or_else(|| {
+            let i8p_type = self.ctx.i8_type().ptr_type(inkwell::AddressSpace::Generic);
+            let fn_type = self.ctx.i32_type().fn_type(&[i8p_type.into()], false);
+            self.module.add_function("fflush", fn_type, Some(Linkage::External))
+        });
+        *self.fflush.get_or_insert(f)
+    }
+
+    /// Reads a line from stdin into a freshly allocated string, without the newline. Anything
+    /// printed so far is flushed first, so that a prompt shows up.
+    pub fn read_line(&mut self) -> FunctionValue<'ctx> {
+        let getchar = self.getchar();
+        let fflush = self.fflush();
+        let realloc = self.realloc();
+        let f = self.read_line.unwrap_or_else(|| {
+            let i8_type = self.ctx.i8_type();
+            let i64_type = self.ctx.i64_type();
+            let i8p_type = i8_type.ptr_type(inkwell::AddressSpace::Generic);
+            let fn_type = i8p_type.fn_type(&[], false);
+            let f = self.module.add_function(".read_line", fn_type, None);
+            let entry_bb = self.ctx.append_basic_block(f, "entry");
+            let loop_bb = self.ctx.append_basic_block(f, "loop");
+            let store_bb = self.ctx.append_basic_block(f, "store");
+            let end_bb = self.ctx.append_basic_block(f, "end");
+
+            self.builder.position_at_end(entry_bb);
+            self.builder.build_call(fflush, &[i8p_type.const_null().into()], "call");
+            let init_cap = i64_type.const_int(16, false);
+            let init_buf = self.builder.build_call(realloc, &[i8p_type.const_null().into(), init_cap.into()], "buf")
+                .try_as_basic_value().left().unwrap();
+            self.builder.build_unconditional_branch(loop_bb);
+
+            // Stops at a newline or the end of the input
+            self.builder.position_at_end(loop_bb);
+            let buf = self.builder.build_phi(i8p_type, "buf");
+            let len = self.builder.build_phi(i64_type, "len");
+            let cap = self.builder.build_phi(i64_type, "cap");
+            let c = self.builder.build_call(getchar, &[], "c").try_as_basic_value().left().unwrap().into_int_value();
+            let eof = self.builder.build_int_compare(IntPredicate::EQ, c, self.ctx.i32_type().const_all_ones(), "eof");
+            let newline = self.builder.build_int_compare(IntPredicate::EQ, c, self.ctx.i32_type().const_int(b'\n' as u64, false), "newline");
+            let done = self.builder.build_or(eof, newline, "done");
+            self.builder.build_conditional_branch(done, end_bb, store_bb);
+
+            // Keeps room for the character and the terminator
+            self.builder.position_at_end(store_bb);
+            let len_val = len.as_basic_value().into_int_value();
+            let cap_val = cap.as_basic_value().into_int_value();
+            let needed = self.builder.build_int_add(len_val, i64_type.const_int(2, false), "needed");
+            let full = self.builder.build_int_compare(IntPredicate::UGT, needed, cap_val, "full");
+            let doubled = self.builder.build_int_mul(cap_val, i64_type.const_int(2, false), "doubled");
+            let new_cap = self.builder.build_select(full, doubled, cap_val, "new_cap").into_int_value();
+            let new_buf = self.builder.build_call(realloc, &[buf.as_basic_value(), new_cap.into()], "new_buf")
+                .try_as_basic_value().left().unwrap().into_pointer_value();
+            let ptr = unsafe { self.builder.build_in_bounds_gep(new_buf, &[len_val], "ptr") };
+            self.builder.build_store(ptr, self.builder.build_int_truncate(c, i8_type, "char"));
+            let new_len = self.builder.build_int_add(len_val, i64_type.const_int(1, false), "new_len");
+            self.builder.build_unconditional_branch(loop_bb);
+
+            buf.add_incoming(&[(&init_buf, entry_bb), (&new_buf, store_bb)]);
+            len.add_incoming(&[(&i64_type.const_zero(), entry_bb), (&new_len, store_bb)]);
+            cap.add_incoming(&[(&init_cap, entry_bb), (&new_cap, store_bb)]);
+
+            self.builder.position_at_end(end_bb);
+            let buf = buf.as_basic_value().into_pointer_value();
+            let ptr = unsafe { self.builder.build_in_bounds_gep(buf, &[len.as_basic_value().into_int_value()], "ptr") };
+            self.builder.build_store(ptr, i8_type.const_zero());
+            self.builder.build_return(Some(&buf));
+            f
+        });
+        *self.read_line.get_or_insert(f)
+    }
+
+    pub fn strlen(&mut self) -> FunctionValue<'ctx> {
+        let f = self.strlen.unwrap_or_else(|| {
+            let i8p_type = self.ctx.i8_type().ptr_type(inkwell::AddressSpace::Generic);
+            let fn_type = self.ctx.i64_type().fn_type(&[i8p_type.into()], false);
+            self.module.add_function("strlen", fn_type, Some(Linkage::External))
+        });
+        *self.strlen.get_or_insert(f)
+    }
+
+    pub fn strspn(&mut self) -> FunctionValue<'ctx> {
+        let f = self.strspn.unwrap_or_else(|| {
+            let i8p_type = self.ctx.i8_type().ptr_type(inkwell::AddressSpace::Generic);
+            let fn_type = self.ctx.i64_type().fn_type(&[i8p_type.into(), i8p_type.into()], false);
+            self.module.add_function("strspn", fn_type, Some(Linkage::External))
+        });
+        *self.strspn.get_or_insert(f)
+    }
+
+    fn strcpy(&mut self) -> FunctionValue<'ctx> {
+        let f = self.strcpy.unwrap_or_else(|| {
+            let i8p_type = self.ctx.i8_type().ptr_type(inkwell::AddressSpace::Generic);
+            let fn_type = i8p_type.fn_type(&[i8p_type.into(), i8p_type.into()], false);
+            self.module.add_function("strcpy", fn_type, Some(Linkage::External))
+        });
+        *self.strcpy.get_or_insert(f)
+    }
+
+    fn strcat(&mut self) -> FunctionValue<'ctx> {
+        let f = self.strcat.unwrap_or_else(|| {
+            let i8p_type = self.ctx.i8_type().ptr_type(inkwell::AddressSpace::Generic);
+            let fn_type = i8p_type.fn_type(&[i8p_type.into(), i8p_type.into()], false);
+            self.module.add_function("strcat", fn_type, Some(Linkage::External))
+        });
+        *self.strcat.get_or_insert(f)
+    }
+
+    pub fn strcmp(&mut self) -> FunctionValue<'ctx> {
+        let f = self.strcmp.unwrap_or_else(|| {
+            let i8p_type = self.ctx.i8_type().ptr_type(inkwell::AddressSpace::Generic);
+            let fn_type = self.ctx.i32_type().fn_type(&[i8p_type.into(), i8p_type.into()], false);
+            self.module.add_function("strcmp", fn_type, Some(Linkage::External))
+        });
+        *self.strcmp.get_or_insert(f)
+    }
+
+    /// Concatenates two strings into a freshly allocated one, which is never freed.
+    pub fn str_concat(&mut self) -> FunctionValue<'ctx> {
+        let strlen = self.strlen();
+        let strcpy = self.strcpy();
+        let strcat = self.strcat();
+        let f = self.str_concat.unwrap_or_else(|| {
+            let i8_type = self.ctx.i8_type();
+            let i8p_type = i8_type.ptr_type(inkwell::AddressSpace::Generic);
+            let fn_type = i8p_type.fn_type(&[i8p_type.into(), i8p_type.into()], false);
+            let f = self.module.add_function(".str_concat", fn_type, None);
+            let block = self.ctx.append_basic_block(f, "entry");
+            self.builder.position_at_end(block);
+
+            let lhs = f.get_nth_param(0).unwrap().into_pointer_value();
+            let rhs = f.get_nth_param(1).unwrap().into_pointer_value();
+            let len_lhs = self.builder.build_call(strlen, &[lhs.into()], "len_lhs")
+                .try_as_basic_value().left().unwrap().into_int_value();
+            let len_rhs = self.builder.build_call(strlen, &[rhs.into()], "len_rhs")
+                .try_as_basic_value().left().unwrap().into_int_value();
+            let len = self.builder.build_int_add(len_lhs, len_rhs, "len");
+            let size = self.builder.build_int_add(len, self.ctx.i64_type().const_int(1, false), "size");
+            let res = self.builder.build_array_malloc(i8_type, size, "res").unwrap();
+            self.builder.build_call(strcpy, &[res.into(), lhs.into()], "call");
+            self.builder.build_call(strcat, &[res.into(), rhs.into()], "call");
+            self.builder.build_return(Some(&res));
+            f
+        });
+        *self.str_concat.get_or_insert(f)
+    }
+
+    /// `a * b mod m` for `a, b < m`, going through 128 bits so that it can't overflow.
+    pub fn mulmod(&mut self) -> FunctionValue<'ctx> {
+        let f = self.mulmod.unwrap_or_else(|| {
+            let i64_type = self.ctx.i64_type();
+            let i128_type = self.ctx.i128_type();
+            let fn_type = i64_type.fn_type(&[i64_type.into(), i64_type.into(), i64_type.into()], false);
+            let f = self.module.add_function(".mulmod", fn_type, None);
+            let block = self.ctx.append_basic_block(f, "entry");
+            self.builder.position_at_end(block);
+            let wide = |k| self.builder.build_int_z_extend(f.get_nth_param(k).unwrap().into_int_value(), i128_type, "wide");
+            let (a, b, m) = (wide(0), wide(1), wide(2));
+            let prod = self.builder.build_int_mul(a, b, "prod");
+            let rem = self.builder.build_int_unsigned_rem(prod, m, "rem");
+            self.builder.build_return(Some(&self.builder.build_int_truncate(rem, i64_type, "res")));
+            f
+        });
+        *self.mulmod.get_or_insert(f)
+    }
+
+    /// `a ** e mod m` by repeated squaring.
+    pub fn powmod(&mut self) -> FunctionValue<'ctx> {
+        let mulmod = self.mulmod();
+        let f = self.powmod.unwrap_or_else(|| {
+            let i64_type = self.ctx.i64_type();
+            let fn_type = i64_type.fn_type(&[i64_type.into(), i64_type.into(), i64_type.into()], false);
+            let f = self.module.add_function(".powmod", fn_type, None);
+            let entry_bb = self.ctx.append_basic_block(f, "entry");
+            let loop_bb = self.ctx.append_basic_block(f, "loop");
+            let body_bb = self.ctx.append_basic_block(f, "body");
+            let exit_bb = self.ctx.append_basic_block(f, "exit");
+            let a = f.get_nth_param(0).unwrap().into_int_value();
+            let e = f.get_nth_param(1).unwrap().into_int_value();
+            let m = f.get_nth_param(2).unwrap().into_int_value();
+            let one = i64_type.const_int(1, false);
+
+            self.builder.position_at_end(entry_bb);
+            let base_init = self.builder.build_int_unsigned_rem(a, m, "base");
+            self.builder.build_unconditional_branch(loop_bb);
+
+            self.builder.position_at_end(loop_bb);
+            let result = self.builder.build_phi(i64_type, "result");
+            let base = self.builder.build_phi(i64_type, "base");
+            let exp = self.builder.build_phi(i64_type, "exp");
+            let result_val = result.as_basic_value().into_int_value();
+            let base_val = base.as_basic_value().into_int_value();
+            let exp_val = exp.as_basic_value().into_int_value();
+            let done = self.builder.build_int_compare(IntPredicate::EQ, exp_val, i64_type.const_zero(), "done");
+            self.builder.build_conditional_branch(done, exit_bb, body_bb);
+
+            self.builder.position_at_end(body_bb);
+            let bit = self.builder.build_and(exp_val, one, "bit");
+            let odd = self.builder.build_int_compare(IntPredicate::NE, bit, i64_type.const_zero(), "odd");
+            let prod = self.builder.build_call(mulmod, &[result_val.into(), base_val.into(), m.into()], "prod")
+                .try_as_basic_value().left().unwrap().into_int_value();
+            let next_result = self.builder.build_select(odd, prod, result_val, "next_result").into_int_value();
+            let next_base = self.builder.build_call(mulmod, &[base_val.into(), base_val.into(), m.into()], "next_base")
+                .try_as_basic_value().left().unwrap().into_int_value();
+            let next_exp = self.builder.build_right_shift(exp_val, one, false, "next_exp");
+            self.builder.build_unconditional_branch(loop_bb);
+
+            result.add_incoming(&[(&one, entry_bb), (&next_result, body_bb)]);
+            base.add_incoming(&[(&base_init, entry_bb), (&next_base, body_bb)]);
+            exp.add_incoming(&[(&e, entry_bb), (&next_exp, body_bb)]);
+
+            self.builder.position_at_end(exit_bb);
+            self.builder.build_return(Some(&result_val));
+            f
+        });
+        *self.powmod.get_or_insert(f)
+    }
+
+    /// Whether `n` is prime, by Miller-Rabin with the first twelve primes as witnesses. That's
+    /// deterministic for every `n` that fits in 64 bits.
+    pub fn is_prime(&mut self) -> FunctionValue<'ctx> {
+        const WITNESSES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+        let mulmod = self.mulmod();
+        let powmod = self.powmod();
+        let f = self.is_prime.unwrap_or_else(|| {
+            let i64_type = self.ctx.i64_type();
+            let bool_type = self.ctx.bool_type();
+            let fn_type = bool_type.fn_type(&[i64_type.into()], false);
+            let f = self.module.add_function(".is_prime", fn_type, None);
+            let n = f.get_nth_param(0).unwrap().into_int_value();
+            let zero = i64_type.const_zero();
+            let one = i64_type.const_int(1, false);
+            let entry_bb = self.ctx.append_basic_block(f, "entry");
+            let true_bb = self.ctx.append_basic_block(f, "prime");
+            let false_bb = self.ctx.append_basic_block(f, "composite");
+
+            self.builder.position_at_end(true_bb);
+            self.builder.build_return(Some(&bool_type.const_int(1, false)));
+            self.builder.position_at_end(false_bb);
+            self.builder.build_return(Some(&bool_type.const_zero()));
+
+            self.builder.position_at_end(entry_bb);
+            let small = self.builder.build_int_compare(IntPredicate::SLT, n, i64_type.const_int(2, false), "small");
+            let next_bb = self.ctx.append_basic_block(f, "trial");
+            self.builder.build_conditional_branch(small, false_bb, next_bb);
+            self.builder.position_at_end(next_bb);
+
+            // The witnesses themselves are prime, and their multiples aren't
+            for &p in WITNESSES.iter() {
+                let p = i64_type.const_int(p, false);
+                let equal = self.builder.build_int_compare(IntPredicate::EQ, n, p, "equal");
+                let rem_bb = self.ctx.append_basic_block(f, "trial_rem");
+                self.builder.build_conditional_branch(equal, true_bb, rem_bb);
+                self.builder.position_at_end(rem_bb);
+                let rem = self.builder.build_int_unsigned_rem(n, p, "rem");
+                let multiple = self.builder.build_int_compare(IntPredicate::EQ, rem, zero, "multiple");
+                let next_bb = self.ctx.append_basic_block(f, "trial");
+                self.builder.build_conditional_branch(multiple, false_bb, next_bb);
+                self.builder.position_at_end(next_bb);
+            }
+
+            // n - 1 = d * 2^s with d odd
+            let n_minus_one = self.builder.build_int_sub(n, one, "n_minus_one");
+            let trial_bb = self.builder.get_insert_block().unwrap();
+            let split_bb = self.ctx.append_basic_block(f, "split");
+            let halve_bb = self.ctx.append_basic_block(f, "halve");
+            let witness_bb = self.ctx.append_basic_block(f, "witness");
+            self.builder.build_unconditional_branch(split_bb);
+            self.builder.position_at_end(split_bb);
+            let d = self.builder.build_phi(i64_type, "d");
+            let s = self.builder.build_phi(i64_type, "s");
+            let d_val = d.as_basic_value().into_int_value();
+            let s_val = s.as_basic_value().into_int_value();
+            let bit = self.builder.build_and(d_val, one, "bit");
+            let even = self.builder.build_int_compare(IntPredicate::EQ, bit, zero, "even");
+            self.builder.build_conditional_branch(even, halve_bb, witness_bb);
+            self.builder.position_at_end(halve_bb);
+            let half = self.builder.build_right_shift(d_val, one, false, "half");
+            let s_next = self.builder.build_int_add(s_val, one, "s_next");
+            self.builder.build_unconditional_branch(split_bb);
+            d.add_incoming(&[(&n_minus_one, trial_bb), (&half, halve_bb)]);
+            s.add_incoming(&[(&zero, trial_bb), (&s_next, halve_bb)]);
+
+            // Each witness a passes if a^d = 1, or a^(d * 2^r) = -1 for some r < s
+            self.builder.position_at_end(witness_bb);
+            for &a in WITNESSES.iter() {
+                let a = i64_type.const_int(a, false);
+                let x = self.builder.build_call(powmod, &[a.into(), d_val.into(), n.into()], "x")
+                    .try_as_basic_value().left().unwrap().into_int_value();
+                let is_one = self.builder.build_int_compare(IntPredicate::EQ, x, one, "is_one");
+                let is_minus_one = self.builder.build_int_compare(IntPredicate::EQ, x, n_minus_one, "is_minus_one");
+                let passed = self.builder.build_or(is_one, is_minus_one, "passed");
+                let start_bb = self.builder.get_insert_block().unwrap();
+                let square_bb = self.ctx.append_basic_block(f, "square");
+                let square_body_bb = self.ctx.append_basic_block(f, "square_body");
+                let next_bb = self.ctx.append_basic_block(f, "witness");
+                self.builder.build_conditional_branch(passed, next_bb, square_bb);
+
+                self.builder.position_at_end(square_bb);
+                let y = self.builder.build_phi(i64_type, "y");
+                let r = self.builder.build_phi(i64_type, "r");
+                let y_val = y.as_basic_value().into_int_value();
+                let r_val = r.as_basic_value().into_int_value();
+                let more = self.builder.build_int_compare(IntPredicate::ULT, r_val, s_val, "more");
+                self.builder.build_conditional_branch(more, square_body_bb, false_bb);
+
+                self.builder.position_at_end(square_body_bb);
+                let y_next = self.builder.build_call(mulmod, &[y_val.into(), y_val.into(), n.into()], "y_next")
+                    .try_as_basic_value().left().unwrap().into_int_value();
+                let r_next = self.builder.build_int_add(r_val, one, "r_next");
+                let found = self.builder.build_int_compare(IntPredicate::EQ, y_next, n_minus_one, "found");
+                self.builder.build_conditional_branch(found, next_bb, square_bb);
+                y.add_incoming(&[(&x, start_bb), (&y_next, square_body_bb)]);
+                r.add_incoming(&[(&one, start_bb), (&r_next, square_body_bb)]);
+
+                self.builder.position_at_end(next_bb);
+            }
+            self.builder.build_unconditional_branch(true_bb);
+            f
+        });
+        *self.is_prime.get_or_insert(f)
+    }
+
     pub fn new(ctx: &'ctx Context, module: Rc<Module<'ctx>>, builder: Rc<Builder<'ctx>>) -> Self {
         Self {
             ctx, module, builder,
             _printf: None, print_float: None, println_float: None, print_str: None, println_str: None,
             sqrt: None, min: None, max: None, abs: None, log: None, cos: None, sin: None,
+            exp: None, floor: None, ceil: None, round: None, trunc: None, atan2: None, powi: None, exit: None, stacksave: None, stackrestore: None, realloc: None, calloc: None, runtime_error: None,
+            setjmp: None, longjmp: None, raise: None,
+            strlen: None, strspn: None, strcpy: None, strcat: None, strcmp: None, str_concat: None, snprintf: None,
+            sscanf: None, getchar: None, fflush: None, read_line: None,
+            mulmod: None, powmod: None, is_prime: None,
         }
     }
 }
@@ -1,60 +1,261 @@
 use std::{collections::HashMap, path::Path, rc::Rc};
 
-use inkwell::{FloatPredicate, OptimizationLevel, basic_block::BasicBlock, builder::Builder, context::Context, execution_engine::JitFunction, module::Module, values::{FunctionValue, InstructionOpcode}};
-
-use crate::{analyse::{Complex, ComplexPointer, ComplexValue, Located, Location, Type, Typed}, builtins::Builtins, error::{LocatedCompileError, InternalError}, parse::{BinOp, Expr, UnOp, Func, Statement}, util::ComplexNum};
+use inkwell::{AddressSpace, FloatPredicate, IntPredicate, OptimizationLevel, basic_block::BasicBlock, builder::Builder, context::Context, execution_engine::JitFunction, module::Module, types::{BasicType, BasicTypeEnum, StructType}, values::{BasicValueEnum, CallSiteValue, FloatValue, FunctionValue, IntValue, PointerValue}};
+
+mod annotation;
+mod combinatorics;
+mod congruence;
+mod dict;
+mod library;
+mod eisenstein;
+mod enums;
+mod factor;
+mod format;
+mod fraction;
+mod generator;
+mod input;
+mod lazy;
+mod list;
+mod matrix;
+mod memo;
+mod modular;
+mod multiplicative;
+mod option;
+mod poly;
+mod quaternion;
+mod random;
+mod rational;
+mod set;
+mod transform;
+
+use crate::{analyse::{Complex, ComplexValue, Located, Location, RangeValue, Type, Value, Variable}, builtins::Builtins, error::{LocatedCompileError, InternalError}, parse::{Annotation, BinOp, Binder, Enum, Expr, UnOp, Func, Global, Item, Pattern, Segment, Statement, Struct, annotate}, util::ComplexNum};
+
+// LLVM's `tailcc` calling convention. A call marked `tail` that's followed by returning its result
+// always reuses the caller's frame under it, even when the two functions take different arguments.
+const TAIL_CC: u32 = 18;
+
+// A function compiled for particular argument types
+#[derive(Debug, Clone)]
+struct Instance<'ctx> {
+    fp: FunctionValue<'ctx>,
+    ret: Type,
+    // Whether the body is still being compiled, and whether it was called while it was
+    compiling: bool,
+    recursive: bool,
+}
 
 struct SymbolTable<'ctx> {
-    // TODO: function types
-    func_map: HashMap<String, FunctionValue<'ctx>>,
-    var_map: HashMap<String, Located<Typed<ComplexPointer<'ctx>>>>,
+    func_map: HashMap<String, Rc<Func>>,
+    struct_map: HashMap<String, Rc<Struct>>,
+    enum_map: HashMap<String, Rc<Enum>>,
+    // Which enum each variant belongs to, and its index
+    variant_map: HashMap<String, (Rc<Enum>, usize)>,
+    instances: HashMap<(String, Vec<Type>), Instance<'ctx>>,
+    // Closures become functions whose first parameters are the captured variables
+    lambda_map: HashMap<Location, Rc<Func>>,
+    var_map: HashMap<String, Located<Variable<'ctx>>>,
+    // Variables defined at the top level, which functions have to ask for with `global`
+    global_map: HashMap<String, Located<Variable<'ctx>>>,
+    // The namespace of each file, which is `None` for the file being run, and the file whose code
+    // is being compiled. Names used there are looked up in its namespace first.
+    namespaces: Vec<Option<String>>,
+    file: usize,
 }
 
 impl<'ctx> SymbolTable<'ctx> {
     fn new() -> Self {
-        Self { func_map: HashMap::new(), var_map: HashMap::new(), }
+        Self { func_map: HashMap::new(), struct_map: HashMap::new(), enum_map: HashMap::new(),
+               variant_map: HashMap::new(), instances: HashMap::new(), lambda_map: HashMap::new(), var_map: HashMap::new(),
+               global_map: HashMap::new(), namespaces: Vec::new(), file: 0, }
+    }
+
+    /// Finds `name` as it's written in the current file: `area` in `geometry.ccc` is `geometry.area`
+    /// if that exists, and otherwise just `area`.
+    fn resolve<'a, T>(&self, map: &'a HashMap<String, T>, name: &str) -> Option<&'a T> {
+        match self.namespaces.get(self.file) {
+            Some(Some(ns)) => map.get(&format!("{}.{}", ns, name)).or_else(|| map.get(name)),
+            _ => map.get(name),
+        }
+    }
+
+    fn is_namespace(&self, name: &str) -> bool {
+        self.namespaces.iter().any(|ns| ns.as_deref() == Some(name))
     }
 
-    fn add_func(&mut self, name: String, ptr: FunctionValue<'ctx>) {
-        self.func_map.insert(name, ptr);
+    fn add_func(&mut self, func: Func) -> Result<(), LocatedCompileError> {
+        if let Some(prev) = self.func_map.get(func.name.borrow_val()) {
+            return Err(LocatedCompileError::duplicate(func.name.clone(), prev.name.pos()));
+        }
+        self.func_map.insert(func.name.borrow_val().clone(), Rc::new(func));
+        Ok(())
     }
     
-    fn func(&self, name: &str) -> Option<&FunctionValue<'ctx>> {
-        self.func_map.get(name)
+    fn func(&self, name: &str) -> Option<&Rc<Func>> {
+        self.resolve(&self.func_map, name)
+    }
+
+    fn add_struct(&mut self, def: Struct) -> Result<(), LocatedCompileError> {
+        if let Some(prev) = self.struct_map.get(def.name.borrow_val()) {
+            return Err(LocatedCompileError::duplicate(def.name.clone(), prev.name.pos()));
+        }
+        for (k, field) in def.fields.iter().enumerate() {
+            if let Some(prev) = def.fields[..k].iter().find(|prev| prev.borrow_val() == field.borrow_val()) {
+                return Err(LocatedCompileError::duplicate(field.clone(), prev.pos()));
+            }
+        }
+        self.struct_map.insert(def.name.borrow_val().clone(), Rc::new(def));
+        Ok(())
+    }
+
+    fn struct_def(&self, name: &str) -> Option<&Rc<Struct>> {
+        self.resolve(&self.struct_map, name)
+    }
+
+    fn add_enum(&mut self, def: Enum) -> Result<(), LocatedCompileError> {
+        if let Some(prev) = self.enum_map.get(def.name.borrow_val()) {
+            return Err(LocatedCompileError::duplicate(def.name.clone(), prev.name.pos()));
+        }
+        let def = Rc::new(def);
+        for (k, (variant, _)) in def.variants.iter().enumerate() {
+            if let Some((prev, index)) = self.variant_map.get(variant.borrow_val()) {
+                return Err(LocatedCompileError::duplicate(variant.clone(), prev.variants[*index].0.pos()));
+            }
+            self.variant_map.insert(variant.borrow_val().clone(), (def.clone(), k));
+        }
+        self.enum_map.insert(def.name.borrow_val().clone(), def);
+        Ok(())
+    }
+
+    fn enum_def(&self, name: &str) -> Option<&Rc<Enum>> {
+        self.resolve(&self.enum_map, name)
+    }
+
+    fn variant(&self, name: &str) -> Option<&(Rc<Enum>, usize)> {
+        self.resolve(&self.variant_map, name)
     }
 
-    fn add_var(&mut self, name: Located<String>, ptr: ComplexPointer<'ctx>, ty: Type) {
+    /// Adds a variable, returning the variable it shadows (if any).
+    fn add_var(&mut self, name: Located<String>, var: Variable<'ctx>) -> Option<Located<Variable<'ctx>>> {
         let pos = name.pos();
-        self.var_map.insert(name.val(), Located::new(Typed::new(ptr, ty), pos));
+        self.var_map.insert(name.val(), Located::new(var, pos))
+    }
+
+    /// Undoes `add_var`, given the variable it returned.
+    fn restore_var(&mut self, name: &str, shadowed: Option<Located<Variable<'ctx>>>) {
+        match shadowed {
+            Some(var) => self.var_map.insert(name.to_owned(), var),
+            None      => self.var_map.remove(name),
+        };
     }
 
-    fn var(&self, name: &str) -> Option<&Located<Typed<ComplexPointer<'ctx>>>> {
-        self.var_map.get(name)
+    /// Local variables come first, and then globals from the current file.
+    fn var(&self, name: &str) -> Option<&Located<Variable<'ctx>>> {
+        self.var_map.get(name).or_else(|| self.resolve(&self.var_map, name))
     }
-}
 
-fn name_re(name: &str) -> String {
-    format!("{}_re", name)
-}
+    fn add_global(&mut self, name: Located<String>, var: Variable<'ctx>) -> Result<(), LocatedCompileError> {
+        if let Some(prev) = self.global_map.get(name.borrow_val()) {
+            return Err(LocatedCompileError::duplicate(name, prev.pos()));
+        }
+        let pos = name.pos();
+        self.global_map.insert(name.val(), Located::new(var, pos));
+        Ok(())
+    }
 
-fn name_im(name: &str) -> String {
-    format!("{}_im", name)
+    fn global(&self, name: &str) -> Option<&Located<Variable<'ctx>>> {
+        self.resolve(&self.global_map, name)
+    }
+
+    /// Whether `name` currently refers to the global of that name, because of `global name`.
+    fn is_global_var(&self, name: &str) -> bool {
+        match (self.var(name), self.global(name)) {
+            (Some(var), Some(global)) => var.borrow_val().ptr == global.borrow_val().ptr,
+            _ => false,
+        }
+    }
 }
 
 pub struct Config {
     accurate_div: bool,
     newton_rhapson_passes: usize,
+    // How deeply functions can call each other before it's a runtime error
+    max_depth: u64,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             accurate_div: false,
-            newton_rhapson_passes: 10
+            newton_rhapson_passes: 10,
+            max_depth: 10_000,
         }
     }
 }
 
+// What the function being compiled returns, and the first other type it tried to return (if any).
+// Tail calls to the same instance jump back to `start` instead.
+#[derive(Debug, Clone)]
+struct Returns<'ctx> {
+    ret: Type,
+    found: Option<Located<Type>>,
+    key: (String, Vec<Type>),
+    start: Option<TailStart<'ctx>>,
+    annotation: Option<Located<Annotation>>,
+    memo: Option<MemoCache<'ctx>>,
+}
+
+// Where the body of a function begins, once its parameters are stored, along with the stack pointer
+// there so that anything allocated by the body can be released before jumping back
+#[derive(Debug, Clone)]
+struct TailStart<'ctx> {
+    block: BasicBlock<'ctx>,
+    params: Vec<PointerValue<'ctx>>,
+    stack: PointerValue<'ctx>,
+}
+
+// The globals holding a `memo fn`'s remembered arguments and results, and the arguments to this call
+#[derive(Debug, Clone)]
+struct MemoCache<'ctx> {
+    keys: PointerValue<'ctx>,
+    values: PointerValue<'ctx>,
+    key: Value<'ctx>,
+}
+
+// Where `continue` and `break` jump to inside a loop. A `loop` expression also collects the values
+// it breaks with, and the blocks they come from.
+#[derive(Debug, Clone)]
+struct LoopBlocks<'ctx> {
+    label: Option<String>,
+    continue_bb: BasicBlock<'ctx>,
+    break_bb: BasicBlock<'ctx>,
+    values: Option<Vec<(Type, BasicValueEnum<'ctx>, BasicBlock<'ctx>)>>,
+}
+
+// A generator being iterated by `for`, whose body is compiled in place of the loop. Each `yield`
+// compiles the loop body again, in the scope the loop was written in, and `return` jumps to `cont_bb`.
+#[derive(Debug, Clone)]
+struct GeneratorFrame<'ctx> {
+    name: String,
+    label: Option<String>,
+    id: Located<String>,
+    body: Vec<Located<Statement>>,
+    vars: HashMap<String, Located<Variable<'ctx>>>,
+    loops: Vec<LoopBlocks<'ctx>>,
+    file: usize,
+    cont_bb: BasicBlock<'ctx>,
+}
+
+// Where a failed runtime check inside `try` jumps to, and where it leaves the error message. The
+// handler that was running before, and how many loops the `try` is inside, are kept so that jumping
+// out of the `try` can put the old handler back.
+#[derive(Debug, Clone, Copy)]
+struct Handler<'ctx> {
+    catch_bb: BasicBlock<'ctx>,
+    msg_ptr: PointerValue<'ctx>,
+    prev: PointerValue<'ctx>,
+    loops: usize,
+}
+
 pub struct Compiler<'ctx> {
     config: Config,
     ctx: &'ctx Context,
@@ -62,11 +263,70 @@ pub struct Compiler<'ctx> {
     builder: Rc<Builder<'ctx>>,
     builtins: Builtins<'ctx>,
     sym: SymbolTable<'ctx>,
-    inside_loop: bool,
+    loops: Vec<LoopBlocks<'ctx>>,
+    generators: Vec<GeneratorFrame<'ctx>>,
+    /// The moduli of the `mod` blocks we're inside, innermost last.
+    moduli: Vec<ComplexValue<'ctx>>,
+    handlers: Vec<Handler<'ctx>>,
+    returns: Option<Returns<'ctx>>,
     current_fp: Option<FunctionValue<'ctx>>,
     current_block: Option<BasicBlock<'ctx>>,
 }
 
+/// The name of the function that overloads a binary operator, if it can be overloaded.
+fn binop_overload(op: BinOp) -> Option<&'static str> {
+    match op {
+        BinOp::Plus      => Some("__add"),
+        BinOp::Minus     => Some("__sub"),
+        BinOp::Times     => Some("__mul"),
+        BinOp::Divide    => Some("__div"),
+        BinOp::Remainder => Some("__rem"),
+        BinOp::Quotient  => Some("__quot"),
+        BinOp::BitAnd    => Some("__and"),
+        BinOp::BitOr     => Some("__or"),
+        BinOp::BitXor    => Some("__xor"),
+        BinOp::ShiftLeft => Some("__shl"),
+        BinOp::ShiftRight => Some("__shr"),
+        BinOp::Power     => Some("__pow"),
+        BinOp::Less      => Some("__lt"),
+        BinOp::LessEquals => Some("__le"),
+        BinOp::Greater   => Some("__gt"),
+        BinOp::GreaterEquals => Some("__ge"),
+        BinOp::In        => Some("__in"),
+        BinOp::Equals | BinOp::NotEquals | BinOp::And | BinOp::Or => None,
+    }
+}
+
+fn unop_overload(op: UnOp) -> &'static str {
+    match op {
+        UnOp::Negate    => "__neg",
+        UnOp::Conjugate => "__conj",
+        UnOp::Modulus   => "__abs",
+        UnOp::Factorial => "__fact",
+    }
+}
+
+/// The type of `expr` when it's obvious without compiling anything, such as for literals and
+/// comparisons. For a conditional, either branch will do, since they have to agree.
+fn obvious_type(expr: &Expr) -> Option<Type> {
+    match expr {
+        Expr::Value(_) => Some(Type::Complex),
+        Expr::Eisenstein(_) => Some(Type::Eisenstein),
+        Expr::Quaternion(_) => Some(Type::Quaternion),
+        Expr::Rational(_) => Some(Type::Rational),
+        Expr::Poly(_) => Some(Type::Poly),
+        Expr::Bool(_) => Some(Type::Bool),
+        Expr::Str(_) => Some(Type::Str),
+        Expr::BinOp(BinOp::Equals, _) | Expr::BinOp(BinOp::NotEquals, _)
+            | Expr::BinOp(BinOp::Less, _) | Expr::BinOp(BinOp::LessEquals, _)
+            | Expr::BinOp(BinOp::Greater, _) | Expr::BinOp(BinOp::GreaterEquals, _)
+            | Expr::BinOp(BinOp::And, _) | Expr::BinOp(BinOp::Or, _) | Expr::Chain(..) => Some(Type::Bool),
+        Expr::IfElse(boxed) => obvious_type(boxed.1.borrow_val()).or_else(|| obvious_type(boxed.2.borrow_val())),
+        Expr::Block(_, result) | Expr::Mod(_, result) => obvious_type(result.borrow_val()),
+        _ => None,
+    }
+}
+
 impl<'ctx> Compiler<'ctx> {
     pub fn new(config: Config, ctx: &'ctx Context) -> Self {
         let module = Rc::new(ctx.create_module("primary"));
@@ -76,7 +336,11 @@ impl<'ctx> Compiler<'ctx> {
         Self {
             config, ctx, module, builder, builtins,
             sym: SymbolTable::new(),
-            inside_loop: false,
+            loops: Vec::new(),
+            generators: Vec::new(),
+            moduli: Vec::new(),
+            handlers: Vec::new(),
+            returns: None,
             current_fp: None,
             current_block: None,
         }
@@ -95,11 +359,33 @@ impl<'ctx> Compiler<'ctx> {
         Ok(())
     }
 
+    /// Allocates memory in the entry block of the current function, so that it's only allocated
+    /// once even if the code that needs it runs in a loop.
+    fn build_entry_alloca<T: BasicType<'ctx>>(&mut self, ty: T, name: &str) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let entry = self.get_fp()?.get_first_basic_block()
+            .ok_or_else(|| InternalError::invalid_state("no blocks in active function"))?;
+        match entry.get_first_instruction() {
+            Some(first) => self.builder.position_before(&first),
+            None => self.builder.position_at_end(entry),
+        }
+        let ptr = self.builder.build_alloca(ty, name);
+        self.move_to_end()?;
+        Ok(ptr)
+    }
+
     fn set_and_move_block(&mut self, block: BasicBlock<'ctx>) -> Result<(), LocatedCompileError> {
         self.current_block = Some(block);
         self.move_to_end()
     }
 
+    /// Branches unconditionally, then moves to a fresh block, since LLVM doesn't allow any
+    /// instructions after a terminator. The new block is unreachable.
+    fn build_jump(&mut self, target: BasicBlock<'ctx>) -> Result<(), LocatedCompileError> {
+        self.builder.build_unconditional_branch(target);
+        let dead_bb = self.ctx.append_basic_block(self.get_fp()?, "dead");
+        self.set_and_move_block(dead_bb)
+    }
+
     fn complex_mul(&self, lval: ComplexValue<'ctx>, rval: ComplexValue<'ctx>) -> ComplexValue<'ctx> {
         let re1 = self.builder.build_float_mul(lval.re, rval.re, "tmp_mul_re1");
         let re2 = self.builder.build_float_mul(lval.im, rval.im, "tmp_mul_re2");
@@ -130,6 +416,165 @@ impl<'ctx> Compiler<'ctx> {
         ComplexValue { re, im }
     }
 
+    /// Euclidean division, giving `(q, r)` with `a == q * b + r`, which is what `~/`, `%` and `divmod`
+    /// use. For real operands, `r` is in `0..|b|`. Otherwise `q` is the Gaussian integer nearest to
+    /// `a / b` (rounding halves away from zero), so `|r|^2 <= |b|^2 / 2`.
+    fn complex_divmod(&mut self, pos: Location, lval: ComplexValue<'ctx>, rval: ComplexValue<'ctx>)
+            -> Result<(ComplexValue<'ctx>, ComplexValue<'ctx>), LocatedCompileError> {
+        let round = self.builtins.round();
+        self.move_to_end()?;
+
+        let t_f64 = self.ctx.f64_type();
+        let zero = t_f64.const_zero();
+        let nonzero = self.builder.build_float_compare(FloatPredicate::ONE, self.complex_norm(rval), zero, "nonzero");
+        self.build_check(nonzero, pos, "division by zero")?;
+
+        let q_real = self.real_quotient(lval.re, rval.re)?;
+
+        // Complex operands: round each part of a / b
+        let exact = self.complex_div(lval, rval);
+        let q_re = self.call_float(round, &[exact.re.into()], "q_re")?;
+        let q_im = self.call_float(round, &[exact.im.into()], "q_im")?;
+
+        let l_real = self.builder.build_float_compare(FloatPredicate::OEQ, lval.im, zero, "l_real");
+        let r_real = self.builder.build_float_compare(FloatPredicate::OEQ, rval.im, zero, "r_real");
+        let real = self.builder.build_and(l_real, r_real, "real");
+        let q = ComplexValue {
+            re: self.builder.build_select(real, q_real, q_re, "q_re").into_float_value(),
+            im: self.builder.build_select(real, zero, q_im, "q_im").into_float_value(),
+        };
+        let qb = self.complex_mul(q, rval);
+        let r = ComplexValue {
+            re: self.builder.build_float_sub(lval.re, qb.re, "r_re"),
+            im: self.builder.build_float_sub(lval.im, qb.im, "r_im"),
+        };
+        Ok((q, r))
+    }
+
+    /// `floor(a / |b|) * sign(b)`, the quotient of real numbers that leaves a remainder in `0..|b|`.
+    fn real_quotient(&mut self, a: FloatValue<'ctx>, b: FloatValue<'ctx>) -> Result<FloatValue<'ctx>, LocatedCompileError> {
+        let floor = self.builtins.floor();
+        self.move_to_end()?;
+        let zero = self.ctx.f64_type().const_zero();
+        let negative = self.builder.build_float_compare(FloatPredicate::OLT, b, zero, "negative");
+        let neg_b = self.builder.build_float_neg(b, "neg_b");
+        let abs_b = self.builder.build_select(negative, neg_b, b, "abs_b").into_float_value();
+        let q = self.builder.build_float_div(a, abs_b, "q_real");
+        let q = self.call_float(floor, &[q.into()], "q_real")?;
+        let neg_q = self.builder.build_float_neg(q, "neg_q");
+        Ok(self.builder.build_select(negative, neg_q, q, "q_real").into_float_value())
+    }
+
+    /// Converts `z` to an `i64`, aborting with `msg` unless it's a real integer. Doubles from `2^63` up
+    /// don't fit, so they abort too rather than converting to garbage.
+    fn build_real_integer(&mut self, pos: Location, z: ComplexValue<'ctx>, msg: &str) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let t_f64 = self.ctx.f64_type();
+        self.check_gaussian(pos, z, z, msg)?;
+        let real = self.builder.build_float_compare(FloatPredicate::OEQ, z.im, t_f64.const_zero(), "real");
+        self.build_check(real, pos, msg)?;
+        let limit = 2f64.powi(63);
+        let above = self.builder.build_float_compare(FloatPredicate::OGE, z.re, t_f64.const_float(-limit), "above");
+        let below = self.builder.build_float_compare(FloatPredicate::OLT, z.re, t_f64.const_float(limit), "below");
+        let fits = self.builder.build_and(above, below, "fits");
+        self.build_check(fits, pos, "integer doesn't fit in 64 bits")?;
+        Ok(self.builder.build_float_to_signed_int(z.re, self.ctx.i64_type(), "integer"))
+    }
+
+    /// Whether both parts of `z` are integers no bigger than `2^53`, which doubles hold exactly.
+    fn build_is_exact_integer(&mut self, z: ComplexValue<'ctx>) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let floor = self.builtins.floor();
+        let fabs = self.builtins.abs();
+        let limit = self.ctx.f64_type().const_float(2f64.powi(53));
+        let mut exact = self.ctx.bool_type().const_int(1, false);
+        for part in [z.re, z.im].iter() {
+            let floored = self.call_float(floor, &[(*part).into()], "floored")?;
+            let integral = self.builder.build_float_compare(FloatPredicate::OEQ, floored, *part, "integral");
+            let abs = self.call_float(fabs, &[(*part).into()], "abs")?;
+            let small = self.builder.build_float_compare(FloatPredicate::OLE, abs, limit, "small");
+            exact = self.builder.build_and(exact, integral, "exact");
+            exact = self.builder.build_and(exact, small, "exact");
+        }
+        Ok(exact)
+    }
+
+    /// Aborts if `lval` and `rval` are exact integers but `res`, which `op` made from them, might not
+    /// be, so that integer arithmetic never rounds silently. Sums are checked directly. Products are
+    /// also checked before adding up the parts, and integer powers by `|re| + |im|`, which bounds
+    /// every product along the way.
+    fn check_exact_arithmetic(&mut self, pos: Location, op: BinOp, lval: ComplexValue<'ctx>, rval: ComplexValue<'ctx>,
+            res: ComplexValue<'ctx>) -> Result<(), LocatedCompileError> {
+        let fabs = self.builtins.abs();
+        let fmax = self.builtins.max();
+        let t_f64 = self.ctx.f64_type();
+        let l_exact = self.build_is_exact_integer(lval)?;
+        let r_exact = self.build_is_exact_integer(rval)?;
+        let mut exact = self.builder.build_and(l_exact, r_exact, "exact");
+        let res_re = self.call_float(fabs, &[res.re.into()], "res_re")?;
+        let res_im = self.call_float(fabs, &[res.im.into()], "res_im")?;
+        let bound = match op {
+            BinOp::Power => {
+                // Negative powers are fractions
+                let real = self.builder.build_float_compare(FloatPredicate::OEQ, rval.im, t_f64.const_zero(), "real");
+                let positive = self.builder.build_float_compare(FloatPredicate::OGE, rval.re, t_f64.const_zero(), "positive");
+                exact = self.builder.build_and(exact, real, "exact");
+                exact = self.builder.build_and(exact, positive, "exact");
+                self.builder.build_float_add(res_re, res_im, "bound")
+            },
+            _ => {
+                let bound = self.call_float(fmax, &[res_re.into(), res_im.into()], "bound")?;
+                if let BinOp::Times = op {
+                    let mut largest = Vec::new();
+                    for z in [lval, rval].iter() {
+                        let re = self.call_float(fabs, &[z.re.into()], "re")?;
+                        let im = self.call_float(fabs, &[z.im.into()], "im")?;
+                        largest.push(self.call_float(fmax, &[re.into(), im.into()], "largest")?);
+                    }
+                    let product = self.builder.build_float_mul(largest[0], largest[1], "product");
+                    self.call_float(fmax, &[bound.into(), product.into()], "bound")?
+                } else {
+                    bound
+                }
+            },
+        };
+        let small = self.builder.build_float_compare(FloatPredicate::OLE, bound, t_f64.const_float(2f64.powi(53)), "small");
+        let inexact = self.builder.build_not(exact, "inexact");
+        let ok = self.builder.build_or(inexact, small, "ok");
+        self.build_check(ok, pos, "integer result is too big to be exact")
+    }
+
+    /// Bitwise operators act on the two's complement of real integers that fit in 64 bits. Anything
+    /// else is a runtime error, as is shifting by a negative amount or by 64 or more, or a result
+    /// beyond `2^53`, which a double couldn't hold exactly.
+    fn build_bitwise(&mut self, pos: Location, op: BinOp, lval: ComplexValue<'ctx>, rval: ComplexValue<'ctx>)
+            -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+        let t_f64 = self.ctx.f64_type();
+        let t_i64 = self.ctx.i64_type();
+        let lhs = self.build_real_integer(pos, lval, "bitwise operators need real integers")?;
+        let rhs = self.build_real_integer(pos, rval, "bitwise operators need real integers")?;
+        let res = match op {
+            BinOp::BitAnd => self.builder.build_and(lhs, rhs, "and"),
+            BinOp::BitOr  => self.builder.build_or(lhs, rhs, "or"),
+            BinOp::BitXor => self.builder.build_xor(lhs, rhs, "xor"),
+            _ => {
+                // Negative amounts are huge when unsigned
+                let in_range = self.builder.build_int_compare(IntPredicate::ULT, rhs, t_i64.const_int(64, false), "in_range");
+                self.build_check(in_range, pos, "can only shift by 0 to 63 bits")?;
+                if let BinOp::ShiftLeft = op {
+                    self.builder.build_left_shift(lhs, rhs, "shl")
+                } else {
+                    self.builder.build_right_shift(lhs, rhs, true, "shr")
+                }
+            },
+        };
+        let limit: i64 = 1 << 53;
+        let above = self.builder.build_int_compare(IntPredicate::SGE, res, t_i64.const_int((-limit) as u64, true), "above");
+        let below = self.builder.build_int_compare(IntPredicate::SLE, res, t_i64.const_int(limit as u64, false), "below");
+        let exact = self.builder.build_and(above, below, "exact");
+        self.build_check(exact, pos, "bitwise result is too big to be exact")?;
+        let re = self.builder.build_signed_int_to_float(res, t_f64, "bits");
+        Ok(ComplexValue { re, im: t_f64.const_zero() })
+    }
+
     // This _may_ turn out to be more accurate for certain inputs.
     #[allow(clippy::many_single_char_names)]
     fn complex_div_accurate(&mut self, lval: ComplexValue<'ctx>, rval: ComplexValue<'ctx>) -> Result<ComplexValue<'ctx>, LocatedCompileError> {
@@ -223,22 +668,61 @@ impl<'ctx> Compiler<'ctx> {
         Ok(ComplexValue { re, im })
     }
 
-    fn complex_cmp(&mut self, pos: Location, op: FloatPredicate, lval: ComplexValue<'ctx>, rval: ComplexValue<'ctx>)
-            -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+    fn complex_cmp(&self, op: FloatPredicate, lval: ComplexValue<'ctx>, rval: ComplexValue<'ctx>) -> IntValue<'ctx> {
         let cmp1 = self.builder.build_float_compare(op, lval.re, rval.re, "tmp_cmp1");
         let cmp2 = self.builder.build_float_compare(op, lval.im, rval.im, "tmp_cmp2");
-        let res = match op {
-            FloatPredicate::OEQ => self.builder.build_and(cmp1, cmp2, "tmp_res"),
+        match op {
             FloatPredicate::ONE => self.builder.build_or(cmp1, cmp2, "tmp_res"),
-            _                   => return Err(LocatedCompileError::unsupported(pos, format!("{:?}", op)))
-        };
-        let res = self.builder.build_int_z_extend(res, self.ctx.i64_type(), "tmp_cast");
-        let res = self.builder.build_cast(InstructionOpcode::SIToFP, res, self.ctx.f64_type(), "tmp_castf")
-            .into_float_value();
-        Ok(ComplexValue {
-            re: res,
-            im: self.ctx.f64_type().const_zero(),
-        })
+            _                   => self.builder.build_and(cmp1, cmp2, "tmp_res"),
+        }
+    }
+
+    fn complex_norm(&self, val: ComplexValue<'ctx>) -> FloatValue<'ctx> {
+        let re2 = self.builder.build_float_mul(val.re, val.re, "tmp_norm_re");
+        let im2 = self.builder.build_float_mul(val.im, val.im, "tmp_norm_im");
+        self.builder.build_float_add(re2, im2, "tmp_norm")
+    }
+
+    /// Complex numbers have no natural order, so we impose one: first by norm, then lexicographically
+    /// by real part and imaginary part. This is a total order, and agrees with the usual order on
+    /// non-negative reals (but not on negative reals, since e.g. -3 has a larger norm than 2).
+    /// Eisenstein integers are ordered the same way, by their own norm and then by their parts.
+    fn complex_less(&self, norm: fn(&Self, ComplexValue<'ctx>) -> FloatValue<'ctx>, lval: ComplexValue<'ctx>, rval: ComplexValue<'ctx>)
+            -> IntValue<'ctx> {
+        let lnorm = norm(self, lval);
+        let rnorm = norm(self, rval);
+
+        let norm_lt = self.builder.build_float_compare(FloatPredicate::OLT, lnorm, rnorm, "tmp_norm_lt");
+        let norm_eq = self.builder.build_float_compare(FloatPredicate::OEQ, lnorm, rnorm, "tmp_norm_eq");
+        let re_lt = self.builder.build_float_compare(FloatPredicate::OLT, lval.re, rval.re, "tmp_re_lt");
+        let re_eq = self.builder.build_float_compare(FloatPredicate::OEQ, lval.re, rval.re, "tmp_re_eq");
+        let im_lt = self.builder.build_float_compare(FloatPredicate::OLT, lval.im, rval.im, "tmp_im_lt");
+
+        // norm_lt || (norm_eq && (re_lt || (re_eq && im_lt)))
+        let res = self.builder.build_and(re_eq, im_lt, "tmp_lex_im");
+        let res = self.builder.build_or(re_lt, res, "tmp_lex_re");
+        let res = self.builder.build_and(norm_eq, res, "tmp_lex");
+        self.builder.build_or(norm_lt, res, "tmp_less")
+    }
+
+    fn complex_order(&self, op: BinOp, lval: ComplexValue<'ctx>, rval: ComplexValue<'ctx>) -> IntValue<'ctx> {
+        self.order_by_norm(op, Self::complex_norm, lval, rval)
+    }
+
+    fn order_by_norm(&self, op: BinOp, norm: fn(&Self, ComplexValue<'ctx>) -> FloatValue<'ctx>, lval: ComplexValue<'ctx>, rval: ComplexValue<'ctx>)
+            -> IntValue<'ctx> {
+        match op {
+            BinOp::Less          => self.complex_less(norm, lval, rval),
+            BinOp::Greater       => self.complex_less(norm, rval, lval),
+            BinOp::LessEquals    => {
+                let res = self.complex_less(norm, rval, lval);
+                self.builder.build_not(res, "tmp_not")
+            },
+            _                    => {
+                let res = self.complex_less(norm, lval, rval);
+                self.builder.build_not(res, "tmp_not")
+            },
+        }
     }
 
     #[inline]
@@ -261,180 +745,1503 @@ impl<'ctx> Compiler<'ctx> {
         })
     }
 
-    fn build_expr(&mut self, expr: Located<Expr>) -> Result<ComplexValue<'ctx>, LocatedCompileError> {
-        let (expr, pos) = expr.unwrap();
-        match expr {
-            Expr::Value(ComplexNum(re, im)) => {
-                let re = self.ctx.f64_type().const_float(re);
-                let im = self.ctx.f64_type().const_float(im);
-                Ok(ComplexValue { re, im })
+    fn call_float(&self, f: FunctionValue<'ctx>, args: &[BasicValueEnum<'ctx>], name: &str) -> Result<FloatValue<'ctx>, LocatedCompileError> {
+        Ok(self.builder.build_call(f, args, name)
+            .try_as_basic_value().left()
+                .ok_or_else(|| InternalError::invalid_state("failed to interpret return value of builtin"))?
+            .into_float_value())
+    }
+
+    /// Integer powers are computed by repeated squaring, so they're as exact as multiplication is.
+    /// Anything else uses the principal branch of the logarithm, i.e. z ** w = exp(w log z).
+    fn complex_pow(&mut self, lval: ComplexValue<'ctx>, rval: ComplexValue<'ctx>) -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+        let floor = self.builtins.floor();
+        let powi = self.builtins.powi();
+        let log = self.builtins.log();
+        let exp = self.builtins.exp();
+        let atan2 = self.builtins.atan2();
+        let cos = self.builtins.cos();
+        let sin = self.builtins.sin();
+        self.move_to_end()?;
+
+        let re_floor = self.call_float(floor, &[rval.re.into()], "tmp_floor")?;
+        let integral = self.builder.build_float_compare(FloatPredicate::OEQ, re_floor, rval.re, "tmp_integral");
+        let real = self.builder.build_float_compare(FloatPredicate::OEQ, rval.im, self.ctx.f64_type().const_zero(), "tmp_real");
+        let is_int = self.builder.build_and(integral, real, "tmp_is_int");
+        // Exponents too big for an `i64` take the general path rather than converting to garbage
+        let limit = 2f64.powi(63);
+        let t_f64 = self.ctx.f64_type();
+        let above = self.builder.build_float_compare(FloatPredicate::OGE, rval.re, t_f64.const_float(-limit), "tmp_above");
+        let below = self.builder.build_float_compare(FloatPredicate::OLT, rval.re, t_f64.const_float(limit), "tmp_below");
+        let fits = self.builder.build_and(above, below, "tmp_fits");
+        let is_int = self.builder.build_and(is_int, fits, "tmp_is_int");
+
+        let int_bb = self.ctx.append_basic_block(self.get_fp()?, "powi");
+        let general_bb = self.ctx.append_basic_block(self.get_fp()?, "pow");
+        let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "cont");
+        self.builder.build_conditional_branch(is_int, int_bb, general_bb);
+
+        self.set_and_move_block(int_bb)?;
+        let n = self.builder.build_float_to_signed_int(rval.re, self.ctx.i64_type(), "tmp_exp");
+        let int_res = self.builder.build_call(powi, &[lval.re.into(), lval.im.into(), n.into()], "tmp_powi")
+            .try_as_basic_value().left()
+                .ok_or_else(|| InternalError::invalid_state("failed to interpret return value of powi"))?;
+        let int_res = self.unpack_complex(int_res)?;
+        self.builder.build_unconditional_branch(cont_bb);
+
+        self.set_and_move_block(general_bb)?;
+        let norm = self.complex_norm(lval);
+        let log_norm = self.call_float(log, &[norm.into()], "tmp_log")?;
+        let log_re = self.builder.build_float_mul(log_norm, self.ctx.f64_type().const_float(0.5), "tmp_log_re");
+        let log_im = self.call_float(atan2, &[lval.im.into(), lval.re.into()], "tmp_arg")?;
+        let exponent = self.complex_mul(rval, (log_re, log_im).into());
+        let magnitude = self.call_float(exp, &[exponent.re.into()], "tmp_exp")?;
+        let cos = self.call_float(cos, &[exponent.im.into()], "tmp_cos")?;
+        let sin = self.call_float(sin, &[exponent.im.into()], "tmp_sin")?;
+        let general_re = self.builder.build_float_mul(magnitude, cos, "tmp_pow_re");
+        let general_im = self.builder.build_float_mul(magnitude, sin, "tmp_pow_im");
+        self.builder.build_unconditional_branch(cont_bb);
+
+        self.set_and_move_block(cont_bb)?;
+        let phi_re = self.builder.build_phi(self.ctx.f64_type(), "pow_re");
+        phi_re.add_incoming(&[(&int_res.re, int_bb), (&general_re, general_bb)]);
+        let phi_im = self.builder.build_phi(self.ctx.f64_type(), "pow_im");
+        phi_im.add_incoming(&[(&int_res.im, int_bb), (&general_im, general_bb)]);
+        Ok(ComplexValue {
+            re: phi_re.as_basic_value().into_float_value(),
+            im: phi_im.as_basic_value().into_float_value(),
+        })
+    }
+
+    fn complex_type(&self) -> StructType<'ctx> {
+        let t_f64 = self.ctx.f64_type();
+        self.ctx.struct_type(&[t_f64.into(), t_f64.into()], false)
+    }
+
+    /// The LLVM type used to store a value of the given type in memory.
+    fn llvm_type(&self, ty: &Type) -> BasicTypeEnum<'ctx> {
+        match ty {
+            Type::Complex | Type::Eisenstein => self.complex_type().into(),
+            Type::Quaternion | Type::Rational => self.tuple_type(&[Type::Complex, Type::Complex]).into(),
+            Type::Bool    => self.ctx.bool_type().into(),
+            Type::Range   => self.range_type().into(),
+            Type::Str     => self.ctx.i8_type().ptr_type(AddressSpace::Generic).into(),
+            Type::List(elem) => self.list_type(elem).ptr_type(AddressSpace::Generic).into(),
+            Type::Dict(key, value) => self.dict_type(key, value).into(),
+            Type::Set(elem) => self.list_type(elem).ptr_type(AddressSpace::Generic).into(),
+            Type::Poly => self.list_type(&Type::Complex).ptr_type(AddressSpace::Generic).into(),
+            Type::Tuple(elems) => self.tuple_type(elems).into(),
+            Type::Matrix  => self.matrix_type().ptr_type(AddressSpace::Generic).into(),
+            Type::Struct(_, fields) => {
+                let elems = fields.iter().map(|(_, ty)| ty.clone()).collect::<Vec<_>>();
+                self.tuple_type(&elems).into()
             },
-            Expr::Id(id) => {
-                if let Some(var) = self.sym.var(id.borrow_val()) {
-                    let re = self.builder.build_load(var.re(), &name_re(id.borrow_val()))
-                        .into_float_value();
-                    let im = self.builder.build_load(var.im(), &name_im(id.borrow_val()))
-                        .into_float_value();
-                    Ok(ComplexValue { re, im })
-                } else {
-                    Err(LocatedCompileError::unknown_symbol(id))
+            Type::Enum(name) => self.enum_type(name).into(),
+            Type::Closure(_, env) => {
+                let elems = env.iter().map(|(_, ty)| ty.clone()).collect::<Vec<_>>();
+                self.tuple_type(&elems).into()
+            },
+            Type::Option(inner) => self.option_type(inner).into(),
+            Type::Lazy(inner, closure) => self.lazy_type(inner, closure).ptr_type(AddressSpace::Generic).into(),
+        }
+    }
+
+    fn tuple_type(&self, elems: &[Type]) -> StructType<'ctx> {
+        let fields = elems.iter().map(|elem| self.llvm_type(elem)).collect::<Vec<_>>();
+        self.ctx.struct_type(&fields, false)
+    }
+
+    fn range_type(&self) -> StructType<'ctx> {
+        let t_complex = self.complex_type();
+        self.ctx.struct_type(&[t_complex.into(), t_complex.into(), self.ctx.f64_type().into()], false)
+    }
+
+    /// Packs a value into a single LLVM value, so that it can be stored or passed around.
+    fn pack(&self, value: &Value<'ctx>) -> Result<BasicValueEnum<'ctx>, LocatedCompileError> {
+        match value {
+            Value::Complex(val) | Value::Eisenstein(val) => {
+                let res = self.complex_type().get_undef();
+                let res = self.builder.build_insert_value(res, val.re, 0, "pack_re")
+                    .ok_or_else(|| InternalError::invalid_state("failed to pack real part"))?
+                    .into_struct_value();
+                let res = self.builder.build_insert_value(res, val.im, 1, "pack_im")
+                    .ok_or_else(|| InternalError::invalid_state("failed to pack imaginary part"))?
+                    .into_struct_value();
+                Ok(res.into())
+            },
+            Value::Quaternion(z, w) | Value::Rational(z, w) => self.pack(&Value::Tuple(vec![Value::Complex(*z), Value::Complex(*w)])),
+            Value::Bool(val) => Ok((*val).into()),
+            Value::Str(ptr) => Ok((*ptr).into()),
+            Value::Range(range) => {
+                let start = self.pack(&Value::Complex(range.start))?;
+                let step = self.pack(&Value::Complex(range.step))?;
+                let res = self.range_type().get_undef();
+                let res = self.builder.build_insert_value(res, start, 0, "pack_start")
+                    .ok_or_else(|| InternalError::invalid_state("failed to pack range start"))?
+                    .into_struct_value();
+                let res = self.builder.build_insert_value(res, step, 1, "pack_step")
+                    .ok_or_else(|| InternalError::invalid_state("failed to pack range step"))?
+                    .into_struct_value();
+                let res = self.builder.build_insert_value(res, range.len, 2, "pack_len")
+                    .ok_or_else(|| InternalError::invalid_state("failed to pack range length"))?
+                    .into_struct_value();
+                Ok(res.into())
+            },
+            Value::List(ptr, _) | Value::Set(ptr, _) | Value::Poly(ptr) => Ok((*ptr).into()),
+            Value::Dict(keys, values, table, key, value) => self.pack_dict((*keys, *values, *table), key, value),
+            Value::Matrix(ptr) => Ok((*ptr).into()),
+            Value::Tuple(elems) => {
+                let field_types = elems.iter().map(Value::ty).collect::<Vec<_>>();
+                let mut res = self.tuple_type(&field_types).get_undef();
+                for (k, elem) in elems.iter().enumerate() {
+                    let elem = self.pack(elem)?;
+                    res = self.builder.build_insert_value(res, elem, k as u32, "pack_field")
+                        .ok_or_else(|| InternalError::invalid_state("failed to pack tuple field"))?
+                        .into_struct_value();
                 }
+                Ok(res.into())
             },
-            Expr::BinOp(op, boxed) => {
-                let (lhs, rhs) = *boxed;
-                let lval = self.build_expr(lhs)?;
-                let rval = self.build_expr(rhs)?;
-                
-                match op {
-                    BinOp::Plus      => Ok((self.builder.build_float_add(lval.re, rval.re, "tmp_add_re"),
-                                            self.builder.build_float_add(lval.im, rval.im, "tmp_add_im")).into()),
-                    BinOp::Minus     => Ok((self.builder.build_float_sub(lval.re, rval.re, "tmp_sub_re"),
-                                            self.builder.build_float_sub(lval.im, rval.im, "tmp_sub_im")).into()),
-                    BinOp::Times     => Ok(self.complex_mul(lval, rval)),
-                    BinOp::Equals    => self.complex_cmp(pos, FloatPredicate::OEQ, lval, rval),
-                    BinOp::NotEquals => self.complex_cmp(pos, FloatPredicate::ONE, lval, rval),
-                    BinOp::Divide    => {
-                        if self.config.accurate_div {
-                            self.complex_div_accurate(lval, rval)
-                        } else {
-                            Ok(self.complex_div(lval, rval))
-                        }
-                    },
-                    BinOp::Remainder => Err(LocatedCompileError::not_yet_impl(pos, "`%`")),
-                    BinOp::Power     => Err(LocatedCompileError::not_yet_impl(pos, "`**`")),
+            Value::Enum(name, tag, payload) => self.pack_enum(name, *tag, payload),
+            Value::Struct(_, fields) => self.pack(&Value::Tuple(fields.iter().map(|(_, value)| value.clone()).collect())),
+            Value::Closure(_, env) => self.pack(&Value::Tuple(env.iter().map(|(_, value)| value.clone()).collect())),
+            Value::Option(present, inner) => self.pack_option(*present, inner),
+            Value::Lazy(ptr, ..) => Ok((*ptr).into()),
+        }
+    }
+
+    fn unpack_complex(&self, value: BasicValueEnum<'ctx>) -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+        let value = value.into_struct_value();
+        let re = self.builder.build_extract_value(value, 0, "unpack_re")
+            .ok_or_else(|| InternalError::invalid_state("failed to unpack real part"))?
+            .into_float_value();
+        let im = self.builder.build_extract_value(value, 1, "unpack_im")
+            .ok_or_else(|| InternalError::invalid_state("failed to unpack imaginary part"))?
+            .into_float_value();
+        Ok(ComplexValue { re, im })
+    }
+
+    /// The inverse of `pack`.
+    fn unpack(&self, value: BasicValueEnum<'ctx>, ty: &Type) -> Result<Value<'ctx>, LocatedCompileError> {
+        match ty {
+            Type::Complex => self.unpack_complex(value).map(Value::Complex),
+            Type::Eisenstein => self.unpack_complex(value).map(Value::Eisenstein),
+            Type::Quaternion | Type::Rational => {
+                let value = value.into_struct_value();
+                let z = self.builder.build_extract_value(value, 0, "unpack_z")
+                    .ok_or_else(|| InternalError::invalid_state("failed to unpack pair"))?;
+                let w = self.builder.build_extract_value(value, 1, "unpack_w")
+                    .ok_or_else(|| InternalError::invalid_state("failed to unpack pair"))?;
+                let (z, w) = (self.unpack_complex(z)?, self.unpack_complex(w)?);
+                Ok(if *ty == Type::Quaternion { Value::Quaternion(z, w) } else { Value::Rational(z, w) })
+            },
+            Type::Bool => Ok(Value::Bool(value.into_int_value())),
+            Type::Str => Ok(Value::Str(value.into_pointer_value())),
+            Type::Range => {
+                let value = value.into_struct_value();
+                let start = self.builder.build_extract_value(value, 0, "unpack_start")
+                    .ok_or_else(|| InternalError::invalid_state("failed to unpack range start"))?;
+                let step = self.builder.build_extract_value(value, 1, "unpack_step")
+                    .ok_or_else(|| InternalError::invalid_state("failed to unpack range step"))?;
+                let len = self.builder.build_extract_value(value, 2, "unpack_len")
+                    .ok_or_else(|| InternalError::invalid_state("failed to unpack range length"))?
+                    .into_float_value();
+                Ok(Value::Range(RangeValue {
+                    start: self.unpack_complex(start)?,
+                    step: self.unpack_complex(step)?,
+                    len,
+                }))
+            },
+            Type::List(elem) => Ok(Value::List(value.into_pointer_value(), (**elem).clone())),
+            Type::Dict(key, dict_value) => self.unpack_dict(value, key, dict_value),
+            Type::Set(elem) => Ok(Value::Set(value.into_pointer_value(), (**elem).clone())),
+            Type::Poly => Ok(Value::Poly(value.into_pointer_value())),
+            Type::Matrix => Ok(Value::Matrix(value.into_pointer_value())),
+            Type::Tuple(elems) => {
+                let value = value.into_struct_value();
+                let mut fields = Vec::new();
+                for (k, elem) in elems.iter().enumerate() {
+                    let field = self.builder.build_extract_value(value, k as u32, "unpack_field")
+                        .ok_or_else(|| InternalError::invalid_state("failed to unpack tuple field"))?;
+                    fields.push(self.unpack(field, elem)?);
                 }
+                Ok(Value::Tuple(fields))
             },
-            Expr::UnOp(op, expr) => {
-                let val = self.build_expr(*expr)?;
-                match op {
-                    UnOp::Negate    => Ok((self.builder.build_float_neg(val.re, "tmp_neg_re"),
-                                           self.builder.build_float_neg(val.im, "tmp_neg_im")).into()),
-                    UnOp::Conjugate => Ok(self.complex_conjugate(val)),
-                    UnOp::Modulus   => self.complex_modulus(val),
+            Type::Enum(name) => self.unpack_enum(name, value),
+            Type::Struct(name, fields) => {
+                let elems = fields.iter().map(|(_, ty)| ty.clone()).collect();
+                match self.unpack(value, &Type::Tuple(elems))? {
+                    Value::Tuple(values) => Ok(Value::Struct(name.clone(),
+                        fields.iter().map(|(field, _)| field.clone()).zip(values).collect())),
+                    _ => Err(InternalError::invalid_state("failed to unpack record")),
                 }
             },
-            Expr::IfElse(boxed) => {
-                let (cond, value_if, value_else) = *boxed;
-                let cond = self.build_expr(cond)?;
+            Type::Closure(pos, env) => {
+                let elems = env.iter().map(|(_, ty)| ty.clone()).collect();
+                match self.unpack(value, &Type::Tuple(elems))? {
+                    Value::Tuple(values) => Ok(Value::Closure(*pos,
+                        env.iter().map(|(name, _)| name.clone()).zip(values).collect())),
+                    _ => Err(InternalError::invalid_state("failed to unpack closure")),
+                }
+            },
+            Type::Option(inner) => self.unpack_option(value, inner),
+            Type::Lazy(inner, closure) => Ok(Value::Lazy(value.into_pointer_value(), inner.clone(), closure.clone())),
+        }
+    }
 
-                let then_bb = self.ctx.append_basic_block(self.get_fp()?, "then");
-                let else_bb = self.ctx.append_basic_block(self.get_fp()?, "else");
-                let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "cont");
-                
-                let re =  self.builder.build_float_compare(FloatPredicate::ONE, cond.re(), self.ctx.f64_type().const_zero(), "test_re");
-                let im =  self.builder.build_float_compare(FloatPredicate::ONE, cond.im(), self.ctx.f64_type().const_zero(), "test_im");
-                let cond = self.builder.build_or(re, im, "test");
-                self.builder.build_conditional_branch(cond, then_bb, else_bb);
+    fn expect_range(&self, pos: Location, value: Value<'ctx>) -> Result<RangeValue<'ctx>, LocatedCompileError> {
+        match value {
+            Value::Range(val) => Ok(val),
+            _ => Err(LocatedCompileError::type_mismatch(pos, &Type::Range, &value.ty())),
+        }
+    }
 
-                self.set_and_move_block(then_bb)?;
-                let value_if = self.build_expr(value_if)?;
-                self.builder.build_unconditional_branch(cont_bb);
+    /// The range `start..end step step` contains `start + k * step` for each integer k with
+    /// `0 <= k < re((end - start) / step)`. For real integers, this is the usual half-open interval.
+    fn build_range(&mut self, start: Located<Expr>, end: Located<Expr>, step: Option<Located<Expr>>)
+            -> Result<RangeValue<'ctx>, LocatedCompileError> {
+        let t_f64 = self.ctx.f64_type();
+        let start = self.build_complex(start)?;
+        let end = self.build_complex(end)?;
+        let step = match step {
+            Some(step) => self.build_complex(step)?,
+            None       => ComplexValue { re: t_f64.const_float(1.), im: t_f64.const_zero() },
+        };
+        let diff = ComplexValue {
+            re: self.builder.build_float_sub(end.re, start.re, "tmp_diff_re"),
+            im: self.builder.build_float_sub(end.im, start.im, "tmp_diff_im"),
+        };
+        let count = self.complex_div(diff, step).re;
 
-                self.set_and_move_block(else_bb)?;
-                let value_else = self.build_expr(value_else)?;
-                self.builder.build_unconditional_branch(cont_bb);
+        // maxnum also takes care of NaN, which we get for a zero step
+        let ceil = self.builtins.ceil();
+        let fmax = self.builtins.max();
+        self.move_to_end()?;
+        let count = self.call_float(ceil, &[count.into()], "tmp_ceil")?;
+        let len = self.call_float(fmax, &[count.into(), t_f64.const_zero().into()], "tmp_len")?;
+        Ok(RangeValue { start, step, len })
+    }
 
-                self.set_and_move_block(cont_bb)?;
-                let phi_re = self.builder.build_phi(self.ctx.f64_type(), "iftmp_re");
-                phi_re.add_incoming(&[(&value_if.re(), then_bb), (&value_else.re(), else_bb)]);
-                let phi_im = self.builder.build_phi(self.ctx.f64_type(), "iftmp_im");
-                phi_im.add_incoming(&[(&value_if.im(), then_bb), (&value_else.im(), else_bb)]);
-
-                let re = phi_re.as_basic_value().into_float_value();
-                let im = phi_im.as_basic_value().into_float_value();
-                Ok(ComplexValue { re, im })
-            }
+    /// The k-th element of a range.
+    fn range_nth(&self, range: RangeValue<'ctx>, k: FloatValue<'ctx>) -> ComplexValue<'ctx> {
+        let offset_re = self.builder.build_float_mul(k, range.step.re, "tmp_offset_re");
+        let offset_im = self.builder.build_float_mul(k, range.step.im, "tmp_offset_im");
+        ComplexValue {
+            re: self.builder.build_float_add(range.start.re, offset_re, "tmp_nth_re"),
+            im: self.builder.build_float_add(range.start.im, offset_im, "tmp_nth_im"),
         }
     }
 
-    fn build_let_general(&mut self, pos: Location, id: Located<String>, expr: Located<Expr>, ty: Type) -> Result<(), LocatedCompileError> {
-        // allocate variable memory
-        let re = self.builder.build_alloca(self.ctx.f64_type(), &name_re(id.borrow_val()));
-        let im = self.builder.build_alloca(self.ctx.f64_type(), &name_im(id.borrow_val()));
-
-        // assign value
-        let value = self.build_expr(expr)?;
-        self.builder.build_store(re, value.re);
-        self.builder.build_store(im, value.im);
-
-        // update symbol table
-        self.sym.add_var(Located::new(id.val(), pos), ComplexPointer { re, im }, ty);
-        Ok(())
+    /// Whether `value` is one of the elements of the range, found by working out which element it
+    /// would have to be without looping. Like iterating, this compares exactly, so `0.3 in 0..1 step 0.1`
+    /// can be false.
+    fn range_contains(&mut self, range: RangeValue<'ctx>, value: ComplexValue<'ctx>) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let diff = ComplexValue {
+            re: self.builder.build_float_sub(value.re, range.start.re, "tmp_diff_re"),
+            im: self.builder.build_float_sub(value.im, range.start.im, "tmp_diff_im"),
+        };
+        let k = self.complex_div(diff, range.step).re;
+        let round = self.builtins.round();
+        self.move_to_end()?;
+        let k = self.call_float(round, &[k.into()], "tmp_index")?;
+
+        let t_f64 = self.ctx.f64_type();
+        let after_start = self.builder.build_float_compare(FloatPredicate::OGE, k, t_f64.const_zero(), "tmp_after_start");
+        let before_end = self.builder.build_float_compare(FloatPredicate::OLT, k, range.len, "tmp_before_end");
+        let nth = self.range_nth(range, k);
+        let re_equal = self.builder.build_float_compare(FloatPredicate::OEQ, nth.re, value.re, "tmp_re_eq");
+        let im_equal = self.builder.build_float_compare(FloatPredicate::OEQ, nth.im, value.im, "tmp_im_eq");
+
+        let in_bounds = self.builder.build_and(after_start, before_end, "tmp_in_bounds");
+        let equal = self.builder.build_and(re_equal, im_equal, "tmp_nth_eq");
+        Ok(self.builder.build_and(in_bounds, equal, "tmp_contains"))
+    }
 
+    fn expect_complex(&self, pos: Location, value: Value<'ctx>) -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+        match value {
+            Value::Complex(val) => Ok(val),
+            _ => Err(LocatedCompileError::type_mismatch(pos, &Type::Complex, &value.ty())),
+        }
     }
 
-    fn build_let(&mut self, pos: Location, id: Located<String>, expr: Located<Expr>) -> Result<(), LocatedCompileError> {
-        self.build_let_general(pos, id, expr, Type::Scalar)
+    fn expect_bool(&self, pos: Location, value: Value<'ctx>) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        match value {
+            Value::Bool(val) => Ok(val),
+            _ => Err(LocatedCompileError::type_mismatch(pos, &Type::Bool, &value.ty())),
+        }
     }
 
-    fn build_let_mut(&mut self, pos: Location, id: Located<String>, expr: Located<Expr>) -> Result<(), LocatedCompileError> {
-        self.build_let_general(pos, id, expr, Type::MutScalar)
+    /// Aborts the program with an error message unless `ok` holds at runtime.
+    fn build_check(&mut self, ok: IntValue<'ctx>, pos: Location, msg: &str) -> Result<(), LocatedCompileError> {
+        let ok_bb = self.ctx.append_basic_block(self.get_fp()?, "ok");
+        let fail_bb = self.ctx.append_basic_block(self.get_fp()?, "fail");
+        self.builder.build_conditional_branch(ok, ok_bb, fail_bb);
+
+        self.set_and_move_block(fail_bb)?;
+        self.build_abort(pos, msg)?;
+        self.set_and_move_block(ok_bb)
     }
 
-    fn build_print(&mut self, expr: Located<Expr>) -> Result<(), LocatedCompileError> {
-        let value = self.build_expr(expr)?;
-        self.move_to_end()?;
-        let f = self.builtins.print_float();
+    /// Unconditionally aborts the program with an error message, or jumps to the `catch` block if
+    /// inside `try`, including a `try` in one of the functions that led to this one being called.
+    /// This terminates the current block.
+    fn build_abort(&mut self, pos: Location, msg: &str) -> Result<(), LocatedCompileError> {
+        if let Some(handler) = self.handlers.last().copied() {
+            let msg = self.builder.build_global_string_ptr(msg, ".error_msg").as_pointer_value();
+            self.builder.build_store(handler.msg_ptr, msg);
+            self.builder.build_unconditional_branch(handler.catch_bb);
+            return Ok(());
+        }
+        // A `try` further up the call stack might catch it
+        let raise = self.builtins.raise();
+        let runtime_error = self.builtins.runtime_error();
         self.move_to_end()?;
-        self.builder.build_call(f, &[value.re.into(), value.im.into()], "call");
+        let short_msg = self.builder.build_global_string_ptr(msg, ".error_msg").as_pointer_value();
+        self.builder.build_call(raise, &[short_msg.into()], "call");
+        let msg = self.builder.build_global_string_ptr(&format!("{}: {}", pos, msg), ".error_msg").as_pointer_value();
+        self.builder.build_call(runtime_error, &[msg.into()], "call");
+        self.builder.build_unreachable();
         Ok(())
     }
 
-    fn build_println(&mut self, expr: Located<Expr>) -> Result<(), LocatedCompileError> {
+    /// Runs `body` with each k in 0..n, where k and n are `i64`s.
+    fn build_counted_loop<F>(&mut self, n: IntValue<'ctx>, mut body: F) -> Result<(), LocatedCompileError>
+            where F: FnMut(&mut Self, IntValue<'ctx>) -> Result<(), LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let counter = self.builder.build_alloca(t_i64, "counter");
+        self.builder.build_store(counter, t_i64.const_zero());
+
+        let test_bb = self.ctx.append_basic_block(self.get_fp()?, "test");
+        let body_bb = self.ctx.append_basic_block(self.get_fp()?, "body");
+        let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "cont");
+
+        self.builder.build_unconditional_branch(test_bb);
+        self.set_and_move_block(test_bb)?;
+        let k = self.builder.build_load(counter, "k").into_int_value();
+        let cond = self.builder.build_int_compare(IntPredicate::SLT, k, n, "test_val");
+        self.builder.build_conditional_branch(cond, body_bb, cont_bb);
+
+        self.set_and_move_block(body_bb)?;
+        body(self, k)?;
+        let next = self.builder.build_int_add(k, t_i64.const_int(1, false), "next_k");
+        self.builder.build_store(counter, next);
+        self.builder.build_unconditional_branch(test_bb);
+
+        self.set_and_move_block(cont_bb)
+    }
+
+    /// Checks that the variable exists and was declared `mut`.
+    fn check_mutable(&self, statement_pos: Location, id: &Located<String>) -> Result<(), LocatedCompileError> {
+        match self.sym.var(id.borrow_val()) {
+            Some(var) if !var.borrow_val().mutable =>
+                Err(LocatedCompileError::immutable(statement_pos, id.borrow_val().clone(), var.pos())),
+            Some(_) => Ok(()),
+            None if self.sym.global(id.borrow_val()).is_some() => Err(LocatedCompileError::undeclared_global(id.clone())),
+            None    => Err(LocatedCompileError::unknown_symbol(id.clone())),
+        }
+    }
+
+    fn build_complex(&mut self, expr: Located<Expr>) -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+        let pos = expr.pos();
         let value = self.build_expr(expr)?;
-        self.move_to_end()?;
-        let f = self.builtins.println_float();
-        self.move_to_end()?;
-        self.builder.build_call(f, &[value.re.into(), value.im.into()], "call");
-        Ok(())
+        self.expect_complex(pos, value)
     }
 
-    fn build_print_str(&mut self, value: String) -> Result<(), LocatedCompileError> {
-        self.move_to_end()?;
-        let f = self.builtins.print_str();
-        self.move_to_end()?;
-        let ptr = self.builder.build_global_string_ptr(&value, ".str_arg").as_pointer_value();
-        self.builder.build_call(f, &[ptr.into()], "call");
-        Ok(())
+    /// Builds the condition of an `if` or `while`, which must be a `bool`.
+    fn build_cond(&mut self, expr: Located<Expr>) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let pos = expr.pos();
+        let value = self.build_expr(expr)?;
+        self.expect_bool(pos, value)
     }
 
-    fn build_println_str(&mut self, value: String) -> Result<(), LocatedCompileError> {
-        self.move_to_end()?;
-        let f = self.builtins.println_str();
-        self.move_to_end()?;
-        let ptr = self.builder.build_global_string_ptr(&value, ".str_arg").as_pointer_value();
-        self.builder.build_call(f, &[ptr.into()], "call");
-        Ok(())
+    /// Builds `&&` and `||`. The right-hand side is only evaluated if the left-hand side does not
+    /// already determine the result.
+    fn build_logical(&mut self, op: BinOp, lhs: Located<Expr>, rhs: Located<Expr>) -> Result<Value<'ctx>, LocatedCompileError> {
+        let ltest = self.build_cond(lhs)?;
+        let lhs_bb = self.get_block()?;
+
+        let rhs_bb = self.ctx.append_basic_block(self.get_fp()?, "rhs");
+        let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "cont");
+        match op {
+            BinOp::And => self.builder.build_conditional_branch(ltest, rhs_bb, cont_bb),
+            _          => self.builder.build_conditional_branch(ltest, cont_bb, rhs_bb),
+        };
+
+        self.set_and_move_block(rhs_bb)?;
+        let rtest = self.build_cond(rhs)?;
+        let rhs_bb = self.get_block()?;
+        self.builder.build_unconditional_branch(cont_bb);
+
+        self.set_and_move_block(cont_bb)?;
+        let phi = self.builder.build_phi(self.ctx.bool_type(), "logic_tmp");
+        phi.add_incoming(&[(&ltest, lhs_bb), (&rtest, rhs_bb)]);
+        Ok(Value::Bool(phi.as_basic_value().into_int_value()))
     }
 
-    fn build_assign(&mut self, statement_pos: Location, id: Located<String>, expr: Located<Expr>)
-            -> Result<(), LocatedCompileError> {
-        let val = self.build_expr(expr)?;
+    /// Builds any other binary operator once both sides have been evaluated.
+    fn build_binop(&mut self, pos: Location, op: BinOp, lpos: Location, lval: Value<'ctx>, rpos: Location, rval: Value<'ctx>)
+            -> Result<Value<'ctx>, LocatedCompileError> {
+        if let Some(func) = self.overload(pos, binop_overload(op), &[&lval, &rval])? {
+            return self.call_func(&func, vec![lval, rval]);
+        }
 
-        if let Some(var) = self.sym.var(id.borrow_val()) {
-            if var.borrow_val().is_mutable() {
-                self.builder.build_store(var.re(), val.re);
-                self.builder.build_store(var.im(), val.im);
-                Ok(())
-            } else {
-                Err(LocatedCompileError::immutable(statement_pos, id.val(), var.pos()))
-            }
-        } else {
-            Err(LocatedCompileError::unknown_symbol(id))
+        // `+` also concatenates strings
+        if let (BinOp::Plus, Value::Str(lval)) = (op, &lval) {
+            let rval = match rval {
+                Value::Str(rval) => rval,
+                rval => return Err(LocatedCompileError::type_mismatch(rpos, &Type::Str, &rval.ty())),
+            };
+            let str_concat = self.builtins.str_concat();
+            self.move_to_end()?;
+            let res = self.builder.build_call(str_concat, &[(*lval).into(), rval.into()], "tmp_concat")
+                .try_as_basic_value().left()
+                    .ok_or_else(|| InternalError::invalid_state("failed to interpret return value of concat"))?
+                .into_pointer_value();
+            return Ok(Value::Str(res));
         }
-    }
 
-    fn build_statement(&mut self, statement: Located<Statement>) -> Result<(), LocatedCompileError> {
-        let (statement, pos) = statement.unwrap();
-        match statement {
-            Statement::Let(name, expr) => self.build_let(pos, name, expr),
-            Statement::LetMut(name, expr) => self.build_let_mut(pos, name, expr),
-            Statement::Print(expr) => self.build_print(expr),
-            Statement::PrintLn(expr) => self.build_println(expr),
-            Statement::PrintLit(val) => self.build_print_str(val),
-            Statement::PrintLitLn(val) => self.build_println_str(val),
-            Statement::Assign(id, expr) => self.build_assign(pos, id, expr),
-            Statement::AddAssign(id, rhs) =>
+        // Matrices have their own arithmetic
+        if matches!(lval, Value::Matrix(_)) || matches!(rval, Value::Matrix(_)) {
+            return self.build_matrix_binop(pos, op, lpos, lval, rpos, rval);
+        }
+        // So do polynomials, quaternions, rationals and Eisenstein integers
+        if matches!(lval, Value::Poly(_)) || matches!(rval, Value::Poly(_)) {
+            return self.build_poly_binop(pos, op, lpos, lval, rpos, rval);
+        }
+        if matches!(lval, Value::Quaternion(..)) || matches!(rval, Value::Quaternion(..)) {
+            return self.build_quaternion_binop(pos, op, lpos, lval, rpos, rval);
+        }
+        if matches!(lval, Value::Rational(..)) || matches!(rval, Value::Rational(..)) {
+            return self.build_rational_binop(pos, op, lpos, lval, rpos, rval);
+        }
+        if matches!(lval, Value::Eisenstein(_)) || matches!(rval, Value::Eisenstein(_)) {
+            return self.build_eisenstein_binop(pos, op, lpos, lval, rpos, rval);
+        }
+
+        let lval = self.expect_complex(lpos, lval)?;
+        let rval = self.expect_complex(rpos, rval)?;
+        if let Some(res) = self.build_mod_binop(pos, op, lval, rval)? {
+            return Ok(Value::Complex(res));
+        }
+        let res: Option<ComplexValue<'ctx>> = match op {
+            BinOp::Plus      => Some((self.builder.build_float_add(lval.re, rval.re, "tmp_add_re"),
+                                    self.builder.build_float_add(lval.im, rval.im, "tmp_add_im")).into()),
+            BinOp::Minus     => Some((self.builder.build_float_sub(lval.re, rval.re, "tmp_sub_re"),
+                                    self.builder.build_float_sub(lval.im, rval.im, "tmp_sub_im")).into()),
+            BinOp::Times     => Some(self.complex_mul(lval, rval)),
+            BinOp::Power     => Some(self.complex_pow(lval, rval)?),
+            _                => None,
+        };
+        if let Some(res) = res {
+            self.check_exact_arithmetic(pos, op, lval, rval, res)?;
+            return Ok(Value::Complex(res));
+        }
+        match op {
+            BinOp::Less | BinOp::LessEquals | BinOp::Greater | BinOp::GreaterEquals
+                             => Ok(Value::Bool(self.complex_order(op, lval, rval))),
+            BinOp::Divide    => {
+                // Either part being NaN doesn't count as zero
+                let zero = self.ctx.f64_type().const_zero();
+                let re_nonzero = self.builder.build_float_compare(FloatPredicate::UNE, rval.re, zero, "re_nonzero");
+                let im_nonzero = self.builder.build_float_compare(FloatPredicate::UNE, rval.im, zero, "im_nonzero");
+                let nonzero = self.builder.build_or(re_nonzero, im_nonzero, "nonzero");
+                self.build_check(nonzero, pos, "division by zero")?;
+                if self.config.accurate_div {
+                    self.complex_div_accurate(lval, rval).map(Value::Complex)
+                } else {
+                    Ok(Value::Complex(self.complex_div(lval, rval)))
+                }
+            },
+            BinOp::Remainder => self.complex_divmod(pos, lval, rval).map(|(_, r)| Value::Complex(r)),
+            BinOp::Quotient  => self.complex_divmod(pos, lval, rval).map(|(q, _)| Value::Complex(q)),
+            BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor | BinOp::ShiftLeft | BinOp::ShiftRight
+                             => self.build_bitwise(pos, op, lval, rval).map(Value::Complex),
+            BinOp::Equals | BinOp::NotEquals | BinOp::In | BinOp::And | BinOp::Or
+                             => Err(InternalError::invalid_state("comparison or logical operator in arithmetic")),
+            BinOp::Plus | BinOp::Minus | BinOp::Times | BinOp::Power
+                             => Err(InternalError::invalid_state("exact arithmetic fell through")),
+        }
+    }
+
+    /// Builds a chain of comparisons like `0 <= k < n`, which holds when each comparison does. The
+    /// operands are evaluated left to right, each at most once, and the chain stops at the first
+    /// comparison that fails. Each operand is only needed by the next comparison, so it's kept as a
+    /// plain value rather than being stored in a variable.
+    fn build_chain(&mut self, pos: Location, first: Located<Expr>, rest: Vec<(BinOp, Located<Expr>)>) -> Result<Value<'ctx>, LocatedCompileError> {
+        let mut lpos = first.pos();
+        let mut lval = self.build_expr(first)?;
+        let done_bb = self.ctx.append_basic_block(self.get_fp()?, "chain_done");
+        let mut incoming = Vec::new();
+        let count = rest.len();
+        for (k, (op, rhs)) in rest.into_iter().enumerate() {
+            let rpos = rhs.pos();
+            let rval = self.build_expr(rhs)?;
+            let res = self.build_binop(pos, op, lpos, lval, rpos, rval.clone())?;
+            let test = self.expect_bool(rpos, res)?;
+            incoming.push((test, self.get_block()?));
+            if k + 1 < count {
+                let next_bb = self.ctx.append_basic_block(self.get_fp()?, "chain_next");
+                self.builder.build_conditional_branch(test, next_bb, done_bb);
+                self.set_and_move_block(next_bb)?;
+            } else {
+                self.builder.build_unconditional_branch(done_bb);
+            }
+            lpos = rpos;
+            lval = rval;
+        }
+
+        self.set_and_move_block(done_bb)?;
+        let phi = self.builder.build_phi(self.ctx.bool_type(), "chain_tmp");
+        for (test, block) in &incoming {
+            phi.add_incoming(&[(test, *block)]);
+        }
+        Ok(Value::Bool(phi.as_basic_value().into_int_value()))
+    }
+
+    fn build_equality(&mut self, pos: Location, op: BinOp, lval: Value<'ctx>, rval: Value<'ctx>) -> Result<Value<'ctx>, LocatedCompileError> {
+        let (lval, rval) = self.promote_rational(pos, lval, pos, rval)?;
+        if lval.ty() != rval.ty() {
+            return Err(LocatedCompileError::type_mismatch(pos, &lval.ty(), &rval.ty()));
+        }
+        let (pred, int_pred) = match op {
+            BinOp::Equals => (FloatPredicate::OEQ, IntPredicate::EQ),
+            _             => (FloatPredicate::ONE, IntPredicate::NE),
+        };
+        match (lval, rval) {
+            (Value::Complex(lval), Value::Complex(rval)) | (Value::Eisenstein(lval), Value::Eisenstein(rval)) =>
+                Ok(Value::Bool(self.complex_cmp(pred, lval, rval))),
+            (Value::Poly(lhs), Value::Poly(rhs)) => {
+                let res = self.poly_equals(lhs, rhs)?;
+                if matches!(op, BinOp::Equals) {
+                    Ok(Value::Bool(res))
+                } else {
+                    Ok(Value::Bool(self.builder.build_not(res, "tmp_poly_ne")))
+                }
+            },
+            // Rationals are kept in lowest terms, so equal ones have equal parts
+            (Value::Quaternion(lz, lw), Value::Quaternion(rz, rw)) | (Value::Rational(lz, lw), Value::Rational(rz, rw)) => {
+                let z = self.complex_cmp(pred, lz, rz);
+                let w = self.complex_cmp(pred, lw, rw);
+                Ok(Value::Bool(match op {
+                    BinOp::Equals => self.builder.build_and(z, w, "tmp_quat_eq"),
+                    _             => self.builder.build_or(z, w, "tmp_quat_ne"),
+                }))
+            },
+            (Value::Bool(lval), Value::Bool(rval)) =>
+                Ok(Value::Bool(self.builder.build_int_compare(int_pred, lval, rval, "tmp_bool_cmp"))),
+            (Value::Str(lval), Value::Str(rval)) => {
+                let strcmp = self.builtins.strcmp();
+                self.move_to_end()?;
+                let cmp = self.builder.build_call(strcmp, &[lval.into(), rval.into()], "tmp_strcmp")
+                    .try_as_basic_value().left()
+                        .ok_or_else(|| InternalError::invalid_state("failed to interpret return value of strcmp"))?
+                    .into_int_value();
+                let zero = self.ctx.i32_type().const_zero();
+                Ok(Value::Bool(self.builder.build_int_compare(int_pred, cmp, zero, "tmp_str_cmp")))
+            },
+            // Tuples are equal when all of their fields are
+            (Value::Tuple(lvals), Value::Tuple(rvals)) => {
+                let equals = matches!(op, BinOp::Equals);
+                let mut res = self.ctx.bool_type().const_int(equals as u64, false);
+                for (lval, rval) in lvals.into_iter().zip(rvals) {
+                    let field = self.build_equality(pos, op, lval, rval)?;
+                    let field = self.expect_bool(pos, field)?;
+                    res = if equals {
+                        self.builder.build_and(res, field, "tmp_tuple_eq")
+                    } else {
+                        self.builder.build_or(res, field, "tmp_tuple_ne")
+                    };
+                }
+                Ok(Value::Bool(res))
+            },
+            (Value::Enum(_, ltag, lvals), Value::Enum(_, rtag, rvals)) => {
+                let res = self.enum_equals((ltag, lvals), (rtag, rvals));
+                if matches!(op, BinOp::Equals) {
+                    Ok(Value::Bool(res))
+                } else {
+                    Ok(Value::Bool(self.builder.build_not(res, "tmp_enum_ne")))
+                }
+            },
+            (Value::Option(lpresent, lval), Value::Option(rpresent, rval)) => {
+                let res = self.option_equals(pos, (lpresent, *lval), (rpresent, *rval))?;
+                if matches!(op, BinOp::Equals) {
+                    Ok(Value::Bool(res))
+                } else {
+                    Ok(Value::Bool(self.builder.build_not(res, "tmp_option_ne")))
+                }
+            },
+            // Records are compared like tuples of their fields
+            (Value::Struct(_, lvals), Value::Struct(_, rvals)) => {
+                let lvals = Value::Tuple(lvals.into_iter().map(|(_, value)| value).collect());
+                let rvals = Value::Tuple(rvals.into_iter().map(|(_, value)| value).collect());
+                self.build_equality(pos, op, lvals, rvals)
+            },
+            (lval, _) => Err(LocatedCompileError::unsupported(pos, format!("`{}` values cannot be compared", lval.ty()))),
+        }
+    }
+
+    fn build_expr(&mut self, expr: Located<Expr>) -> Result<Value<'ctx>, LocatedCompileError> {
+        let (expr, pos) = expr.unwrap();
+        match expr {
+            Expr::Value(ComplexNum(re, im)) => {
+                let re = self.ctx.f64_type().const_float(re);
+                let im = self.ctx.f64_type().const_float(im);
+                Ok(Value::Complex(ComplexValue { re, im }))
+            },
+            Expr::Eisenstein(b) => {
+                let a = self.ctx.f64_type().const_zero();
+                let b = self.ctx.f64_type().const_float(b);
+                Ok(Value::Eisenstein(ComplexValue { re: a, im: b }))
+            },
+            Expr::Quaternion(ComplexNum(c, d)) => {
+                let zero = self.ctx.f64_type().const_zero();
+                let c = self.ctx.f64_type().const_float(c);
+                let d = self.ctx.f64_type().const_float(d);
+                Ok(Value::Quaternion(ComplexValue { re: zero, im: zero }, ComplexValue { re: c, im: d }))
+            },
+            Expr::Rational(val) => self.build_rational_literal(pos, val),
+            Expr::Bool(val) => Ok(Value::Bool(self.ctx.bool_type().const_int(val as u64, false))),
+            Expr::Str(val) => Ok(Value::Str(self.builder.build_global_string_ptr(&val, ".str").as_pointer_value())),
+            Expr::Id(id) => {
+                if let Some(var) = self.sym.var(id.borrow_val()) {
+                    let var = var.borrow_val();
+                    let value = self.builder.build_load(var.ptr, id.borrow_val());
+                    match self.unpack(value, &var.ty.clone())? {
+                        // Using the variable is what makes it worth computing
+                        Value::Lazy(ptr, inner, closure) => self.build_force(ptr, &inner, &closure),
+                        value => Ok(value),
+                    }
+                } else if let Some(func) = self.sym.func(id.borrow_val()).cloned() {
+                    Ok(self.build_func_value(func))
+                } else if self.sym.variant(id.borrow_val()).is_some() {
+                    self.build_variant(pos, id, Vec::new())
+                } else if id.borrow_val() == "none" {
+                    // Nothing says what it would have held, so like an empty list, assume a number
+                    self.build_none(&Type::Complex)
+                } else if self.sym.global(id.borrow_val()).is_some() {
+                    Err(LocatedCompileError::undeclared_global(id))
+                } else {
+                    Err(LocatedCompileError::unknown_symbol(id))
+                }
+            },
+            Expr::BinOp(op @ (BinOp::And | BinOp::Or), boxed) => {
+                let (lhs, rhs) = *boxed;
+                self.build_logical(op, lhs, rhs)
+            },
+            Expr::BinOp(op @ (BinOp::Equals | BinOp::NotEquals), boxed) => {
+                let (lhs, rhs) = *boxed;
+                let lpos = lhs.pos();
+                let rpos = rhs.pos();
+                let lval = self.build_expr(lhs)?;
+                let rval = self.build_expr(rhs)?;
+                let (lval, rval) = self.promote_eisenstein(lpos, lval, rpos, rval)?;
+                let (lval, rval) = self.promote_quaternion(lval, rval);
+                let (lval, rval) = self.promote_poly(lval, rval)?;
+                // In a `mod` block, numbers are equal when they're congruent
+                let (lval, rval) = match (lval, rval) {
+                    (Value::Complex(lval), Value::Complex(rval)) if !self.moduli.is_empty() =>
+                        (Value::Complex(self.build_residue(lpos, lval)?), Value::Complex(self.build_residue(rpos, rval)?)),
+                    vals => vals,
+                };
+                self.build_equality(rpos, op, lval, rval)
+            },
+            Expr::BinOp(BinOp::In, boxed) => {
+                let (lhs, rhs) = *boxed;
+                let lpos = lhs.pos();
+                let rpos = rhs.pos();
+                let lval = self.build_expr(lhs)?;
+                let rval = self.build_expr(rhs)?;
+                if let Some(func) = self.overload(pos, binop_overload(BinOp::In), &[&lval, &rval])? {
+                    return self.call_func(&func, vec![lval, rval]);
+                }
+                match rval {
+                    Value::List(list, elem) => {
+                        if lval.ty() != elem {
+                            return Err(LocatedCompileError::type_mismatch(lpos, &elem, &lval.ty()));
+                        }
+                        match self.build_list_find(lpos, list, &elem, lval)? {
+                            Value::Option(present, _) => Ok(Value::Bool(present)),
+                            _ => Err(InternalError::invalid_state("`find` did not return an optional value")),
+                        }
+                    },
+                    Value::Range(range) => {
+                        let lval = self.expect_complex(lpos, lval)?;
+                        self.range_contains(range, lval).map(Value::Bool)
+                    },
+                    Value::Dict(keys, _, table, key, _) => self.build_dict_contains(lpos, (keys, table), &key, lval).map(Value::Bool),
+                    Value::Set(set, elem) => self.build_set_contains(lpos, set, &elem, lval).map(Value::Bool),
+                    rval => Err(LocatedCompileError::type_mismatch(rpos, &Type::List(Box::new(lval.ty())), &rval.ty())),
+                }
+            },
+            Expr::BinOp(op, boxed) => {
+                let (lhs, rhs) = *boxed;
+                let lpos = lhs.pos();
+                let rpos = rhs.pos();
+                let lval = self.build_expr(lhs)?;
+                let rval = self.build_expr(rhs)?;
+                self.build_binop(pos, op, lpos, lval, rpos, rval)
+            },
+            Expr::Chain(first, rest) => self.build_chain(pos, *first, rest),
+            Expr::UnOp(op, expr) => {
+                let expr_pos = expr.pos();
+                let val = self.build_expr(*expr)?;
+                if let Some(func) = self.overload(pos, Some(unop_overload(op)), &[&val])? {
+                    return self.call_func(&func, vec![val]);
+                }
+                let val = match val {
+                    Value::Matrix(matrix) => return self.build_matrix_unop(pos, op, matrix),
+                    Value::Eisenstein(val) => return self.build_eisenstein_unop(pos, op, val),
+                    Value::Quaternion(z, w) => return self.build_quaternion_unop(pos, op, z, w),
+                    Value::Rational(p, q) => return self.build_rational_unop(pos, op, p, q),
+                    Value::Poly(p) => return self.build_poly_unop(pos, op, p),
+                    val => self.expect_complex(expr_pos, val)?,
+                };
+                match op {
+                    UnOp::Negate    => {
+                        let res: ComplexValue = (self.builder.build_float_neg(val.re, "tmp_neg_re"),
+                                   self.builder.build_float_neg(val.im, "tmp_neg_im")).into();
+                        if self.moduli.is_empty() {
+                            Ok(Value::Complex(res))
+                        } else {
+                            self.build_residue(pos, res).map(Value::Complex)
+                        }
+                    },
+                    UnOp::Conjugate => Ok(Value::Complex(self.complex_conjugate(val))),
+                    UnOp::Modulus   => self.complex_modulus(val).map(Value::Complex),
+                    UnOp::Factorial => self.build_exact_factorial(pos, val).map(Value::Complex),
+                }
+            },
+            Expr::Range(boxed) => {
+                let (start, end, step) = *boxed;
+                self.build_range(start, end, step).map(Value::Range)
+            },
+            // Variables shadow functions, which shadow enum variants, which shadow builtins
+            Expr::Call(id, args, named) => {
+                let is_var = self.sym.var(id.borrow_val()).is_some();
+                let func = if is_var { None } else { self.sym.func(id.borrow_val()).cloned() };
+                if let Some(func) = func {
+                    self.build_user_call(pos, func, args, named)
+                } else if let Some((name, _)) = named.into_iter().next() {
+                    Err(LocatedCompileError::unsupported(name.pos(),
+                        format!("only functions declared with `fn` take named arguments, not `{}`", id.val())))
+                } else if is_var {
+                    let callee = self.build_expr(Located::new(Expr::Id(id.clone()), id.pos()))?;
+                    self.build_closure_call(pos, id, callee, args)
+                } else if self.sym.variant(id.borrow_val()).is_some() {
+                    self.build_variant(pos, id, args)
+                } else {
+                    self.build_call(pos, id, args)
+                }
+            },
+            Expr::Lambda(params, body) => self.build_lambda(pos, params, *body),
+            Expr::Lazy(expr) => self.build_lazy(pos, *expr),
+            Expr::Poly(coeffs) => {
+                let coeffs_pos = coeffs.pos();
+                match self.build_expr(*coeffs)? {
+                    Value::List(list, Type::Complex) => self.build_poly_from(list).map(Value::Poly),
+                    value => Err(LocatedCompileError::type_mismatch(coeffs_pos, &Type::List(Box::new(Type::Complex)), &value.ty())),
+                }
+            },
+            Expr::Annotated(expr, annotation) => {
+                let expr_pos = expr.pos();
+                let value = self.build_expr(*expr)?;
+                self.build_annotation_check(expr_pos, &value, annotation.borrow_val())?;
+                Ok(value)
+            },
+            Expr::Match(scrutinee, arms) => self.build_match(pos, *scrutinee, arms),
+            Expr::List(elems) => {
+                let mut values = Vec::new();
+                let mut elem = None;
+                for expr in elems {
+                    let expr_pos = expr.pos();
+                    let value = self.build_expr(expr)?;
+                    match &elem {
+                        Some(ty) if *ty != value.ty() => return Err(LocatedCompileError::type_mismatch(expr_pos, ty, &value.ty())),
+                        Some(_) => {},
+                        None    => elem = Some(value.ty()),
+                    }
+                    values.push(value);
+                }
+                // An empty list could hold anything, but complex numbers are the most likely
+                let elem = elem.unwrap_or(Type::Complex);
+                self.build_list_from(&elem, values).map(|list| Value::List(list, elem))
+            },
+            Expr::Matrix(rows) => {
+                let cols = rows.first().map_or(0, |row| row.borrow_val().len());
+                let mut entries = Vec::new();
+                for row in rows {
+                    let (row, row_pos) = row.unwrap();
+                    if row.len() != cols {
+                        return Err(LocatedCompileError::unsupported(row_pos,
+                            format!("every row of a matrix needs the same length: expected {}, found {}", cols, row.len())));
+                    }
+                    let mut values = Vec::new();
+                    for expr in row {
+                        values.push(self.build_complex(expr)?);
+                    }
+                    entries.push(values);
+                }
+                self.build_matrix_from(entries).map(Value::Matrix)
+            },
+            Expr::Tuple(elems) => {
+                let mut values = Vec::new();
+                for expr in elems {
+                    values.push(self.build_expr(expr)?);
+                }
+                Ok(Value::Tuple(values))
+            },
+            Expr::Field(target, index) => {
+                let target_pos = target.pos();
+                match self.build_expr(*target)? {
+                    Value::Tuple(mut fields) if index < fields.len() => Ok(fields.swap_remove(index)),
+                    value => Err(LocatedCompileError::unsupported(target_pos, format!("`{}` has no field {}", value.ty(), index))),
+                }
+            },
+            Expr::Struct(name, fields) => self.build_struct(pos, name, fields),
+            Expr::Block(body, result) => {
+                // Like `build_block`, but the result is still in scope
+                let saved_vars = self.sym.var_map.clone();
+                for statement in body {
+                    self.build_statement(statement)?;
+                }
+                let value = self.build_expr(*result);
+                self.sym.var_map = saved_vars;
+                value
+            },
+            Expr::Mod(modulus, body) => {
+                let modulus_pos = modulus.pos();
+                let modulus = self.build_expr(*modulus)?;
+                let modulus = self.expect_complex(modulus_pos, modulus)?;
+                self.check_gaussian(modulus_pos, modulus, modulus, "`mod` needs a Gaussian integer")?;
+                self.check_modulus(modulus_pos, modulus, "mod")?;
+                self.moduli.push(modulus);
+                let body_pos = body.pos();
+                let value = self.build_expr(*body).and_then(|value| self.build_residue_value(body_pos, value));
+                self.moduli.pop();
+                value
+            },
+            Expr::Member(target, field) => {
+                // `geometry.area` names something from `geometry.ccc`, unless `geometry` is a variable
+                if let Expr::Id(ns) = target.borrow_val() {
+                    if self.sym.var(ns.borrow_val()).is_none() && self.sym.is_namespace(ns.borrow_val()) {
+                        let name = Located::new(format!("{}.{}", ns.borrow_val(), field.borrow_val()), pos);
+                        return self.build_expr(Located::new(Expr::Id(name), pos));
+                    }
+                }
+                let value = self.build_expr(*target)?;
+                let ty = value.ty();
+                match value {
+                    Value::Struct(_, mut fields) => match fields.iter().position(|(name, _)| name == field.borrow_val()) {
+                        Some(k) => Ok(fields.swap_remove(k).1),
+                        None    => Err(LocatedCompileError::no_field(field, ty)),
+                    },
+                    _ => Err(LocatedCompileError::no_field(field, ty)),
+                }
+            },
+            Expr::Comprehension(elem, clauses) => self.build_comprehension(*elem, clauses),
+            Expr::Set(elems) => {
+                let mut values = Vec::new();
+                let mut elem = None;
+                for expr in elems {
+                    let expr_pos = expr.pos();
+                    let value = self.build_expr(expr)?;
+                    match &elem {
+                        Some(ty) if *ty != value.ty() => return Err(LocatedCompileError::type_mismatch(expr_pos, ty, &value.ty())),
+                        Some(_) => {},
+                        None    => elem = Some(value.ty()),
+                    }
+                    values.push(value);
+                }
+                // There's always at least one element
+                let elem = elem.ok_or_else(|| InternalError::invalid_state("empty set literal"))?;
+                self.build_set_from(pos, &elem, values).map(|set| Value::Set(set, elem))
+            },
+            Expr::Dict(pairs) => {
+                let mut values = Vec::new();
+                let mut types = None;
+                for (key, value) in pairs {
+                    let key_pos = key.pos();
+                    let value_pos = value.pos();
+                    let key = self.build_expr(key)?;
+                    let value = self.build_expr(value)?;
+                    match &types {
+                        Some((key_type, _)) if *key_type != key.ty() => return Err(LocatedCompileError::type_mismatch(key_pos, key_type, &key.ty())),
+                        Some((_, value_type)) if *value_type != value.ty() => return Err(LocatedCompileError::type_mismatch(value_pos, value_type, &value.ty())),
+                        Some(_) => {},
+                        None    => types = Some((key.ty(), value.ty())),
+                    }
+                    values.push((key, value));
+                }
+                // Like an empty list, an empty dictionary is most likely to be of complex numbers
+                let (key, value) = types.unwrap_or((Type::Complex, Type::Complex));
+                self.build_dict_from(pos, &key, &value, values)
+            },
+            Expr::Index(boxed) => {
+                let (target, index) = *boxed;
+                let target_pos = target.pos();
+                let index_pos = index.pos();
+                match self.build_expr(target)? {
+                    Value::List(list, elem) => match self.build_expr(index)? {
+                        Value::Range(range) => self.build_list_slice(index_pos, list, &elem, range).map(|list| Value::List(list, elem)),
+                        index => {
+                            let index = self.expect_complex(index_pos, index)?;
+                            self.build_list_get(index_pos, list, &elem, index)
+                        },
+                    },
+                    Value::Str(ptr) => {
+                        let index = self.build_expr(index)?;
+                        let range = self.expect_range(index_pos, index)?;
+                        self.build_str_slice(index_pos, ptr, range).map(Value::Str)
+                    },
+                    Value::Dict(keys, values, table, key, value) => {
+                        let index = self.build_expr(index)?;
+                        self.build_dict_index(index_pos, (keys, values, table, &key, &value), index)
+                    },
+                    value => Err(LocatedCompileError::type_mismatch(target_pos, &Type::List(Box::new(Type::Complex)), &value.ty())),
+                }
+            },
+            Expr::Loop(label, body) => {
+                let body_bb = self.ctx.append_basic_block(self.get_fp()?, "loop");
+                let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "cont");
+                self.builder.build_unconditional_branch(body_bb);
+
+                self.set_and_move_block(body_bb)?;
+                self.loops.push(LoopBlocks { label: label.map(Located::val), continue_bb: body_bb, break_bb: cont_bb, values: Some(Vec::new()) });
+                self.build_block(body)?;
+                let values = self.loops.pop()
+                    .and_then(|target| target.values)
+                    .ok_or_else(|| InternalError::invalid_state("lost track of the enclosing loop"))?;
+                self.builder.build_unconditional_branch(body_bb);
+
+                self.set_and_move_block(cont_bb)?;
+                // Without a `break`, the loop never finishes, so any value would do
+                let ty = match values.first() {
+                    Some((ty, _, _)) => ty.clone(),
+                    None => return Ok(Value::Tuple(Vec::new())),
+                };
+                let phi = self.builder.build_phi(self.llvm_type(&ty), "looptmp");
+                for (_, value, block) in &values {
+                    phi.add_incoming(&[(value, *block)]);
+                }
+                self.unpack(phi.as_basic_value(), &ty)
+            },
+            Expr::IfElse(boxed) => {
+                let (cond, value_if, value_else) = *boxed;
+                let else_pos = value_else.pos();
+                let cond = self.build_cond(cond)?;
+
+                let then_bb = self.ctx.append_basic_block(self.get_fp()?, "then");
+                let else_bb = self.ctx.append_basic_block(self.get_fp()?, "else");
+                let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "cont");
+                self.builder.build_conditional_branch(cond, then_bb, else_bb);
+
+                // The branches may themselves introduce new blocks, so take note of where they end
+                self.set_and_move_block(then_bb)?;
+                let value_if = self.build_expr(value_if)?;
+                let ty = value_if.ty();
+                let value_if = self.pack(&value_if)?;
+                let then_bb = self.get_block()?;
+                self.builder.build_unconditional_branch(cont_bb);
+
+                self.set_and_move_block(else_bb)?;
+                let value_else = self.build_expr(value_else)?;
+                if value_else.ty() != ty {
+                    return Err(LocatedCompileError::type_mismatch(else_pos, &ty, &value_else.ty()));
+                }
+                let value_else = self.pack(&value_else)?;
+                let else_bb = self.get_block()?;
+                self.builder.build_unconditional_branch(cont_bb);
+
+                self.set_and_move_block(cont_bb)?;
+                let phi = self.builder.build_phi(self.llvm_type(&ty), "iftmp");
+                phi.add_incoming(&[(&value_if, then_bb), (&value_else, else_bb)]);
+                self.unpack(phi.as_basic_value(), &ty)
+            }
+        }
+    }
+
+    /// Builds a record. The fields are evaluated in the order they're written, then stored in the
+    /// order they were declared.
+    fn build_struct(&mut self, pos: Location, name: Located<String>, fields: Vec<(Located<String>, Located<Expr>)>)
+            -> Result<Value<'ctx>, LocatedCompileError> {
+        let def = self.sym.struct_def(name.borrow_val()).cloned()
+            .ok_or_else(|| LocatedCompileError::unknown_symbol(name.clone()))?;
+        for (k, (field, _)) in fields.iter().enumerate() {
+            if !def.fields.iter().any(|decl| decl.borrow_val() == field.borrow_val()) {
+                return Err(LocatedCompileError::no_field(field.clone(), name.borrow_val()));
+            }
+            if let Some((prev, _)) = fields[..k].iter().find(|(prev, _)| prev.borrow_val() == field.borrow_val()) {
+                return Err(LocatedCompileError::duplicate(field.clone(), prev.pos()));
+            }
+        }
+
+        let mut values = Vec::new();
+        for (field, expr) in fields {
+            values.push((field.val(), self.build_expr(expr)?));
+        }
+        let mut ordered = Vec::new();
+        for decl in &def.fields {
+            match values.iter().position(|(field, _)| field == decl.borrow_val()) {
+                Some(k) => ordered.push(values.swap_remove(k)),
+                None    => return Err(LocatedCompileError::missing_field(pos, name.borrow_val(), decl)),
+            }
+        }
+        Ok(Value::Struct(def.name.borrow_val().clone(), ordered))
+    }
+
+    /// Tries each arm in order. Literal patterns are compared with `==`, while bindings and `_` match
+    /// anything. It's a runtime error if nothing matches.
+    fn build_match(&mut self, pos: Location, scrutinee: Located<Expr>, arms: Vec<(Pattern, Located<Expr>)>)
+            -> Result<Value<'ctx>, LocatedCompileError> {
+        let value = self.build_expr(scrutinee)?;
+        let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "cont");
+        let mut ty = None;
+        let mut incoming = Vec::new();
+
+        for (pattern, body) in arms {
+            match self.constructor_pattern(&value, pattern) {
+                Pattern::Literal(literal) => {
+                    let literal_pos = literal.pos();
+                    let literal = self.build_expr(literal)?;
+                    let matched = self.build_equality(literal_pos, BinOp::Equals, value.clone(), literal)?;
+                    let matched = self.expect_bool(literal_pos, matched)?;
+                    let arm_bb = self.ctx.append_basic_block(self.get_fp()?, "arm");
+                    let next_bb = self.ctx.append_basic_block(self.get_fp()?, "next_arm");
+                    self.builder.build_conditional_branch(matched, arm_bb, next_bb);
+
+                    self.set_and_move_block(arm_bb)?;
+                    self.build_arm(body, &mut ty, &mut incoming)?;
+                    self.builder.build_unconditional_branch(cont_bb);
+                    self.set_and_move_block(next_bb)?;
+                },
+                // Irrefutable patterns make any later arms unreachable, hence `build_jump`
+                Pattern::Binding(id) => {
+                    let ptr = self.builder.build_alloca(self.llvm_type(&value.ty()), id.borrow_val());
+                    let packed = self.pack(&value)?;
+                    self.builder.build_store(ptr, packed);
+                    let name = id.borrow_val().clone();
+                    let shadowed = self.sym.add_var(id, Variable { ptr, ty: value.ty(), mutable: false });
+                    self.build_arm(body, &mut ty, &mut incoming)?;
+                    self.sym.restore_var(&name, shadowed);
+                    self.build_jump(cont_bb)?;
+                },
+                Pattern::Wildcard => {
+                    self.build_arm(body, &mut ty, &mut incoming)?;
+                    self.build_jump(cont_bb)?;
+                },
+                Pattern::Variant(name, fields) => {
+                    let next_bb = self.ctx.append_basic_block(self.get_fp()?, "next_arm");
+                    let bound = self.build_constructor_test(&value, name, fields, next_bb)?;
+                    self.build_arm(body, &mut ty, &mut incoming)?;
+                    for (id, shadowed) in bound.into_iter().rev() {
+                        self.sym.restore_var(&id, shadowed);
+                    }
+                    self.builder.build_unconditional_branch(cont_bb);
+                    self.set_and_move_block(next_bb)?;
+                },
+            }
+        }
+        self.build_abort(pos, "no arm of `match` matched")?;
+
+        self.set_and_move_block(cont_bb)?;
+        let ty = ty.ok_or_else(|| InternalError::invalid_state("`match` without arms"))?;
+        let phi = self.builder.build_phi(self.llvm_type(&ty), "match_tmp");
+        for (value, block) in &incoming {
+            phi.add_incoming(&[(value, *block)]);
+        }
+        self.unpack(phi.as_basic_value(), &ty)
+    }
+
+    /// A binding that names a variant without fields, or `none` when matching an optional value, is
+    /// really a constructor pattern.
+    fn constructor_pattern(&self, value: &Value<'ctx>, pattern: Pattern) -> Pattern {
+        match pattern {
+            Pattern::Binding(id) if self.sym.variant(id.borrow_val()).is_some() => Pattern::Variant(id, Vec::new()),
+            Pattern::Binding(id) if matches!(value, Value::Option(..)) && id.borrow_val() == "none" => Pattern::Variant(id, Vec::new()),
+            pattern => pattern,
+        }
+    }
+
+    /// Checks whether the value was built with the given variant (or `some` or `none`), jumping to
+    /// `next_bb` if not. Otherwise, binds the fields and returns what they shadowed.
+    fn build_constructor_test(&mut self, value: &Value<'ctx>, name: Located<String>, fields: Vec<Pattern>, next_bb: BasicBlock<'ctx>)
+            -> Result<Vec<(String, Option<Located<Variable<'ctx>>>)>, LocatedCompileError> {
+        match value {
+            Value::Option(present, inner) => self.build_option_test(*present, (**inner).clone(), name, fields, next_bb),
+            value => self.build_variant_test(value, name, fields, next_bb),
+        }
+    }
+
+    /// Builds the body of a `match` arm, which must have the same type as the other arms.
+    fn build_arm(&mut self, body: Located<Expr>, ty: &mut Option<Type>, incoming: &mut Vec<(BasicValueEnum<'ctx>, BasicBlock<'ctx>)>)
+            -> Result<(), LocatedCompileError> {
+        let body_pos = body.pos();
+        let value = self.build_expr(body)?;
+        match ty {
+            Some(ty) if *ty != value.ty() => return Err(LocatedCompileError::type_mismatch(body_pos, ty, &value.ty())),
+            Some(_) => {},
+            None    => *ty = Some(value.ty()),
+        }
+        incoming.push((self.pack(&value)?, self.get_block()?));
+        Ok(())
+    }
+
+    fn build_let(&mut self, id: Located<String>, expr: Located<Expr>, mutable: bool) -> Result<(), LocatedCompileError> {
+        let value = self.build_expr(expr)?;
+        self.build_binding(id, value, mutable)
+    }
+
+    /// Binds each name on the left to the matching part of the value.
+    fn build_destructure(&mut self, binder: Binder, value: Value<'ctx>, mutable: bool) -> Result<(), LocatedCompileError> {
+        let mut parts = Vec::new();
+        self.destructure(binder, value, &mut parts)?;
+        for (id, value) in parts {
+            self.build_binding(id, value, mutable)?;
+        }
+        Ok(())
+    }
+
+    /// Pairs each name on the left with the matching part of the value, which must be a tuple with
+    /// the same shape.
+    fn destructure(&self, binder: Binder, value: Value<'ctx>, out: &mut Vec<(Located<String>, Value<'ctx>)>) -> Result<(), LocatedCompileError> {
+        match binder {
+            Binder::Name(id) => out.push((id, value)),
+            Binder::Wildcard => {},
+            Binder::Tuple(binders) => {
+                let (binders, pos) = binders.unwrap();
+                match value {
+                    Value::Tuple(values) if values.len() == binders.len() => {
+                        for (binder, value) in binders.into_iter().zip(values) {
+                            self.destructure(binder, value, out)?;
+                        }
+                    },
+                    value => return Err(LocatedCompileError::unsupported(pos,
+                        format!("cannot unpack `{}` into {} value{}", value.ty(), binders.len(), if binders.len() == 1 { "" } else { "s" }))),
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn build_binding(&mut self, id: Located<String>, value: Value<'ctx>, mutable: bool) -> Result<(), LocatedCompileError> {
+        if self.sym.is_global_var(id.borrow_val()) {
+            return Err(LocatedCompileError::shadowed_global(id));
+        }
+        let value = if mutable { self.build_unshare(value)? } else { value };
+        let ty = value.ty();
+
+        // allocate variable memory and assign value
+        let ptr = self.builder.build_alloca(self.llvm_type(&ty), id.borrow_val());
+        let value = self.pack(&value)?;
+        self.builder.build_store(ptr, value);
+
+        // update symbol table
+        self.sym.add_var(id, Variable { ptr, ty, mutable });
+        Ok(())
+    }
+
+    /// Lists are shared by reference, so a list stored in a mutable variable is copied first. That
+    /// way, mutating it can't change a list that some immutable variable also refers to.
+    fn build_unshare(&mut self, value: Value<'ctx>) -> Result<Value<'ctx>, LocatedCompileError> {
+        match value {
+            Value::List(list, elem) => self.build_list_copy(list, &elem).map(|list| Value::List(list, elem)),
+            Value::Dict(keys, values, _, key, value) => self.build_dict_copy(keys, values, key, value),
+            Value::Set(set, elem) => self.build_list_copy(set, &elem).map(|set| Value::Set(set, elem)),
+            value => Ok(value),
+        }
+    }
+
+    fn print_value(&mut self, pos: Location, value: Value<'ctx>, newline: bool) -> Result<(), LocatedCompileError> {
+        match value {
+            Value::Complex(value) => {
+                let f = if newline { self.builtins.println_float() } else { self.builtins.print_float() };
+                self.move_to_end()?;
+                self.builder.build_call(f, &[value.re.into(), value.im.into()], "call");
+            },
+            Value::Bool(value) => {
+                let f = if newline { self.builtins.println_str() } else { self.builtins.print_str() };
+                self.move_to_end()?;
+                let true_str = self.builder.build_global_string_ptr("true", ".true_str").as_pointer_value();
+                let false_str = self.builder.build_global_string_ptr("false", ".false_str").as_pointer_value();
+                let ptr = self.builder.build_select(value, true_str, false_str, "bool_str");
+                self.builder.build_call(f, &[ptr.into()], "call");
+            },
+            Value::Eisenstein(_) | Value::Quaternion(..) | Value::Rational(..) => {
+                let text = self.build_to_str(pos, value)?;
+                return self.print_value(pos, Value::Str(text), newline);
+            },
+            Value::Range(_) => return Err(LocatedCompileError::unsupported(pos, "cannot print a range".to_owned())),
+            Value::Closure(..) => return Err(LocatedCompileError::unsupported(pos, "cannot print a function".to_owned())),
+            Value::Lazy(ptr, inner, closure) => {
+                let value = self.build_force(ptr, &inner, &closure)?;
+                return self.print_value(pos, value, newline);
+            },
+            Value::Str(ptr) => {
+                let f = if newline { self.builtins.println_str() } else { self.builtins.print_str() };
+                self.move_to_end()?;
+                self.builder.build_call(f, &[ptr.into()], "call");
+            },
+            Value::List(list, elem) => self.print_elements(pos, list, &elem, ("[", "]"), newline)?,
+            Value::Set(set, elem) => self.print_elements(pos, set, &elem, ("{", "}"), newline)?,
+            Value::Dict(keys, values, _, key, value) => self.print_dict(pos, (keys, values, &key, &value), newline)?,
+            Value::Matrix(matrix) => self.print_matrix(matrix, newline)?,
+            Value::Poly(p) => {
+                self.build_print_str("poly ".to_owned())?;
+                self.print_elements(pos, p, &Type::Complex, ("[", "]"), newline)?
+            },
+            Value::Enum(name, tag, payload) => self.print_enum(&name, tag, payload, newline)?,
+            Value::Option(present, inner) => self.print_option(pos, present, *inner, newline)?,
+            Value::Struct(name, fields) => {
+                self.build_print_str(format!("{} {{ ", name))?;
+                for (k, (field, value)) in fields.into_iter().enumerate() {
+                    let sep = if k > 0 { ", " } else { "" };
+                    self.build_print_str(format!("{}{}: ", sep, field))?;
+                    self.print_value(pos, value, false)?;
+                }
+                if newline {
+                    self.build_println_str(" }".to_owned())?;
+                } else {
+                    self.build_print_str(" }".to_owned())?;
+                }
+            },
+            Value::Tuple(fields) => {
+                self.build_print_str("(".to_owned())?;
+                for (k, field) in fields.into_iter().enumerate() {
+                    if k > 0 {
+                        self.build_print_str(", ".to_owned())?;
+                    }
+                    self.print_value(pos, field, false)?;
+                }
+                if newline {
+                    self.build_println_str(")".to_owned())?;
+                } else {
+                    self.build_print_str(")".to_owned())?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn build_print(&mut self, expr: Located<Expr>) -> Result<(), LocatedCompileError> {
+        let pos = expr.pos();
+        let value = self.build_expr(expr)?;
+        self.print_value(pos, value, false)
+    }
+
+    /// Prints each value in turn with a space in between. Each value is computed just before it's
+    /// printed.
+    fn build_print_all(&mut self, exprs: Vec<Located<Expr>>, newline: bool) -> Result<(), LocatedCompileError> {
+        let count = exprs.len();
+        for (k, expr) in exprs.into_iter().enumerate() {
+            let pos = expr.pos();
+            let value = self.build_expr(expr)?;
+            let last = k + 1 == count;
+            self.print_value(pos, value, newline && last)?;
+            if !last {
+                self.build_print_str(" ".to_owned())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn build_print_str(&mut self, value: String) -> Result<(), LocatedCompileError> {
+        self.move_to_end()?;
+        let f = self.builtins.print_str();
+        self.move_to_end()?;
+        let ptr = self.builder.build_global_string_ptr(&value, ".str_arg").as_pointer_value();
+        self.builder.build_call(f, &[ptr.into()], "call");
+        Ok(())
+    }
+
+    fn build_println_str(&mut self, value: String) -> Result<(), LocatedCompileError> {
+        self.move_to_end()?;
+        let f = self.builtins.println_str();
+        self.move_to_end()?;
+        let ptr = self.builder.build_global_string_ptr(&value, ".str_arg").as_pointer_value();
+        self.builder.build_call(f, &[ptr.into()], "call");
+        Ok(())
+    }
+
+    fn build_print_segments(&mut self, segments: Vec<Segment>, newline: bool) -> Result<(), LocatedCompileError> {
+        for segment in segments {
+            match segment {
+                Segment::Text(text) => self.build_print_str(text)?,
+                Segment::Expr(expr) => self.build_print(expr)?,
+            }
+        }
+        if newline {
+            self.build_println_str(String::new())?;
+        }
+        Ok(())
+    }
+
+    fn build_assign(&mut self, statement_pos: Location, id: Located<String>, expr: Located<Expr>)
+            -> Result<(), LocatedCompileError> {
+        let expr_pos = expr.pos();
+        let val = self.build_expr(expr)?;
+        self.build_store_var(statement_pos, id, val, expr_pos)
+    }
+
+    /// Builds `(a, b) = expr`. The whole right side is evaluated before any variable changes, so
+    /// `(a, b) = (b, a)` swaps them.
+    fn build_assign_tuple(&mut self, statement_pos: Location, binders: Located<Vec<Binder>>, expr: Located<Expr>)
+            -> Result<(), LocatedCompileError> {
+        let val = self.build_expr(expr)?;
+        let mut parts = Vec::new();
+        self.destructure(Binder::Tuple(binders), val, &mut parts)?;
+        for (id, val) in parts {
+            let pos = id.pos();
+            self.build_store_var(statement_pos, id, val, pos)?;
+        }
+        Ok(())
+    }
+
+    /// Stores a new value in a mutable variable, which must keep its type.
+    fn build_store_var(&mut self, statement_pos: Location, id: Located<String>, val: Value<'ctx>, val_pos: Location)
+            -> Result<(), LocatedCompileError> {
+        self.check_mutable(statement_pos, &id)?;
+        let val = self.build_unshare(val)?;
+        let var = self.sym.var(id.borrow_val())
+            .ok_or_else(|| InternalError::invalid_state("variable vanished"))?
+            .borrow_val().clone();
+        if var.ty != val.ty() {
+            Err(LocatedCompileError::type_mismatch(val_pos, &var.ty, &val.ty()))
+        } else {
+            let val = self.pack(&val)?;
+            self.builder.build_store(var.ptr, val);
+            Ok(())
+        }
+    }
+
+    /// Builds `id[index] = expr`. The list itself must be declared `mut`. For a dictionary, this
+    /// inserts the key if it isn't there yet.
+    fn build_assign_index(&mut self, statement_pos: Location, id: Located<String>, index: Located<Expr>, expr: Located<Expr>)
+            -> Result<(), LocatedCompileError> {
+        self.check_mutable(statement_pos, &id)?;
+        let index_pos = index.pos();
+        let expr_pos = expr.pos();
+        let target = self.build_expr(Located::new(Expr::Id(id.clone()), id.pos()))?;
+        if let Value::Dict(keys, values, table, key, value_type) = target {
+            let index = self.build_expr(index)?;
+            let value = self.build_expr(expr)?;
+            return self.build_dict_insert(expr_pos, (keys, values, table, &key, &value_type), index, value);
+        }
+        let index = self.build_complex(index)?;
+        let value = self.build_expr(expr)?;
+        match target {
+            Value::List(list, elem) => {
+                if value.ty() != elem {
+                    return Err(LocatedCompileError::type_mismatch(expr_pos, &elem, &value.ty()));
+                }
+                self.build_list_set(index_pos, list, index, value)
+            },
+            target => Err(LocatedCompileError::type_mismatch(id.pos(), &Type::List(Box::new(value.ty())), &target.ty())),
+        }
+    }
+
+    fn build_for(&mut self, label: Option<Located<String>>, id: Located<String>, iter: Located<Expr>, body: Vec<Located<Statement>>) -> Result<(), LocatedCompileError> {
+        if let Expr::Call(callee, _, _) = iter.borrow_val() {
+            let is_var = self.sym.var(callee.borrow_val()).is_some();
+            if let Some(func) = self.sym.func(callee.borrow_val()).filter(|func| !is_var && func.is_generator()).cloned() {
+                return self.build_generator_for(label, id, func, iter, body);
+            }
+        }
+        self.build_for_each(label, id, iter, |this| this.build_block(body))
+    }
+
+    /// Runs `body` once for each number in the range, each element of a list or set, or each key of
+    /// a dictionary, with `id` bound to it.
+    fn build_for_each<F>(&mut self, label: Option<Located<String>>, id: Located<String>, iter: Located<Expr>, body: F)
+            -> Result<(), LocatedCompileError> where F: FnOnce(&mut Self) -> Result<(), LocatedCompileError> {
+        let t_f64 = self.ctx.f64_type();
+        let iter_pos = iter.pos();
+        let iter = self.build_expr(iter)?;
+        let (len, ty) = match &iter {
+            Value::Range(range) => (range.len, Type::Complex),
+            Value::List(list, elem) | Value::Set(list, elem) | Value::Dict(list, _, _, elem, _) => {
+                let len = self.list_len(*list)?;
+                (self.builder.build_signed_int_to_float(len, t_f64, "len_float"), elem.clone())
+            },
+            _ => return Err(LocatedCompileError::type_mismatch(iter_pos, &Type::Range, &iter.ty())),
+        };
+
+        let counter = self.builder.build_alloca(t_f64, "for_counter");
+        self.builder.build_store(counter, t_f64.const_zero());
+        let ptr = self.builder.build_alloca(self.llvm_type(&ty), id.borrow_val());
+
+        let test_bb = self.ctx.append_basic_block(self.get_fp()?, "test");
+        let body_bb = self.ctx.append_basic_block(self.get_fp()?, "body");
+        let step_bb = self.ctx.append_basic_block(self.get_fp()?, "step");
+        let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "cont");
+
+        self.builder.build_unconditional_branch(test_bb);
+        self.set_and_move_block(test_bb)?;
+        let k = self.builder.build_load(counter, "k").into_float_value();
+        let cond = self.builder.build_float_compare(FloatPredicate::OLT, k, len, "test_val");
+        self.builder.build_conditional_branch(cond, body_bb, cont_bb);
+
+        self.set_and_move_block(body_bb)?;
+        let value = match &iter {
+            Value::Range(range) => Value::Complex(self.range_nth(*range, k)),
+            Value::List(list, elem) | Value::Set(list, elem) | Value::Dict(list, _, _, elem, _) => {
+                let k = self.builder.build_float_to_signed_int(k, self.ctx.i64_type(), "index");
+                self.build_list_nth(*list, elem, k)?
+            },
+            _ => return Err(InternalError::invalid_state("lost track of what the loop goes through")),
+        };
+        let value = self.pack(&value)?;
+        self.builder.build_store(ptr, value);
+
+        let name = id.borrow_val().clone();
+        let shadowed = self.sym.add_var(id, Variable { ptr, ty, mutable: false });
+        self.loops.push(LoopBlocks { label: label.map(Located::val), continue_bb: step_bb, break_bb: cont_bb, values: None });
+        body(self)?;
+        self.loops.pop();
+        self.sym.restore_var(&name, shadowed);
+        self.builder.build_unconditional_branch(step_bb);
+
+        self.set_and_move_block(step_bb)?;
+        let k = self.builder.build_load(counter, "k").into_float_value();
+        let k = self.builder.build_float_add(k, t_f64.const_float(1.), "next_k");
+        self.builder.build_store(counter, k);
+        self.builder.build_unconditional_branch(test_bb);
+
+        self.set_and_move_block(cont_bb)?;
+        Ok(())
+    }
+
+    /// Builds the statements in a new scope: variables declared inside go out of scope at the end,
+    /// and any they shadowed become visible again.
+    fn build_block(&mut self, body: Vec<Located<Statement>>) -> Result<(), LocatedCompileError> {
+        let saved_vars = self.sym.var_map.clone();
+        for statement in body {
+            self.build_statement(statement)?;
+        }
+        self.sym.var_map = saved_vars;
+        Ok(())
+    }
+
+    fn build_statement(&mut self, statement: Located<Statement>) -> Result<(), LocatedCompileError> {
+        let (statement, pos) = statement.unwrap();
+        match statement {
+            Statement::Let(name, expr) => self.build_let(name, expr, false),
+            Statement::LetMut(name, expr) => self.build_let(name, expr, true),
+            Statement::LetTuple(binders, expr) => {
+                let value = self.build_expr(expr)?;
+                self.build_destructure(Binder::Tuple(binders), value, false)
+            },
+            Statement::LetMutTuple(binders, expr) => {
+                let value = self.build_expr(expr)?;
+                self.build_destructure(Binder::Tuple(binders), value, true)
+            },
+            Statement::Print(exprs) => self.build_print_all(exprs, false),
+            Statement::PrintLn(exprs) => self.build_print_all(exprs, true),
+            Statement::PrintLit(segments) => self.build_print_segments(segments, false),
+            Statement::PrintLitLn(segments) => self.build_print_segments(segments, true),
+            Statement::Assign(id, expr) => self.build_assign(pos, id, expr),
+            Statement::AssignTuple(binders, expr) => self.build_assign_tuple(pos, binders, expr),
+            Statement::AssignIndex(id, index, expr) => self.build_assign_index(pos, id, index, expr),
+            Statement::Expr(expr) => self.build_expr(expr).map(|_| ()),
+            Statement::AddAssign(id, rhs) =>
                 self.build_assign(pos, id.clone(), 
                     Located::new(Expr::BinOp(
                         BinOp::Plus,
@@ -460,105 +2267,854 @@ impl<'ctx> Compiler<'ctx> {
                         BinOp::Remainder,
                         Box::new((Located::new(Expr::Id(id), pos), rhs))), pos)),
             Statement::If(cond, then) => {
-                let cond = self.build_expr(cond)?;
+                let cond = self.build_cond(cond)?;
 
                 let then_bb = self.ctx.append_basic_block(self.get_fp()?, "then");
                 let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "cont");
                 
-                let re =  self.builder.build_float_compare(FloatPredicate::ONE, cond.re(), self.ctx.f64_type().const_zero(), "test_re");
-                let im =  self.builder.build_float_compare(FloatPredicate::ONE, cond.im(), self.ctx.f64_type().const_zero(), "test_im");
-                let cond = self.builder.build_or(re, im, "test");
                 self.builder.build_conditional_branch(cond, then_bb, cont_bb);
 
                 self.set_and_move_block(then_bb)?;
-                for statement in then {
-                    self.build_statement(statement)?;
-                }
+                self.build_block(then)?;
                 self.builder.build_unconditional_branch(cont_bb);
                 self.set_and_move_block(cont_bb)?;
                 Ok(())
             },
             Statement::IfElse(cond, then_st, else_st) => {
-                let cond = self.build_expr(cond)?;
+                let cond = self.build_cond(cond)?;
 
                 let then_bb = self.ctx.append_basic_block(self.get_fp()?, "then");
                 let else_bb = self.ctx.append_basic_block(self.get_fp()?, "else");
                 let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "cont");
                 
-                let re =  self.builder.build_float_compare(FloatPredicate::ONE, cond.re(), self.ctx.f64_type().const_zero(), "test_re");
-                let im =  self.builder.build_float_compare(FloatPredicate::ONE, cond.im(), self.ctx.f64_type().const_zero(), "test_im");
-                let cond = self.builder.build_or(re, im, "test");
                 self.builder.build_conditional_branch(cond, then_bb, else_bb);
 
                 self.set_and_move_block(then_bb)?;
-                for statement in then_st {
-                    self.build_statement(statement)?;
-                }
+                self.build_block(then_st)?;
                 self.builder.build_unconditional_branch(cont_bb);
 
                 self.set_and_move_block(else_bb)?;
-                for statement in else_st {
-                    self.build_statement(statement)?;
-                }
+                self.build_block(else_st)?;
                 self.builder.build_unconditional_branch(cont_bb);
                 self.set_and_move_block(cont_bb)?;
                 Ok(())
             },
-            Statement::While(cond, body) => {
+            Statement::While(label, cond, body) => {
                 let test_bb = self.ctx.append_basic_block(self.get_fp()?, "test");
                 let body_bb = self.ctx.append_basic_block(self.get_fp()?, "body");
                 let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "cont");
 
                 self.builder.build_unconditional_branch(test_bb);
                 self.set_and_move_block(test_bb)?;
-                let cond = self.build_expr(cond)?;
+                let cond = self.build_cond(cond)?;
                 
-                let re =  self.builder.build_float_compare(FloatPredicate::ONE, cond.re(), self.ctx.f64_type().const_zero(), "test_re");
-                let im =  self.builder.build_float_compare(FloatPredicate::ONE, cond.im(), self.ctx.f64_type().const_zero(), "test_im");
-                let cond = self.builder.build_or(re, im, "test_val");
                 self.builder.build_conditional_branch(cond, body_bb, cont_bb);
 
 
                 self.set_and_move_block(body_bb)?;
-                for statement in body {
-                    self.build_statement(statement)?;
-                }
+                self.loops.push(LoopBlocks { label: label.map(Located::val), continue_bb: test_bb, break_bb: cont_bb, values: None });
+                self.build_block(body)?;
+                self.loops.pop();
                 self.builder.build_unconditional_branch(test_bb);
 
                 self.set_and_move_block(cont_bb)?;
                 Ok(())
             },
-            Statement::Break => {
-                if !self.inside_loop {
-                    Err(LocatedCompileError::not_inside_loop(Located::new("break".to_owned(), pos)))
+            Statement::DoWhile(label, body, cond) => {
+                let body_bb = self.ctx.append_basic_block(self.get_fp()?, "body");
+                let test_bb = self.ctx.append_basic_block(self.get_fp()?, "test");
+                let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "cont");
+
+                self.builder.build_unconditional_branch(body_bb);
+                self.set_and_move_block(body_bb)?;
+                self.loops.push(LoopBlocks { label: label.map(Located::val), continue_bb: test_bb, break_bb: cont_bb, values: None });
+                self.build_block(body)?;
+                self.loops.pop();
+                self.builder.build_unconditional_branch(test_bb);
+
+                // The condition can't see variables declared in the body
+                self.set_and_move_block(test_bb)?;
+                let cond = self.build_cond(cond)?;
+                self.builder.build_conditional_branch(cond, body_bb, cont_bb);
+
+                self.set_and_move_block(cont_bb)?;
+                Ok(())
+            },
+            Statement::For(label, id, iter, body) => self.build_for(label, id, iter, body),
+            Statement::Return(expr) if !self.generators.is_empty() => self.build_generator_return(pos, expr),
+            Statement::Return(expr) => self.build_return(pos, expr),
+            Statement::Yield(expr) => self.build_yield(pos, expr),
+            Statement::Global(ids) => self.build_global_decl(ids),
+            Statement::Assert(cond, text, message) => self.build_assert(pos, cond, text, message),
+            Statement::Try(body, id, handler) => self.build_try(body, id, handler),
+            Statement::Break(label, value) => self.build_break(pos, label, value),
+            Statement::Continue(label) => {
+                let target = self.find_loop(pos, "continue", label)?;
+                let continue_bb = self.loops[target].continue_bb;
+                self.build_leave_try(Some(target));
+                self.build_jump(continue_bb)
+            },
+        }
+    }
+
+    /// Brings top-level variables into scope. They refer to the same memory, so changes are seen
+    /// everywhere.
+    fn build_global_decl(&mut self, ids: Vec<Located<String>>) -> Result<(), LocatedCompileError> {
+        for id in ids {
+            let (var, decl) = self.sym.global(id.borrow_val()).cloned()
+                .ok_or_else(|| LocatedCompileError::unknown_symbol(id.clone()))?
+                .unwrap();
+            self.sym.add_var(Located::new(id.val(), decl), var);
+        }
+        Ok(())
+    }
+
+    /// Builds `assert cond`. If it fails, the error shows the condition as written, and if it's a
+    /// comparison, the values of both sides.
+    fn build_assert(&mut self, pos: Location, cond: Located<Expr>, text: String, message: Option<String>) -> Result<(), LocatedCompileError> {
+        let (cond, cond_pos) = cond.unwrap();
+        let (ok, sides) = match cond {
+            Expr::BinOp(op @ (BinOp::Equals | BinOp::NotEquals | BinOp::Less | BinOp::LessEquals | BinOp::Greater | BinOp::GreaterEquals), boxed) => {
+                let (lhs, rhs) = *boxed;
+                let lpos = lhs.pos();
+                let rpos = rhs.pos();
+                let lval = self.build_expr(lhs)?;
+                let rval = self.build_expr(rhs)?;
+                let res = if matches!(op, BinOp::Equals | BinOp::NotEquals) {
+                    self.build_equality(rpos, op, lval.clone(), rval.clone())?
                 } else {
-                    Err(LocatedCompileError::not_yet_impl(pos, format!("statement: {:?}", statement)))
+                    self.build_binop(cond_pos, op, lpos, lval.clone(), rpos, rval.clone())?
+                };
+                let ok = self.expect_bool(cond_pos, res)?;
+                (ok, vec![("left", lval), ("right", rval)])
+            },
+            cond => (self.build_cond(Located::new(cond, cond_pos))?, Vec::new()),
+        };
+
+        let msg = match message {
+            Some(message) => format!("assertion failed: `{}`: {}", text, message),
+            None => format!("assertion failed: `{}`", text),
+        };
+        // Only the message makes it to a `catch` block
+        if !self.handlers.is_empty() {
+            return self.build_check(ok, pos, &msg);
+        }
+
+        let ok_bb = self.ctx.append_basic_block(self.get_fp()?, "ok");
+        let fail_bb = self.ctx.append_basic_block(self.get_fp()?, "fail");
+        self.builder.build_conditional_branch(ok, ok_bb, fail_bb);
+
+        self.set_and_move_block(fail_bb)?;
+        let raise = self.builtins.raise();
+        self.move_to_end()?;
+        let short_msg = self.builder.build_global_string_ptr(&msg, ".error_msg").as_pointer_value();
+        self.builder.build_call(raise, &[short_msg.into()], "call");
+        self.build_print_str(format!("\nruntime error: {}: {}\n", pos, msg))?;
+        for (side, value) in sides {
+            if !matches!(value, Value::Range(_) | Value::Closure(..)) {
+                self.build_print_str(format!("  {}: ", side))?;
+                self.print_value(pos, value, true)?;
+            }
+        }
+        let exit = self.builtins.exit();
+        self.move_to_end()?;
+        self.builder.build_call(exit, &[self.ctx.i32_type().const_int(1, false).into()], "call");
+        self.builder.build_unreachable();
+
+        self.set_and_move_block(ok_bb)
+    }
+
+    /// Runs `body`, but if a runtime check fails inside it, runs `handler` instead of aborting, with
+    /// the error message bound to `id`. Checks written inside `body` jump straight to `handler`.
+    /// Functions called from `body` are compiled separately, so for them, the `try` sets up a
+    /// `jmp_buf` that `raise` jumps back to, which also puts back the call depth.
+    fn build_try(&mut self, body: Vec<Located<Statement>>, id: Option<Located<String>>, handler: Vec<Located<Statement>>)
+            -> Result<(), LocatedCompileError> {
+        let setjmp = self.builtins.setjmp();
+        self.move_to_end()?;
+        let msg_ptr = self.build_entry_alloca(self.llvm_type(&Type::Str), "error_msg")?;
+        // Big enough for any platform's `jmp_buf`
+        let jmp_buf = self.build_entry_alloca(self.ctx.i64_type().array_type(64), "jmp_buf")?;
+        let jmp_buf = self.builder.build_pointer_cast(jmp_buf, self.ctx.i8_type().ptr_type(AddressSpace::Generic), "jmp_buf");
+        let catch_bb = self.ctx.append_basic_block(self.get_fp()?, "catch");
+        let raised_bb = self.ctx.append_basic_block(self.get_fp()?, "raised");
+        let body_bb = self.ctx.append_basic_block(self.get_fp()?, "body");
+        let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "cont");
+
+        let handler_ptr = self.builtins.handler();
+        let prev = self.builder.build_load(handler_ptr, "prev_handler").into_pointer_value();
+        let depth = self.builder.build_load(self.depth_ptr(), "depth");
+        let jumped = self.builder.build_call(setjmp, &[jmp_buf.into()], "jumped")
+            .try_as_basic_value().left()
+                .ok_or_else(|| InternalError::invalid_state("failed to interpret return value of setjmp"))?
+            .into_int_value();
+        let raised = self.builder.build_int_compare(IntPredicate::NE, jumped, self.ctx.i32_type().const_zero(), "raised");
+        self.builder.build_conditional_branch(raised, raised_bb, body_bb);
+
+        self.set_and_move_block(raised_bb)?;
+        self.builder.build_store(self.depth_ptr(), depth);
+        let msg = self.builder.build_load(self.builtins.error(), "error_msg");
+        self.builder.build_store(msg_ptr, msg);
+        self.builder.build_unconditional_branch(catch_bb);
+
+        self.set_and_move_block(body_bb)?;
+        self.builder.build_store(handler_ptr, jmp_buf);
+        self.handlers.push(Handler { catch_bb, msg_ptr, prev, loops: self.loops.len() });
+        self.build_block(body)?;
+        self.handlers.pop();
+        self.builder.build_store(handler_ptr, prev);
+        self.builder.build_unconditional_branch(cont_bb);
+
+        self.set_and_move_block(catch_bb)?;
+        self.builder.build_store(handler_ptr, prev);
+        let saved_vars = self.sym.var_map.clone();
+        if let Some(id) = id {
+            self.sym.add_var(id, Variable { ptr: msg_ptr, ty: Type::Str, mutable: false });
+        }
+        self.build_block(handler)?;
+        self.sym.var_map = saved_vars;
+        self.builder.build_unconditional_branch(cont_bb);
+
+        self.set_and_move_block(cont_bb)
+    }
+
+    /// Before jumping out of the loop `target` (or out of the function, if it's `None`), puts back the
+    /// handler that was running outside any `try` that the jump leaves.
+    fn build_leave_try(&mut self, target: Option<usize>) {
+        let left = self.handlers.iter().find(|handler| target.map_or(true, |target| handler.loops > target));
+        if let Some(handler) = left {
+            self.builder.build_store(self.builtins.handler(), handler.prev);
+        }
+    }
+
+    /// Finds the loop that `break` or `continue` refers to: the one with the given label, or else the
+    /// innermost one.
+    fn find_loop(&self, pos: Location, keyword: &str, label: Option<Located<String>>) -> Result<usize, LocatedCompileError> {
+        match label {
+            Some(label) => self.loops.iter().rposition(|target| target.label.as_ref() == Some(label.borrow_val()))
+                .ok_or_else(|| LocatedCompileError::unknown_label(label)),
+            None => self.loops.len().checked_sub(1)
+                .ok_or_else(|| LocatedCompileError::not_inside_loop(Located::new(keyword.to_owned(), pos))),
+        }
+    }
+
+    /// Jumps out of the loop. If it's a `loop` expression, the value (or unit, if there isn't one)
+    /// is taken note of so that the loop can produce it.
+    fn build_break(&mut self, pos: Location, label: Option<Located<String>>, value: Option<Located<Expr>>) -> Result<(), LocatedCompileError> {
+        let target = self.find_loop(pos, "break", label)?;
+        let break_bb = self.loops[target].break_bb;
+        if self.loops[target].values.is_some() {
+            let value_pos = value.as_ref().map_or(pos, |expr| expr.pos());
+            let value = match value {
+                Some(expr) => self.build_expr(expr)?,
+                None => Value::Tuple(Vec::new()),
+            };
+            let ty = value.ty();
+            let packed = self.pack(&value)?;
+            let block = self.get_block()?;
+            let values = self.loops[target].values.as_mut()
+                .ok_or_else(|| InternalError::invalid_state("lost track of the enclosing loop"))?;
+            if let Some((expected, _, _)) = values.first() {
+                if *expected != ty {
+                    return Err(LocatedCompileError::type_mismatch(value_pos, expected, &ty));
                 }
+            }
+            values.push((ty, packed, block));
+        } else if let Some(expr) = value {
+            return Err(LocatedCompileError::unsupported(expr.pos(), "only `loop` can break with a value".to_owned()));
+        }
+        self.build_leave_try(Some(target));
+        self.build_jump(break_bb)
+    }
+
+    /// Records and enums can overload operators by defining a function such as `fn __add(a, b)`,
+    /// which is used whenever an operand is a record or an enum.
+    fn overload(&self, pos: Location, name: Option<&str>, operands: &[&Value<'ctx>]) -> Result<Option<Rc<Func>>, LocatedCompileError> {
+        let user_type = operands.iter().any(|value| matches!(value, Value::Struct(..) | Value::Enum(..)));
+        match name.and_then(|name| self.sym.func(name)) {
+            Some(func) if user_type => if func.params.len() == operands.len() {
+                Ok(Some(func.clone()))
+            } else {
+                Err(LocatedCompileError::arity(pos, func.name.borrow_val(), func.params.len(), operands.len()))
             },
-            Statement::Continue => {
-                if !self.inside_loop {
-                    Err(LocatedCompileError::not_inside_loop(Located::new("continue".to_owned(), pos)))
-                } else {
-                    Err(LocatedCompileError::not_yet_impl(pos, format!("statement: {:?}", statement)))
+            _ => Ok(None),
+        }
+    }
+
+    fn build_user_call(&mut self, pos: Location, func: Rc<Func>, args: Vec<Located<Expr>>, named: Vec<(Located<String>, Located<Expr>)>)
+            -> Result<Value<'ctx>, LocatedCompileError> {
+        if func.is_generator() {
+            return Err(LocatedCompileError::unsupported(pos,
+                format!("`{}` is a generator, so it can only be iterated by `for`", func.name.borrow_val())));
+        }
+        let values = self.build_user_args(pos, &func, args, named)?;
+        self.call_func(&func, values)
+    }
+
+    /// Arguments are evaluated in the order they're written, and then any missing ones are filled in
+    /// from the defaults.
+    fn build_user_args(&mut self, pos: Location, func: &Func, mut args: Vec<Located<Expr>>, named: Vec<(Located<String>, Located<Expr>)>)
+            -> Result<Vec<Value<'ctx>>, LocatedCompileError> {
+        let required = func.required_params();
+        let too_few = named.is_empty() && args.len() < required;
+        if func.variadic {
+            if too_few {
+                return Err(LocatedCompileError::arity_at_least(pos, func.name.borrow_val(), required, args.len()));
+            }
+            // The extra arguments become a list literal, which checks that they have the same type
+            let fixed = func.params.len() - 1;
+            if args.len() > fixed {
+                let rest = args.split_off(fixed);
+                let rest_pos = rest[0].pos().span_to(rest[rest.len() - 1].pos());
+                args.push(Located::new(Expr::List(rest), rest_pos));
+            }
+        } else if too_few || args.len() > func.params.len() {
+            return Err(if required == func.params.len() {
+                LocatedCompileError::arity(pos, func.name.borrow_val(), required, args.len())
+            } else {
+                LocatedCompileError::arity_between(pos, func.name.borrow_val(), required, func.params.len(), args.len())
+            });
+        }
+
+        // Named arguments come after the positional ones, so this is the order they're written in
+        let mut indices = (0..args.len()).collect::<Vec<_>>();
+        for (name, _) in &named {
+            let index = func.params.iter().position(|param| param.borrow_val() == name.borrow_val())
+                .ok_or_else(|| LocatedCompileError::no_param(name.clone(), func.name.borrow_val()))?;
+            if indices.contains(&index) {
+                return Err(LocatedCompileError::duplicate_arg(name.clone(), func.name.borrow_val()));
+            }
+            indices.push(index);
+        }
+        for (k, (param, default)) in func.params.iter().zip(&func.defaults).enumerate() {
+            if default.is_none() && !indices.contains(&k) {
+                return Err(LocatedCompileError::missing_arg(pos, func.name.borrow_val(), param));
+            }
+        }
+
+        let mut values = vec![None; func.params.len()];
+        for (index, arg) in indices.into_iter().zip(args.into_iter().chain(named.into_iter().map(|(_, arg)| arg))) {
+            values[index] = Some(self.build_expr(annotate(arg, func.annotations[index].clone()))?);
+        }
+
+        // Functions are defined at the top level, so defaults can't see the caller's variables
+        let mut args = Vec::new();
+        for ((value, default), annotation) in values.into_iter().zip(func.defaults.iter().cloned()).zip(&func.annotations) {
+            match (value, default) {
+                (Some(value), _) => args.push(value),
+                (None, Some(default)) => {
+                    let saved_file = std::mem::replace(&mut self.sym.file, default.pos().file);
+                    let saved_vars = std::mem::take(&mut self.sym.var_map);
+                    let value = self.build_expr(annotate(default, annotation.clone()));
+                    self.sym.var_map = saved_vars;
+                    self.sym.file = saved_file;
+                    args.push(value?);
+                },
+                (None, None) => return Err(InternalError::invalid_state("missing argument after checking")),
+            }
+        }
+        Ok(args)
+    }
+
+    /// Captures the variables used by the body. They're copied, so later assignments to them don't
+    /// affect the closure.
+    fn build_lambda(&mut self, pos: Location, params: Vec<Located<String>>, body: Located<Expr>) -> Result<Value<'ctx>, LocatedCompileError> {
+        let mut ids = Vec::new();
+        body.borrow_val().collect_ids(&mut ids);
+        let mut env = Vec::new();
+        for id in ids {
+            let captured = self.sym.var(&id).is_some()
+                && !params.iter().any(|param| *param.borrow_val() == id)
+                && !env.iter().any(|(name, _)| *name == id);
+            if captured {
+                let value = self.build_expr(Located::new(Expr::Id(Located::new(id.clone(), pos)), pos))?;
+                env.push((id, value));
+            }
+        }
+
+        if !self.sym.lambda_map.contains_key(&pos) {
+            let mut all_params = env.iter().map(|(name, _)| Located::new(name.clone(), pos)).collect::<Vec<_>>();
+            all_params.extend(params);
+            let func = Func {
+                name: Located::new(format!("fn at {}", pos), pos),
+                doc: None,
+                memo: false,
+                annotations: vec![None; all_params.len()],
+                defaults: vec![None; all_params.len()],
+                variadic: false,
+                returns: None,
+                params: all_params,
+                body: Vec::new(),
+                result: Some(body),
+            };
+            self.sym.lambda_map.insert(pos, Rc::new(func));
+        }
+        Ok(Value::Closure(pos, env))
+    }
+
+    /// A named function used as a value is a closure that captured nothing. It's filed under where the
+    /// function was declared, so every mention of it has the same type.
+    fn build_func_value(&mut self, func: Rc<Func>) -> Value<'ctx> {
+        let pos = func.name.pos();
+        self.sym.lambda_map.entry(pos).or_insert(func);
+        Value::Closure(pos, Vec::new())
+    }
+
+    fn build_closure_call(&mut self, pos: Location, id: Located<String>, callee: Value<'ctx>, args: Vec<Located<Expr>>)
+            -> Result<Value<'ctx>, LocatedCompileError> {
+        match callee {
+            Value::Closure(closure_pos, env) => {
+                let func = self.sym.lambda_map.get(&closure_pos).cloned()
+                    .ok_or_else(|| InternalError::invalid_state("unknown closure"))?;
+                // Named functions still get their defaults and rest parameters. Any arguments `partial`
+                // bound come first, so the parameters after them are filled in as though on their own
+                let named = self.sym.func(func.name.borrow_val()).map_or(false, |named| Rc::ptr_eq(named, &func));
+                if named {
+                    let bound = env.len();
+                    let rest = Func {
+                        name: func.name.clone(),
+                        doc: None,
+                        memo: false,
+                        params: func.params[bound..].to_vec(),
+                        annotations: func.annotations[bound..].to_vec(),
+                        defaults: func.defaults[bound..].to_vec(),
+                        variadic: func.variadic,
+                        returns: None,
+                        body: Vec::new(),
+                        result: None,
+                    };
+                    let mut values = env.into_iter().map(|(_, value)| value).collect::<Vec<_>>();
+                    values.extend(self.build_user_args(pos, &rest, args, Vec::new())?);
+                    return self.call_func(&func, values);
+                }
+                let expected = func.params.len() - env.len();
+                if args.len() != expected {
+                    return Err(LocatedCompileError::arity(pos, id.borrow_val(), expected, args.len()));
+                }
+                let mut values = env.into_iter().map(|(_, value)| value).collect::<Vec<_>>();
+                for arg in args {
+                    values.push(self.build_expr(arg)?);
+                }
+                self.call_func(&func, values)
+            },
+            // Polynomials can be evaluated like functions
+            Value::Poly(p) => {
+                if args.len() != 1 {
+                    return Err(LocatedCompileError::arity(pos, id.borrow_val(), 1, args.len()));
                 }
+                let arg = args.into_iter().next()
+                    .ok_or_else(|| InternalError::invalid_state("missing argument"))?;
+                let arg_pos = arg.pos();
+                let z = self.build_expr(arg)?;
+                let z = self.expect_complex(arg_pos, z)?;
+                self.build_poly_eval(p, z).map(Value::Complex)
             },
+            callee => Err(LocatedCompileError::unsupported(id.pos(), format!("`{}` is not a function", callee.ty()))),
         }
     }
 
-    fn build_func(&mut self, func: Func) -> Result<(), LocatedCompileError> {
-        let fn_type = self.ctx.void_type().fn_type(&[], false);
-        let fp = self.module.add_function(&func.name, fn_type, None);
-        self.sym.add_func(func.name, fp);
+    /// The function a closure stands for, and the captured values to pass before `args` more
+    /// arguments. Builtins use this to call function values they were given.
+    fn closure_func(&self, pos: Location, callee: Value<'ctx>, args: usize) -> Result<(Rc<Func>, Vec<Value<'ctx>>), LocatedCompileError> {
+        match callee {
+            Value::Closure(closure_pos, env) => {
+                let func = self.sym.lambda_map.get(&closure_pos).cloned()
+                    .ok_or_else(|| InternalError::invalid_state("unknown closure"))?;
+                let expected = func.params.len() - env.len();
+                if args != expected {
+                    return Err(LocatedCompileError::arity(pos, func.name.borrow_val(), expected, args));
+                }
+                Ok((func, env.into_iter().map(|(_, value)| value).collect()))
+            },
+            callee => Err(LocatedCompileError::unsupported(pos, format!("`{}` is not a function", callee.ty()))),
+        }
+    }
+
+    fn call_func(&mut self, func: &Rc<Func>, values: Vec<Value<'ctx>>) -> Result<Value<'ctx>, LocatedCompileError> {
+        let instance = self.instance(func, values.iter().map(Value::ty).collect())?;
+        let mut packed = Vec::new();
+        for value in &values {
+            packed.push(self.pack(value)?);
+        }
+        let res = self.build_instance_call(&instance, &packed, "call")
+            .try_as_basic_value().left()
+                .ok_or_else(|| InternalError::invalid_state("failed to interpret return value of function"))?;
+        self.unpack(res, &instance.ret)
+    }
+
+    /// Calls a compiled function, which has to use the same calling convention as its definition.
+    fn build_instance_call(&self, instance: &Instance<'ctx>, args: &[BasicValueEnum<'ctx>], name: &str) -> CallSiteValue<'ctx> {
+        let call = self.builder.build_call(instance.fp, args, name);
+        call.set_call_convention(TAIL_CC);
+        call
+    }
+
+    /// Finds the version of `func` for the given argument types, compiling it if this is the first
+    /// time it's been called with them.
+    fn instance(&mut self, func: &Rc<Func>, types: Vec<Type>) -> Result<Instance<'ctx>, LocatedCompileError> {
+        let key = (func.name.borrow_val().clone(), types);
+        if let Some(instance) = self.sym.instances.get_mut(&key) {
+            instance.recursive |= instance.compiling;
+            return Ok(instance.clone());
+        }
+
+        // Recursive calls happen before we know the result type, so make a guess. If it's wrong,
+        // then the function can be recompiled, unless something already relied on the guess. Mutually
+        // recursive functions rely on each other's guesses, so it helps if they're good ones.
+        // An annotation on the result is the best guess of all
+        let annotated = func.returns.as_ref().and_then(|annotation| annotation.borrow_val().known_type());
+        let guess = annotated.unwrap_or_else(|| match &func.result {
+            Some(result) => obvious_type(result.borrow_val()).unwrap_or(Type::Complex),
+            None if func.returns_value() => Type::Complex,
+            None => Type::Tuple(Vec::new()),
+        });
+        let (fp, found) = self.build_instance(func, &key, guess.clone())?;
+        if *found.borrow_val() != guess {
+            let recursive = self.sym.instances.get(&key).map_or(false, |instance| instance.recursive);
+            if recursive {
+                return Err(LocatedCompileError::recursive_result(found.pos(), func.name.borrow_val(), &guess, found.borrow_val()));
+            }
+            // Safety: nothing refers to the old version, since it wasn't called recursively
+            unsafe { fp.delete(); }
+            let ret = found.borrow_val().clone();
+            let (_, found) = self.build_instance(func, &key, ret.clone())?;
+            // Different results disagree with each other
+            if *found.borrow_val() != ret {
+                return Err(LocatedCompileError::type_mismatch(found.pos(), &ret, found.borrow_val()));
+            }
+        }
+
+        let instance = self.sym.instances.get_mut(&key)
+            .ok_or_else(|| InternalError::invalid_state("instance vanished"))?;
+        instance.compiling = false;
+        Ok(instance.clone())
+    }
+
+    /// Compiles `func` for the given argument types, declaring that it returns `ret`. If the function
+    /// (or one of its `return` statements) produces something other than `ret`, returns the first
+    /// such type found; otherwise returns `ret`.
+    fn build_instance(&mut self, func: &Func, key: &(String, Vec<Type>), ret: Type)
+            -> Result<(FunctionValue<'ctx>, Located<Type>), LocatedCompileError> {
+        let (name, types) = key;
+        let param_types = types.iter().map(|ty| self.llvm_type(ty)).collect::<Vec<_>>();
+        let fn_type = self.llvm_type(&ret).fn_type(&param_types, false);
+        let type_names = types.iter().map(Type::to_string).collect::<Vec<_>>();
+        let fp_name = format!("{}({})", name, type_names.join(", "));
+        let fp = self.module.add_function(&fp_name, fn_type, None);
+        fp.set_call_conventions(TAIL_CC);
+        self.sym.instances.insert(key.clone(), Instance { fp, ret: ret.clone(), compiling: true, recursive: false });
+
+        // Functions can't see their caller's variables, so start from a clean slate
+        let saved_fp = self.current_fp;
+        let saved_block = self.current_block;
+        let saved_file = std::mem::replace(&mut self.sym.file, func.name.pos().file);
+        let saved_vars = std::mem::take(&mut self.sym.var_map);
+        let saved_loops = std::mem::take(&mut self.loops);
+        let saved_generators = std::mem::take(&mut self.generators);
+        let saved_moduli = std::mem::take(&mut self.moduli);
+        let saved_handlers = std::mem::take(&mut self.handlers);
+        let saved_returns = self.returns.replace(Returns {
+            ret: ret.clone(),
+            found: None,
+            key: key.clone(),
+            start: None,
+            annotation: func.returns.clone(),
+            memo: None,
+        });
 
         let block = self.ctx.append_basic_block(fp, "entry");
         self.current_fp = Some(fp);
         self.set_and_move_block(block)?;
-        
-        for statement in func.body {
+        self.build_enter(func.name.pos())?;
+        let mut params = Vec::new();
+        let mut args = Vec::new();
+        for ((param, ty), value) in func.params.iter().zip(types).zip(fp.get_param_iter()) {
+            let ptr = self.builder.build_alloca(self.llvm_type(ty), param.borrow_val());
+            self.builder.build_store(ptr, value);
+            self.sym.add_var(param.clone(), Variable { ptr, ty: ty.clone(), mutable: false });
+            params.push(ptr);
+            args.push(self.unpack(value, ty)?);
+        }
+        // Every result has to be remembered, so tail calls can't skip returning
+        if func.memo {
+            let memo = self.build_memo_lookup(func.name.pos(), &fp_name, args, &ret)?;
+            if let Some(returns) = self.returns.as_mut() {
+                returns.memo = Some(memo);
+            }
+        }
+        let start_bb = self.ctx.append_basic_block(fp, "start");
+        self.builder.build_unconditional_branch(start_bb);
+        self.set_and_move_block(start_bb)?;
+        let stacksave = self.builtins.stacksave();
+        let stack = self.builder.build_call(stacksave, &[], "stack")
+            .try_as_basic_value().left()
+                .ok_or_else(|| InternalError::invalid_state("failed to interpret return value of stacksave"))?
+            .into_pointer_value();
+        if let Some(returns) = self.returns.as_mut().filter(|returns| returns.memo.is_none()) {
+            returns.start = Some(TailStart { block: start_bb, params, stack });
+        }
+        for statement in func.body.clone() {
             self.build_statement(statement)?;
         }
+        match func.result.clone() {
+            Some(expr) => self.build_return(expr.pos(), Some(expr))?,
+            None if ret == Type::Tuple(Vec::new()) => self.build_return(func.name.pos(), None)?,
+            // The function returns values with `return`, so it mustn't fall off the end
+            None => {
+                let msg = format!("`{}` finished without returning a value", func.name.borrow_val());
+                self.build_check(self.ctx.bool_type().const_zero(), func.name.pos(), &msg)?;
+            },
+        }
+        // Nothing can reach the block we're left in
+        self.builder.build_unreachable();
+        let found = self.returns.take()
+            .ok_or_else(|| InternalError::invalid_state("no active function"))?
+            .found
+            .unwrap_or_else(|| Located::new(ret, func.name.pos()));
+
+        self.current_fp = saved_fp;
+        self.current_block = saved_block;
+        self.sym.file = saved_file;
+        self.sym.var_map = saved_vars;
+        self.loops = saved_loops;
+        self.generators = saved_generators;
+        self.moduli = saved_moduli;
+        self.handlers = saved_handlers;
+        self.returns = saved_returns;
+        if let Some(block) = saved_block {
+            self.builder.position_at_end(block);
+        }
+        Ok((fp, found))
+    }
+
+    /// Returns from the current function, with unit if there's no expression. Inside `try`, the
+    /// handler has to be put back after the value is worked out, so that can't be a tail call.
+    fn build_return(&mut self, pos: Location, expr: Option<Located<Expr>>) -> Result<(), LocatedCompileError> {
+        if !self.handlers.is_empty() {
+            let value = match expr {
+                Some(expr) => self.build_expr(expr)?,
+                None => Value::Tuple(Vec::new()),
+            };
+            self.build_leave_try(None);
+            return self.build_return_value(pos, value);
+        }
+        match expr {
+            Some(expr) => self.build_tail(expr),
+            None => self.build_return_value(pos, Value::Tuple(Vec::new())),
+        }
+    }
+
+    /// Returns the value of `expr`, which is in tail position. The arms of `if`-`then`-`else` and the
+    /// results of blocks are also in tail position, so each returns separately.
+    fn build_tail(&mut self, expr: Located<Expr>) -> Result<(), LocatedCompileError> {
+        let (expr, pos) = expr.unwrap();
+        match expr {
+            Expr::IfElse(boxed) => {
+                let (cond, value_if, value_else) = *boxed;
+                let cond = self.build_cond(cond)?;
+                let then_bb = self.ctx.append_basic_block(self.get_fp()?, "then");
+                let else_bb = self.ctx.append_basic_block(self.get_fp()?, "else");
+                self.builder.build_conditional_branch(cond, then_bb, else_bb);
+                self.set_and_move_block(then_bb)?;
+                self.build_tail(value_if)?;
+                self.set_and_move_block(else_bb)?;
+                self.build_tail(value_else)
+            },
+            Expr::Block(body, result) => {
+                let saved_vars = self.sym.var_map.clone();
+                for statement in body {
+                    self.build_statement(statement)?;
+                }
+                let res = self.build_tail(*result);
+                self.sym.var_map = saved_vars;
+                res
+            },
+            Expr::Call(id, args, named) if self.sym.var(id.borrow_val()).is_none() => match self.sym.func(id.borrow_val()).cloned() {
+                Some(func) => self.build_tail_call(pos, func, args, named),
+                None => {
+                    let value = self.build_expr(Located::new(Expr::Call(id, args, named), pos))?;
+                    self.build_return_value(pos, value)
+                },
+            },
+            expr => {
+                let value = self.build_expr(Located::new(expr, pos))?;
+                self.build_return_value(pos, value)
+            },
+        }
+    }
+
+    /// A call to the instance being compiled stores the new arguments and jumps back to the start, so
+    /// it doesn't use any more stack. Other calls release their depth before calling, and since every
+    /// instance uses `tailcc`, marking them as tail calls means the callee replaces our frame.
+    fn build_tail_call(&mut self, pos: Location, func: Rc<Func>, args: Vec<Located<Expr>>, named: Vec<(Located<String>, Located<Expr>)>)
+            -> Result<(), LocatedCompileError> {
+        let values = self.build_user_args(pos, &func, args, named)?;
+        let returns = self.returns.clone()
+            .ok_or_else(|| InternalError::invalid_state("no active function"))?;
+        let key = (func.name.borrow_val().clone(), values.iter().map(Value::ty).collect::<Vec<_>>());
+        let mut packed = Vec::new();
+        for value in &values {
+            packed.push(self.pack(value)?);
+        }
+
+        if let (true, Some(start)) = (key == returns.key, returns.start) {
+            let stackrestore = self.builtins.stackrestore();
+            self.builder.build_call(stackrestore, &[start.stack.into()], "call");
+            for (ptr, value) in start.params.into_iter().zip(packed) {
+                self.builder.build_store(ptr, value);
+            }
+            self.builder.build_unconditional_branch(start.block);
+        } else {
+            let instance = self.instance(&func, key.1)?;
+            // The result has to be checked against our annotation or remembered, so it can't be passed
+            // straight through
+            if instance.ret != returns.ret || returns.annotation.is_some() || returns.memo.is_some() {
+                let res = self.build_instance_call(&instance, &packed, "call")
+                    .try_as_basic_value().left()
+                        .ok_or_else(|| InternalError::invalid_state("failed to interpret return value of function"))?;
+                let value = self.unpack(res, &instance.ret)?;
+                return self.build_return_value(pos, value);
+            }
+            self.build_leave()?;
+            let call = self.build_instance_call(&instance, &packed, "tail_call");
+            call.set_tail_call(true);
+            let res = call.try_as_basic_value().left()
+                .ok_or_else(|| InternalError::invalid_state("failed to interpret return value of function"))?;
+            self.builder.build_return(Some(&res));
+        }
+        let dead_bb = self.ctx.append_basic_block(self.get_fp()?, "dead");
+        self.set_and_move_block(dead_bb)
+    }
+
+    /// A value of the wrong type is noted so that the function can be recompiled.
+    fn build_return_value(&mut self, pos: Location, value: Value<'ctx>) -> Result<(), LocatedCompileError> {
+        let annotation = self.returns.as_ref().and_then(|returns| returns.annotation.clone());
+        if let Some(annotation) = annotation {
+            self.build_annotation_check(pos, &value, annotation.borrow_val())?;
+        }
+        let returns = self.returns.as_mut()
+            .ok_or_else(|| InternalError::invalid_state("no active function"))?;
+        if value.ty() == returns.ret {
+            if let Some(memo) = returns.memo.clone() {
+                self.build_memo_store(&memo, &value)?;
+            }
+            let value = self.pack(&value)?;
+            self.build_leave()?;
+            self.builder.build_return(Some(&value));
+        } else {
+            // The caller will throw this version away
+            if returns.found.is_none() {
+                returns.found = Some(Located::new(value.ty(), pos));
+            }
+            self.builder.build_unreachable();
+        }
+        let dead_bb = self.ctx.append_basic_block(self.get_fp()?, "dead");
+        self.set_and_move_block(dead_bb)
+    }
+
+    /// The current call depth, shared by all functions.
+    fn depth_ptr(&self) -> PointerValue<'ctx> {
+        let global = self.module.get_global(".depth").unwrap_or_else(|| {
+            let global = self.module.add_global(self.ctx.i64_type(), None, ".depth");
+            global.set_initializer(&self.ctx.i64_type().const_zero());
+            global
+        });
+        global.as_pointer_value()
+    }
+
+    /// Called on entry to a function. It's a runtime error to go deeper than `max_depth`, which is
+    /// nicer than overflowing the stack.
+    fn build_enter(&mut self, pos: Location) -> Result<(), LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let ptr = self.depth_ptr();
+        let depth = self.builder.build_load(ptr, "depth").into_int_value();
+        let depth = self.builder.build_int_add(depth, t_i64.const_int(1, false), "depth");
+        self.builder.build_store(ptr, depth);
+        let ok = self.builder.build_int_compare(IntPredicate::ULE, depth, t_i64.const_int(self.config.max_depth, false), "depth_ok");
+        self.build_check(ok, pos, "maximum recursion depth exceeded")
+    }
+
+    /// Called before returning from a function.
+    fn build_leave(&mut self) -> Result<(), LocatedCompileError> {
+        let ptr = self.depth_ptr();
+        let depth = self.builder.build_load(ptr, "depth").into_int_value();
+        let depth = self.builder.build_int_sub(depth, self.ctx.i64_type().const_int(1, false), "depth");
+        self.builder.build_store(ptr, depth);
+        Ok(())
+    }
+
+    /// Compiles the program, starting from `main`. The other functions are compiled as they're used.
+    fn build_program(&mut self, items: Vec<Item>) -> Result<(), LocatedCompileError> {
+        let mut globals = Vec::new();
+        for item in items {
+            match item {
+                Item::Func(func) => self.sym.add_func(func)?,
+                Item::Struct(def) => self.sym.add_struct(def)?,
+                Item::Enum(def) => self.sym.add_enum(def)?,
+                Item::Global(global) => globals.push(global),
+                // The driver replaces these with the contents of the imported file
+                Item::Import(path) => return Err(LocatedCompileError::unsupported(path.pos(), "unresolved import".to_owned())),
+            }
+        }
+        let main = self.sym.func("main").cloned()
+            .ok_or_else(LocatedCompileError::no_main)?;
+        if let Some(param) = main.params.first() {
+            return Err(LocatedCompileError::unsupported(param.pos(), "`main` cannot take parameters".to_owned()));
+        }
+
+        // The entry point sets up the globals in order, then calls `main` and ignores its result
+        let fn_type = self.ctx.void_type().fn_type(&[], false);
+        let fp = self.module.add_function("main", fn_type, None);
+        let block = self.ctx.append_basic_block(fp, "entry");
+        self.current_fp = Some(fp);
+        self.set_and_move_block(block)?;
+        for global in globals {
+            self.build_global(global)?;
+        }
+        self.sym.file = 0;
+        let instance = self.instance(&main, Vec::new())?;
+        self.build_instance_call(&instance, &[], "call");
         self.builder.build_return(None);
+        Ok(())
+    }
 
+    /// Evaluates a top-level variable and stores it in a global. Later top-level variables can refer
+    /// to it directly.
+    fn build_global(&mut self, global: Global) -> Result<(), LocatedCompileError> {
+        let Global { name, value, mutable } = global;
+        self.sym.file = name.pos().file;
+        let value = self.build_expr(value)?;
+        let value = if mutable { self.build_unshare(value)? } else { value };
+        let ty = value.ty();
+        let llvm_ty = self.llvm_type(&ty);
+        let ptr = self.module.add_global(llvm_ty, None, &format!(".global.{}", name.borrow_val()));
+        ptr.set_initializer(&llvm_ty.const_zero());
+        let ptr = ptr.as_pointer_value();
+        let packed = self.pack(&value)?;
+        self.builder.build_store(ptr, packed);
+
+        let var = Variable { ptr, ty, mutable };
+        self.sym.add_global(name.clone(), var.clone())?;
+        self.sym.add_var(name, var);
+        Ok(())
+    }
+
+    /// Compiles each function whose parameters are all annotated, even if nothing calls it, so that
+    /// its mistakes can be found without running the program. Functions are otherwise only compiled
+    /// once we know what they're called with.
+    fn build_annotated(&mut self, names: &[String]) -> Result<(), LocatedCompileError> {
+        for name in names {
+            let func = match self.sym.func(name).cloned() {
+                Some(func) => func,
+                None => continue,
+            };
+            let types = func.annotations.iter()
+                .map(|annotation| annotation.as_ref().and_then(|annotation| annotation.borrow_val().known_type()))
+                .collect::<Option<Vec<_>>>();
+            if let Some(types) = types {
+                self.instance(&func, types)?;
+            }
+        }
         Ok(())
     }
 
@@ -585,11 +3141,34 @@ impl<'ctx> Compiler<'ctx> {
     }
 }
 
-pub fn run<P: AsRef<Path>>(dest: P, func: Func) -> Result<(), LocatedCompileError> {
+/// Compiles and runs the program. `namespaces` has the namespace of each file the items came from,
+/// whose names the driver has already qualified with it.
+pub fn run<P: AsRef<Path>>(dest: P, items: Vec<Item>, namespaces: Vec<Option<String>>) -> Result<(), LocatedCompileError> {
     let ctx = Context::create();
     let mut gen = Compiler::new(Config::default(), &ctx);
-    gen.build_func(func)?;
+    gen.sym.namespaces = namespaces;
+    gen.build_program(items)?;
     gen.print_to_file(dest)?;
     eprintln!("Executing program...\n---");
     gen.exec()
 }
+
+/// Compiles the program without running it, which finds every type error in the code that `main`
+/// can reach. With `all_annotated`, functions with fully annotated parameters are checked too.
+pub fn check(items: Vec<Item>, namespaces: Vec<Option<String>>, all_annotated: bool) -> Result<(), LocatedCompileError> {
+    let names = items.iter()
+        .filter_map(|item| match item {
+            Item::Func(func) => Some(func.name.borrow_val().clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let ctx = Context::create();
+    let mut gen = Compiler::new(Config::default(), &ctx);
+    gen.sym.namespaces = namespaces;
+    gen.build_program(items)?;
+    if all_annotated {
+        gen.build_annotated(&names)?;
+    }
+    gen.module.verify()?;
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use inkwell::FloatPredicate;
+
+use crate::{analyse::{Location, Value}, error::LocatedCompileError, parse::Annotation};
+
+use super::Compiler;
+
+/// Annotations are checked where the value is produced. Whether the value has the right shape is
+/// known while compiling, but whether a number is an integer or real has to wait until it runs.
+impl<'ctx> Compiler<'ctx> {
+    pub(super) fn build_annotation_check(&mut self, pos: Location, value: &Value<'ctx>, annotation: &Annotation)
+            -> Result<(), LocatedCompileError> {
+        let annotation = &self.resolve_annotation(annotation);
+        if !annotation.admits(&value.ty()) {
+            return Err(LocatedCompileError::type_mismatch(pos, annotation, &value.ty()));
+        }
+        if !annotation.needs_check() {
+            return Ok(());
+        }
+
+        match (annotation, value) {
+            (Annotation::Int, Value::Complex(value)) => {
+                let floor = self.builtins.floor();
+                self.move_to_end()?;
+                let floor = self.call_float(floor, &[value.re.into()], "tmp_floor")?;
+                let whole = self.builder.build_float_compare(FloatPredicate::OEQ, value.re, floor, "tmp_whole");
+                let real = self.builder.build_float_compare(FloatPredicate::OEQ, value.im, self.ctx.f64_type().const_zero(), "tmp_real");
+                let ok = self.builder.build_and(whole, real, "tmp_int");
+                self.build_check(ok, pos, "expected an integer")
+            },
+            (Annotation::Real, Value::Complex(value)) => {
+                let ok = self.builder.build_float_compare(FloatPredicate::OEQ, value.im, self.ctx.f64_type().const_zero(), "tmp_real");
+                self.build_check(ok, pos, "expected a real number")
+            },
+            (Annotation::List(elem_annotation), Value::List(list, elem)) => {
+                let len = self.list_len(*list)?;
+                self.build_counted_loop(len, |this, k| {
+                    let value = this.build_list_nth(*list, elem, k)?;
+                    this.build_annotation_check(pos, &value, elem_annotation)
+                })
+            },
+            (Annotation::Tuple(annotations), Value::Tuple(values)) => {
+                for (annotation, value) in annotations.iter().zip(values) {
+                    self.build_annotation_check(pos, value, annotation)?;
+                }
+                Ok(())
+            },
+            // A missing value is zero, which passes either check
+            (Annotation::Option(annotation), Value::Option(_, value)) => self.build_annotation_check(pos, value, annotation),
+            _ => Ok(()),
+        }
+    }
+
+    /// The names in an annotation are written the way the current file sees them, so `circle` in
+    /// `geometry.ccc` means `geometry.circle`.
+    fn resolve_annotation(&self, annotation: &Annotation) -> Annotation {
+        match annotation {
+            Annotation::Named(name) => {
+                let full = self.sym.struct_def(name).map(|def| def.name.borrow_val().clone())
+                    .or_else(|| self.sym.enum_def(name).map(|def| def.name.borrow_val().clone()))
+                    .unwrap_or_else(|| name.clone());
+                Annotation::Named(full)
+            },
+            Annotation::List(elem) => Annotation::List(Box::new(self.resolve_annotation(elem))),
+            Annotation::Option(elem) => Annotation::Option(Box::new(self.resolve_annotation(elem))),
+            Annotation::Tuple(elems) => Annotation::Tuple(elems.iter().map(|elem| self.resolve_annotation(elem)).collect()),
+            annotation => annotation.clone(),
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use inkwell::{IntPredicate, values::IntValue};
+
+use crate::{analyse::{ComplexValue, Location}, error::LocatedCompileError};
+
+use super::Compiler;
+
+/// Exact `factorial` (and `n!`) and `binomial`. There's no big-integer type to fall back on, so
+/// they're worked out in 128-bit integers, and a result above `2^53` (where doubles stop being exact)
+/// is a runtime error rather than being rounded.
+impl<'ctx> Compiler<'ctx> {
+    /// `factorial(n)` or `n!` for a non-negative integer `n`, which is exact up to `18!`.
+    pub(super) fn build_exact_factorial(&mut self, pos: Location, n: ComplexValue<'ctx>) -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+        let t_i128 = self.ctx.i128_type();
+        let n = self.build_natural(pos, n, "`factorial` needs a non-negative integer")?;
+        let acc = self.builder.build_alloca(t_i128, "acc");
+        self.builder.build_store(acc, t_i128.const_int(1, false));
+        self.build_counted_loop(n, |this, k| {
+            let factor = this.builder.build_int_s_extend(k, t_i128, "factor");
+            let factor = this.builder.build_int_add(factor, t_i128.const_int(1, false), "factor");
+            let prod = this.builder.build_load(acc, "acc").into_int_value();
+            let prod = this.builder.build_int_mul(prod, factor, "prod");
+            this.check_exact(pos, prod, "`factorial` overflowed")?;
+            this.builder.build_store(acc, prod);
+            Ok(())
+        })?;
+        Ok(self.exact_result(self.builder.build_load(acc, "factorial").into_int_value()))
+    }
+
+    /// `binomial(n, k)` for a non-negative integer `n`, which is 0 unless `0 <= k <= n`. It's built up
+    /// as `C(n, 1), C(n, 2), ...` up to `C(n, min(k, n - k))`, each dividing exactly, and those only
+    /// get bigger, so none of them overflow unless the result does.
+    pub(super) fn build_binomial(&mut self, pos: Location, n: ComplexValue<'ctx>, k: ComplexValue<'ctx>) -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+        let t_i128 = self.ctx.i128_type();
+        let n = self.build_natural(pos, n, "`binomial` needs a non-negative integer")?;
+        let k = self.build_real_integer(pos, k, "`binomial` needs integers")?;
+        let zero = self.ctx.i64_type().const_zero();
+        let non_negative = self.builder.build_int_compare(IntPredicate::SGE, k, zero, "non_negative");
+        let at_most_n = self.builder.build_int_compare(IntPredicate::SLE, k, n, "at_most_n");
+        let in_range = self.builder.build_and(non_negative, at_most_n, "in_range");
+        let rest = self.builder.build_int_sub(n, k, "rest");
+        let smaller = self.builder.build_int_compare(IntPredicate::SLT, rest, k, "smaller");
+        let steps = self.builder.build_select(smaller, rest, k, "steps").into_int_value();
+        let steps = self.builder.build_select(in_range, steps, zero, "steps").into_int_value();
+
+        let acc = self.builder.build_alloca(t_i128, "acc");
+        self.builder.build_store(acc, t_i128.const_int(1, false));
+        let wide_n = self.builder.build_int_s_extend(n, t_i128, "n");
+        self.build_counted_loop(steps, |this, i| {
+            let i = this.builder.build_int_s_extend(i, t_i128, "i");
+            let factor = this.builder.build_int_sub(wide_n, i, "factor");
+            let divisor = this.builder.build_int_add(i, t_i128.const_int(1, false), "divisor");
+            let prod = this.builder.build_load(acc, "acc").into_int_value();
+            let prod = this.builder.build_int_mul(prod, factor, "prod");
+            let quot = this.builder.build_int_signed_div(prod, divisor, "quot");
+            this.check_exact(pos, quot, "`binomial` overflowed")?;
+            this.builder.build_store(acc, quot);
+            Ok(())
+        })?;
+        let res = self.builder.build_load(acc, "binomial").into_int_value();
+        let res = self.builder.build_select(in_range, res, t_i128.const_zero(), "binomial").into_int_value();
+        Ok(self.exact_result(res))
+    }
+
+    fn build_natural(&mut self, pos: Location, z: ComplexValue<'ctx>, msg: &str) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let n = self.build_real_integer(pos, z, msg)?;
+        let non_negative = self.builder.build_int_compare(IntPredicate::SGE, n, self.ctx.i64_type().const_zero(), "non_negative");
+        self.build_check(non_negative, pos, msg)?;
+        Ok(n)
+    }
+
+    fn check_exact(&mut self, pos: Location, value: IntValue<'ctx>, msg: &str) -> Result<(), LocatedCompileError> {
+        let limit = self.ctx.i128_type().const_int(1 << 53, false);
+        let exact = self.builder.build_int_compare(IntPredicate::SLE, value, limit, "exact");
+        self.build_check(exact, pos, msg)
+    }
+
+    fn exact_result(&self, value: IntValue<'ctx>) -> ComplexValue<'ctx> {
+        ComplexValue {
+            re: self.builder.build_signed_int_to_float(value, self.ctx.f64_type(), "exact"),
+            im: self.ctx.f64_type().const_zero(),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use inkwell::{FloatPredicate, values::{IntValue, PointerValue}};
+
+use crate::{analyse::{ComplexValue, Location, Type, Value}, error::{InternalError, LocatedCompileError}};
+
+use super::Compiler;
+
+/// Systems of congruences `x = r (mod m)`, solved one at a time by the Chinese remainder theorem. The
+/// division is the same as `divmod`'s, so this works the same way for real integers and for Gaussian
+/// integers, where the moduli needn't be coprime as long as the residues agree mod their gcd.
+impl<'ctx> Compiler<'ctx> {
+    /// `crt([(r1, m1), (r2, m2), ...])`, which gives `(r, m)` such that `x = r (mod m)` exactly when
+    /// `x = rk (mod mk)` for every `k`. The modulus is the canonical associate of the lcm, and `r` is
+    /// reduced by it, so for real integers `r` is in `0..m`. It aborts if there's no such `x`.
+    pub(super) fn build_crt(&mut self, pos: Location, congruences: PointerValue<'ctx>) -> Result<(ComplexValue<'ctx>, ComplexValue<'ctx>), LocatedCompileError> {
+        let t_f64 = self.ctx.f64_type();
+        let zero = t_f64.const_zero();
+        let residue = (self.builder.build_alloca(t_f64, "residue_re"), self.builder.build_alloca(t_f64, "residue_im"));
+        let modulus = (self.builder.build_alloca(t_f64, "modulus_re"), self.builder.build_alloca(t_f64, "modulus_im"));
+        self.store_complex(residue, ComplexValue { re: zero, im: zero });
+        self.store_complex(modulus, ComplexValue { re: t_f64.const_float(1.), im: zero });
+
+        let len = self.list_len(congruences)?;
+        self.build_counted_loop(len, |this, k| {
+            let (r2, m2) = this.congruence_nth(congruences, k)?;
+            this.check_gaussian(pos, r2, m2, "`crt` needs Gaussian integers")?;
+            let nonzero = this.builder.build_float_compare(FloatPredicate::ONE, this.complex_norm(m2), zero, "nonzero");
+            this.build_check(nonzero, pos, "`crt` needs non-zero moduli")?;
+            let (r1, m1) = (this.load_complex(residue), this.load_complex(modulus));
+
+            // With g = s m1 + t m2, x = r1 + m1 s (r2 - r1) / g solves both
+            let (g, s) = this.build_bezout(pos, m1, m2)?;
+            let diff = ComplexValue {
+                re: this.builder.build_float_sub(r2.re, r1.re, "diff_re"),
+                im: this.builder.build_float_sub(r2.im, r1.im, "diff_im"),
+            };
+            let (quot, rem) = this.complex_divmod(pos, diff, g)?;
+            let consistent = this.builder.build_float_compare(FloatPredicate::OEQ, this.complex_norm(rem), zero, "consistent");
+            this.build_check(consistent, pos, "the congruences are inconsistent")?;
+            let (step, _) = this.complex_divmod(pos, m2, g)?;
+            let (_, lift) = this.complex_divmod(pos, this.complex_mul(s, quot), step)?;
+            let lcm = this.canonical_associate(this.complex_mul(m1, step));
+            let x = this.complex_mul(m1, lift);
+            let x = ComplexValue {
+                re: this.builder.build_float_add(r1.re, x.re, "x_re"),
+                im: this.builder.build_float_add(r1.im, x.im, "x_im"),
+            };
+            let (_, x) = this.complex_divmod(pos, x, lcm)?;
+            this.store_complex(residue, x);
+            this.store_complex(modulus, lcm);
+            Ok(())
+        })?;
+        Ok((self.load_complex(residue), self.load_complex(modulus)))
+    }
+
+    /// The extended Euclidean algorithm, which gives `(g, s)` where `g` is a gcd of `a` and `b` and
+    /// `g = s a + t b` for some `t`.
+    fn build_bezout(&mut self, pos: Location, a: ComplexValue<'ctx>, b: ComplexValue<'ctx>) -> Result<(ComplexValue<'ctx>, ComplexValue<'ctx>), LocatedCompileError> {
+        let t_f64 = self.ctx.f64_type();
+        let zero = t_f64.const_zero();
+        let old_r = (self.builder.build_alloca(t_f64, "old_r_re"), self.builder.build_alloca(t_f64, "old_r_im"));
+        let r = (self.builder.build_alloca(t_f64, "r_re"), self.builder.build_alloca(t_f64, "r_im"));
+        let old_s = (self.builder.build_alloca(t_f64, "old_s_re"), self.builder.build_alloca(t_f64, "old_s_im"));
+        let s = (self.builder.build_alloca(t_f64, "s_re"), self.builder.build_alloca(t_f64, "s_im"));
+        self.store_complex(old_r, a);
+        self.store_complex(r, b);
+        self.store_complex(old_s, ComplexValue { re: t_f64.const_float(1.), im: zero });
+        self.store_complex(s, ComplexValue { re: zero, im: zero });
+
+        let test_bb = self.ctx.append_basic_block(self.get_fp()?, "bezout_test");
+        let body_bb = self.ctx.append_basic_block(self.get_fp()?, "bezout_body");
+        let done_bb = self.ctx.append_basic_block(self.get_fp()?, "bezout_done");
+        self.builder.build_unconditional_branch(test_bb);
+
+        self.set_and_move_block(test_bb)?;
+        let more = self.builder.build_float_compare(FloatPredicate::ONE, self.complex_norm(self.load_complex(r)), zero, "more");
+        self.builder.build_conditional_branch(more, body_bb, done_bb);
+
+        // (old_r, r) = (r, old_r - q r), and the same for s
+        self.set_and_move_block(body_bb)?;
+        let (q, rem) = self.complex_divmod(pos, self.load_complex(old_r), self.load_complex(r))?;
+        let (prev_s, next_s) = (self.load_complex(old_s), self.load_complex(s));
+        let product = self.complex_mul(q, next_s);
+        let new_s = ComplexValue {
+            re: self.builder.build_float_sub(prev_s.re, product.re, "new_s_re"),
+            im: self.builder.build_float_sub(prev_s.im, product.im, "new_s_im"),
+        };
+        self.store_complex(old_r, self.load_complex(r));
+        self.store_complex(r, rem);
+        self.store_complex(old_s, next_s);
+        self.store_complex(s, new_s);
+        self.builder.build_unconditional_branch(test_bb);
+
+        self.set_and_move_block(done_bb)?;
+        Ok((self.load_complex(old_r), self.load_complex(old_s)))
+    }
+
+    fn congruence_nth(&mut self, congruences: PointerValue<'ctx>, k: IntValue<'ctx>) -> Result<(ComplexValue<'ctx>, ComplexValue<'ctx>), LocatedCompileError> {
+        match self.build_list_nth(congruences, &Type::Tuple(vec![Type::Complex, Type::Complex]), k)? {
+            Value::Tuple(fields) => match fields.as_slice() {
+                [Value::Complex(r), Value::Complex(m)] => Ok((*r, *m)),
+                _ => Err(InternalError::invalid_state("congruence is not a pair of numbers")),
+            },
+            _ => Err(InternalError::invalid_state("congruence is not a tuple")),
+        }
+    }
+}
@@ -0,0 +1,431 @@
+use inkwell::{AddressSpace, FloatPredicate, IntPredicate, types::StructType, values::{BasicValueEnum, FloatValue, IntValue, PointerValue}};
+
+use crate::{analyse::{Location, Type, Value}, error::{InternalError, LocatedCompileError}, parse::BinOp};
+
+use super::Compiler;
+
+/// Hash tables start with this many slots, and double whenever they'd be more than half full.
+const MIN_SLOTS: u64 = 8;
+
+/// Whether keys of this type are hashed. The hash is worked out from the bits of the key, so this is
+/// only for types whose values are equal exactly when their bits are, once `-0` and `NaN` are made
+/// canonical. Strings are hashed by their contents instead.
+fn hashable(ty: &Type) -> bool {
+    match ty {
+        Type::Complex | Type::Eisenstein | Type::Quaternion | Type::Rational | Type::Bool | Type::Str => true,
+        Type::Tuple(fields) => fields.iter().all(hashable),
+        Type::Struct(_, fields) => fields.iter().all(|(_, ty)| hashable(ty)),
+        _ => false,
+    }
+}
+
+/// A dictionary is a list of keys and a list of values, stored as `{ K* keys, V* values, table* }`.
+/// The value for a key is at the same position as the key, and keys stay in the order they were first
+/// inserted. If the keys can be hashed, the table is `{ i64 capacity, i64* slots }`, an open-addressed
+/// hash table whose slots hold one more than the position of a key (or 0 if they're empty), so
+/// finding a key takes constant time on average. Other keys, like lists, have a null table and are
+/// found by a linear search, like `memo fn` does. Since the lists are shared, storing a dictionary in
+/// a mutable variable copies it.
+impl<'ctx> Compiler<'ctx> {
+    pub(super) fn dict_type(&self, key: &Type, value: &Type) -> StructType<'ctx> {
+        let keys = self.list_type(key).ptr_type(AddressSpace::Generic);
+        let values = self.list_type(value).ptr_type(AddressSpace::Generic);
+        let table = self.dict_table_type().ptr_type(AddressSpace::Generic);
+        self.ctx.struct_type(&[keys.into(), values.into(), table.into()], false)
+    }
+
+    fn dict_table_type(&self) -> StructType<'ctx> {
+        let t_i64 = self.ctx.i64_type();
+        self.ctx.struct_type(&[t_i64.into(), t_i64.ptr_type(AddressSpace::Generic).into()], false)
+    }
+
+    fn table_field(&self, table: PointerValue<'ctx>, index: u32, name: &str) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        self.builder.build_struct_gep(table, index, name)
+            .map_err(|_| InternalError::invalid_state("malformed hash table"))
+    }
+
+    pub(super) fn pack_dict(&self, (keys, values, table): (PointerValue<'ctx>, PointerValue<'ctx>, PointerValue<'ctx>), key: &Type, value: &Type)
+            -> Result<BasicValueEnum<'ctx>, LocatedCompileError> {
+        let mut res = self.dict_type(key, value).get_undef();
+        for (k, ptr) in [keys, values, table].iter().enumerate() {
+            res = self.builder.build_insert_value(res, *ptr, k as u32, "pack_dict")
+                .ok_or_else(|| InternalError::invalid_state("failed to pack dictionary"))?
+                .into_struct_value();
+        }
+        Ok(res.into())
+    }
+
+    pub(super) fn unpack_dict(&self, packed: BasicValueEnum<'ctx>, key: &Type, value: &Type) -> Result<Value<'ctx>, LocatedCompileError> {
+        let packed = packed.into_struct_value();
+        let mut ptrs = Vec::new();
+        for k in 0..3 {
+            ptrs.push(self.builder.build_extract_value(packed, k, "unpack_dict")
+                .ok_or_else(|| InternalError::invalid_state("failed to unpack dictionary"))?
+                .into_pointer_value());
+        }
+        Ok(Value::Dict(ptrs[0], ptrs[1], ptrs[2], key.clone(), value.clone()))
+    }
+
+    /// Builds a dictionary out of already-computed pairs, which must all have types `key` and `value`.
+    /// A key that appears twice keeps the later value.
+    pub(super) fn build_dict_from(&mut self, pos: Location, key: &Type, value: &Type, pairs: Vec<(Value<'ctx>, Value<'ctx>)>)
+            -> Result<Value<'ctx>, LocatedCompileError> {
+        let zero = self.ctx.i64_type().const_zero();
+        let keys = self.build_list_alloc(key, zero)?;
+        let values = self.build_list_alloc(value, zero)?;
+        let table = self.build_dict_table(keys, key)?;
+        for (k, v) in pairs {
+            self.build_dict_insert(pos, (keys, values, table, key, value), k, v)?;
+        }
+        Ok(Value::Dict(keys, values, table, key.clone(), value.clone()))
+    }
+
+    /// A hash table holding every key so far, or null if the keys can't be hashed.
+    fn build_dict_table(&mut self, keys: PointerValue<'ctx>, key_type: &Type) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let table_type = self.dict_table_type();
+        if !hashable(key_type) {
+            return Ok(table_type.ptr_type(AddressSpace::Generic).const_null());
+        }
+        let table = self.builder.build_malloc(table_type, "table")
+            .map_err(InternalError::invalid_state)?;
+        self.build_dict_rehash(table, keys, key_type)?;
+        Ok(table)
+    }
+
+    /// Starts the table again with every key, using the smallest power of two that's at least twice
+    /// the number of keys as its capacity.
+    fn build_dict_rehash(&mut self, table: PointerValue<'ctx>, keys: PointerValue<'ctx>, key_type: &Type) -> Result<(), LocatedCompileError> {
+        let calloc = self.builtins.calloc();
+        self.move_to_end()?;
+
+        let t_i64 = self.ctx.i64_type();
+        let len = self.list_len(keys)?;
+        let needed = self.builder.build_int_mul(len, t_i64.const_int(2, false), "needed");
+        let entry_bb = self.get_block()?;
+        let size_bb = self.ctx.append_basic_block(self.get_fp()?, "table_size");
+        let sized_bb = self.ctx.append_basic_block(self.get_fp()?, "table_sized");
+        self.builder.build_unconditional_branch(size_bb);
+
+        self.set_and_move_block(size_bb)?;
+        let phi = self.builder.build_phi(t_i64, "capacity");
+        let capacity = phi.as_basic_value().into_int_value();
+        let enough = self.builder.build_int_compare(IntPredicate::UGE, capacity, needed, "enough");
+        let doubled = self.builder.build_int_mul(capacity, t_i64.const_int(2, false), "doubled");
+        phi.add_incoming(&[(&t_i64.const_int(MIN_SLOTS, false), entry_bb), (&doubled, size_bb)]);
+        self.builder.build_conditional_branch(enough, sized_bb, size_bb);
+
+        self.set_and_move_block(sized_bb)?;
+        let slots = self.builder.build_call(calloc, &[capacity.into(), t_i64.const_int(8, false).into()], "slots")
+            .try_as_basic_value().left()
+                .ok_or_else(|| InternalError::invalid_state("failed to interpret return value of calloc"))?
+            .into_pointer_value();
+        let slots = self.builder.build_pointer_cast(slots, t_i64.ptr_type(AddressSpace::Generic), "slots");
+        self.builder.build_store(self.table_field(table, 0, "capacity_ptr")?, capacity);
+        self.builder.build_store(self.table_field(table, 1, "slots_ptr")?, slots);
+        self.build_counted_loop(len, |this, k| {
+            let key = this.build_list_nth(keys, key_type, k)?;
+            let hash = this.build_hash(&key)?;
+            let slot = this.build_free_slot(table, hash)?;
+            let stored = this.builder.build_int_add(k, t_i64.const_int(1, false), "stored");
+            this.builder.build_store(slot, stored);
+            Ok(())
+        })
+    }
+
+    /// Whether the key is there, and if so, its position in the list of keys. For a hashed key, this
+    /// also gives the slot where the search stopped, which is where the key would go.
+    fn dict_lookup(&mut self, pos: Location, (keys, table): (PointerValue<'ctx>, PointerValue<'ctx>), key_type: &Type, key: Value<'ctx>)
+            -> Result<(IntValue<'ctx>, IntValue<'ctx>, Option<PointerValue<'ctx>>), LocatedCompileError> {
+        if key.ty() != *key_type {
+            return Err(LocatedCompileError::type_mismatch(pos, key_type, &key.ty()));
+        }
+        if hashable(key_type) {
+            let hash = self.build_hash(&key)?;
+            return self.build_table_probe(pos, (keys, table), key_type, key, hash)
+                .map(|(present, index, slot)| (present, index, Some(slot)));
+        }
+        match self.build_list_find(pos, keys, key_type, key)? {
+            Value::Option(present, index) => match *index {
+                Value::Complex(index) => {
+                    let index = self.builder.build_float_to_signed_int(index.re, self.ctx.i64_type(), "index");
+                    Ok((present, index, None))
+                },
+                _ => Err(InternalError::invalid_state("`find` did not return a number")),
+            },
+            _ => Err(InternalError::invalid_state("`find` did not return an optional value")),
+        }
+    }
+
+    /// Looks through the slots from `hash` onwards until it finds the key or an empty slot. The table
+    /// is never more than half full, so there's always an empty slot to stop at.
+    fn build_table_probe(&mut self, pos: Location, (keys, table): (PointerValue<'ctx>, PointerValue<'ctx>), key_type: &Type, key: Value<'ctx>,
+            hash: IntValue<'ctx>) -> Result<(IntValue<'ctx>, IntValue<'ctx>, PointerValue<'ctx>), LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let one = t_i64.const_int(1, false);
+        let capacity = self.builder.build_load(self.table_field(table, 0, "capacity_ptr")?, "capacity").into_int_value();
+        let slots = self.builder.build_load(self.table_field(table, 1, "slots_ptr")?, "slots").into_pointer_value();
+        let mask = self.builder.build_int_sub(capacity, one, "mask");
+        let start = self.builder.build_and(hash, mask, "start");
+        let entry_bb = self.get_block()?;
+        let probe_bb = self.ctx.append_basic_block(self.get_fp()?, "probe");
+        let check_bb = self.ctx.append_basic_block(self.get_fp()?, "probe_check");
+        let done_bb = self.ctx.append_basic_block(self.get_fp()?, "probe_done");
+        self.builder.build_unconditional_branch(probe_bb);
+
+        self.set_and_move_block(probe_bb)?;
+        let i = self.builder.build_phi(t_i64, "i");
+        i.add_incoming(&[(&start, entry_bb)]);
+        let i_val = i.as_basic_value().into_int_value();
+        // Safety: `i` is masked to be less than the capacity
+        let slot = unsafe { self.builder.build_in_bounds_gep(slots, &[i_val], "slot") };
+        let stored = self.builder.build_load(slot, "stored").into_int_value();
+        let empty = self.builder.build_int_compare(IntPredicate::EQ, stored, t_i64.const_zero(), "empty");
+        self.builder.build_conditional_branch(empty, done_bb, check_bb);
+
+        self.set_and_move_block(check_bb)?;
+        let index = self.builder.build_int_sub(stored, one, "index");
+        let candidate = self.build_list_nth(keys, key_type, index)?;
+        let equal = self.build_equality(pos, BinOp::Equals, candidate, key)?;
+        let equal = self.expect_bool(pos, equal)?;
+        let next = self.builder.build_int_add(i_val, one, "next");
+        let next = self.builder.build_and(next, mask, "next");
+        let check_bb = self.get_block()?;
+        i.add_incoming(&[(&next, check_bb)]);
+        self.builder.build_conditional_branch(equal, done_bb, probe_bb);
+
+        self.set_and_move_block(done_bb)?;
+        let t_bool = self.ctx.bool_type();
+        let present = self.builder.build_phi(t_bool, "present");
+        present.add_incoming(&[(&t_bool.const_zero(), probe_bb), (&t_bool.const_int(1, false), check_bb)]);
+        let found = self.builder.build_phi(t_i64, "found");
+        found.add_incoming(&[(&t_i64.const_zero(), probe_bb), (&index, check_bb)]);
+        Ok((present.as_basic_value().into_int_value(), found.as_basic_value().into_int_value(), slot))
+    }
+
+    /// The first empty slot from `hash` onwards, for a key that isn't in the table.
+    fn build_free_slot(&mut self, table: PointerValue<'ctx>, hash: IntValue<'ctx>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let capacity = self.builder.build_load(self.table_field(table, 0, "capacity_ptr")?, "capacity").into_int_value();
+        let slots = self.builder.build_load(self.table_field(table, 1, "slots_ptr")?, "slots").into_pointer_value();
+        let mask = self.builder.build_int_sub(capacity, t_i64.const_int(1, false), "mask");
+        let start = self.builder.build_and(hash, mask, "start");
+        let entry_bb = self.get_block()?;
+        let probe_bb = self.ctx.append_basic_block(self.get_fp()?, "free_probe");
+        let done_bb = self.ctx.append_basic_block(self.get_fp()?, "free_done");
+        self.builder.build_unconditional_branch(probe_bb);
+
+        self.set_and_move_block(probe_bb)?;
+        let i = self.builder.build_phi(t_i64, "i");
+        let i_val = i.as_basic_value().into_int_value();
+        // Safety: `i` is masked to be less than the capacity
+        let slot = unsafe { self.builder.build_in_bounds_gep(slots, &[i_val], "slot") };
+        let stored = self.builder.build_load(slot, "stored").into_int_value();
+        let empty = self.builder.build_int_compare(IntPredicate::EQ, stored, t_i64.const_zero(), "empty");
+        let next = self.builder.build_int_add(i_val, t_i64.const_int(1, false), "next");
+        let next = self.builder.build_and(next, mask, "next");
+        i.add_incoming(&[(&start, entry_bb), (&next, probe_bb)]);
+        self.builder.build_conditional_branch(empty, done_bb, probe_bb);
+
+        self.set_and_move_block(done_bb)?;
+        Ok(slot)
+    }
+
+    /// Mixes the 64-bit words that make up a key into its hash.
+    fn build_hash(&mut self, key: &Value<'ctx>) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let mut words = Vec::new();
+        self.hash_words(key, &mut words)?;
+        let mut hash = t_i64.const_zero();
+        for word in words {
+            let mixed = self.builder.build_xor(hash, word, "mixed");
+            let mixed = self.builder.build_int_mul(mixed, t_i64.const_int(0x9e37_79b9_7f4a_7c15, false), "mixed");
+            let shifted = self.builder.build_right_shift(mixed, t_i64.const_int(32, false), false, "shifted");
+            hash = self.builder.build_xor(mixed, shifted, "hash");
+        }
+        Ok(hash)
+    }
+
+    fn hash_words(&mut self, key: &Value<'ctx>, out: &mut Vec<IntValue<'ctx>>) -> Result<(), LocatedCompileError> {
+        match key {
+            Value::Complex(z) | Value::Eisenstein(z) => out.extend(vec![self.canonical_bits(z.re), self.canonical_bits(z.im)]),
+            Value::Quaternion(z, w) | Value::Rational(z, w) => out.extend(vec![
+                self.canonical_bits(z.re), self.canonical_bits(z.im), self.canonical_bits(w.re), self.canonical_bits(w.im)]),
+            Value::Bool(b) => out.push(self.builder.build_int_z_extend(*b, self.ctx.i64_type(), "bool_bits")),
+            Value::Str(s) => out.push(self.build_str_hash(*s)?),
+            Value::Tuple(fields) => {
+                for field in fields {
+                    self.hash_words(field, out)?;
+                }
+            },
+            Value::Struct(_, fields) => {
+                for (_, field) in fields {
+                    self.hash_words(field, out)?;
+                }
+            },
+            _ => return Err(InternalError::invalid_state("key cannot be hashed")),
+        }
+        Ok(())
+    }
+
+    /// The bits of `x`, except that `-0` becomes `0` and every `NaN` becomes the same one, so that
+    /// numbers that are equal have the same bits.
+    fn canonical_bits(&self, x: FloatValue<'ctx>) -> IntValue<'ctx> {
+        let t_f64 = self.ctx.f64_type();
+        // Adding zero leaves everything but `-0` alone
+        let x = self.builder.build_float_add(x, t_f64.const_zero(), "unsigned_zero");
+        let nan = self.builder.build_float_compare(FloatPredicate::UNO, x, x, "nan");
+        let x = self.builder.build_select(nan, t_f64.const_float(f64::NAN), x, "canonical");
+        self.builder.build_bitcast(x, self.ctx.i64_type(), "bits").into_int_value()
+    }
+
+    /// FNV-1a over the bytes of a string, so that equal strings hash the same wherever they're kept.
+    fn build_str_hash(&mut self, s: PointerValue<'ctx>) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let strlen = self.builtins.strlen();
+        self.move_to_end()?;
+
+        let t_i64 = self.ctx.i64_type();
+        let basis = t_i64.const_int(0xcbf2_9ce4_8422_2325, false);
+        let len = self.builder.build_call(strlen, &[s.into()], "len")
+            .try_as_basic_value().left()
+                .ok_or_else(|| InternalError::invalid_state("failed to interpret return value of strlen"))?
+            .into_int_value();
+        let entry_bb = self.get_block()?;
+        let loop_bb = self.ctx.append_basic_block(self.get_fp()?, "str_hash");
+        let done_bb = self.ctx.append_basic_block(self.get_fp()?, "str_hash_done");
+        let empty = self.builder.build_int_compare(IntPredicate::EQ, len, t_i64.const_zero(), "empty");
+        self.builder.build_conditional_branch(empty, done_bb, loop_bb);
+
+        self.set_and_move_block(loop_bb)?;
+        let k = self.builder.build_phi(t_i64, "k");
+        let hash = self.builder.build_phi(t_i64, "hash");
+        let k_val = k.as_basic_value().into_int_value();
+        // Safety: `k` is less than the length of the string
+        let byte = unsafe { self.builder.build_in_bounds_gep(s, &[k_val], "byte_ptr") };
+        let byte = self.builder.build_load(byte, "byte").into_int_value();
+        let byte = self.builder.build_int_z_extend(byte, t_i64, "byte");
+        let next_hash = self.builder.build_xor(hash.as_basic_value().into_int_value(), byte, "next_hash");
+        let next_hash = self.builder.build_int_mul(next_hash, t_i64.const_int(0x100_0000_01b3, false), "next_hash");
+        let next_k = self.builder.build_int_add(k_val, t_i64.const_int(1, false), "next_k");
+        k.add_incoming(&[(&t_i64.const_zero(), entry_bb), (&next_k, loop_bb)]);
+        hash.add_incoming(&[(&basis, entry_bb), (&next_hash, loop_bb)]);
+        let more = self.builder.build_int_compare(IntPredicate::ULT, next_k, len, "more");
+        self.builder.build_conditional_branch(more, loop_bb, done_bb);
+
+        self.set_and_move_block(done_bb)?;
+        let res = self.builder.build_phi(t_i64, "str_hash");
+        res.add_incoming(&[(&basis, entry_bb), (&next_hash, loop_bb)]);
+        Ok(res.as_basic_value().into_int_value())
+    }
+
+    pub(super) fn build_dict_contains(&mut self, pos: Location, (keys, table): (PointerValue<'ctx>, PointerValue<'ctx>), key_type: &Type, key: Value<'ctx>)
+            -> Result<IntValue<'ctx>, LocatedCompileError> {
+        self.dict_lookup(pos, (keys, table), key_type, key).map(|(present, _, _)| present)
+    }
+
+    /// `d[key]`, which aborts if the key isn't there.
+    pub(super) fn build_dict_index(&mut self, pos: Location,
+            (keys, values, table, key_type, value_type): (PointerValue<'ctx>, PointerValue<'ctx>, PointerValue<'ctx>, &Type, &Type),
+            key: Value<'ctx>) -> Result<Value<'ctx>, LocatedCompileError> {
+        let (present, index, _) = self.dict_lookup(pos, (keys, table), key_type, key)?;
+        self.build_check(present, pos, "key not found in dictionary")?;
+        self.build_list_nth(values, value_type, index)
+    }
+
+    /// `get(d, key)`, which gives `none` if the key isn't there.
+    pub(super) fn build_dict_get(&mut self, pos: Location,
+            (keys, values, table, key_type, value_type): (PointerValue<'ctx>, PointerValue<'ctx>, PointerValue<'ctx>, &Type, &Type),
+            key: Value<'ctx>) -> Result<Value<'ctx>, LocatedCompileError> {
+        let (present, index, _) = self.dict_lookup(pos, (keys, table), key_type, key)?;
+        let none = self.build_none(value_type)?;
+        let none = self.pack(&none)?;
+        let entry_bb = self.get_block()?;
+        let some_bb = self.ctx.append_basic_block(self.get_fp()?, "some");
+        let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "cont");
+        self.builder.build_conditional_branch(present, some_bb, cont_bb);
+
+        self.set_and_move_block(some_bb)?;
+        let value = self.build_list_nth(values, value_type, index)?;
+        let some = self.pack(&self.build_some(value))?;
+        let some_bb = self.get_block()?;
+        self.builder.build_unconditional_branch(cont_bb);
+
+        self.set_and_move_block(cont_bb)?;
+        let phi = self.builder.build_phi(self.option_type(value_type), "get_tmp");
+        phi.add_incoming(&[(&none, entry_bb), (&some, some_bb)]);
+        self.unpack(phi.as_basic_value(), &Type::Option(Box::new(value_type.clone())))
+    }
+
+    /// Replaces the value for the key if it's there, and otherwise adds the pair at the end. Adding a
+    /// hashed key that makes the table more than half full starts it again with twice the capacity.
+    pub(super) fn build_dict_insert(&mut self, pos: Location,
+            (keys, values, table, key_type, value_type): (PointerValue<'ctx>, PointerValue<'ctx>, PointerValue<'ctx>, &Type, &Type),
+            key: Value<'ctx>, value: Value<'ctx>) -> Result<(), LocatedCompileError> {
+        if value.ty() != *value_type {
+            return Err(LocatedCompileError::type_mismatch(pos, value_type, &value.ty()));
+        }
+        let (present, index, slot) = self.dict_lookup(pos, (keys, table), key_type, key.clone())?;
+        let replace_bb = self.ctx.append_basic_block(self.get_fp()?, "replace");
+        let add_bb = self.ctx.append_basic_block(self.get_fp()?, "add");
+        let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "cont");
+        self.builder.build_conditional_branch(present, replace_bb, add_bb);
+
+        self.set_and_move_block(replace_bb)?;
+        self.build_list_store(values, index, value.clone())?;
+        self.builder.build_unconditional_branch(cont_bb);
+
+        self.set_and_move_block(add_bb)?;
+        self.build_list_push(keys, key_type, key)?;
+        self.build_list_push(values, value_type, value)?;
+        if let Some(slot) = slot {
+            let t_i64 = self.ctx.i64_type();
+            let len = self.list_len(keys)?;
+            self.builder.build_store(slot, len);
+            let capacity = self.builder.build_load(self.table_field(table, 0, "capacity_ptr")?, "capacity").into_int_value();
+            let needed = self.builder.build_int_mul(len, t_i64.const_int(2, false), "needed");
+            let full = self.builder.build_int_compare(IntPredicate::UGT, needed, capacity, "full");
+            let grow_bb = self.ctx.append_basic_block(self.get_fp()?, "grow");
+            self.builder.build_conditional_branch(full, grow_bb, cont_bb);
+
+            self.set_and_move_block(grow_bb)?;
+            self.build_dict_rehash(table, keys, key_type)?;
+        }
+        self.builder.build_unconditional_branch(cont_bb);
+
+        self.set_and_move_block(cont_bb)
+    }
+
+    /// Copies both lists, along with a new table for the copied keys.
+    pub(super) fn build_dict_copy(&mut self, keys: PointerValue<'ctx>, values: PointerValue<'ctx>, key: Type, value: Type)
+            -> Result<Value<'ctx>, LocatedCompileError> {
+        let keys = self.build_list_copy(keys, &key)?;
+        let values = self.build_list_copy(values, &value)?;
+        let table = self.build_dict_table(keys, &key)?;
+        Ok(Value::Dict(keys, values, table, key, value))
+    }
+
+    /// Prints `{key: value, ...}`.
+    pub(super) fn print_dict(&mut self, pos: Location, (keys, values, key_type, value_type): (PointerValue<'ctx>, PointerValue<'ctx>, &Type, &Type),
+            newline: bool) -> Result<(), LocatedCompileError> {
+        let print_str = self.builtins.print_str();
+        self.move_to_end()?;
+        self.build_print_str("{".to_owned())?;
+        let len = self.list_len(keys)?;
+        let empty = self.builder.build_global_string_ptr("", ".empty_str").as_pointer_value();
+        let comma = self.builder.build_global_string_ptr(", ", ".comma_str").as_pointer_value();
+        self.build_counted_loop(len, |this, k| {
+            let first = this.builder.build_int_compare(IntPredicate::EQ, k, this.ctx.i64_type().const_zero(), "first");
+            let sep = this.builder.build_select(first, empty, comma, "sep");
+            this.builder.build_call(print_str, &[sep], "call");
+            let key = this.build_list_nth(keys, key_type, k)?;
+            this.print_value(pos, key, false)?;
+            this.build_print_str(": ".to_owned())?;
+            let value = this.build_list_nth(values, value_type, k)?;
+            this.print_value(pos, value, false)
+        })?;
+        if newline {
+            self.build_println_str("}".to_owned())
+        } else {
+            self.build_print_str("}".to_owned())
+        }
+    }
+}
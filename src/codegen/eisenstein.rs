@@ -0,0 +1,170 @@
+use inkwell::{FloatPredicate, values::FloatValue};
+
+use crate::{analyse::{ComplexValue, Location, Type, Value}, error::LocatedCompileError, parse::{BinOp, UnOp}};
+
+use super::Compiler;
+
+/// Eisenstein integers `a + bω`, where `ω = (-1 + √3 i) / 2` is a cube root of unity, so that
+/// `ω^2 = -1 - ω`. They're kept as the pair `(a, b)` rather than as a complex number, which keeps
+/// them exact. A real number can be used wherever an Eisenstein integer is expected, but any other
+/// complex number is an error, since it usually isn't one.
+impl<'ctx> Compiler<'ctx> {
+    /// Arithmetic where at least one side is an Eisenstein integer.
+    pub(super) fn build_eisenstein_binop(&mut self, pos: Location, op: BinOp, lpos: Location, lval: Value<'ctx>, rpos: Location, rval: Value<'ctx>)
+            -> Result<Value<'ctx>, LocatedCompileError> {
+        let (lval, rval) = match self.promote_eisenstein(lpos, lval, rpos, rval)? {
+            (Value::Eisenstein(lval), Value::Eisenstein(rval)) => (lval, rval),
+            (Value::Eisenstein(_), rval) => return Err(LocatedCompileError::type_mismatch(rpos, &Type::Eisenstein, &rval.ty())),
+            (lval, _) => return Err(LocatedCompileError::type_mismatch(lpos, &Type::Eisenstein, &lval.ty())),
+        };
+        match op {
+            BinOp::Plus  => Ok(Value::Eisenstein((self.builder.build_float_add(lval.re, rval.re, "tmp_add_a"),
+                                self.builder.build_float_add(lval.im, rval.im, "tmp_add_b")).into())),
+            BinOp::Minus => Ok(Value::Eisenstein((self.builder.build_float_sub(lval.re, rval.re, "tmp_sub_a"),
+                                self.builder.build_float_sub(lval.im, rval.im, "tmp_sub_b")).into())),
+            BinOp::Times => Ok(Value::Eisenstein(self.eisenstein_mul(lval, rval))),
+            BinOp::Power => self.eisenstein_pow(rpos, lval, rval).map(Value::Eisenstein),
+            // Like Gaussian integers, `/` is true division, which usually leaves the ring
+            BinOp::Divide => {
+                let zero = self.ctx.f64_type().const_zero();
+                let nonzero = self.builder.build_float_compare(FloatPredicate::ONE, self.eisenstein_norm(rval), zero, "nonzero");
+                self.build_check(nonzero, pos, "division by zero")?;
+                let lval = self.eisenstein_to_complex(lval);
+                let rval = self.eisenstein_to_complex(rval);
+                Ok(Value::Complex(self.complex_div(lval, rval)))
+            },
+            BinOp::Quotient  => self.eisenstein_divmod(pos, lval, rval).map(|(q, _)| Value::Eisenstein(q)),
+            BinOp::Remainder => self.eisenstein_divmod(pos, lval, rval).map(|(_, r)| Value::Eisenstein(r)),
+            BinOp::Less | BinOp::LessEquals | BinOp::Greater | BinOp::GreaterEquals =>
+                Ok(Value::Bool(self.order_by_norm(op, Self::eisenstein_norm, lval, rval))),
+            _ => Err(LocatedCompileError::unsupported(pos, "this operator is not defined for Eisenstein integers".to_owned())),
+        }
+    }
+
+    /// `-z`, and `z^`, which is the complex conjugate `a + bω^2 = (a - b) - bω`.
+    pub(super) fn build_eisenstein_unop(&mut self, pos: Location, op: UnOp, val: ComplexValue<'ctx>) -> Result<Value<'ctx>, LocatedCompileError> {
+        match op {
+            UnOp::Negate    => Ok(Value::Eisenstein((self.builder.build_float_neg(val.re, "tmp_neg_a"),
+                                   self.builder.build_float_neg(val.im, "tmp_neg_b")).into())),
+            UnOp::Conjugate => Ok(Value::Eisenstein(self.eisenstein_conjugate(val))),
+            UnOp::Modulus   => Err(LocatedCompileError::unsupported(pos, "Eisenstein integers have no modulus; use `norm`".to_owned())),
+            UnOp::Factorial => Err(LocatedCompileError::unsupported(pos, "Eisenstein integers have no factorial".to_owned())),
+        }
+    }
+
+    /// If one side is an Eisenstein integer and the other is a number, turns the number into one
+    /// too, which aborts unless it's real.
+    pub(super) fn promote_eisenstein(&mut self, lpos: Location, lval: Value<'ctx>, rpos: Location, rval: Value<'ctx>)
+            -> Result<(Value<'ctx>, Value<'ctx>), LocatedCompileError> {
+        match (lval, rval) {
+            (Value::Complex(lval), rval @ Value::Eisenstein(_)) => Ok((self.to_eisenstein(lpos, lval)?, rval)),
+            (lval @ Value::Eisenstein(_), Value::Complex(rval)) => Ok((lval, self.to_eisenstein(rpos, rval)?)),
+            vals => Ok(vals),
+        }
+    }
+
+    fn to_eisenstein(&mut self, pos: Location, val: ComplexValue<'ctx>) -> Result<Value<'ctx>, LocatedCompileError> {
+        let zero = self.ctx.f64_type().const_zero();
+        let real = self.builder.build_float_compare(FloatPredicate::OEQ, val.im, zero, "real");
+        self.build_check(real, pos, "only a real number can be used as an Eisenstein integer")?;
+        Ok(Value::Eisenstein(ComplexValue { re: val.re, im: zero }))
+    }
+
+    /// `a + bω^2 = (a - b) - bω`
+    fn eisenstein_conjugate(&self, val: ComplexValue<'ctx>) -> ComplexValue<'ctx> {
+        (self.builder.build_float_sub(val.re, val.im, "tmp_conj_a"), self.builder.build_float_neg(val.im, "tmp_conj_b")).into()
+    }
+
+    /// `a + bω = (a - b/2) + (b√3/2)i`
+    fn eisenstein_to_complex(&self, val: ComplexValue<'ctx>) -> ComplexValue<'ctx> {
+        let t_f64 = self.ctx.f64_type();
+        let half_b = self.builder.build_float_mul(val.im, t_f64.const_float(0.5), "half_b");
+        let re = self.builder.build_float_sub(val.re, half_b, "re");
+        let im = self.builder.build_float_mul(val.im, t_f64.const_float(3f64.sqrt() / 2.), "im");
+        ComplexValue { re, im }
+    }
+
+    /// Euclidean division in `ℤ[ω]`, giving `(q, r)` with `a == q * b + r` for `~/`, `%` and `divmod`.
+    /// As with Gaussian integers, `q` is floored when both sides are real, so `r` is in `0..|b|`.
+    /// Otherwise each coordinate of `a / b = a b^ / norm(b)` is rounded, which leaves each of them
+    /// off by at most a half, so `norm(r) <= 3 norm(b) / 4`.
+    pub(super) fn eisenstein_divmod(&mut self, pos: Location, lval: ComplexValue<'ctx>, rval: ComplexValue<'ctx>)
+            -> Result<(ComplexValue<'ctx>, ComplexValue<'ctx>), LocatedCompileError> {
+        let round = self.builtins.round();
+        self.move_to_end()?;
+
+        let zero = self.ctx.f64_type().const_zero();
+        let norm = self.eisenstein_norm(rval);
+        let nonzero = self.builder.build_float_compare(FloatPredicate::ONE, norm, zero, "nonzero");
+        self.build_check(nonzero, pos, "division by zero")?;
+
+        let q_real = self.real_quotient(lval.re, rval.re)?;
+
+        let scaled = self.eisenstein_mul(lval, self.eisenstein_conjugate(rval));
+        let q_a = self.builder.build_float_div(scaled.re, norm, "q_a");
+        let q_a = self.call_float(round, &[q_a.into()], "q_a")?;
+        let q_b = self.builder.build_float_div(scaled.im, norm, "q_b");
+        let q_b = self.call_float(round, &[q_b.into()], "q_b")?;
+
+        let l_real = self.builder.build_float_compare(FloatPredicate::OEQ, lval.im, zero, "l_real");
+        let r_real = self.builder.build_float_compare(FloatPredicate::OEQ, rval.im, zero, "r_real");
+        let real = self.builder.build_and(l_real, r_real, "real");
+        let q = ComplexValue {
+            re: self.builder.build_select(real, q_real, q_a, "q_a").into_float_value(),
+            im: self.builder.build_select(real, zero, q_b, "q_b").into_float_value(),
+        };
+        let qb = self.eisenstein_mul(q, rval);
+        let r = ComplexValue {
+            re: self.builder.build_float_sub(lval.re, qb.re, "r_a"),
+            im: self.builder.build_float_sub(lval.im, qb.im, "r_b"),
+        };
+        Ok((q, r))
+    }
+
+    /// `(a + bω)(c + dω) = ac + (ad + bc)ω + bdω^2 = (ac - bd) + (ad + bc - bd)ω`
+    fn eisenstein_mul(&self, lval: ComplexValue<'ctx>, rval: ComplexValue<'ctx>) -> ComplexValue<'ctx> {
+        let ac = self.builder.build_float_mul(lval.re, rval.re, "ac");
+        let bd = self.builder.build_float_mul(lval.im, rval.im, "bd");
+        let ad = self.builder.build_float_mul(lval.re, rval.im, "ad");
+        let bc = self.builder.build_float_mul(lval.im, rval.re, "bc");
+        let a = self.builder.build_float_sub(ac, bd, "tmp_mul_a");
+        let b = self.builder.build_float_add(ad, bc, "tmp_mul_b");
+        let b = self.builder.build_float_sub(b, bd, "tmp_mul_b");
+        ComplexValue { re: a, im: b }
+    }
+
+    /// `a^2 - ab + b^2`, which is `|a + bω|^2`.
+    pub(super) fn eisenstein_norm(&self, val: ComplexValue<'ctx>) -> FloatValue<'ctx> {
+        let a2 = self.builder.build_float_mul(val.re, val.re, "a_squared");
+        let ab = self.builder.build_float_mul(val.re, val.im, "ab");
+        let b2 = self.builder.build_float_mul(val.im, val.im, "b_squared");
+        let norm = self.builder.build_float_sub(a2, ab, "norm");
+        self.builder.build_float_add(norm, b2, "norm")
+    }
+
+    /// `z ** n` for a natural number `n`, by multiplying `n` times.
+    fn eisenstein_pow(&mut self, pos: Location, base: ComplexValue<'ctx>, exp: ComplexValue<'ctx>)
+            -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+        let t_f64 = self.ctx.f64_type();
+        let zero = t_f64.const_zero();
+        let floor = self.builtins.floor();
+        self.move_to_end()?;
+        let floored = self.call_float(floor, &[exp.re.into()], "floored")?;
+        let whole = self.builder.build_float_compare(FloatPredicate::OEQ, exp.re, floored, "whole");
+        let real = self.builder.build_float_compare(FloatPredicate::OEQ, exp.im, zero, "real");
+        let non_negative = self.builder.build_float_compare(FloatPredicate::OGE, exp.re, zero, "non_negative");
+        let ok = self.builder.build_and(whole, real, "integer");
+        let ok = self.builder.build_and(ok, non_negative, "natural");
+        self.build_check(ok, pos, "Eisenstein integers can only be raised to a non-negative integer power")?;
+
+        let res = (self.builder.build_alloca(t_f64, "pow_a"), self.builder.build_alloca(t_f64, "pow_b"));
+        self.store_complex(res, ComplexValue { re: t_f64.const_float(1.), im: zero });
+        let n = self.builder.build_float_to_signed_int(exp.re, self.ctx.i64_type(), "n");
+        self.build_counted_loop(n, |this, _| {
+            let product = this.eisenstein_mul(this.load_complex(res), base);
+            this.store_complex(res, product);
+            Ok(())
+        })?;
+        Ok(self.load_complex(res))
+    }
+}
@@ -0,0 +1,159 @@
+use inkwell::{FloatPredicate, IntPredicate, basic_block::BasicBlock, types::{BasicTypeEnum, StructType}, values::{BasicValueEnum, IntValue}};
+
+use crate::{analyse::{ComplexValue, Located, Location, Type, Value, Variable}, error::{InternalError, LocatedCompileError}, parse::{Expr, Pattern}};
+
+use super::Compiler;
+
+/// Enum values are stored as `{ i64 tag, complex fields... }`, where the tag is the index of the
+/// variant and there's room for the fields of whichever variant has the most.
+impl<'ctx> Compiler<'ctx> {
+    pub(super) fn enum_type(&self, name: &str) -> StructType<'ctx> {
+        let len = self.sym.enum_def(name).map_or(0, |def| def.payload_len());
+        let mut fields: Vec<BasicTypeEnum> = vec![self.ctx.i64_type().into()];
+        fields.resize(len + 1, self.complex_type().into());
+        self.ctx.struct_type(&fields, false)
+    }
+
+    pub(super) fn pack_enum(&self, name: &str, tag: IntValue<'ctx>, payload: &[ComplexValue<'ctx>]) -> Result<BasicValueEnum<'ctx>, LocatedCompileError> {
+        let mut res = self.enum_type(name).get_undef();
+        res = self.builder.build_insert_value(res, tag, 0, "pack_tag")
+            .ok_or_else(|| InternalError::invalid_state("failed to pack enum tag"))?
+            .into_struct_value();
+        for (k, field) in payload.iter().enumerate() {
+            let field = self.pack(&Value::Complex(*field))?;
+            res = self.builder.build_insert_value(res, field, k as u32 + 1, "pack_field")
+                .ok_or_else(|| InternalError::invalid_state("failed to pack enum field"))?
+                .into_struct_value();
+        }
+        Ok(res.into())
+    }
+
+    pub(super) fn unpack_enum(&self, name: &str, value: BasicValueEnum<'ctx>) -> Result<Value<'ctx>, LocatedCompileError> {
+        let def = self.sym.enum_def(name)
+            .ok_or_else(|| InternalError::invalid_state("unknown enum"))?;
+        let value = value.into_struct_value();
+        let tag = self.builder.build_extract_value(value, 0, "unpack_tag")
+            .ok_or_else(|| InternalError::invalid_state("failed to unpack enum tag"))?
+            .into_int_value();
+        let mut payload = Vec::new();
+        for k in 0..def.payload_len() {
+            let field = self.builder.build_extract_value(value, k as u32 + 1, "unpack_field")
+                .ok_or_else(|| InternalError::invalid_state("failed to unpack enum field"))?;
+            payload.push(self.unpack_complex(field)?);
+        }
+        Ok(Value::Enum(name.to_owned(), tag, payload))
+    }
+
+    /// Builds the given variant, e.g. `Ok(3)`, or `None` for a variant without fields.
+    pub(super) fn build_variant(&mut self, pos: Location, id: Located<String>, args: Vec<Located<Expr>>) -> Result<Value<'ctx>, LocatedCompileError> {
+        let (def, tag) = self.sym.variant(id.borrow_val()).cloned()
+            .ok_or_else(|| LocatedCompileError::unknown_symbol(id.clone()))?;
+        let fields = def.variants[tag].1.len();
+        if args.len() != fields {
+            return Err(LocatedCompileError::arity(pos, id.borrow_val(), fields, args.len()));
+        }
+
+        let mut payload = Vec::new();
+        for arg in args {
+            payload.push(self.build_complex(arg)?);
+        }
+        let t_f64 = self.ctx.f64_type();
+        payload.resize(def.payload_len(), ComplexValue { re: t_f64.const_zero(), im: t_f64.const_zero() });
+        let tag = self.ctx.i64_type().const_int(tag as u64, false);
+        Ok(Value::Enum(def.name.borrow_val().clone(), tag, payload))
+    }
+
+    /// Two enum values are equal when they're the same variant with the same fields. Unused fields
+    /// are always zero, so they can be compared too.
+    pub(super) fn enum_equals(&self, (ltag, lvals): (IntValue<'ctx>, Vec<ComplexValue<'ctx>>), (rtag, rvals): (IntValue<'ctx>, Vec<ComplexValue<'ctx>>))
+            -> IntValue<'ctx> {
+        let mut res = self.builder.build_int_compare(IntPredicate::EQ, ltag, rtag, "tmp_tag_eq");
+        for (lval, rval) in lvals.into_iter().zip(rvals) {
+            let field = self.complex_cmp(FloatPredicate::OEQ, lval, rval);
+            res = self.builder.build_and(res, field, "tmp_enum_eq");
+        }
+        res
+    }
+
+    /// Checks whether the value is the given variant and its fields match the field patterns, jumping
+    /// to `next_bb` if not. Otherwise, binds the fields and returns what they shadowed.
+    pub(super) fn build_variant_test(&mut self, value: &Value<'ctx>, name: Located<String>, fields: Vec<Pattern>, next_bb: BasicBlock<'ctx>)
+            -> Result<Vec<(String, Option<Located<Variable<'ctx>>>)>, LocatedCompileError> {
+        let (def, tag) = self.sym.variant(name.borrow_val()).cloned()
+            .ok_or_else(|| LocatedCompileError::unknown_symbol(name.clone()))?;
+        let (value_tag, payload) = match value {
+            Value::Enum(enum_name, tag, payload) if enum_name == def.name.borrow_val() => (*tag, payload.clone()),
+            value => return Err(LocatedCompileError::type_mismatch(name.pos(), &Type::Enum(def.name.borrow_val().clone()), &value.ty())),
+        };
+        let expected = def.variants[tag].1.len();
+        if fields.len() != expected {
+            return Err(LocatedCompileError::arity(name.pos(), name.borrow_val(), expected, fields.len()));
+        }
+
+        let arm_bb = self.ctx.append_basic_block(self.get_fp()?, "arm");
+        let tag = self.ctx.i64_type().const_int(tag as u64, false);
+        let matched = self.builder.build_int_compare(IntPredicate::EQ, value_tag, tag, "matched");
+        self.builder.build_conditional_branch(matched, arm_bb, next_bb);
+        self.set_and_move_block(arm_bb)?;
+
+        let mut bound = Vec::new();
+        for (field, entry) in fields.into_iter().zip(payload) {
+            match field {
+                Pattern::Literal(literal) => {
+                    let literal = self.build_complex(literal)?;
+                    let matched = self.complex_cmp(FloatPredicate::OEQ, entry, literal);
+                    let field_bb = self.ctx.append_basic_block(self.get_fp()?, "field_matched");
+                    self.builder.build_conditional_branch(matched, field_bb, next_bb);
+                    self.set_and_move_block(field_bb)?;
+                },
+                Pattern::Binding(id) => {
+                    let ptr = self.builder.build_alloca(self.complex_type(), id.borrow_val());
+                    let packed = self.pack(&Value::Complex(entry))?;
+                    self.builder.build_store(ptr, packed);
+                    let id_name = id.borrow_val().clone();
+                    let shadowed = self.sym.add_var(id, Variable { ptr, ty: Type::Complex, mutable: false });
+                    bound.push((id_name, shadowed));
+                },
+                Pattern::Wildcard => {},
+                Pattern::Variant(inner, _) =>
+                    return Err(LocatedCompileError::unsupported(inner.pos(), "the fields of a variant can only hold numbers".to_owned())),
+            }
+        }
+        Ok(bound)
+    }
+
+    /// Prints e.g. `Ok(3)`, by switching on the tag.
+    pub(super) fn print_enum(&mut self, name: &str, tag: IntValue<'ctx>, payload: Vec<ComplexValue<'ctx>>, newline: bool) -> Result<(), LocatedCompileError> {
+        let def = self.sym.enum_def(name).cloned()
+            .ok_or_else(|| InternalError::invalid_state("unknown enum"))?;
+        let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "cont");
+        let mut cases = Vec::new();
+        for k in 0..def.variants.len() {
+            let bb = self.ctx.append_basic_block(self.get_fp()?, "print_variant");
+            cases.push((self.ctx.i64_type().const_int(k as u64, false), bb));
+        }
+        self.builder.build_switch(tag, cont_bb, &cases);
+
+        for ((variant, fields), (_, bb)) in def.variants.iter().zip(cases) {
+            self.set_and_move_block(bb)?;
+            self.build_print_str(variant.borrow_val().clone())?;
+            if !fields.is_empty() {
+                self.build_print_str("(".to_owned())?;
+                for (k, field) in payload.iter().take(fields.len()).enumerate() {
+                    if k > 0 {
+                        self.build_print_str(", ".to_owned())?;
+                    }
+                    self.print_value(variant.pos(), Value::Complex(*field), false)?;
+                }
+                self.build_print_str(")".to_owned())?;
+            }
+            self.builder.build_unconditional_branch(cont_bb);
+        }
+
+        self.set_and_move_block(cont_bb)?;
+        if newline {
+            self.build_println_str(String::new())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,269 @@
+use inkwell::{FloatPredicate, values::{FloatValue, PointerValue}};
+
+use crate::{analyse::{ComplexValue, Location, Type, Value}, error::LocatedCompileError};
+
+use super::Compiler;
+
+/// Factoring a Gaussian integer `z`. Every Gaussian prime lies over a rational prime `p` dividing the
+/// norm `|z|^2`, so trial division by `p = 2, 3, 4, ...` finds them all:
+///   * `p = 2` gives `1 + i`;
+///   * `p = 3 mod 4` stays prime, and gives `p`;
+///   * `p = 1 mod 4` splits into `a + bi` and `b + ai`, where `a^2 + b^2 = p`.
+/// Each prime is divided out of `z` as often as it goes. What's left at the end is either a unit or
+/// a prime bigger than the square root of its norm.
+impl<'ctx> Compiler<'ctx> {
+    /// `factor(z)`, which gives the unit followed by the primes (each a canonical associate), ordered
+    /// by the rational prime they lie over and repeated as often as they divide `z`. The product of
+    /// the list is `z`.
+    pub(super) fn build_factor(&mut self, pos: Location, z: ComplexValue<'ctx>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        self.check_gaussian(pos, z, z, "`factor` needs a Gaussian integer")?;
+        let t_f64 = self.ctx.f64_type();
+        let zero = t_f64.const_zero();
+        let one = t_f64.const_float(1.);
+        let nonzero = self.builder.build_float_compare(FloatPredicate::ONE, self.complex_norm(z), zero, "nonzero");
+        self.build_check(nonzero, pos, "cannot factor zero")?;
+
+        let factors = self.build_list_alloc(&Type::Complex, self.ctx.i64_type().const_zero())?;
+        let rest = (self.builder.build_alloca(t_f64, "rest_re"), self.builder.build_alloca(t_f64, "rest_im"));
+        self.store_complex(rest, z);
+        let p_ptr = self.builder.build_alloca(t_f64, "p");
+        self.builder.build_store(p_ptr, t_f64.const_float(2.));
+
+        let test_bb = self.ctx.append_basic_block(self.get_fp()?, "factor_test");
+        let body_bb = self.ctx.append_basic_block(self.get_fp()?, "factor_body");
+        let split_bb = self.ctx.append_basic_block(self.get_fp()?, "factor_split");
+        let next_bb = self.ctx.append_basic_block(self.get_fp()?, "factor_next");
+        let done_bb = self.ctx.append_basic_block(self.get_fp()?, "factor_done");
+        self.builder.build_unconditional_branch(test_bb);
+
+        // Stops once p^2 > |rest|^2
+        self.set_and_move_block(test_bb)?;
+        let norm = self.complex_norm(self.load_complex(rest));
+        let p = self.builder.build_load(p_ptr, "p").into_float_value();
+        let p_squared = self.builder.build_float_mul(p, p, "p_squared");
+        let small = self.builder.build_float_compare(FloatPredicate::OLE, p_squared, norm, "small");
+        self.builder.build_conditional_branch(small, body_bb, done_bb);
+
+        self.set_and_move_block(body_bb)?;
+        let norm_rem = self.builder.build_float_rem(norm, p, "norm_rem");
+        let divides = self.builder.build_float_compare(FloatPredicate::OEQ, norm_rem, zero, "divides");
+        self.builder.build_conditional_branch(divides, split_bb, next_bb);
+
+        self.set_and_move_block(split_bb)?;
+        let (first, second) = self.build_primes_over(p)?;
+        self.build_divide_out(rest, factors, first)?;
+        self.build_divide_out(rest, factors, second)?;
+        self.builder.build_unconditional_branch(next_bb);
+
+        self.set_and_move_block(next_bb)?;
+        let p_next = self.builder.build_float_add(p, one, "p_next");
+        self.builder.build_store(p_ptr, p_next);
+        self.builder.build_unconditional_branch(test_bb);
+
+        // Whatever's left over is a unit times at most one more prime
+        self.set_and_move_block(done_bb)?;
+        let left = self.load_complex(rest);
+        let is_unit = self.builder.build_float_compare(FloatPredicate::OEQ, self.complex_norm(left), one, "is_unit");
+        let prime_bb = self.ctx.append_basic_block(self.get_fp()?, "factor_last");
+        let unit_bb = self.ctx.append_basic_block(self.get_fp()?, "factor_unit");
+        self.builder.build_conditional_branch(is_unit, unit_bb, prime_bb);
+
+        self.set_and_move_block(prime_bb)?;
+        let prime = self.canonical_associate(left);
+        self.build_list_push(factors, &Type::Complex, Value::Complex(prime))?;
+        let unit = self.complex_div(left, prime);
+        self.store_complex(rest, unit);
+        self.builder.build_unconditional_branch(unit_bb);
+
+        // The unit goes first, so the primes have to be copied after it
+        self.set_and_move_block(unit_bb)?;
+        let unit = self.load_complex(rest);
+        let round = self.builtins.round();
+        self.move_to_end()?;
+        let unit = ComplexValue {
+            re: self.call_float(round, &[unit.re.into()], "unit_re")?,
+            im: self.call_float(round, &[unit.im.into()], "unit_im")?,
+        };
+        let res = self.build_list_alloc(&Type::Complex, self.ctx.i64_type().const_zero())?;
+        self.build_list_push(res, &Type::Complex, Value::Complex(unit))?;
+        let len = self.list_len(factors)?;
+        self.build_counted_loop(len, |this, k| {
+            let factor = this.build_list_nth(factors, &Type::Complex, k)?;
+            this.build_list_push(res, &Type::Complex, factor)
+        })?;
+        Ok(res)
+    }
+
+    /// The factorisation of a positive integer `n` over the integers, as a list of `(p, e)` where
+    /// `p ** e` exactly divides `n`, ordered by `p`. It's the same trial division as `factor`, except
+    /// that every `p` that divides is a rational prime.
+    pub(super) fn build_prime_powers(&mut self, pos: Location, n: ComplexValue<'ctx>, name: &str) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let msg = format!("`{}` needs a positive integer", name);
+        self.check_gaussian(pos, n, n, &msg)?;
+        let t_f64 = self.ctx.f64_type();
+        let zero = t_f64.const_zero();
+        let one = t_f64.const_float(1.);
+        let real = self.builder.build_float_compare(FloatPredicate::OEQ, n.im, zero, "real");
+        let positive = self.builder.build_float_compare(FloatPredicate::OGE, n.re, one, "positive");
+        let ok = self.builder.build_and(real, positive, "ok");
+        self.build_check(ok, pos, &msg)?;
+
+        let ty = Type::Tuple(vec![Type::Complex, Type::Complex]);
+        let powers = self.build_list_alloc(&ty, self.ctx.i64_type().const_zero())?;
+        let rest = self.builder.build_alloca(t_f64, "rest");
+        self.builder.build_store(rest, n.re);
+        let p_ptr = self.builder.build_alloca(t_f64, "p");
+        self.builder.build_store(p_ptr, t_f64.const_float(2.));
+        let e_ptr = self.builder.build_alloca(t_f64, "e");
+
+        let test_bb = self.ctx.append_basic_block(self.get_fp()?, "prime_powers_test");
+        let body_bb = self.ctx.append_basic_block(self.get_fp()?, "prime_powers_body");
+        let divide_bb = self.ctx.append_basic_block(self.get_fp()?, "prime_powers_divide");
+        let push_bb = self.ctx.append_basic_block(self.get_fp()?, "prime_powers_push");
+        let next_bb = self.ctx.append_basic_block(self.get_fp()?, "prime_powers_next");
+        let done_bb = self.ctx.append_basic_block(self.get_fp()?, "prime_powers_done");
+        self.builder.build_unconditional_branch(test_bb);
+
+        // Stops once p^2 > rest
+        self.set_and_move_block(test_bb)?;
+        let left = self.builder.build_load(rest, "left").into_float_value();
+        let p = self.builder.build_load(p_ptr, "p").into_float_value();
+        let p_squared = self.builder.build_float_mul(p, p, "p_squared");
+        let small = self.builder.build_float_compare(FloatPredicate::OLE, p_squared, left, "small");
+        self.builder.build_store(e_ptr, zero);
+        self.builder.build_conditional_branch(small, body_bb, next_bb);
+
+        // Divides p out for as long as it goes
+        self.set_and_move_block(body_bb)?;
+        let left = self.builder.build_load(rest, "left").into_float_value();
+        let rem = self.builder.build_float_rem(left, p, "rem");
+        let divides = self.builder.build_float_compare(FloatPredicate::OEQ, rem, zero, "divides");
+        self.builder.build_conditional_branch(divides, divide_bb, push_bb);
+
+        self.set_and_move_block(divide_bb)?;
+        self.builder.build_store(rest, self.builder.build_float_div(left, p, "quot"));
+        let e = self.builder.build_load(e_ptr, "e").into_float_value();
+        self.builder.build_store(e_ptr, self.builder.build_float_add(e, one, "e"));
+        self.builder.build_unconditional_branch(body_bb);
+
+        self.set_and_move_block(push_bb)?;
+        let e = self.builder.build_load(e_ptr, "e").into_float_value();
+        let found = self.builder.build_float_compare(FloatPredicate::OGT, e, zero, "found");
+        let found_bb = self.ctx.append_basic_block(self.get_fp()?, "prime_powers_found");
+        let step_bb = self.ctx.append_basic_block(self.get_fp()?, "prime_powers_step");
+        self.builder.build_conditional_branch(found, found_bb, step_bb);
+        self.set_and_move_block(found_bb)?;
+        let power = Value::Tuple(vec![Value::Complex(ComplexValue { re: p, im: zero }), Value::Complex(ComplexValue { re: e, im: zero })]);
+        self.build_list_push(powers, &ty, power)?;
+        self.builder.build_unconditional_branch(step_bb);
+        self.set_and_move_block(step_bb)?;
+        self.builder.build_store(p_ptr, self.builder.build_float_add(p, one, "p_next"));
+        self.builder.build_unconditional_branch(test_bb);
+
+        // Whatever's left over is 1 or a prime
+        self.set_and_move_block(next_bb)?;
+        let left = self.builder.build_load(rest, "left").into_float_value();
+        let prime = self.builder.build_float_compare(FloatPredicate::OGT, left, one, "prime");
+        let last_bb = self.ctx.append_basic_block(self.get_fp()?, "prime_powers_last");
+        self.builder.build_conditional_branch(prime, last_bb, done_bb);
+        self.set_and_move_block(last_bb)?;
+        let power = Value::Tuple(vec![Value::Complex(ComplexValue { re: left, im: zero }), Value::Complex(ComplexValue { re: one, im: zero })]);
+        self.build_list_push(powers, &ty, power)?;
+        self.builder.build_unconditional_branch(done_bb);
+
+        self.set_and_move_block(done_bb)?;
+        Ok(powers)
+    }
+
+    /// The primes over `p`, as canonical associates. They're the same unless `p = 1 mod 4`.
+    fn build_primes_over(&mut self, p: FloatValue<'ctx>) -> Result<(ComplexValue<'ctx>, ComplexValue<'ctx>), LocatedCompileError> {
+        let t_f64 = self.ctx.f64_type();
+        let zero = t_f64.const_zero();
+        let one = t_f64.const_float(1.);
+        let entry_bb = self.get_block()?;
+        let search_bb = self.ctx.append_basic_block(self.get_fp()?, "sum_of_squares");
+        let found_bb = self.ctx.append_basic_block(self.get_fp()?, "sum_of_squares_found");
+        let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "primes_over");
+        let p_mod_4 = self.builder.build_float_rem(p, t_f64.const_float(4.), "p_mod_4");
+        let splits = self.builder.build_float_compare(FloatPredicate::OEQ, p_mod_4, one, "splits");
+        let is_two = self.builder.build_float_compare(FloatPredicate::OEQ, p, t_f64.const_float(2.), "is_two");
+        // 1 + i for 2, and p itself otherwise
+        let inert = ComplexValue {
+            re: self.builder.build_select(is_two, one, p, "inert_re").into_float_value(),
+            im: self.builder.build_select(is_two, one, zero, "inert_im").into_float_value(),
+        };
+        self.builder.build_conditional_branch(splits, search_bb, cont_bb);
+
+        // Tries a = 1, 2, ... until p - a^2 is a square, which it must be eventually
+        self.set_and_move_block(search_bb)?;
+        let sqrt = self.builtins.sqrt();
+        let round = self.builtins.round();
+        self.move_to_end()?;
+        let a = self.builder.build_phi(t_f64, "a");
+        let a_val = a.as_basic_value().into_float_value();
+        let a_squared = self.builder.build_float_mul(a_val, a_val, "a_squared");
+        let b_squared = self.builder.build_float_sub(p, a_squared, "b_squared");
+        let b = self.call_float(sqrt, &[b_squared.into()], "b")?;
+        let b = self.call_float(round, &[b.into()], "b")?;
+        let square = self.builder.build_float_mul(b, b, "square");
+        let found = self.builder.build_float_compare(FloatPredicate::OEQ, square, b_squared, "found");
+        let a_next = self.builder.build_float_add(a_val, one, "a_next");
+        a.add_incoming(&[(&one, entry_bb), (&a_next, search_bb)]);
+        self.builder.build_conditional_branch(found, found_bb, search_bb);
+
+        self.set_and_move_block(found_bb)?;
+        self.builder.build_unconditional_branch(cont_bb);
+
+        self.set_and_move_block(cont_bb)?;
+        let mut parts = Vec::new();
+        for (name, split, inert) in [("first_re", a_val, inert.re), ("first_im", b, inert.im), ("second_re", b, inert.re), ("second_im", a_val, inert.im)].iter() {
+            let phi = self.builder.build_phi(t_f64, name);
+            phi.add_incoming(&[(inert, entry_bb), (split, found_bb)]);
+            parts.push(phi.as_basic_value().into_float_value());
+        }
+        Ok((ComplexValue { re: parts[0], im: parts[1] }, ComplexValue { re: parts[2], im: parts[3] }))
+    }
+
+    /// Divides `prime` out of `rest` for as long as it goes, adding it to the list each time.
+    fn build_divide_out(&mut self, rest: (PointerValue<'ctx>, PointerValue<'ctx>), factors: PointerValue<'ctx>, prime: ComplexValue<'ctx>)
+            -> Result<(), LocatedCompileError> {
+        let zero = self.ctx.f64_type().const_zero();
+        let test_bb = self.ctx.append_basic_block(self.get_fp()?, "divide_test");
+        let body_bb = self.ctx.append_basic_block(self.get_fp()?, "divide_body");
+        let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "divide_done");
+        self.builder.build_unconditional_branch(test_bb);
+
+        // prime divides rest exactly when both parts of rest * conj(prime) are multiples of |prime|^2
+        self.set_and_move_block(test_bb)?;
+        let numer = self.complex_mul(self.load_complex(rest), self.complex_conjugate(prime));
+        let norm = self.complex_norm(prime);
+        let re_rem = self.builder.build_float_rem(numer.re, norm, "re_rem");
+        let im_rem = self.builder.build_float_rem(numer.im, norm, "im_rem");
+        let re_ok = self.builder.build_float_compare(FloatPredicate::OEQ, re_rem, zero, "re_ok");
+        let im_ok = self.builder.build_float_compare(FloatPredicate::OEQ, im_rem, zero, "im_ok");
+        let divides = self.builder.build_and(re_ok, im_ok, "divides");
+        self.builder.build_conditional_branch(divides, body_bb, cont_bb);
+
+        self.set_and_move_block(body_bb)?;
+        self.store_complex(rest, ComplexValue {
+            re: self.builder.build_float_div(numer.re, norm, "quot_re"),
+            im: self.builder.build_float_div(numer.im, norm, "quot_im"),
+        });
+        self.build_list_push(factors, &Type::Complex, Value::Complex(prime))?;
+        self.builder.build_unconditional_branch(test_bb);
+
+        self.set_and_move_block(cont_bb)
+    }
+
+    pub(super) fn load_complex(&self, (re, im): (PointerValue<'ctx>, PointerValue<'ctx>)) -> ComplexValue<'ctx> {
+        ComplexValue {
+            re: self.builder.build_load(re, "re").into_float_value(),
+            im: self.builder.build_load(im, "im").into_float_value(),
+        }
+    }
+
+    pub(super) fn store_complex(&self, (re, im): (PointerValue<'ctx>, PointerValue<'ctx>), value: ComplexValue<'ctx>) {
+        self.builder.build_store(re, value.re);
+        self.builder.build_store(im, value.im);
+    }
+}
@@ -0,0 +1,364 @@
+use inkwell::{FloatPredicate, IntPredicate, values::{BasicValueEnum, IntValue, PointerValue}};
+
+use crate::{analyse::{ComplexValue, Located, Location, Value}, error::{InternalError, LocatedCompileError}, parse::Expr};
+
+use super::Compiler;
+
+#[derive(Clone, Copy)]
+enum Align {
+    Left,
+    Right,
+    Centre,
+}
+
+/// What goes between the braces of a placeholder: `{:[<>^][width][.precision][type]}`.
+#[derive(Default)]
+struct Spec {
+    align: Option<Align>,
+    width: Option<usize>,
+    precision: Option<usize>,
+    kind: Option<char>,
+}
+
+enum Piece {
+    Text(String),
+    Value(Spec),
+}
+
+/// Splits a format string into text and placeholders. `{{` and `}}` stand for literal braces.
+fn parse_format(text: &str) -> Result<Vec<Piece>, String> {
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                current.push('{');
+            },
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                current.push('}');
+            },
+            '{' => {
+                let mut inner = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => inner.push(c),
+                        None => return Err("unclosed `{` in format string".to_owned()),
+                    }
+                }
+                if !current.is_empty() {
+                    pieces.push(Piece::Text(std::mem::take(&mut current)));
+                }
+                pieces.push(Piece::Value(parse_spec(&inner)?));
+            },
+            '}' => return Err("unmatched `}` in format string (write `}}` for a brace)".to_owned()),
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        pieces.push(Piece::Text(current));
+    }
+    Ok(pieces)
+}
+
+fn parse_spec(inner: &str) -> Result<Spec, String> {
+    let bad = || format!("invalid placeholder `{{{}}}`", inner);
+    if inner.is_empty() {
+        return Ok(Spec::default());
+    }
+    let mut rest = inner.strip_prefix(':').ok_or_else(bad)?;
+    let mut spec = Spec::default();
+
+    spec.align = match rest.chars().next() {
+        Some('<') => Some(Align::Left),
+        Some('>') => Some(Align::Right),
+        Some('^') => Some(Align::Centre),
+        _ => None,
+    };
+    if spec.align.is_some() {
+        rest = &rest[1..];
+    }
+
+    let digits = rest.chars().take_while(char::is_ascii_digit).count();
+    if digits > 0 {
+        spec.width = Some(rest[..digits].parse().map_err(|_| bad())?);
+        rest = &rest[digits..];
+    }
+    if let Some(after) = rest.strip_prefix('.') {
+        let digits = after.chars().take_while(char::is_ascii_digit).count();
+        if digits == 0 {
+            return Err(bad());
+        }
+        spec.precision = Some(after[..digits].parse().map_err(|_| bad())?);
+        rest = &after[digits..];
+    }
+
+    let mut kinds = rest.chars();
+    spec.kind = kinds.next();
+    match spec.kind {
+        None | Some('d') | Some('x') | Some('X') | Some('o') | Some('b') | Some('e') | Some('f') | Some('g')
+            if kinds.next().is_none() => Ok(spec),
+        _ => Err(bad()),
+    }
+}
+
+/// `format("...", args)` builds a string by filling in each `{}` in order. The format string has to
+/// be written out, since it's taken apart while compiling. Numbers are written like `print` does,
+/// unless the placeholder asks for a type: `d` for a whole number, `x`/`X`/`o`/`b` for hex, octal or
+/// binary, and `e`/`f`/`g` like C's `printf`. A width pads the result with spaces, on the right for
+/// strings and booleans and on the left for numbers, unless `<`, `>` or `^` says otherwise.
+impl<'ctx> Compiler<'ctx> {
+    pub(super) fn build_format(&mut self, pos: Location, mut args: Vec<Located<Expr>>) -> Result<Value<'ctx>, LocatedCompileError> {
+        if args.is_empty() {
+            return Err(LocatedCompileError::arity_at_least(pos, "format", 1, 0));
+        }
+        let (fmt, fmt_pos) = args.remove(0).unwrap();
+        let pieces = match fmt {
+            Expr::Str(text) => parse_format(&text).map_err(|msg| LocatedCompileError::unsupported(fmt_pos, msg))?,
+            _ => return Err(LocatedCompileError::unsupported(fmt_pos, "the first argument of `format` must be a string literal".to_owned())),
+        };
+        let placeholders = pieces.iter().filter(|piece| matches!(piece, Piece::Value(_))).count();
+        if placeholders != args.len() {
+            return Err(LocatedCompileError::arity(pos, "format", placeholders + 1, args.len() + 1));
+        }
+
+        let mut args = args.into_iter();
+        let mut result = self.builder.build_global_string_ptr("", ".empty_str").as_pointer_value();
+        for piece in pieces {
+            let next = match piece {
+                Piece::Text(text) => self.builder.build_global_string_ptr(&text, ".str").as_pointer_value(),
+                Piece::Value(spec) => {
+                    let arg = args.next()
+                        .ok_or_else(|| InternalError::invalid_state("ran out of arguments to format"))?;
+                    let arg_pos = arg.pos();
+                    let value = self.build_expr(arg)?;
+                    self.build_format_value(arg_pos, value, &spec)?
+                },
+            };
+            let str_concat = self.builtins.str_concat();
+            self.move_to_end()?;
+            result = self.builder.build_call(str_concat, &[result.into(), next.into()], "tmp_concat")
+                .try_as_basic_value().left()
+                    .ok_or_else(|| InternalError::invalid_state("failed to interpret return value of concat"))?
+                .into_pointer_value();
+        }
+        Ok(Value::Str(result))
+    }
+
+    /// The string `format("{}", value)` would give.
+    pub(super) fn build_to_str(&mut self, pos: Location, value: Value<'ctx>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        self.build_format_value(pos, value, &Spec::default())
+    }
+
+    fn build_format_value(&mut self, pos: Location, value: Value<'ctx>, spec: &Spec) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let (text, default_align) = match value {
+            Value::Complex(z) => (self.build_format_complex(pos, z, spec)?, Align::Right),
+            Value::Eisenstein(z) if spec.kind.is_none() => {
+                let (precision, kind) = match spec.precision {
+                    Some(precision) => (precision, 'f'),
+                    None => (12, 'g'),
+                };
+                let real = format!("%.{}{}", precision, kind);
+                let both = format!("%.{0}{1}%+.{0}{1}ω", precision, kind);
+                (self.build_format_float(z, &real, &both)?, Align::Right)
+            },
+            Value::Bool(b) if spec.kind.is_none() && spec.precision.is_none() => {
+                let t = self.builder.build_global_string_ptr("true", ".true_str").as_pointer_value();
+                let f = self.builder.build_global_string_ptr("false", ".false_str").as_pointer_value();
+                (self.builder.build_select(b, t, f, "bool_str").into_pointer_value(), Align::Left)
+            },
+            Value::Str(s) if spec.kind.is_none() => match spec.precision {
+                Some(precision) => (self.build_sprintf(&format!("%.{}s", precision), &[s.into()])?, Align::Left),
+                None => (s, Align::Left),
+            },
+            // Every part is written out, since most quaternions that get printed have all four
+            Value::Quaternion(z, w) if spec.kind.is_none() => {
+                let (precision, kind) = match spec.precision {
+                    Some(precision) => (precision, 'f'),
+                    None => (12, 'g'),
+                };
+                let fmt = format!("%.{0}{1}%+.{0}{1}i%+.{0}{1}j%+.{0}{1}k", precision, kind);
+                (self.build_sprintf(&fmt, &[z.re.into(), z.im.into(), w.re.into(), w.im.into()])?, Align::Right)
+            },
+            Value::Rational(num, den) if spec.kind.is_none() && spec.precision.is_none() =>
+                (self.build_format_rational(pos, num, den)?, Align::Right),
+            Value::Eisenstein(_) | Value::Quaternion(..) | Value::Rational(..) | Value::Bool(_) | Value::Str(_) =>
+                return Err(LocatedCompileError::unsupported(pos, format!("cannot format `{}` as a number", value.ty()))),
+            value => return Err(LocatedCompileError::unsupported(pos, format!("cannot format `{}`", value.ty()))),
+        };
+        match spec.width {
+            Some(width) => self.build_pad(text, width, spec.align.unwrap_or(default_align)),
+            None => Ok(text),
+        }
+    }
+
+    fn build_format_complex(&mut self, pos: Location, z: ComplexValue<'ctx>, spec: &Spec) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let i64_type = self.ctx.i64_type();
+        let conversion = match spec.kind {
+            Some('d') => "lld",
+            Some('x') => "llx",
+            Some('X') => "llX",
+            Some('o') => "llo",
+            Some('b') => return self.build_format_binary(pos, z),
+            Some(kind) => {
+                let precision = spec.precision.unwrap_or(6);
+                let real = format!("%.{}{}", precision, kind);
+                let both = format!("%.{0}{1}%+.{0}{1}i", precision, kind);
+                return self.build_format_float(z, &real, &both);
+            },
+            None => {
+                let (precision, kind) = match spec.precision {
+                    Some(precision) => (precision, 'f'),
+                    None => (12, 'g'),
+                };
+                let real = format!("%.{}{}", precision, kind);
+                let both = format!("%.{0}{1}%+.{0}{1}i", precision, kind);
+                return self.build_format_float(z, &real, &both);
+            },
+        };
+
+        let n = self.build_format_integer(pos, z, spec.kind.unwrap_or('d'))?;
+        if conversion == "lld" {
+            return self.build_sprintf("%lld", &[n.into()]);
+        }
+        // Hex and octal are unsigned in C, so the sign goes in front by hand
+        let negative = self.builder.build_int_compare(IntPredicate::SLT, n, i64_type.const_zero(), "negative");
+        let negated = self.builder.build_int_neg(n, "negated");
+        let magnitude = self.builder.build_select(negative, negated, n, "magnitude");
+        let positive_fmt = self.builder.build_global_string_ptr(&format!("%{}", conversion), ".format").as_pointer_value();
+        let negative_fmt = self.builder.build_global_string_ptr(&format!("-%{}", conversion), ".format").as_pointer_value();
+        let fmt = self.builder.build_select(negative, negative_fmt, positive_fmt, "fmt").into_pointer_value();
+        self.build_sprintf_with(fmt, &[magnitude.into()])
+    }
+
+    /// `p/q`, with parentheses around either part that isn't real, or just `p` if `q` is 1.
+    fn build_format_rational(&mut self, pos: Location, num: ComplexValue<'ctx>, den: ComplexValue<'ctx>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let zero = self.ctx.f64_type().const_zero();
+        let mut parts = Vec::new();
+        for z in [num, den].iter() {
+            let text = self.build_to_str(pos, Value::Complex(*z))?;
+            let wrapped = self.build_sprintf("(%s)", &[text.into()])?;
+            let real = self.builder.build_float_compare(FloatPredicate::OEQ, z.im, zero, "real");
+            parts.push(self.builder.build_select(real, text, wrapped, "part"));
+        }
+        let fraction = self.build_sprintf("%s/%s", &parts)?;
+        let one = self.builder.build_float_compare(FloatPredicate::OEQ, den.re, self.ctx.f64_type().const_float(1.), "one");
+        let real = self.builder.build_float_compare(FloatPredicate::OEQ, den.im, zero, "real");
+        let whole = self.builder.build_and(one, real, "whole");
+        let num_text = self.build_to_str(pos, Value::Complex(num))?;
+        Ok(self.builder.build_select(whole, num_text, fraction, "rational").into_pointer_value())
+    }
+
+    /// Writes just the real part when the imaginary part is zero.
+    fn build_format_float(&mut self, z: ComplexValue<'ctx>, real: &str, both: &str) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let is_real = self.builder.build_float_compare(FloatPredicate::OEQ, z.im, self.ctx.f64_type().const_zero(), "is_real");
+        let real_fmt = self.builder.build_global_string_ptr(real, ".format").as_pointer_value();
+        let both_fmt = self.builder.build_global_string_ptr(both, ".format").as_pointer_value();
+        let fmt = self.builder.build_select(is_real, real_fmt, both_fmt, "fmt").into_pointer_value();
+        self.build_sprintf_with(fmt, &[z.re.into(), z.im.into()])
+    }
+
+    /// Aborts unless the number is a real integer, and returns it.
+    fn build_format_integer(&mut self, pos: Location, z: ComplexValue<'ctx>, kind: char) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        self.build_real_integer(pos, z, &format!("`{{:{}}}` needs a real integer", kind))
+    }
+
+    /// C has no conversion for binary, so this writes the digits into a buffer from the end.
+    fn build_format_binary(&mut self, pos: Location, z: ComplexValue<'ctx>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let i8_type = self.ctx.i8_type();
+        let i64_type = self.ctx.i64_type();
+        let n = self.build_format_integer(pos, z, 'b')?;
+        let negative = self.builder.build_int_compare(IntPredicate::SLT, n, i64_type.const_zero(), "negative");
+        let negated = self.builder.build_int_neg(n, "negated");
+        let magnitude = self.builder.build_select(negative, negated, n, "magnitude").into_int_value();
+
+        // A sign, 64 digits and the terminator
+        let buf = self.builder.build_array_malloc(i8_type, i64_type.const_int(66, false), "bin")
+            .map_err(InternalError::invalid_state)?;
+        let end = unsafe { self.builder.build_in_bounds_gep(buf, &[i64_type.const_int(65, false)], "end") };
+        self.builder.build_store(end, i8_type.const_zero());
+
+        let entry_bb = self.get_block()?;
+        let loop_bb = self.ctx.append_basic_block(self.get_fp()?, "digit");
+        let done_bb = self.ctx.append_basic_block(self.get_fp()?, "digits_done");
+        self.builder.build_unconditional_branch(loop_bb);
+
+        // Always writes at least one digit, so that zero comes out as `0`
+        self.set_and_move_block(loop_bb)?;
+        let rest = self.builder.build_phi(i64_type, "rest");
+        let index = self.builder.build_phi(i64_type, "index");
+        let index_next = self.builder.build_int_sub(index.as_basic_value().into_int_value(), i64_type.const_int(1, false), "index_next");
+        let bit = self.builder.build_and(rest.as_basic_value().into_int_value(), i64_type.const_int(1, false), "bit");
+        let bit = self.builder.build_int_truncate(bit, i8_type, "bit");
+        let digit = self.builder.build_int_add(bit, i8_type.const_int(b'0' as u64, false), "digit");
+        let ptr = unsafe { self.builder.build_in_bounds_gep(buf, &[index_next], "digit_ptr") };
+        self.builder.build_store(ptr, digit);
+        let rest_next = self.builder.build_right_shift(rest.as_basic_value().into_int_value(), i64_type.const_int(1, false), false, "rest_next");
+        let more = self.builder.build_int_compare(IntPredicate::NE, rest_next, i64_type.const_zero(), "more");
+        rest.add_incoming(&[(&magnitude, entry_bb), (&rest_next, loop_bb)]);
+        index.add_incoming(&[(&i64_type.const_int(65, false), entry_bb), (&index_next, loop_bb)]);
+        self.builder.build_conditional_branch(more, loop_bb, done_bb);
+
+        self.set_and_move_block(done_bb)?;
+        let sign_index = self.builder.build_int_sub(index_next, i64_type.const_int(1, false), "sign_index");
+        let sign_ptr = unsafe { self.builder.build_in_bounds_gep(buf, &[sign_index], "sign_ptr") };
+        self.builder.build_store(sign_ptr, i8_type.const_int(b'-' as u64, false));
+        let start = self.builder.build_select(negative, sign_index, index_next, "start").into_int_value();
+        Ok(unsafe { self.builder.build_in_bounds_gep(buf, &[start], "bin_str") })
+    }
+
+    fn build_pad(&mut self, text: PointerValue<'ctx>, width: usize, align: Align) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        match align {
+            Align::Left => self.build_sprintf(&format!("%-{}s", width), &[text.into()]),
+            Align::Right => self.build_sprintf(&format!("%{}s", width), &[text.into()]),
+            Align::Centre => {
+                let i32_type = self.ctx.i32_type();
+                let strlen = self.builtins.strlen();
+                self.move_to_end()?;
+                let len = self.builder.build_call(strlen, &[text.into()], "len")
+                    .try_as_basic_value().left()
+                        .ok_or_else(|| InternalError::invalid_state("failed to interpret return value of strlen"))?
+                    .into_int_value();
+                let len = self.builder.build_int_truncate(len, i32_type, "len");
+                let width = i32_type.const_int(width as u64, false);
+                let short = self.builder.build_int_compare(IntPredicate::SLT, len, width, "short");
+                let pad = self.builder.build_int_sub(width, len, "pad");
+                let pad = self.builder.build_select(short, pad, i32_type.const_zero(), "pad").into_int_value();
+                let left = self.builder.build_int_signed_div(pad, i32_type.const_int(2, false), "left");
+                let right = self.builder.build_int_sub(pad, left, "right");
+                let empty = self.builder.build_global_string_ptr("", ".empty_str").as_pointer_value();
+                self.build_sprintf("%*s%s%*s", &[left.into(), empty.into(), text.into(), right.into(), empty.into()])
+            },
+        }
+    }
+
+    fn build_sprintf(&mut self, fmt: &str, args: &[BasicValueEnum<'ctx>]) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let fmt = self.builder.build_global_string_ptr(fmt, ".format").as_pointer_value();
+        self.build_sprintf_with(fmt, args)
+    }
+
+    /// Runs `snprintf` once to find out how long the result is, then again into a buffer that size.
+    fn build_sprintf_with(&mut self, fmt: PointerValue<'ctx>, args: &[BasicValueEnum<'ctx>]) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let i64_type = self.ctx.i64_type();
+        let snprintf = self.builtins.snprintf();
+        self.move_to_end()?;
+        let null = self.ctx.i8_type().ptr_type(inkwell::AddressSpace::Generic).const_null();
+        let mut call_args: Vec<BasicValueEnum> = vec![null.into(), i64_type.const_zero().into(), fmt.into()];
+        call_args.extend_from_slice(args);
+        let len = self.builder.build_call(snprintf, &call_args, "len")
+            .try_as_basic_value().left()
+                .ok_or_else(|| InternalError::invalid_state("failed to interpret return value of snprintf"))?
+            .into_int_value();
+        let len = self.builder.build_int_s_extend(len, i64_type, "len");
+        let size = self.builder.build_int_add(len, i64_type.const_int(1, false), "size");
+        let buf = self.builder.build_array_malloc(self.ctx.i8_type(), size, "formatted")
+            .map_err(InternalError::invalid_state)?;
+        call_args[0] = buf.into();
+        call_args[1] = size.into();
+        self.builder.build_call(snprintf, &call_args, "call");
+        Ok(buf)
+    }
+}
@@ -0,0 +1,90 @@
+use inkwell::{FloatPredicate, values::PointerValue};
+
+use crate::{analyse::{ComplexValue, Location, Type, Value}, error::LocatedCompileError};
+
+use super::Compiler;
+
+/// Continued fractions of `p / q`, found by running the Euclidean algorithm and keeping the
+/// quotients. The division is the same as `divmod`'s, so real fractions get the usual expansion with
+/// floored terms, and Gaussian ones get terms rounded to the nearest Gaussian integer. Either way,
+/// the expansion is finite.
+impl<'ctx> Compiler<'ctx> {
+    /// `cf(p, q)`, the list of terms `[a0, a1, ...]` with `p / q == a0 + 1 / (a1 + 1 / ...)`.
+    pub(super) fn build_cf(&mut self, pos: Location, p: ComplexValue<'ctx>, q: ComplexValue<'ctx>)
+            -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        self.check_gaussian(pos, p, q, "continued fractions need Gaussian integers")?;
+        let t_f64 = self.ctx.f64_type();
+        let zero = t_f64.const_zero();
+        let nonzero = self.builder.build_float_compare(FloatPredicate::ONE, self.complex_norm(q), zero, "nonzero");
+        self.build_check(nonzero, pos, "division by zero")?;
+
+        let terms = self.build_list_alloc(&Type::Complex, self.ctx.i64_type().const_zero())?;
+        let numer = (self.builder.build_alloca(t_f64, "numer_re"), self.builder.build_alloca(t_f64, "numer_im"));
+        let denom = (self.builder.build_alloca(t_f64, "denom_re"), self.builder.build_alloca(t_f64, "denom_im"));
+        self.store_complex(numer, p);
+        self.store_complex(denom, q);
+
+        let test_bb = self.ctx.append_basic_block(self.get_fp()?, "cf_test");
+        let body_bb = self.ctx.append_basic_block(self.get_fp()?, "cf_body");
+        let done_bb = self.ctx.append_basic_block(self.get_fp()?, "cf_done");
+        self.builder.build_unconditional_branch(test_bb);
+
+        self.set_and_move_block(test_bb)?;
+        let b = self.load_complex(denom);
+        let more = self.builder.build_float_compare(FloatPredicate::ONE, self.complex_norm(b), zero, "more");
+        self.builder.build_conditional_branch(more, body_bb, done_bb);
+
+        self.set_and_move_block(body_bb)?;
+        let a = self.load_complex(numer);
+        let (term, rem) = self.complex_divmod(pos, a, b)?;
+        self.build_list_push(terms, &Type::Complex, Value::Complex(term))?;
+        self.store_complex(numer, b);
+        self.store_complex(denom, rem);
+        self.builder.build_unconditional_branch(test_bb);
+
+        self.set_and_move_block(done_bb)?;
+        Ok(terms)
+    }
+
+    /// `convergents(p, q)`, the list of `(h, k)` where each `h / k` is the continued fraction cut
+    /// off after another term. The last one is `p / q` in lowest terms.
+    pub(super) fn build_convergents(&mut self, pos: Location, p: ComplexValue<'ctx>, q: ComplexValue<'ctx>)
+            -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let terms = self.build_cf(pos, p, q)?;
+        let t_f64 = self.ctx.f64_type();
+        let zero = ComplexValue { re: t_f64.const_zero(), im: t_f64.const_zero() };
+        let one = ComplexValue { re: t_f64.const_float(1.), im: t_f64.const_zero() };
+        let elem = Type::Tuple(vec![Type::Complex, Type::Complex]);
+        let res = self.build_list_alloc(&elem, self.ctx.i64_type().const_zero())?;
+
+        // h(n) = a(n) h(n - 1) + h(n - 2), starting from h(-1) = 1, h(-2) = 0, and the same for k
+        // starting from k(-1) = 0, k(-2) = 1
+        let alloc = |this: &Self, name| (this.builder.build_alloca(t_f64, name), this.builder.build_alloca(t_f64, name));
+        let (h, h_prev, k, k_prev) = (alloc(self, "h"), alloc(self, "h_prev"), alloc(self, "k"), alloc(self, "k_prev"));
+        self.store_complex(h, one);
+        self.store_complex(h_prev, zero);
+        self.store_complex(k, zero);
+        self.store_complex(k_prev, one);
+
+        let len = self.list_len(terms)?;
+        self.build_counted_loop(len, |this, n| {
+            let term = this.build_list_nth(terms, &Type::Complex, n)?;
+            let term = this.expect_complex(pos, term)?;
+            let mut next = Vec::new();
+            for (cur, prev) in [(h, h_prev), (k, k_prev)].iter() {
+                let cur_val = this.load_complex(*cur);
+                let prev_val = this.load_complex(*prev);
+                let product = this.complex_mul(term, cur_val);
+                let val = ComplexValue {
+                    re: this.builder.build_float_add(product.re, prev_val.re, "next_re"),
+                    im: this.builder.build_float_add(product.im, prev_val.im, "next_im"),
+                };
+                this.store_complex(*prev, cur_val);
+                this.store_complex(*cur, val);
+                next.push(Value::Complex(val));
+            }
+            this.build_list_push(res, &elem, Value::Tuple(next))
+        })?;
+        Ok(res)
+    }
+}
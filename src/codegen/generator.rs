@@ -0,0 +1,90 @@
+use std::rc::Rc;
+
+use crate::{analyse::{Located, Location, Variable}, error::{InternalError, LocatedCompileError}, parse::{Expr, Func, Statement}};
+
+use super::{Compiler, GeneratorFrame, LoopBlocks};
+
+/// A generator is never called. Instead, `for x in gen(args) { ... }` compiles the generator's body
+/// where the loop is, and every `yield value` runs the loop body with `x` bound to the value. So a
+/// generator can go on forever, as long as the loop breaks out eventually. Since the body is copied
+/// into the loop, a generator can't iterate over itself.
+impl<'ctx> Compiler<'ctx> {
+    pub(super) fn build_generator_for(&mut self, label: Option<Located<String>>, id: Located<String>, func: Rc<Func>,
+            iter: Located<Expr>, body: Vec<Located<Statement>>) -> Result<(), LocatedCompileError> {
+        let (iter, pos) = iter.unwrap();
+        let (args, named) = match iter {
+            Expr::Call(_, args, named) => (args, named),
+            _ => return Err(InternalError::invalid_state("generator loop without a call")),
+        };
+        let name = func.name.borrow_val().clone();
+        if self.generators.iter().any(|frame| frame.name == name) {
+            return Err(LocatedCompileError::unsupported(pos, format!("generator `{}` cannot iterate over itself", name)));
+        }
+        if let Some(result) = &func.result {
+            return Err(LocatedCompileError::unsupported(result.pos(), format!("generator `{}` cannot have a result", name)));
+        }
+        let values = self.build_user_args(pos, &func, args, named)?;
+        let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "cont");
+
+        // The generator can't see the loop's variables, just like any other function
+        let vars = std::mem::take(&mut self.sym.var_map);
+        let loops = std::mem::take(&mut self.loops);
+        let file = std::mem::replace(&mut self.sym.file, func.name.pos().file);
+        for (param, value) in func.params.iter().zip(values) {
+            let ptr = self.builder.build_alloca(self.llvm_type(&value.ty()), param.borrow_val());
+            let packed = self.pack(&value)?;
+            self.builder.build_store(ptr, packed);
+            self.sym.add_var(param.clone(), Variable { ptr, ty: value.ty(), mutable: false });
+        }
+        self.generators.push(GeneratorFrame { name, label: label.map(Located::val), id, body, vars, loops, file, cont_bb });
+        for statement in func.body.clone() {
+            self.build_statement(statement)?;
+        }
+        let frame = self.generators.pop()
+            .ok_or_else(|| InternalError::invalid_state("lost track of the generator"))?;
+        self.builder.build_unconditional_branch(cont_bb);
+
+        self.sym.var_map = frame.vars;
+        self.loops = frame.loops;
+        self.sym.file = frame.file;
+        self.set_and_move_block(cont_bb)
+    }
+
+    /// Runs the loop body with the value, then carries on with the generator. Inside the loop body,
+    /// `yield` and `return` belong to whatever generator the loop is written in, if any.
+    pub(super) fn build_yield(&mut self, pos: Location, expr: Located<Expr>) -> Result<(), LocatedCompileError> {
+        let frame = self.generators.pop()
+            .ok_or_else(|| LocatedCompileError::unsupported(pos, "`yield` only works in a generator iterated by `for`".to_owned()))?;
+        let value = self.build_expr(expr)?;
+        let next_bb = self.ctx.append_basic_block(self.get_fp()?, "next");
+
+        let generator_vars = std::mem::replace(&mut self.sym.var_map, frame.vars.clone());
+        let generator_loops = std::mem::replace(&mut self.loops, frame.loops.clone());
+        let generator_file = std::mem::replace(&mut self.sym.file, frame.file);
+        let ptr = self.builder.build_alloca(self.llvm_type(&value.ty()), frame.id.borrow_val());
+        let packed = self.pack(&value)?;
+        self.builder.build_store(ptr, packed);
+        self.sym.add_var(frame.id.clone(), Variable { ptr, ty: value.ty(), mutable: false });
+        self.loops.push(LoopBlocks { label: frame.label.clone(), continue_bb: next_bb, break_bb: frame.cont_bb, values: None });
+        self.build_block(frame.body.clone())?;
+        self.builder.build_unconditional_branch(next_bb);
+
+        self.set_and_move_block(next_bb)?;
+        self.sym.var_map = generator_vars;
+        self.loops = generator_loops;
+        self.sym.file = generator_file;
+        self.generators.push(frame);
+        Ok(())
+    }
+
+    /// Finishes iterating over the generator.
+    pub(super) fn build_generator_return(&mut self, pos: Location, expr: Option<Located<Expr>>) -> Result<(), LocatedCompileError> {
+        if let Some(expr) = expr {
+            return Err(LocatedCompileError::unsupported(expr.pos(), "a generator cannot return a value".to_owned()));
+        }
+        let cont_bb = self.generators.last()
+            .ok_or_else(|| InternalError::invalid_state("lost track of the generator"))?
+            .cont_bb;
+        self.build_jump(cont_bb)
+    }
+}
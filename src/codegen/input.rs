@@ -0,0 +1,171 @@
+use inkwell::{FloatPredicate, IntPredicate, values::{BasicValueEnum, IntValue, PointerValue}};
+
+use crate::{analyse::{ComplexValue, Location, Type, Value}, error::{InternalError, LocatedCompileError}};
+
+use super::Compiler;
+
+/// Where the real or imaginary part comes from, in one of the ways a number can be written.
+#[derive(Clone, Copy)]
+enum Part {
+    Zero,
+    Read,
+    // `i` on its own, as in `1 + i`
+    Unit,
+}
+
+/// The ways `read_complex` and `parse` accept a number, tried in order: `1 - 4i`, `1 + i`, `-2.5i`,
+/// `-i`, `i` and `3`, with any spacing. Parts are decimal, with an optional exponent like `1e-3`.
+const FORMATS: [(&str, Part, Part); 6] = [
+    (" %lf %1[+-] %lf %1[i] %c", Part::Read, Part::Read),
+    (" %lf %1[+-] %1[i] %c", Part::Read, Part::Unit),
+    (" %lf %1[i] %c", Part::Zero, Part::Read),
+    (" %1[+-] %1[i] %c", Part::Zero, Part::Unit),
+    (" %1[i] %c", Part::Zero, Part::Unit),
+    (" %lf %c", Part::Read, Part::Zero),
+];
+
+/// Reading from stdin. A line is read up to the newline, and `read_complex` (like `parse`) accepts
+/// numbers the way they're written in code (see `FORMATS`), but not `inf`, `nan` or hexadecimal.
+impl<'ctx> Compiler<'ctx> {
+    /// `input()` or `input(prompt)`, which prints the prompt on the same line first.
+    pub(super) fn build_input(&mut self, prompt: Option<(Location, Value<'ctx>)>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        if let Some((prompt_pos, prompt)) = prompt {
+            match prompt {
+                Value::Str(_) => self.print_value(prompt_pos, prompt, false)?,
+                prompt => return Err(LocatedCompileError::type_mismatch(prompt_pos, &Type::Str, &prompt.ty())),
+            }
+        }
+        let read_line = self.builtins.read_line();
+        self.move_to_end()?;
+        Ok(self.builder.build_call(read_line, &[], "line")
+            .try_as_basic_value().left()
+                .ok_or_else(|| InternalError::invalid_state("failed to interpret return value of read_line"))?
+            .into_pointer_value())
+    }
+
+    /// Reads a line and makes a number out of it, which aborts if it isn't one.
+    pub(super) fn build_read_complex(&mut self, pos: Location, prompt: Option<(Location, Value<'ctx>)>) -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+        let line = self.build_input(prompt)?;
+        self.build_parse_complex(pos, line)
+    }
+
+    /// Makes a number out of a string, like `parse` does, which aborts if it isn't one.
+    pub(super) fn build_parse_complex(&mut self, pos: Location, line: PointerValue<'ctx>) -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+        let f64_type = self.ctx.f64_type();
+        let i8_type = self.ctx.i8_type();
+
+        // Only characters that can be part of a number are allowed, which keeps `sscanf` from
+        // reading `inf`, `nan` or hexadecimal
+        let strspn = self.builtins.strspn();
+        let strlen = self.builtins.strlen();
+        self.move_to_end()?;
+        let allowed = self.builder.build_global_string_ptr("0123456789.eE+-i \t\r", ".number_chars").as_pointer_value();
+        let span = self.builder.build_call(strspn, &[line.into(), allowed.into()], "span")
+            .try_as_basic_value().left()
+                .ok_or_else(|| InternalError::invalid_state("failed to interpret return value of strspn"))?
+            .into_int_value();
+        let len = self.builder.build_call(strlen, &[line.into()], "len")
+            .try_as_basic_value().left()
+                .ok_or_else(|| InternalError::invalid_state("failed to interpret return value of strlen"))?
+            .into_int_value();
+        let ok = self.builder.build_int_compare(IntPredicate::EQ, span, len, "ok");
+        self.build_check(ok, pos, "not a number")?;
+
+        let re_ptr = self.builder.build_alloca(f64_type, "re_ptr");
+        let im_ptr = self.builder.build_alloca(f64_type, "im_ptr");
+        let sign_ptr = self.builder.build_alloca(i8_type.array_type(2), "sign_ptr");
+        let i_ptr = self.builder.build_alloca(i8_type.array_type(2), "i_ptr");
+        let rest_ptr = self.builder.build_alloca(i8_type, "rest_ptr");
+        let sign_ptr = self.builder.build_pointer_cast(sign_ptr, i8_type.ptr_type(inkwell::AddressSpace::Generic), "sign_ptr");
+        let i_ptr = self.builder.build_pointer_cast(i_ptr, i8_type.ptr_type(inkwell::AddressSpace::Generic), "i_ptr");
+        let zero = f64_type.const_zero();
+        let one = f64_type.const_float(1.);
+        let done_bb = self.ctx.append_basic_block(self.get_fp()?, "read_done");
+        let mut incoming = Vec::new();
+
+        // Each attempt only counts if it matches and nothing but spaces follow. The trailing `%c`
+        // picks up anything else, which makes the count one too many.
+        for (k, (fmt, re_part, im_part)) in FORMATS.iter().enumerate() {
+            let signed = fmt.contains("[+-]");
+            let mut ptrs: Vec<BasicValueEnum> = Vec::new();
+            if let Part::Read = re_part {
+                ptrs.push(re_ptr.into());
+            }
+            if signed {
+                ptrs.push(sign_ptr.into());
+            }
+            if let Part::Read = im_part {
+                ptrs.push(im_ptr.into());
+            }
+            if !matches!(im_part, Part::Zero) {
+                ptrs.push(i_ptr.into());
+            }
+            ptrs.push(rest_ptr.into());
+            let matched = self.build_scan(line, fmt, &ptrs, ptrs.len() as u64 - 1)?;
+
+            let re = match re_part {
+                Part::Read => self.builder.build_load(re_ptr, "re").into_float_value(),
+                _ => zero,
+            };
+            let im = match im_part {
+                Part::Read => self.builder.build_load(im_ptr, "im").into_float_value(),
+                Part::Unit => one,
+                Part::Zero => zero,
+            };
+            let im = if signed {
+                let sign = self.builder.build_load(sign_ptr, "sign").into_int_value();
+                let negative = self.builder.build_int_compare(IntPredicate::EQ, sign, i8_type.const_int(b'-' as u64, false), "negative");
+                let negated = self.builder.build_float_neg(im, "negated");
+                self.builder.build_select(negative, negated, im, "im").into_float_value()
+            } else {
+                im
+            };
+            if k + 1 < FORMATS.len() {
+                incoming.push((self.get_block()?, re, im));
+                let next_bb = self.ctx.append_basic_block(self.get_fp()?, "read_next");
+                self.builder.build_conditional_branch(matched, done_bb, next_bb);
+                self.set_and_move_block(next_bb)?;
+            } else {
+                self.build_check(matched, pos, "not a number")?;
+                incoming.push((self.get_block()?, re, im));
+                self.builder.build_unconditional_branch(done_bb);
+            }
+        }
+
+        self.set_and_move_block(done_bb)?;
+        let re = self.builder.build_phi(f64_type, "re");
+        let im = self.builder.build_phi(f64_type, "im");
+        for (block, re_val, im_val) in incoming {
+            re.add_incoming(&[(&re_val, block)]);
+            im.add_incoming(&[(&im_val, block)]);
+        }
+        let z = ComplexValue { re: re.as_basic_value().into_float_value(), im: im.as_basic_value().into_float_value() };
+
+        // Digits alone can still be too big for a double, like `1e999`
+        let fabs = self.builtins.abs();
+        self.move_to_end()?;
+        let infinity = f64_type.const_float(f64::INFINITY);
+        let re_abs = self.call_float(fabs, &[z.re.into()], "re_abs")?;
+        let im_abs = self.call_float(fabs, &[z.im.into()], "im_abs")?;
+        let re_finite = self.builder.build_float_compare(FloatPredicate::OLT, re_abs, infinity, "re_finite");
+        let im_finite = self.builder.build_float_compare(FloatPredicate::OLT, im_abs, infinity, "im_finite");
+        let finite = self.builder.build_and(re_finite, im_finite, "finite");
+        self.build_check(finite, pos, "number is too big")?;
+        Ok(z)
+    }
+
+    /// Whether `sscanf` filled in exactly `expected` values.
+    fn build_scan(&mut self, line: PointerValue<'ctx>, fmt: &str, ptrs: &[BasicValueEnum<'ctx>], expected: u64)
+            -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let sscanf = self.builtins.sscanf();
+        self.move_to_end()?;
+        let fmt = self.builder.build_global_string_ptr(fmt, ".scan_format").as_pointer_value();
+        let mut args: Vec<BasicValueEnum> = vec![line.into(), fmt.into()];
+        args.extend_from_slice(ptrs);
+        let count = self.builder.build_call(sscanf, &args, "count")
+            .try_as_basic_value().left()
+                .ok_or_else(|| InternalError::invalid_state("failed to interpret return value of sscanf"))?
+            .into_int_value();
+        Ok(self.builder.build_int_compare(IntPredicate::EQ, count, self.ctx.i32_type().const_int(expected, false), "matched"))
+    }
+}
@@ -0,0 +1,66 @@
+use inkwell::{types::StructType, values::PointerValue};
+
+use crate::{analyse::{Located, Location, Type, Value}, error::{InternalError, LocatedCompileError}, parse::Expr};
+
+use super::Compiler;
+
+/// A `lazy` value is a pointer to `{ i1 forced, T value, env }`, where `env` is what the closure
+/// computing the value captured. The first use runs the closure and keeps its result; later uses
+/// just load it. Until then, the value field is zero, but it should never be looked at.
+impl<'ctx> Compiler<'ctx> {
+    pub(super) fn lazy_type(&self, inner: &Type, closure: &Type) -> StructType<'ctx> {
+        self.ctx.struct_type(&[self.ctx.bool_type().into(), self.llvm_type(inner), self.llvm_type(closure)], false)
+    }
+
+    fn lazy_field(&self, thunk: PointerValue<'ctx>, index: u32, name: &str) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        self.builder.build_struct_gep(thunk, index, name)
+            .map_err(|_| InternalError::invalid_state("malformed lazy value"))
+    }
+
+    /// Captures what `expr` needs without evaluating it. The closure is compiled here, since that's
+    /// the only way to find out what type the value will have.
+    pub(super) fn build_lazy(&mut self, pos: Location, expr: Located<Expr>) -> Result<Value<'ctx>, LocatedCompileError> {
+        let closure = self.build_lambda(pos, Vec::new(), expr)?;
+        let env = match &closure {
+            Value::Closure(_, env) => env.iter().map(|(_, value)| value.ty()).collect(),
+            _ => return Err(InternalError::invalid_state("`lazy` did not build a closure")),
+        };
+        let func = self.sym.lambda_map.get(&pos).cloned()
+            .ok_or_else(|| InternalError::invalid_state("unknown closure"))?;
+        let inner = self.instance(&func, env)?.ret;
+        let closure_type = closure.ty();
+
+        let thunk = self.builder.build_malloc(self.lazy_type(&inner, &closure_type), "lazy")
+            .map_err(InternalError::invalid_state)?;
+        self.builder.build_store(self.lazy_field(thunk, 0, "forced_ptr")?, self.ctx.bool_type().const_zero());
+        self.builder.build_store(self.lazy_field(thunk, 1, "value_ptr")?, self.llvm_type(&inner).const_zero());
+        self.builder.build_store(self.lazy_field(thunk, 2, "env_ptr")?, self.pack(&closure)?);
+        Ok(Value::Lazy(thunk, Box::new(inner), Box::new(closure_type)))
+    }
+
+    /// Computes the value if nobody has yet, then returns it.
+    pub(super) fn build_force(&mut self, thunk: PointerValue<'ctx>, inner: &Type, closure: &Type) -> Result<Value<'ctx>, LocatedCompileError> {
+        let forced_ptr = self.lazy_field(thunk, 0, "forced_ptr")?;
+        let forced = self.builder.build_load(forced_ptr, "forced").into_int_value();
+        let compute_bb = self.ctx.append_basic_block(self.get_fp()?, "force");
+        let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "cont");
+        self.builder.build_conditional_branch(forced, cont_bb, compute_bb);
+
+        self.set_and_move_block(compute_bb)?;
+        let env = self.builder.build_load(self.lazy_field(thunk, 2, "env_ptr")?, "env");
+        let (pos, env) = match self.unpack(env, closure)? {
+            Value::Closure(pos, env) => (pos, env),
+            _ => return Err(InternalError::invalid_state("lazy value did not hold a closure")),
+        };
+        let func = self.sym.lambda_map.get(&pos).cloned()
+            .ok_or_else(|| InternalError::invalid_state("unknown closure"))?;
+        let value = self.call_func(&func, env.into_iter().map(|(_, value)| value).collect())?;
+        self.builder.build_store(self.lazy_field(thunk, 1, "value_ptr")?, self.pack(&value)?);
+        self.builder.build_store(forced_ptr, self.ctx.bool_type().const_int(1, false));
+        self.builder.build_unconditional_branch(cont_bb);
+
+        self.set_and_move_block(cont_bb)?;
+        let value = self.builder.build_load(self.lazy_field(thunk, 1, "value_ptr")?, "value");
+        self.unpack(value, inner)
+    }
+}
@@ -0,0 +1,1089 @@
+use std::rc::Rc;
+
+use inkwell::{FloatPredicate, IntPredicate, values::{FloatValue, IntValue, PointerValue}};
+
+use crate::{analyse::{ComplexValue, Located, Location, RangeValue, Type, Value}, error::{InternalError, LocatedCompileError}, parse::Expr};
+
+use super::Compiler;
+
+/// Builtin functions callable from the language.
+impl<'ctx> Compiler<'ctx> {
+    pub(super) fn build_call(&mut self, pos: Location, id: Located<String>, args: Vec<Located<Expr>>) -> Result<Value<'ctx>, LocatedCompileError> {
+        // The format string is taken apart while compiling, so it can't be evaluated first
+        if id.borrow_val() == "format" {
+            return self.build_format(pos, args);
+        }
+
+        // Builtins that mutate their first argument need it to be a mutable variable
+        let mutates = match id.borrow_val().as_str() {
+            "push" => Some(("push to", 2)),
+            "insert" => Some(("insert into", 3)),
+            _ => None,
+        };
+        if let Some((action, arity)) = mutates {
+            match args.first() {
+                Some(arg) => match arg.borrow_val() {
+                    Expr::Id(name) => self.check_mutable(pos, name)?,
+                    _ => return Err(LocatedCompileError::unsupported(arg.pos(), format!("can only {} a mutable variable", action))),
+                },
+                None => return Err(LocatedCompileError::arity(pos, id.borrow_val(), arity, 0)),
+            }
+        }
+
+        let mut values = Vec::new();
+        let mut positions = Vec::new();
+        for arg in args {
+            positions.push(arg.pos());
+            values.push(self.build_expr(arg)?);
+        }
+
+        let check_arity = |expected: usize| {
+            if values.len() == expected {
+                Ok(())
+            } else {
+                Err(LocatedCompileError::arity(pos, id.borrow_val(), expected, values.len()))
+            }
+        };
+
+        match id.borrow_val().as_str() {
+            "len" => {
+                check_arity(1)?;
+                match values.remove(0) {
+                    Value::Range(range) => Ok(Value::Complex(self.real(range.len))),
+                    // This counts code points, like slicing does, not bytes
+                    Value::Str(ptr) => {
+                        let (_, len) = self.build_str_offsets(ptr)?;
+                        let len = self.builder.build_unsigned_int_to_float(len, self.ctx.f64_type(), "len_float");
+                        Ok(Value::Complex(self.real(len)))
+                    },
+                    Value::List(list, _) | Value::Set(list, _) | Value::Dict(list, ..) => {
+                        let len = self.list_len(list)?;
+                        let len = self.builder.build_signed_int_to_float(len, self.ctx.f64_type(), "len_float");
+                        Ok(Value::Complex(self.real(len)))
+                    },
+                    value => Err(LocatedCompileError::unsupported(positions[0], format!("`{}` has no length", value.ty()))),
+                }
+            },
+            "sum" => {
+                check_arity(1)?;
+                match values.remove(0) {
+                    Value::Range(range) => Ok(Value::Complex(self.range_sum(range))),
+                    Value::List(list, Type::Complex) => self.list_sum(list).map(Value::Complex),
+                    value => Err(LocatedCompileError::type_mismatch(positions[0], &Type::List(Box::new(Type::Complex)), &value.ty())),
+                }
+            },
+            "push" => {
+                check_arity(2)?;
+                let value = values.remove(1);
+                match values.remove(0) {
+                    Value::List(list, elem) => {
+                        if value.ty() != elem {
+                            return Err(LocatedCompileError::type_mismatch(positions[1], &elem, &value.ty()));
+                        }
+                        self.build_list_push(list, &elem, value)?;
+                        Ok(Value::List(list, elem))
+                    },
+                    other => Err(LocatedCompileError::type_mismatch(positions[0], &Type::List(Box::new(value.ty())), &other.ty())),
+                }
+            },
+            "list" => {
+                check_arity(1)?;
+                let range = self.expect_range(positions[0], values.remove(0))?;
+                self.range_to_list(range).map(|list| Value::List(list, Type::Complex))
+            },
+            "re" | "im" => {
+                check_arity(1)?;
+                let val = self.expect_complex(positions[0], values.remove(0))?;
+                let part = if id.borrow_val() == "re" { val.re } else { val.im };
+                Ok(Value::Complex(self.real(part)))
+            },
+            "divmod" => {
+                check_arity(2)?;
+                let b = values.remove(1);
+                let a = values.remove(0);
+                match self.promote_eisenstein(positions[0], a, positions[1], b)? {
+                    (Value::Eisenstein(a), Value::Eisenstein(b)) => {
+                        let (q, r) = self.eisenstein_divmod(pos, a, b)?;
+                        Ok(Value::Tuple(vec![Value::Eisenstein(q), Value::Eisenstein(r)]))
+                    },
+                    (a, b) => {
+                        let b = self.expect_complex(positions[1], b)?;
+                        let a = self.expect_complex(positions[0], a)?;
+                        let (q, r) = self.complex_divmod(pos, a, b)?;
+                        Ok(Value::Tuple(vec![Value::Complex(q), Value::Complex(r)]))
+                    },
+                }
+            },
+            "divides" => {
+                check_arity(2)?;
+                let b = self.expect_complex(positions[1], values.remove(1))?;
+                let a = self.expect_complex(positions[0], values.remove(0))?;
+                self.divides(pos, a, b).map(Value::Bool)
+            },
+            "gcd" => {
+                check_arity(2)?;
+                let b = self.expect_complex(positions[1], values.remove(1))?;
+                let a = self.expect_complex(positions[0], values.remove(0))?;
+                self.gcd(pos, a, b).map(Value::Complex)
+            },
+            "is_prime" => {
+                check_arity(1)?;
+                let n = self.expect_complex(positions[0], values.remove(0))?;
+                let n_int = self.builder.build_float_to_signed_int(n.re, self.ctx.i64_type(), "n");
+                let back = self.builder.build_signed_int_to_float(n_int, self.ctx.f64_type(), "back");
+                let whole = self.builder.build_float_compare(FloatPredicate::OEQ, back, n.re, "whole");
+                let real = self.builder.build_float_compare(FloatPredicate::OEQ, n.im, self.ctx.f64_type().const_zero(), "real");
+                let ok = self.builder.build_and(whole, real, "integer");
+                self.build_check(ok, positions[0], "`is_prime` needs a real integer")?;
+                self.build_is_prime(n_int).map(Value::Bool)
+            },
+            "is_gaussian_prime" => {
+                check_arity(1)?;
+                let z = self.expect_complex(positions[0], values.remove(0))?;
+                self.is_gaussian_prime(pos, z).map(Value::Bool)
+            },
+            "degree" => {
+                check_arity(1)?;
+                match values.remove(0) {
+                    Value::Poly(p) => {
+                        let degree = self.build_poly_degree(p)?;
+                        let degree = self.builder.build_signed_int_to_float(degree, self.ctx.f64_type(), "degree");
+                        Ok(Value::Complex(self.real(degree)))
+                    },
+                    value => Err(LocatedCompileError::type_mismatch(positions[0], &Type::Poly, &value.ty())),
+                }
+            },
+            "roots" => {
+                check_arity(1)?;
+                match values.remove(0) {
+                    Value::Poly(p) => self.build_poly_roots(pos, p).map(|list| Value::List(list, Type::Complex)),
+                    value => Err(LocatedCompileError::type_mismatch(positions[0], &Type::Poly, &value.ty())),
+                }
+            },
+            // The coefficients as a list, starting from the constant term
+            "coeffs" => {
+                check_arity(1)?;
+                match values.remove(0) {
+                    Value::Poly(p) => self.build_list_copy(p, &Type::Complex).map(|list| Value::List(list, Type::Complex)),
+                    value => Err(LocatedCompileError::type_mismatch(positions[0], &Type::Poly, &value.ty())),
+                }
+            },
+            "cf" | "convergents" => {
+                check_arity(2)?;
+                let q = self.expect_complex(positions[1], values.remove(1))?;
+                let p = self.expect_complex(positions[0], values.remove(0))?;
+                if id.borrow_val() == "cf" {
+                    self.build_cf(pos, p, q).map(|list| Value::List(list, Type::Complex))
+                } else {
+                    self.build_convergents(pos, p, q).map(|list| Value::List(list, Type::Tuple(vec![Type::Complex, Type::Complex])))
+                }
+            },
+            "factor" => {
+                check_arity(1)?;
+                let z = self.expect_complex(positions[0], values.remove(0))?;
+                self.build_factor(pos, z).map(|list| Value::List(list, Type::Complex))
+            },
+            "factorial" => {
+                check_arity(1)?;
+                let n = self.expect_complex(positions[0], values.remove(0))?;
+                self.build_exact_factorial(positions[0], n).map(Value::Complex)
+            },
+            "binomial" => {
+                check_arity(2)?;
+                let k = self.expect_complex(positions[1], values.remove(1))?;
+                let n = self.expect_complex(positions[0], values.remove(0))?;
+                self.build_binomial(pos, n, k).map(Value::Complex)
+            },
+            "crt" => {
+                check_arity(1)?;
+                let pair = Type::Tuple(vec![Type::Complex, Type::Complex]);
+                match values.remove(0) {
+                    Value::List(list, elem) if elem == pair => {
+                        let (r, m) = self.build_crt(pos, list)?;
+                        Ok(Value::Tuple(vec![Value::Complex(r), Value::Complex(m)]))
+                    },
+                    value => Err(LocatedCompileError::type_mismatch(positions[0], &Type::List(Box::new(pair)), &value.ty())),
+                }
+            },
+            "totient" | "sigma" | "mobius" => {
+                check_arity(1)?;
+                let n = self.expect_complex(positions[0], values.remove(0))?;
+                let res = match id.borrow_val().as_str() {
+                    "totient" => self.build_totient(positions[0], n)?,
+                    "sigma" => self.build_sigma(positions[0], n)?,
+                    _ => self.build_mobius(positions[0], n)?,
+                };
+                Ok(Value::Complex(self.real(res)))
+            },
+            "divisors" => {
+                check_arity(1)?;
+                let n = self.expect_complex(positions[0], values.remove(0))?;
+                self.build_divisors(positions[0], n).map(|list| Value::List(list, Type::Complex))
+            },
+            // `a^2 + b^2`, which is exact for Gaussian integers whose norm fits in 53 bits, unlike `|z|`
+            "norm" => {
+                check_arity(1)?;
+                let norm = match values.remove(0) {
+                    Value::Eisenstein(val) => self.eisenstein_norm(val),
+                    Value::Quaternion(z, w) => {
+                        let z = self.complex_norm(z);
+                        let w = self.complex_norm(w);
+                        self.builder.build_float_add(z, w, "norm")
+                    },
+                    val => {
+                        let val = self.expect_complex(positions[0], val)?;
+                        self.complex_norm(val)
+                    },
+                };
+                Ok(Value::Complex(self.real(norm)))
+            },
+            "powmod" => {
+                check_arity(3)?;
+                let modulus = self.expect_complex(positions[2], values.remove(2))?;
+                let exp = self.expect_complex(positions[1], values.remove(1))?;
+                let base = self.expect_complex(positions[0], values.remove(0))?;
+                self.build_powmod(pos, base, exp, modulus).map(Value::Complex)
+            },
+            "isqrt" => {
+                check_arity(1)?;
+                let val = self.expect_complex(positions[0], values.remove(0))?;
+                self.isqrt(positions[0], val).map(|root| Value::Complex(self.real(root)))
+            },
+            "sqrt" => {
+                check_arity(1)?;
+                let val = self.expect_complex(positions[0], values.remove(0))?;
+                self.gaussian_sqrt(val)
+            },
+            "parts" => {
+                check_arity(1)?;
+                let val = self.expect_complex(positions[0], values.remove(0))?;
+                Ok(Value::Tuple(vec![Value::Complex(self.real(val.re)), Value::Complex(self.real(val.im))]))
+            },
+            // These round each part separately, e.g. to get the nearest Gaussian integer
+            "round" | "floor" | "ceil" | "trunc" => {
+                check_arity(1)?;
+                let val = self.expect_complex(positions[0], values.remove(0))?;
+                let f = match id.borrow_val().as_str() {
+                    "round" => self.builtins.round(),
+                    "floor" => self.builtins.floor(),
+                    "ceil"  => self.builtins.ceil(),
+                    _       => self.builtins.trunc(),
+                };
+                self.move_to_end()?;
+                Ok(Value::Complex(ComplexValue {
+                    re: self.call_float(f, &[val.re.into()], "re")?,
+                    im: self.call_float(f, &[val.im.into()], "im")?,
+                }))
+            },
+            // `a / b` rounded one way or another, where each part is rounded separately
+            "div_floor" | "div_ceil" | "div_trunc" | "div_round" => {
+                check_arity(2)?;
+                let b = self.expect_complex(positions[1], values.remove(1))?;
+                let a = self.expect_complex(positions[0], values.remove(0))?;
+                let zero = self.ctx.f64_type().const_zero();
+                let nonzero = self.builder.build_float_compare(FloatPredicate::ONE, self.complex_norm(b), zero, "nonzero");
+                self.build_check(nonzero, positions[1], "division by zero")?;
+                let f = match id.borrow_val().as_str() {
+                    "div_floor" => self.builtins.floor(),
+                    "div_ceil"  => self.builtins.ceil(),
+                    "div_trunc" => self.builtins.trunc(),
+                    _           => self.builtins.round(),
+                };
+                self.move_to_end()?;
+                let exact = self.complex_div(a, b);
+                Ok(Value::Complex(ComplexValue {
+                    re: self.call_float(f, &[exact.re.into()], "re")?,
+                    im: self.call_float(f, &[exact.im.into()], "im")?,
+                }))
+            },
+            // The unit `i ** k`, which is exact when k is an integer
+            "cis" => {
+                check_arity(1)?;
+                let k = self.expect_complex(positions[0], values.remove(0))?;
+                let t_f64 = self.ctx.f64_type();
+                let i = ComplexValue { re: t_f64.const_zero(), im: t_f64.const_float(1.) };
+                self.complex_pow(i, k).map(Value::Complex)
+            },
+            "polar" => {
+                check_arity(1)?;
+                let val = self.expect_complex(positions[0], values.remove(0))?;
+                self.polar(val)
+            },
+            // The angle in (-pi, pi], which is 0 for 0
+            "arg" => {
+                check_arity(1)?;
+                let val = self.expect_complex(positions[0], values.remove(0))?;
+                let atan2 = self.builtins.atan2();
+                self.move_to_end()?;
+                let angle = self.call_float(atan2, &[val.im.into(), val.re.into()], "angle")?;
+                Ok(Value::Complex(self.real(angle)))
+            },
+            "quadrant" => {
+                check_arity(1)?;
+                let val = self.expect_complex(positions[0], values.remove(0))?;
+                Ok(Value::Complex(self.real(self.quadrant(val))))
+            },
+            // Prints how a function was declared, and its documentation if it has any
+            "help" => {
+                check_arity(1)?;
+                match values.remove(0) {
+                    Value::Closure(closure_pos, env) => {
+                        let func = self.sym.lambda_map.get(&closure_pos).cloned()
+                            .ok_or_else(|| InternalError::invalid_state("unknown closure"))?;
+                        let named = self.sym.func(func.name.borrow_val()).map_or(false, |named| Rc::ptr_eq(named, &func));
+                        let signature = func.signature(env.len());
+                        self.build_println_str(if named {
+                            format!("fn {}{}", func.name.borrow_val(), signature)
+                        } else {
+                            format!("fn{} (at {})", signature, closure_pos)
+                        })?;
+                        if let Some(doc) = &func.doc {
+                            self.build_println_str(doc.clone())?;
+                        }
+                        Ok(Value::Tuple(Vec::new()))
+                    },
+                    value => Err(LocatedCompileError::unsupported(positions[0], format!("`{}` is not a function", value.ty()))),
+                }
+            },
+            // Binds the first arguments of a function, which works just like capturing them
+            "partial" => {
+                if values.is_empty() {
+                    return Err(LocatedCompileError::arity_at_least(pos, id.borrow_val(), 1, 0));
+                }
+                match values.remove(0) {
+                    Value::Closure(closure_pos, mut env) => {
+                        let func = self.sym.lambda_map.get(&closure_pos).cloned()
+                            .ok_or_else(|| InternalError::invalid_state("unknown closure"))?;
+                        // The rest parameter of a named function can't be bound, since it collects a list
+                        let remaining = func.params.len() - env.len() - func.variadic as usize;
+                        if values.len() > remaining {
+                            return Err(LocatedCompileError::arity(pos, func.name.borrow_val(), remaining, values.len()));
+                        }
+                        for value in values {
+                            env.push((format!("${}", env.len()), value));
+                        }
+                        Ok(Value::Closure(closure_pos, env))
+                    },
+                    value => Err(LocatedCompileError::unsupported(positions[0], format!("`{}` is not a function", value.ty()))),
+                }
+            },
+            "some" => {
+                check_arity(1)?;
+                Ok(self.build_some(values.remove(0)))
+            },
+            "unwrap_or" => {
+                check_arity(2)?;
+                let default = values.remove(1);
+                match values.remove(0) {
+                    Value::Option(present, inner) => {
+                        if default.ty() != inner.ty() {
+                            return Err(LocatedCompileError::type_mismatch(positions[1], &inner.ty(), &default.ty()));
+                        }
+                        let ty = default.ty();
+                        let inner = self.pack(&inner)?;
+                        let default = self.pack(&default)?;
+                        let value = self.builder.build_select(present, inner, default, "unwrapped");
+                        self.unpack(value, &ty)
+                    },
+                    value => Err(LocatedCompileError::type_mismatch(positions[0], &Type::Option(Box::new(default.ty())), &value.ty())),
+                }
+            },
+            // Safe indexing, which gives an optional value
+            "get" => {
+                check_arity(2)?;
+                let index = values.remove(1);
+                match values.remove(0) {
+                    Value::List(list, elem) => {
+                        let index = self.expect_complex(positions[1], index)?;
+                        self.build_list_try_get(list, &elem, index)
+                    },
+                    Value::Dict(keys, dict_values, table, key, value) => self.build_dict_get(positions[1], (keys, dict_values, table, &key, &value), index),
+                    value => Err(LocatedCompileError::type_mismatch(positions[0], &Type::List(Box::new(Type::Complex)), &value.ty())),
+                }
+            },
+            "insert" if matches!(values.first(), Some(Value::Set(..))) => {
+                check_arity(2)?;
+                let value = values.remove(1);
+                match values.remove(0) {
+                    Value::Set(set, elem) => {
+                        self.build_set_insert(positions[1], set, &elem, value)?;
+                        Ok(Value::Set(set, elem))
+                    },
+                    _ => Err(InternalError::invalid_state("set vanished")),
+                }
+            },
+            "insert" => {
+                check_arity(3)?;
+                let value = values.remove(2);
+                let key = values.remove(1);
+                match values.remove(0) {
+                    Value::Dict(keys, dict_values, table, key_type, value_type) => {
+                        self.build_dict_insert(positions[2], (keys, dict_values, table, &key_type, &value_type), key, value)?;
+                        Ok(Value::Dict(keys, dict_values, table, key_type, value_type))
+                    },
+                    other => Err(LocatedCompileError::type_mismatch(positions[0],
+                        &Type::Dict(Box::new(key.ty()), Box::new(value.ty())), &other.ty())),
+                }
+            },
+            "contains" => {
+                check_arity(2)?;
+                let key = values.remove(1);
+                match values.remove(0) {
+                    Value::Dict(keys, _, table, key_type, _) => self.build_dict_contains(positions[1], (keys, table), &key_type, key).map(Value::Bool),
+                    other => Err(LocatedCompileError::type_mismatch(positions[0],
+                        &Type::Dict(Box::new(key.ty()), Box::new(Type::Complex)), &other.ty())),
+                }
+            },
+            // Copies, so that inserting afterwards doesn't change them
+            "keys" | "values" => {
+                check_arity(1)?;
+                match values.remove(0) {
+                    Value::Dict(keys, dict_values, _, key, value) => {
+                        let (list, elem) = if id.borrow_val() == "keys" { (keys, key) } else { (dict_values, value) };
+                        self.build_list_copy(list, &elem).map(|list| Value::List(list, elem))
+                    },
+                    other => Err(LocatedCompileError::type_mismatch(positions[0],
+                        &Type::Dict(Box::new(Type::Complex), Box::new(Type::Complex)), &other.ty())),
+                }
+            },
+            "find" => {
+                check_arity(2)?;
+                let value = values.remove(1);
+                match values.remove(0) {
+                    Value::List(list, elem) => {
+                        if value.ty() != elem {
+                            return Err(LocatedCompileError::type_mismatch(positions[1], &elem, &value.ty()));
+                        }
+                        self.build_list_find(positions[1], list, &elem, value)
+                    },
+                    other => Err(LocatedCompileError::type_mismatch(positions[0], &Type::List(Box::new(value.ty())), &other.ty())),
+                }
+            },
+            // Each element goes through the function, in order
+            "map" => {
+                check_arity(2)?;
+                let (list, elem) = self.expect_iterable(positions[1], values.remove(1))?;
+                let (func, env) = self.closure_func(positions[0], values.remove(0), 1)?;
+                let mut types = env.iter().map(Value::ty).collect::<Vec<_>>();
+                types.push(elem.clone());
+                let ret = self.instance(&func, types)?.ret;
+                let len = self.list_len(list)?;
+                let res = self.build_list_alloc(&ret, len)?;
+                self.build_counted_loop(len, |this, k| {
+                    let mut args = env.clone();
+                    args.push(this.build_list_nth(list, &elem, k)?);
+                    let value = this.call_func(&func, args)?;
+                    this.build_list_store(res, k, value)
+                })?;
+                Ok(Value::List(res, ret))
+            },
+            "filter" => {
+                check_arity(2)?;
+                let (list, elem) = self.expect_iterable(positions[1], values.remove(1))?;
+                let (func, env) = self.closure_func(positions[0], values.remove(0), 1)?;
+                let len = self.list_len(list)?;
+                let res = self.build_list_alloc(&elem, self.ctx.i64_type().const_zero())?;
+                self.build_counted_loop(len, |this, k| {
+                    let value = this.build_list_nth(list, &elem, k)?;
+                    let mut args = env.clone();
+                    args.push(value.clone());
+                    let keep = this.call_func(&func, args)?;
+                    let keep = this.expect_bool(positions[0], keep)?;
+                    let keep_bb = this.ctx.append_basic_block(this.get_fp()?, "keep");
+                    let cont_bb = this.ctx.append_basic_block(this.get_fp()?, "cont");
+                    this.builder.build_conditional_branch(keep, keep_bb, cont_bb);
+                    this.set_and_move_block(keep_bb)?;
+                    this.build_list_push(res, &elem, value)?;
+                    this.builder.build_unconditional_branch(cont_bb);
+                    this.set_and_move_block(cont_bb)
+                })?;
+                Ok(Value::List(res, elem))
+            },
+            // `fold(f, init, xs)` is `f(...f(f(init, xs[0]), xs[1])..., xs[n - 1])`
+            "fold" => {
+                check_arity(3)?;
+                let (list, elem) = self.expect_iterable(positions[2], values.remove(2))?;
+                let init = values.remove(1);
+                let (func, env) = self.closure_func(positions[0], values.remove(0), 2)?;
+                let ty = init.ty();
+                let acc = self.builder.build_alloca(self.llvm_type(&ty), "acc");
+                let packed = self.pack(&init)?;
+                self.builder.build_store(acc, packed);
+                let len = self.list_len(list)?;
+                self.build_counted_loop(len, |this, k| {
+                    let mut args = env.clone();
+                    args.push(this.unpack(this.builder.build_load(acc, "acc"), &ty)?);
+                    args.push(this.build_list_nth(list, &elem, k)?);
+                    let value = this.call_func(&func, args)?;
+                    if value.ty() != ty {
+                        return Err(LocatedCompileError::type_mismatch(positions[0], &ty, &value.ty()));
+                    }
+                    let value = this.pack(&value)?;
+                    this.builder.build_store(acc, value);
+                    Ok(())
+                })?;
+                self.unpack(self.builder.build_load(acc, "folded"), &ty)
+            },
+            // Duplicates are dropped, keeping the first of each
+            "set" => {
+                check_arity(1)?;
+                let (list, elem) = self.expect_iterable(positions[0], values.remove(0))?;
+                let set = self.build_set_from(pos, &elem, Vec::new())?;
+                let len = self.list_len(list)?;
+                self.build_counted_loop(len, |this, k| {
+                    let value = this.build_list_nth(list, &elem, k)?;
+                    this.build_set_insert(pos, set, &elem, value)
+                })?;
+                Ok(Value::Set(set, elem))
+            },
+            "union" | "intersect" | "diff" => {
+                check_arity(2)?;
+                let rhs = values.remove(1);
+                match (values.remove(0), rhs) {
+                    (Value::Set(lhs, elem), Value::Set(rhs, rhs_elem)) => {
+                        if rhs_elem != elem {
+                            return Err(LocatedCompileError::type_mismatch(positions[1], &Type::Set(Box::new(elem)), &Type::Set(Box::new(rhs_elem))));
+                        }
+                        let res = match id.borrow_val().as_str() {
+                            "union" => self.build_set_union(pos, lhs, rhs, &elem)?,
+                            "intersect" => self.build_set_filter(pos, lhs, rhs, &elem, true)?,
+                            _ => self.build_set_filter(pos, lhs, rhs, &elem, false)?,
+                        };
+                        Ok(Value::Set(res, elem))
+                    },
+                    (Value::Set(_, elem), other) => Err(LocatedCompileError::type_mismatch(positions[1], &Type::Set(Box::new(elem)), &other.ty())),
+                    (other, _) => Err(LocatedCompileError::type_mismatch(positions[0], &Type::Set(Box::new(Type::Complex)), &other.ty())),
+                }
+            },
+            // Only works on real numbers that are already integers, since anything else would lose
+            // information: `trunc`, `floor`, `round` and `ceil` say how to round first
+            "int" => {
+                check_arity(1)?;
+                match values.remove(0) {
+                    Value::Complex(val) => {
+                        let zero = self.ctx.f64_type().const_zero();
+                        let real = self.builder.build_float_compare(FloatPredicate::OEQ, val.im, zero, "real");
+                        self.build_check(real, positions[0], "cannot convert a non-real number to an integer")?;
+                        let floor = self.builtins.floor();
+                        self.move_to_end()?;
+                        let floored = self.call_float(floor, &[val.re.into()], "floored")?;
+                        let whole = self.builder.build_float_compare(FloatPredicate::OEQ, floored, val.re, "whole");
+                        self.build_check(whole, positions[0], "cannot convert a non-integer to an integer without rounding")?;
+                        Ok(Value::Complex(self.real(val.re)))
+                    },
+                    Value::Bool(b) => {
+                        let re = self.builder.build_unsigned_int_to_float(b, self.ctx.f64_type(), "re");
+                        Ok(Value::Complex(self.real(re)))
+                    },
+                    value => Err(LocatedCompileError::unsupported(positions[0], format!("cannot convert `{}` to an integer", value.ty()))),
+                }
+            },
+            "complex" => {
+                check_arity(2)?;
+                let im = self.expect_complex(positions[1], values.remove(1))?;
+                let re = self.expect_complex(positions[0], values.remove(0))?;
+                let zero = self.ctx.f64_type().const_zero();
+                let re_real = self.builder.build_float_compare(FloatPredicate::OEQ, re.im, zero, "re_real");
+                self.build_check(re_real, positions[0], "the real part must be a real number")?;
+                let im_real = self.builder.build_float_compare(FloatPredicate::OEQ, im.im, zero, "im_real");
+                self.build_check(im_real, positions[1], "the imaginary part must be a real number")?;
+                Ok(Value::Complex(ComplexValue { re: re.re, im: im.re }))
+            },
+            "rational" => {
+                check_arity(2)?;
+                let den = self.expect_complex(positions[1], values.remove(1))?;
+                let num = self.expect_complex(positions[0], values.remove(0))?;
+                self.build_rational_call(pos, num, den)
+            },
+            "numer" | "denom" => {
+                check_arity(1)?;
+                let (num, den) = self.expect_rational(positions[0], values.remove(0))?;
+                Ok(Value::Complex(if id.borrow_val() == "numer" { num } else { den }))
+            },
+            // Explicit conversions between the floating-point and exact types
+            "exact" => {
+                check_arity(1)?;
+                match values.remove(0) {
+                    value @ Value::Rational(..) => Ok(value),
+                    value => {
+                        let z = self.expect_complex(positions[0], value)?;
+                        self.build_exact(positions[0], z)
+                    },
+                }
+            },
+            "float" => {
+                check_arity(1)?;
+                match values.remove(0) {
+                    Value::Rational(num, den) => Ok(Value::Complex(self.complex_div(num, den))),
+                    value => self.expect_complex(positions[0], value).map(Value::Complex),
+                }
+            },
+            "str" => {
+                check_arity(1)?;
+                let value = values.remove(0);
+                match value {
+                    Value::Complex(_) | Value::Eisenstein(_) | Value::Quaternion(..) | Value::Rational(..) | Value::Bool(_) | Value::Str(_) => self.build_to_str(positions[0], value).map(Value::Str),
+                    value => Err(LocatedCompileError::unsupported(positions[0], format!("cannot convert `{}` to a string", value.ty()))),
+                }
+            },
+            "parse" => {
+                check_arity(1)?;
+                match values.remove(0) {
+                    Value::Str(s) => self.build_parse_complex(positions[0], s).map(Value::Complex),
+                    value => Err(LocatedCompileError::type_mismatch(positions[0], &Type::Str, &value.ty())),
+                }
+            },
+            // Types are all known while compiling, so this is just a string constant
+            "typeof" => {
+                check_arity(1)?;
+                let kind = values[0].ty().kind();
+                Ok(Value::Str(self.builder.build_global_string_ptr(kind, ".kind_str").as_pointer_value()))
+            },
+            "exit" => {
+                check_arity(1)?;
+                let code = self.expect_complex(positions[0], values.remove(0))?;
+                let n = self.build_real_integer(positions[0], code, "exit status must be a real integer")?;
+                let status = self.builder.build_int_truncate(n, self.ctx.i32_type(), "status");
+                let back = self.builder.build_int_s_extend(status, self.ctx.i64_type(), "back");
+                let fits = self.builder.build_int_compare(IntPredicate::EQ, back, n, "fits");
+                self.build_check(fits, positions[0], "exit status doesn't fit in 32 bits")?;
+
+                // Output still waiting to be written is flushed by `exit`
+                let exit = self.builtins.exit();
+                self.move_to_end()?;
+                self.builder.build_call(exit, &[status.into()], "call");
+                self.builder.build_unreachable();
+                let dead_bb = self.ctx.append_basic_block(self.get_fp()?, "dead");
+                self.set_and_move_block(dead_bb)?;
+                Ok(Value::Complex(code))
+            },
+            "input" | "read_complex" => {
+                if values.len() > 1 {
+                    return Err(LocatedCompileError::arity_between(pos, id.borrow_val(), 0, 1, values.len()));
+                }
+                let prompt = values.pop().map(|prompt| (positions[0], prompt));
+                if id.borrow_val() == "input" {
+                    self.build_input(prompt).map(Value::Str)
+                } else {
+                    self.build_read_complex(pos, prompt).map(Value::Complex)
+                }
+            },
+            "transpose" => {
+                check_arity(1)?;
+                match values.remove(0) {
+                    Value::Matrix(matrix) => self.build_matrix_transpose(matrix, false).map(Value::Matrix),
+                    value => Err(LocatedCompileError::type_mismatch(positions[0], &Type::Matrix, &value.ty())),
+                }
+            },
+            "solve" => {
+                check_arity(2)?;
+                match (values.remove(0), values.remove(0)) {
+                    (Value::Matrix(a), Value::Matrix(b)) => self.build_matrix_solve(pos, a, b).map(|x| Value::List(x, Type::Rational)),
+                    (Value::Matrix(_), b) => Err(LocatedCompileError::type_mismatch(positions[1], &Type::Matrix, &b.ty())),
+                    (a, _) => Err(LocatedCompileError::type_mismatch(positions[0], &Type::Matrix, &a.ty())),
+                }
+            },
+            "seed" => {
+                check_arity(1)?;
+                let n = self.expect_complex(positions[0], values.remove(0))?;
+                self.build_seed(positions[0], n)?;
+                Ok(Value::Tuple(Vec::new()))
+            },
+            "rand_int" => {
+                check_arity(2)?;
+                let b = self.expect_complex(positions[1], values.remove(1))?;
+                let a = self.expect_complex(positions[0], values.remove(0))?;
+                self.build_rand_int(pos, a, b).map(Value::Complex)
+            },
+            "rand_gaussian" => {
+                check_arity(1)?;
+                let max_norm = self.expect_complex(positions[0], values.remove(0))?;
+                self.build_rand_gaussian(positions[0], max_norm).map(Value::Complex)
+            },
+            "fft" | "ifft" => {
+                check_arity(1)?;
+                match values.remove(0) {
+                    Value::List(xs, Type::Complex) =>
+                        self.build_fft(pos, xs, id.borrow_val() == "ifft").map(|list| Value::List(list, Type::Complex)),
+                    value => Err(LocatedCompileError::type_mismatch(positions[0], &Type::List(Box::new(Type::Complex)), &value.ty())),
+                }
+            },
+            "ntt" | "intt" => {
+                check_arity(2)?;
+                let modulus = self.expect_complex(positions[1], values.remove(1))?;
+                match values.remove(0) {
+                    Value::List(xs, Type::Complex) =>
+                        self.build_ntt(pos, xs, modulus, id.borrow_val() == "intt").map(|list| Value::List(list, Type::Complex)),
+                    value => Err(LocatedCompileError::type_mismatch(positions[0], &Type::List(Box::new(Type::Complex)), &value.ty())),
+                }
+            },
+            "dot" | "cross" => {
+                check_arity(2)?;
+                match (values.remove(0), values.remove(0)) {
+                    (Value::Matrix(u), Value::Matrix(v)) => if id.borrow_val() == "dot" {
+                        self.build_vector_dot(pos, u, v).map(Value::Complex)
+                    } else {
+                        self.build_vector_cross(pos, u, v).map(Value::Matrix)
+                    },
+                    (Value::Matrix(_), v) => Err(LocatedCompileError::type_mismatch(positions[1], &Type::Matrix, &v.ty())),
+                    (u, _) => Err(LocatedCompileError::type_mismatch(positions[0], &Type::Matrix, &u.ty())),
+                }
+            },
+            "norm2" => {
+                check_arity(1)?;
+                match values.remove(0) {
+                    Value::Matrix(v) => {
+                        let norm = self.build_vector_norm(positions[0], v)?;
+                        Ok(Value::Complex(self.real(norm)))
+                    },
+                    value => Err(LocatedCompileError::type_mismatch(positions[0], &Type::Matrix, &value.ty())),
+                }
+            },
+            "det" | "inverse" | "rank" => {
+                check_arity(1)?;
+                let matrix = match values.remove(0) {
+                    Value::Matrix(matrix) => matrix,
+                    value => return Err(LocatedCompileError::type_mismatch(positions[0], &Type::Matrix, &value.ty())),
+                };
+                match id.borrow_val().as_str() {
+                    "det" => self.build_matrix_det(positions[0], matrix).map(Value::Complex),
+                    "inverse" => self.build_matrix_inverse(positions[0], matrix)
+                        .map(|rows| Value::List(rows, Type::List(Box::new(Type::Rational)))),
+                    _ => {
+                        let rank = self.build_matrix_rank(matrix)?;
+                        let rank = self.builder.build_signed_int_to_float(rank, self.ctx.f64_type(), "rank");
+                        Ok(Value::Complex(self.real(rank)))
+                    },
+                }
+            },
+            _ => Err(LocatedCompileError::unknown_symbol(id)),
+        }
+    }
+
+    /// A list to go through element by element. A range is turned into a list first.
+    fn expect_iterable(&mut self, pos: Location, value: Value<'ctx>) -> Result<(PointerValue<'ctx>, Type), LocatedCompileError> {
+        match value {
+            Value::List(list, elem) => Ok((list, elem)),
+            Value::Range(range) => Ok((self.range_to_list(range)?, Type::Complex)),
+            value => Err(LocatedCompileError::type_mismatch(pos, &Type::List(Box::new(Type::Complex)), &value.ty())),
+        }
+    }
+
+    fn real(&self, re: FloatValue<'ctx>) -> ComplexValue<'ctx> {
+        ComplexValue { re, im: self.ctx.f64_type().const_zero() }
+    }
+
+    /// The norm of z, and the k in 0..4 such that `z / cis(k)` has `re > 0` and `im >= 0` (or 0 if z is
+    /// zero). So z is `cis(k)` times something in the first quadrant.
+    fn polar(&mut self, val: ComplexValue<'ctx>) -> Result<Value<'ctx>, LocatedCompileError> {
+        let atan2 = self.builtins.atan2();
+        let floor = self.builtins.floor();
+        self.move_to_end()?;
+
+        let t_f64 = self.ctx.f64_type();
+        let angle = self.call_float(atan2, &[val.im.into(), val.re.into()], "angle")?;
+        // Dividing by the double nearest pi/2 makes the axes land exactly on integers
+        let quarter_turns = self.builder.build_float_div(angle, t_f64.const_float(std::f64::consts::FRAC_PI_2), "quarter_turns");
+        let k = self.call_float(floor, &[quarter_turns.into()], "k")?;
+        let negative = self.builder.build_float_compare(FloatPredicate::OLT, k, t_f64.const_zero(), "negative");
+        let wrapped = self.builder.build_float_add(k, t_f64.const_float(4.), "wrapped");
+        let k = self.builder.build_select(negative, wrapped, k, "k").into_float_value();
+
+        let norm = self.complex_norm(val);
+        Ok(Value::Tuple(vec![Value::Complex(self.real(norm)), Value::Complex(self.real(k))]))
+    }
+
+    /// Which quarter turn `z` is in, from 0 to 3, like the second part of `polar` but decided exactly.
+    /// Each axis belongs to the quadrant anticlockwise of it, so 1 is in quadrant 0 and i is in
+    /// quadrant 1. Zero is in quadrant 0.
+    fn quadrant(&self, z: ComplexValue<'ctx>) -> FloatValue<'ctx> {
+        let t_f64 = self.ctx.f64_type();
+        let zero = t_f64.const_zero();
+        let (second, third, fourth) = self.quadrants(z);
+        let k = self.builder.build_select(second, t_f64.const_float(1.), zero, "k").into_float_value();
+        let k = self.builder.build_select(third, t_f64.const_float(2.), k, "k").into_float_value();
+        self.builder.build_select(fourth, t_f64.const_float(3.), k, "k").into_float_value()
+    }
+
+    /// The largest integer whose square is at most `n`. The square root of a double can be off by
+    /// one for big `n`, so this adjusts it until it's right.
+    fn isqrt(&mut self, pos: Location, n: ComplexValue<'ctx>) -> Result<FloatValue<'ctx>, LocatedCompileError> {
+        let sqrt = self.builtins.sqrt();
+        let floor = self.builtins.floor();
+        self.move_to_end()?;
+
+        let t_f64 = self.ctx.f64_type();
+        let zero = t_f64.const_zero();
+        let one = t_f64.const_float(1.);
+        let real = self.builder.build_float_compare(FloatPredicate::OEQ, n.im, zero, "real");
+        let floored = self.call_float(floor, &[n.re.into()], "floored")?;
+        let whole = self.builder.build_float_compare(FloatPredicate::OEQ, n.re, floored, "whole");
+        let non_negative = self.builder.build_float_compare(FloatPredicate::OGE, n.re, zero, "non_negative");
+        let ok = self.builder.build_and(real, whole, "integer");
+        let ok = self.builder.build_and(ok, non_negative, "natural");
+        self.build_check(ok, pos, "`isqrt` needs a non-negative real integer")?;
+
+        let root = self.call_float(sqrt, &[n.re.into()], "root")?;
+        let root = self.call_float(floor, &[root.into()], "root")?;
+        let square = self.builder.build_float_mul(root, root, "square");
+        let too_big = self.builder.build_float_compare(FloatPredicate::OGT, square, n.re, "too_big");
+        let smaller = self.builder.build_float_sub(root, one, "smaller");
+        let root = self.builder.build_select(too_big, smaller, root, "root").into_float_value();
+        let bigger = self.builder.build_float_add(root, one, "bigger");
+        let bigger_square = self.builder.build_float_mul(bigger, bigger, "bigger_square");
+        let too_small = self.builder.build_float_compare(FloatPredicate::OLE, bigger_square, n.re, "too_small");
+        Ok(self.builder.build_select(too_small, bigger, root, "root").into_float_value())
+    }
+
+    /// `sqrt(z)` is `some(w)` if `w * w == z` for a Gaussian integer `w`, and `none` otherwise. Of the
+    /// two roots, this is the one with `re(w) > 0`, or `im(w) > 0` if `re(w) == 0`. If `z = a + bi`,
+    /// then `|w|^2 = |z|`, so `re(w)^2 = (|z| + a) / 2` and `im(w)^2 = (|z| - a) / 2`. Rounding
+    /// those and checking the square catches every way it can fail.
+    fn gaussian_sqrt(&mut self, z: ComplexValue<'ctx>) -> Result<Value<'ctx>, LocatedCompileError> {
+        let sqrt = self.builtins.sqrt();
+        let round = self.builtins.round();
+        self.move_to_end()?;
+
+        let t_f64 = self.ctx.f64_type();
+        let zero = t_f64.const_zero();
+        let half = t_f64.const_float(0.5);
+        let modulus = self.call_float(sqrt, &[self.complex_norm(z).into()], "modulus")?;
+        let modulus = self.call_float(round, &[modulus.into()], "modulus")?;
+        let re_squared = self.builder.build_float_add(modulus, z.re, "re_squared");
+        let re_squared = self.builder.build_float_mul(re_squared, half, "re_squared");
+        let im_squared = self.builder.build_float_sub(modulus, z.re, "im_squared");
+        let im_squared = self.builder.build_float_mul(im_squared, half, "im_squared");
+        let re = self.call_float(sqrt, &[re_squared.into()], "re")?;
+        let re = self.call_float(round, &[re.into()], "re")?;
+        let im = self.call_float(sqrt, &[im_squared.into()], "im")?;
+        let im = self.call_float(round, &[im.into()], "im")?;
+        // The imaginary part of w^2 is 2 re(w) im(w), so im(w) has the sign of b
+        let negative = self.builder.build_float_compare(FloatPredicate::OLT, z.im, zero, "negative");
+        let neg_im = self.builder.build_float_neg(im, "neg_im");
+        let im = self.builder.build_select(negative, neg_im, im, "im").into_float_value();
+
+        let root = ComplexValue { re, im };
+        let square = self.complex_mul(root, root);
+        let re_ok = self.builder.build_float_compare(FloatPredicate::OEQ, square.re, z.re, "re_ok");
+        let im_ok = self.builder.build_float_compare(FloatPredicate::OEQ, square.im, z.im, "im_ok");
+        let exact = self.builder.build_and(re_ok, im_ok, "exact");
+        let root = ComplexValue {
+            re: self.builder.build_select(exact, re, zero, "root_re").into_float_value(),
+            im: self.builder.build_select(exact, im, zero, "root_im").into_float_value(),
+        };
+        Ok(Value::Option(exact, Box::new(Value::Complex(root))))
+    }
+
+    /// Whether `z` is in quadrants 1, 2 and 3, in the sense of `quadrant`.
+    pub(super) fn quadrants(&self, z: ComplexValue<'ctx>) -> (IntValue<'ctx>, IntValue<'ctx>, IntValue<'ctx>) {
+        let zero = self.ctx.f64_type().const_zero();
+        let re_pos = self.builder.build_float_compare(FloatPredicate::OGT, z.re, zero, "re_pos");
+        let re_neg = self.builder.build_float_compare(FloatPredicate::OLT, z.re, zero, "re_neg");
+        let im_pos = self.builder.build_float_compare(FloatPredicate::OGT, z.im, zero, "im_pos");
+        let im_neg = self.builder.build_float_compare(FloatPredicate::OLT, z.im, zero, "im_neg");
+        let re_non_pos = self.builder.build_not(re_pos, "re_non_pos");
+        let im_non_pos = self.builder.build_not(im_pos, "im_non_pos");
+        let re_non_neg = self.builder.build_not(re_neg, "re_non_neg");
+        let second = self.builder.build_and(re_non_pos, im_pos, "second");
+        let third = self.builder.build_and(re_neg, im_non_pos, "third");
+        let fourth = self.builder.build_and(re_non_neg, im_neg, "fourth");
+        (second, third, fourth)
+    }
+
+    /// Whether `b == a * q` for some Gaussian integer `q`, given Gaussian integers `a` and `b`. That
+    /// holds when both parts of `b * conj(a)` are multiples of `|a|^2`, which can be checked exactly.
+    /// Only zero is a multiple of zero.
+    fn divides(&mut self, pos: Location, a: ComplexValue<'ctx>, b: ComplexValue<'ctx>) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        self.check_gaussian(pos, a, b, "`divides` needs Gaussian integers")?;
+        let zero = self.ctx.f64_type().const_zero();
+
+        let numer = self.complex_mul(b, self.complex_conjugate(a));
+        let norm = self.complex_norm(a);
+        let re_rem = self.builder.build_float_rem(numer.re, norm, "re_rem");
+        let im_rem = self.builder.build_float_rem(numer.im, norm, "im_rem");
+        let re_ok = self.builder.build_float_compare(FloatPredicate::OEQ, re_rem, zero, "re_ok");
+        let im_ok = self.builder.build_float_compare(FloatPredicate::OEQ, im_rem, zero, "im_ok");
+        let multiple = self.builder.build_and(re_ok, im_ok, "multiple");
+
+        let a_zero = self.builder.build_float_compare(FloatPredicate::OEQ, norm, zero, "a_zero");
+        let b_zero = self.builder.build_float_compare(FloatPredicate::OEQ, self.complex_norm(b), zero, "b_zero");
+        Ok(self.builder.build_select(a_zero, b_zero, multiple, "divides").into_int_value())
+    }
+
+    pub(super) fn build_is_prime(&mut self, n: IntValue<'ctx>) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let is_prime = self.builtins.is_prime();
+        self.move_to_end()?;
+        Ok(self.builder.build_call(is_prime, &[n.into()], "is_prime")
+            .try_as_basic_value().left()
+                .ok_or_else(|| InternalError::invalid_state("failed to interpret return value of is_prime"))?
+            .into_int_value())
+    }
+
+    /// `a + bi` is a Gaussian prime if `a^2 + b^2` is prime, or if one part is zero and the other is
+    /// a prime that's 3 mod 4 (up to sign). The norm is worked out in 128 bits, and has to fit in 63
+    /// for the primality test, which is deterministic over that whole range.
+    fn is_gaussian_prime(&mut self, pos: Location, z: ComplexValue<'ctx>) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let msg = "`is_gaussian_prime` needs a Gaussian integer";
+        let i64_type = self.ctx.i64_type();
+        let i128_type = self.ctx.i128_type();
+        let zero = i64_type.const_zero();
+        let re = self.build_real_integer(pos, self.real(z.re), msg)?;
+        let im = self.build_real_integer(pos, self.real(z.im), msg)?;
+        let mut parts = Vec::new();
+        let mut norm = i128_type.const_zero();
+        for part in [re, im].iter() {
+            let wide = self.builder.build_int_s_extend(*part, i128_type, "wide");
+            let squared = self.builder.build_int_mul(wide, wide, "squared");
+            norm = self.builder.build_int_add(norm, squared, "norm");
+            let negative = self.builder.build_int_compare(IntPredicate::SLT, *part, zero, "negative");
+            let negated = self.builder.build_int_neg(*part, "negated");
+            parts.push(self.builder.build_select(negative, negated, *part, "abs").into_int_value());
+        }
+        let (a, b) = (parts[0], parts[1]);
+        let fits = self.builder.build_int_compare(IntPredicate::ULT, norm, i128_type.const_int(1 << 63, false), "fits");
+        self.build_check(fits, pos, "`is_gaussian_prime` needs a norm that fits in 63 bits")?;
+        let norm = self.builder.build_int_truncate(norm, i64_type, "norm");
+        let norm_prime = self.build_is_prime(norm)?;
+
+        let axis = self.builder.build_int_add(a, b, "axis");
+        let axis_prime = self.build_is_prime(axis)?;
+        let rem = self.builder.build_int_unsigned_rem(axis, i64_type.const_int(4, false), "rem");
+        let inert = self.builder.build_int_compare(IntPredicate::EQ, rem, i64_type.const_int(3, false), "inert");
+        let axis_prime = self.builder.build_and(axis_prime, inert, "axis_prime");
+
+        let a_zero = self.builder.build_int_compare(IntPredicate::EQ, a, zero, "a_zero");
+        let b_zero = self.builder.build_int_compare(IntPredicate::EQ, b, zero, "b_zero");
+        let on_axis = self.builder.build_or(a_zero, b_zero, "on_axis");
+        Ok(self.builder.build_select(on_axis, axis_prime, norm_prime, "is_gaussian_prime").into_int_value())
+    }
+
+    /// Aborts unless both parts of both numbers are whole.
+    pub(super) fn check_gaussian(&mut self, pos: Location, a: ComplexValue<'ctx>, b: ComplexValue<'ctx>, msg: &str) -> Result<(), LocatedCompileError> {
+        let floor = self.builtins.floor();
+        self.move_to_end()?;
+
+        let mut ok = self.ctx.bool_type().const_int(1, false);
+        for part in [a.re, a.im, b.re, b.im].iter() {
+            let floored = self.call_float(floor, &[(*part).into()], "floored")?;
+            let whole = self.builder.build_float_compare(FloatPredicate::OEQ, *part, floored, "whole");
+            ok = self.builder.build_and(ok, whole, "ok");
+        }
+        self.build_check(ok, pos, msg)
+    }
+
+    /// The Euclidean algorithm, using the same division as `divmod`. The result is the canonical
+    /// associate, so that e.g. `gcd(2i, 4) == 2`.
+    pub(super) fn gcd(&mut self, pos: Location, a: ComplexValue<'ctx>, b: ComplexValue<'ctx>) -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+        self.check_gaussian(pos, a, b, "`gcd` needs Gaussian integers")?;
+        let t_f64 = self.ctx.f64_type();
+        let zero = t_f64.const_zero();
+        let entry_bb = self.get_block()?;
+        let test_bb = self.ctx.append_basic_block(self.get_fp()?, "gcd_test");
+        let body_bb = self.ctx.append_basic_block(self.get_fp()?, "gcd_body");
+        let done_bb = self.ctx.append_basic_block(self.get_fp()?, "gcd_done");
+        self.builder.build_unconditional_branch(test_bb);
+
+        self.set_and_move_block(test_bb)?;
+        let phis = [
+            self.builder.build_phi(t_f64, "a_re"),
+            self.builder.build_phi(t_f64, "a_im"),
+            self.builder.build_phi(t_f64, "b_re"),
+            self.builder.build_phi(t_f64, "b_im"),
+        ];
+        let part = |k: usize| phis[k].as_basic_value().into_float_value();
+        let (x, y) = (ComplexValue { re: part(0), im: part(1) }, ComplexValue { re: part(2), im: part(3) });
+        let done = self.builder.build_float_compare(FloatPredicate::OEQ, self.complex_norm(y), zero, "done");
+        self.builder.build_conditional_branch(done, done_bb, body_bb);
+
+        self.set_and_move_block(body_bb)?;
+        let (_, r) = self.complex_divmod(pos, x, y)?;
+        let body_end = self.get_block()?;
+        self.builder.build_unconditional_branch(test_bb);
+
+        let initial = [a.re, a.im, b.re, b.im];
+        let next = [y.re, y.im, r.re, r.im];
+        for ((phi, initial), next) in phis.iter().zip(initial.iter()).zip(next.iter()) {
+            phi.add_incoming(&[(initial, entry_bb), (next, body_end)]);
+        }
+
+        self.set_and_move_block(done_bb)?;
+        Ok(self.canonical_associate(x))
+    }
+
+    /// Of the four associates of a Gaussian integer, the one with `re > 0` and `im >= 0`, found by
+    /// multiplying by -i, -1 or i. Zero stays zero.
+    pub(super) fn canonical_associate(&self, x: ComplexValue<'ctx>) -> ComplexValue<'ctx> {
+        self.rotate(x, self.quadrants(x))
+    }
+
+    /// Multiplies `x` by -i, -1 or i if `(second, third, fourth)` says that some other number is in
+    /// the second, third or fourth quadrant, which takes that number to its canonical associate.
+    pub(super) fn rotate(&self, x: ComplexValue<'ctx>, (second, third, fourth): (IntValue<'ctx>, IntValue<'ctx>, IntValue<'ctx>))
+            -> ComplexValue<'ctx> {
+        let zero = self.ctx.f64_type().const_zero();
+
+        let neg_re = self.builder.build_float_neg(x.re, "neg_re");
+        let neg_im = self.builder.build_float_neg(x.im, "neg_im");
+        // -i(x + iy) = y - ix, -(x + iy) = -x - iy, i(x + iy) = -y + ix
+        let re = self.builder.build_select(second, x.im, x.re, "re").into_float_value();
+        let re = self.builder.build_select(third, neg_re, re, "re").into_float_value();
+        let re = self.builder.build_select(fourth, neg_im, re, "re").into_float_value();
+        let im = self.builder.build_select(second, neg_re, x.im, "im").into_float_value();
+        let im = self.builder.build_select(third, neg_im, im, "im").into_float_value();
+        let im = self.builder.build_select(fourth, x.re, im, "im").into_float_value();
+        // Adding zero turns `-0` into `0`
+        ComplexValue {
+            re: self.builder.build_float_add(re, zero, "re"),
+            im: self.builder.build_float_add(im, zero, "im"),
+        }
+    }
+
+    /// n * start + step * n(n - 1)/2
+    fn range_sum(&self, range: RangeValue<'ctx>) -> ComplexValue<'ctx> {
+        let t_f64 = self.ctx.f64_type();
+        let n = range.len;
+        let n_minus_one = self.builder.build_float_sub(n, t_f64.const_float(1.), "tmp_n1");
+        let triangle = self.builder.build_float_mul(n, n_minus_one, "tmp_tri");
+        let triangle = self.builder.build_float_mul(triangle, t_f64.const_float(0.5), "tmp_tri_half");
+        let re1 = self.builder.build_float_mul(n, range.start.re, "tmp_sum_re1");
+        let re2 = self.builder.build_float_mul(triangle, range.step.re, "tmp_sum_re2");
+        let im1 = self.builder.build_float_mul(n, range.start.im, "tmp_sum_im1");
+        let im2 = self.builder.build_float_mul(triangle, range.step.im, "tmp_sum_im2");
+        ComplexValue {
+            re: self.builder.build_float_add(re1, re2, "tmp_sum_re"),
+            im: self.builder.build_float_add(im1, im2, "tmp_sum_im"),
+        }
+    }
+
+    fn list_sum(&mut self, list: PointerValue<'ctx>) -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+        let acc = self.builder.build_alloca(self.complex_type(), "acc");
+        let zero = self.pack(&Value::Complex(self.real(self.ctx.f64_type().const_zero())))?;
+        self.builder.build_store(acc, zero);
+
+        let len = self.list_len(list)?;
+        self.build_counted_loop(len, |this, k| {
+            let elem = this.builder.build_load(this.list_elem_ptr(list, k)?, "elem");
+            let elem = this.unpack_complex(elem)?;
+            let sum = this.unpack_complex(this.builder.build_load(acc, "acc"))?;
+            let sum = Value::Complex(ComplexValue {
+                re: this.builder.build_float_add(sum.re, elem.re, "sum_re"),
+                im: this.builder.build_float_add(sum.im, elem.im, "sum_im"),
+            });
+            let sum = this.pack(&sum)?;
+            this.builder.build_store(acc, sum);
+            Ok(())
+        })?;
+        self.unpack_complex(self.builder.build_load(acc, "sum"))
+    }
+
+    fn range_to_list(&mut self, range: RangeValue<'ctx>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let len = self.builder.build_float_to_signed_int(range.len, self.ctx.i64_type(), "len");
+        let list = self.build_list_alloc(&Type::Complex, len)?;
+        self.build_counted_loop(len, |this, k| {
+            let k_float = this.builder.build_signed_int_to_float(k, this.ctx.f64_type(), "k_float");
+            let value = Value::Complex(this.range_nth(range, k_float));
+            this.build_list_store(list, k, value)
+        })?;
+        Ok(list)
+    }
+}
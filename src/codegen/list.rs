@@ -0,0 +1,440 @@
+use inkwell::{AddressSpace, FloatPredicate, IntPredicate, basic_block::BasicBlock, types::{BasicType, StructType}, values::{IntValue, PointerValue}};
+
+use crate::{analyse::{ComplexValue, Located, Location, RangeValue, Type, Value}, error::{InternalError, LocatedCompileError}, parse::{BinOp, Clause, Expr}};
+
+use super::Compiler;
+
+/// Every list has room for at least this many elements, so that pushing never needs to special-case
+/// an empty allocation.
+const MIN_CAPACITY: u64 = 4;
+
+/// Lists live on the heap as `{ i64 len, i64 capacity, T* data }` and are passed around by pointer.
+/// Storing a list in a mutable variable copies it, so only the variable can see changes. They are
+/// never freed.
+impl<'ctx> Compiler<'ctx> {
+    pub(super) fn list_type(&self, elem: &Type) -> StructType<'ctx> {
+        let t_i64 = self.ctx.i64_type();
+        let data_type = self.llvm_type(elem).ptr_type(AddressSpace::Generic);
+        self.ctx.struct_type(&[t_i64.into(), t_i64.into(), data_type.into()], false)
+    }
+
+    fn list_field(&self, list: PointerValue<'ctx>, index: u32, name: &str) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        self.builder.build_struct_gep(list, index, name)
+            .map_err(|_| InternalError::invalid_state("malformed list"))
+    }
+
+    pub(super) fn list_len(&self, list: PointerValue<'ctx>) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let ptr = self.list_field(list, 0, "len_ptr")?;
+        Ok(self.builder.build_load(ptr, "len").into_int_value())
+    }
+
+    /// Shortens the list without any bounds checking.
+    pub(super) fn set_list_len(&self, list: PointerValue<'ctx>, len: IntValue<'ctx>) -> Result<(), LocatedCompileError> {
+        self.builder.build_store(self.list_field(list, 0, "len_ptr")?, len);
+        Ok(())
+    }
+
+    pub(super) fn list_elem_ptr(&self, list: PointerValue<'ctx>, k: IntValue<'ctx>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let ptr = self.list_field(list, 2, "data_ptr")?;
+        let data = self.builder.build_load(ptr, "data").into_pointer_value();
+        // Safety: callers check the index against the length first
+        Ok(unsafe { self.builder.build_in_bounds_gep(data, &[k], "elem_ptr") })
+    }
+
+    /// Allocates a list with the given length, whose elements are uninitialised.
+    pub(super) fn build_list_alloc(&mut self, elem: &Type, len: IntValue<'ctx>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let min_capacity = self.ctx.i64_type().const_int(MIN_CAPACITY, false);
+        let small = self.builder.build_int_compare(IntPredicate::SLT, len, min_capacity, "small");
+        let capacity = self.builder.build_select(small, min_capacity, len, "capacity").into_int_value();
+
+        let list = self.builder.build_malloc(self.list_type(elem), "list")
+            .map_err(InternalError::invalid_state)?;
+        let data = self.builder.build_array_malloc(self.llvm_type(elem), capacity, "list_data")
+            .map_err(InternalError::invalid_state)?;
+        self.builder.build_store(self.list_field(list, 0, "len_ptr")?, len);
+        self.builder.build_store(self.list_field(list, 1, "capacity_ptr")?, capacity);
+        self.builder.build_store(self.list_field(list, 2, "data_ptr")?, data);
+        Ok(list)
+    }
+
+    /// Builds a list out of already-computed values, which must all have type `elem`.
+    pub(super) fn build_list_from(&mut self, elem: &Type, values: Vec<Value<'ctx>>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let list = self.build_list_alloc(elem, t_i64.const_int(values.len() as u64, false))?;
+        for (k, value) in values.into_iter().enumerate() {
+            self.build_list_store(list, t_i64.const_int(k as u64, false), value)?;
+        }
+        Ok(list)
+    }
+
+    /// Converts a complex index into an `i64`, aborting unless it is a valid index into a list with
+    /// the given length.
+    fn build_list_index(&mut self, pos: Location, index: ComplexValue<'ctx>, len: IntValue<'ctx>) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let (k, valid) = self.list_index(index, len);
+        self.build_check(valid, pos, "list index out of bounds")?;
+        Ok(k)
+    }
+
+    /// Converts a complex index into an `i64`, along with whether it's a valid index into a list with
+    /// the given length. Negative indices count back from the end, so -1 is the last element. Indices
+    /// that don't fit in an `i64` (including `NaN`) are replaced by 0 before converting, since the
+    /// conversion would give garbage, and are never valid.
+    fn list_index(&self, index: ComplexValue<'ctx>, len: IntValue<'ctx>) -> (IntValue<'ctx>, IntValue<'ctx>) {
+        let t_i64 = self.ctx.i64_type();
+        let t_f64 = self.ctx.f64_type();
+        let limit = 2f64.powi(63);
+        let above = self.builder.build_float_compare(FloatPredicate::OGE, index.re, t_f64.const_float(-limit), "above");
+        let below = self.builder.build_float_compare(FloatPredicate::OLT, index.re, t_f64.const_float(limit), "below");
+        let fits = self.builder.build_and(above, below, "fits");
+        let re = self.builder.build_select(fits, index.re, t_f64.const_zero(), "index_re").into_float_value();
+        let k = self.builder.build_float_to_signed_int(re, t_i64, "index");
+        let k_float = self.builder.build_signed_int_to_float(k, t_f64, "index_float");
+        let integral = self.builder.build_float_compare(FloatPredicate::OEQ, k_float, index.re, "integral");
+        let integral = self.builder.build_and(integral, fits, "integral");
+        let real = self.builder.build_float_compare(FloatPredicate::OEQ, index.im, t_f64.const_zero(), "real");
+        let from_end = self.builder.build_int_compare(IntPredicate::SLT, k, t_i64.const_zero(), "from_end");
+        let wrapped = self.builder.build_int_add(k, len, "wrapped");
+        let k = self.builder.build_select(from_end, wrapped, k, "index").into_int_value();
+        let non_negative = self.builder.build_int_compare(IntPredicate::SGE, k, t_i64.const_zero(), "non_negative");
+        let in_bounds = self.builder.build_int_compare(IntPredicate::SLT, k, len, "in_bounds");
+
+        let valid = self.builder.build_and(integral, real, "valid_index");
+        let valid = self.builder.build_and(valid, non_negative, "valid_index");
+        let valid = self.builder.build_and(valid, in_bounds, "valid_index");
+        (k, valid)
+    }
+
+    /// The number of elements in a slice, which is zero if the range is empty. Lengths that don't fit
+    /// in an `i64` abort, since no list is that long anyway.
+    fn slice_len(&mut self, pos: Location, range: RangeValue<'ctx>) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let limit = self.ctx.f64_type().const_float(2f64.powi(63));
+        let fits = self.builder.build_float_compare(FloatPredicate::OLT, range.len, limit, "fits");
+        self.build_check(fits, pos, "slice is too long")?;
+        let n = self.builder.build_float_to_signed_int(range.len, t_i64, "slice_len");
+        let empty = self.builder.build_int_compare(IntPredicate::SLT, n, t_i64.const_zero(), "empty");
+        Ok(self.builder.build_select(empty, t_i64.const_zero(), n, "slice_len").into_int_value())
+    }
+
+    /// `xs[a..b]`, a new list of the elements at each index in the range. The indices can be negative,
+    /// just like for `xs[k]`, but they all have to be valid.
+    pub(super) fn build_list_slice(&mut self, pos: Location, list: PointerValue<'ctx>, elem: &Type, range: RangeValue<'ctx>)
+            -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let len = self.list_len(list)?;
+        let n = self.slice_len(pos, range)?;
+        let res = self.build_list_alloc(elem, n)?;
+        self.build_counted_loop(n, |this, k| {
+            let k_float = this.builder.build_signed_int_to_float(k, this.ctx.f64_type(), "k_float");
+            let (index, valid) = this.list_index(this.range_nth(range, k_float), len);
+            this.build_check(valid, pos, "list slice out of bounds")?;
+            let value = this.build_list_nth(list, elem, index)?;
+            this.build_list_store(res, k, value)
+        })?;
+        Ok(res)
+    }
+
+    /// `s[a..b]`, like slicing a list of characters. The indices count code points rather than bytes,
+    /// so a slice never splits a UTF-8 sequence. The first pass checks the indices and adds up how many
+    /// bytes the result needs, and the second copies them.
+    pub(super) fn build_str_slice(&mut self, pos: Location, ptr: PointerValue<'ctx>, range: RangeValue<'ctx>)
+            -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let (offsets, count) = self.build_str_offsets(ptr)?;
+        let n = self.slice_len(pos, range)?;
+        let cursor = self.builder.build_alloca(t_i64, "cursor");
+        self.builder.build_store(cursor, t_i64.const_zero());
+        self.build_counted_loop(n, |this, k| {
+            let (_, width) = this.str_slice_char(pos, range, (offsets, count), k)?;
+            let size = this.builder.build_load(cursor, "size").into_int_value();
+            this.builder.build_store(cursor, this.builder.build_int_add(size, width, "size"));
+            Ok(())
+        })?;
+        let size = self.builder.build_load(cursor, "size").into_int_value();
+        let size = self.builder.build_int_add(size, t_i64.const_int(1, false), "size");
+        let res = self.builder.build_array_malloc(self.ctx.i8_type(), size, "slice")
+            .map_err(InternalError::invalid_state)?;
+
+        self.builder.build_store(cursor, t_i64.const_zero());
+        self.build_counted_loop(n, |this, k| {
+            let (from, width) = this.str_slice_char(pos, range, (offsets, count), k)?;
+            let at = this.builder.build_load(cursor, "at").into_int_value();
+            // Safety: `from` is the start of a code point, and the first pass made room for it
+            let (src, dest) = unsafe {
+                (this.builder.build_in_bounds_gep(ptr, &[from], "src"), this.builder.build_in_bounds_gep(res, &[at], "dest"))
+            };
+            this.builder.build_memcpy(dest, 1, src, 1, width).map_err(InternalError::invalid_state)?;
+            this.builder.build_store(cursor, this.builder.build_int_add(at, width, "at"));
+            Ok(())
+        })?;
+        let at = self.builder.build_load(cursor, "at").into_int_value();
+        // Safety: the result has room for the terminator
+        let end = unsafe { self.builder.build_in_bounds_gep(res, &[at], "end") };
+        self.builder.build_store(end, self.ctx.i8_type().const_zero());
+        Ok(res)
+    }
+
+    /// Where the `k`-th code point of a slice starts in the string, and how many bytes it takes.
+    fn str_slice_char(&mut self, pos: Location, range: RangeValue<'ctx>, (offsets, count): (PointerValue<'ctx>, IntValue<'ctx>), k: IntValue<'ctx>)
+            -> Result<(IntValue<'ctx>, IntValue<'ctx>), LocatedCompileError> {
+        let k_float = self.builder.build_signed_int_to_float(k, self.ctx.f64_type(), "k_float");
+        let (index, valid) = self.list_index(self.range_nth(range, k_float), count);
+        self.build_check(valid, pos, "string slice out of bounds")?;
+        let next = self.builder.build_int_add(index, self.ctx.i64_type().const_int(1, false), "next");
+        // Safety: the index was just checked, and there's one more offset than there are code points
+        let (from, to) = unsafe {
+            (self.builder.build_in_bounds_gep(offsets, &[index], "from"), self.builder.build_in_bounds_gep(offsets, &[next], "to"))
+        };
+        let from = self.builder.build_load(from, "from").into_int_value();
+        let to = self.builder.build_load(to, "to").into_int_value();
+        Ok((from, self.builder.build_int_sub(to, from, "width")))
+    }
+
+    /// The byte offset where each code point of a string starts, followed by the length in bytes, and
+    /// the number of code points. A byte starts a code point unless it's a continuation byte `10xxxxxx`.
+    pub(super) fn build_str_offsets(&mut self, ptr: PointerValue<'ctx>) -> Result<(PointerValue<'ctx>, IntValue<'ctx>), LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let i8_type = self.ctx.i8_type();
+        let strlen = self.builtins.strlen();
+        self.move_to_end()?;
+        let len = self.builder.build_call(strlen, &[ptr.into()], "len")
+            .try_as_basic_value().left()
+                .ok_or_else(|| InternalError::invalid_state("failed to interpret return value of strlen"))?
+            .into_int_value();
+        let size = self.builder.build_int_add(len, t_i64.const_int(1, false), "size");
+        let offsets = self.builder.build_array_malloc(t_i64, size, "offsets")
+            .map_err(InternalError::invalid_state)?;
+        let count = self.builder.build_alloca(t_i64, "count");
+        self.builder.build_store(count, t_i64.const_zero());
+        self.build_counted_loop(len, |this, k| {
+            // Safety: `k` is less than the length of the string
+            let byte = unsafe { this.builder.build_in_bounds_gep(ptr, &[k], "byte") };
+            let byte = this.builder.build_load(byte, "byte").into_int_value();
+            let high = this.builder.build_and(byte, i8_type.const_int(0xc0, false), "high");
+            let starts = this.builder.build_int_compare(IntPredicate::NE, high, i8_type.const_int(0x80, false), "starts");
+            let start_bb = this.ctx.append_basic_block(this.get_fp()?, "char_start");
+            let cont_bb = this.ctx.append_basic_block(this.get_fp()?, "cont");
+            this.builder.build_conditional_branch(starts, start_bb, cont_bb);
+
+            this.set_and_move_block(start_bb)?;
+            let c = this.builder.build_load(count, "c").into_int_value();
+            // Safety: there are at most as many code points as bytes
+            let slot = unsafe { this.builder.build_in_bounds_gep(offsets, &[c], "offset") };
+            this.builder.build_store(slot, k);
+            this.builder.build_store(count, this.builder.build_int_add(c, t_i64.const_int(1, false), "c"));
+            this.builder.build_unconditional_branch(cont_bb);
+
+            this.set_and_move_block(cont_bb)
+        })?;
+        let count = self.builder.build_load(count, "count").into_int_value();
+        // Safety: `count` is at most the length, and there's room for one more
+        let end = unsafe { self.builder.build_in_bounds_gep(offsets, &[count], "end") };
+        self.builder.build_store(end, len);
+        Ok((offsets, count))
+    }
+
+    /// Makes a new list with the same elements.
+    pub(super) fn build_list_copy(&mut self, list: PointerValue<'ctx>, elem: &Type) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let len = self.list_len(list)?;
+        let copy = self.build_list_alloc(elem, len)?;
+        self.build_counted_loop(len, |this, k| {
+            let value = this.builder.build_load(this.list_elem_ptr(list, k)?, "elem");
+            this.builder.build_store(this.list_elem_ptr(copy, k)?, value);
+            Ok(())
+        })?;
+        Ok(copy)
+    }
+
+    /// Reads the k-th element without any bounds checking.
+    pub(super) fn build_list_nth(&mut self, list: PointerValue<'ctx>, elem: &Type, k: IntValue<'ctx>) -> Result<Value<'ctx>, LocatedCompileError> {
+        let ptr = self.list_elem_ptr(list, k)?;
+        let value = self.builder.build_load(ptr, "elem");
+        self.unpack(value, elem)
+    }
+
+    pub(super) fn build_list_get(&mut self, pos: Location, list: PointerValue<'ctx>, elem: &Type, index: ComplexValue<'ctx>)
+            -> Result<Value<'ctx>, LocatedCompileError> {
+        let len = self.list_len(list)?;
+        let k = self.build_list_index(pos, index, len)?;
+        self.build_list_nth(list, elem, k)
+    }
+
+    /// Like `build_list_get`, but gives `none` instead of aborting if the index is out of bounds.
+    pub(super) fn build_list_try_get(&mut self, list: PointerValue<'ctx>, elem: &Type, index: ComplexValue<'ctx>)
+            -> Result<Value<'ctx>, LocatedCompileError> {
+        let len = self.list_len(list)?;
+        let (k, valid) = self.list_index(index, len);
+        let none = self.build_none(elem)?;
+        let none = self.pack(&none)?;
+        let entry_bb = self.get_block()?;
+        let some_bb = self.ctx.append_basic_block(self.get_fp()?, "some");
+        let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "cont");
+        self.builder.build_conditional_branch(valid, some_bb, cont_bb);
+
+        self.set_and_move_block(some_bb)?;
+        let value = self.build_list_nth(list, elem, k)?;
+        let some = self.pack(&self.build_some(value))?;
+        let some_bb = self.get_block()?;
+        self.builder.build_unconditional_branch(cont_bb);
+
+        self.set_and_move_block(cont_bb)?;
+        let phi = self.builder.build_phi(self.option_type(elem), "get_tmp");
+        phi.add_incoming(&[(&none, entry_bb), (&some, some_bb)]);
+        self.unpack(phi.as_basic_value(), &Type::Option(Box::new(elem.clone())))
+    }
+
+    /// The index of the first element equal to `value`, or `none` if there isn't one.
+    pub(super) fn build_list_find(&mut self, pos: Location, list: PointerValue<'ctx>, elem: &Type, value: Value<'ctx>)
+            -> Result<Value<'ctx>, LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let len = self.list_len(list)?;
+        let found_ptr = self.builder.build_alloca(t_i64, "found_ptr");
+        self.builder.build_store(found_ptr, len);
+        // Starts at `len` to mean "not found", and keeps the first match after that
+        self.build_counted_loop(len, |this, k| {
+            let candidate = this.build_list_nth(list, elem, k)?;
+            let equal = this.build_equality(pos, BinOp::Equals, candidate, value.clone())?;
+            let equal = this.expect_bool(pos, equal)?;
+            let found = this.builder.build_load(found_ptr, "found").into_int_value();
+            let first = this.builder.build_int_compare(IntPredicate::EQ, found, len, "first");
+            let first = this.builder.build_and(first, equal, "first_match");
+            let found = this.builder.build_select(first, k, found, "found");
+            this.builder.build_store(found_ptr, found);
+            Ok(())
+        })?;
+        let found = self.builder.build_load(found_ptr, "found").into_int_value();
+        let present = self.builder.build_int_compare(IntPredicate::SLT, found, len, "present");
+        let index = self.builder.build_signed_int_to_float(found, self.ctx.f64_type(), "index");
+        let index = ComplexValue { re: index, im: self.ctx.f64_type().const_zero() };
+        Ok(Value::Option(present, Box::new(Value::Complex(index))))
+    }
+
+    /// Writes the k-th element without any bounds checking.
+    pub(super) fn build_list_store(&mut self, list: PointerValue<'ctx>, k: IntValue<'ctx>, value: Value<'ctx>) -> Result<(), LocatedCompileError> {
+        let ptr = self.list_elem_ptr(list, k)?;
+        let value = self.pack(&value)?;
+        self.builder.build_store(ptr, value);
+        Ok(())
+    }
+
+    pub(super) fn build_list_set(&mut self, pos: Location, list: PointerValue<'ctx>, index: ComplexValue<'ctx>, value: Value<'ctx>)
+            -> Result<(), LocatedCompileError> {
+        let len = self.list_len(list)?;
+        let k = self.build_list_index(pos, index, len)?;
+        self.build_list_store(list, k, value)
+    }
+
+    /// Appends a value to the end of the list, doubling its capacity if it's full.
+    pub(super) fn build_list_push(&mut self, list: PointerValue<'ctx>, elem: &Type, value: Value<'ctx>) -> Result<(), LocatedCompileError> {
+        let realloc = self.builtins.realloc();
+        self.move_to_end()?;
+
+        let len = self.list_len(list)?;
+        let capacity_ptr = self.list_field(list, 1, "capacity_ptr")?;
+        let capacity = self.builder.build_load(capacity_ptr, "capacity").into_int_value();
+        let full = self.builder.build_int_compare(IntPredicate::EQ, len, capacity, "full");
+
+        let grow_bb = self.ctx.append_basic_block(self.get_fp()?, "grow");
+        let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "cont");
+        self.builder.build_conditional_branch(full, grow_bb, cont_bb);
+
+        self.set_and_move_block(grow_bb)?;
+        let elem_type = self.llvm_type(elem);
+        let elem_size = elem_type.size_of()
+            .ok_or_else(|| InternalError::invalid_state("list element has no size"))?;
+        let new_capacity = self.builder.build_int_mul(capacity, self.ctx.i64_type().const_int(2, false), "new_capacity");
+        let size = self.builder.build_int_mul(new_capacity, elem_size, "size");
+        let data_ptr = self.list_field(list, 2, "data_ptr")?;
+        let data = self.builder.build_load(data_ptr, "data").into_pointer_value();
+        let raw = self.builder.build_pointer_cast(data, self.ctx.i8_type().ptr_type(AddressSpace::Generic), "raw");
+        let raw = self.builder.build_call(realloc, &[raw.into(), size.into()], "raw")
+            .try_as_basic_value().left()
+                .ok_or_else(|| InternalError::invalid_state("failed to interpret return value of realloc"))?
+            .into_pointer_value();
+        let data = self.builder.build_pointer_cast(raw, elem_type.ptr_type(AddressSpace::Generic), "data");
+        self.builder.build_store(data_ptr, data);
+        self.builder.build_store(capacity_ptr, new_capacity);
+        self.builder.build_unconditional_branch(cont_bb);
+
+        self.set_and_move_block(cont_bb)?;
+        let ptr = self.list_elem_ptr(list, len)?;
+        let value = self.pack(&value)?;
+        self.builder.build_store(ptr, value);
+        let len_ptr = self.list_field(list, 0, "len_ptr")?;
+        let new_len = self.builder.build_int_add(len, self.ctx.i64_type().const_int(1, false), "new_len");
+        self.builder.build_store(len_ptr, new_len);
+        Ok(())
+    }
+
+    /// Prints the elements between the brackets, separated by commas.
+    pub(super) fn print_elements(&mut self, pos: Location, list: PointerValue<'ctx>, elem: &Type, (open, close): (&str, &str), newline: bool)
+            -> Result<(), LocatedCompileError> {
+        let print_str = self.builtins.print_str();
+        self.move_to_end()?;
+        self.build_print_str(open.to_owned())?;
+        let len = self.list_len(list)?;
+        let empty = self.builder.build_global_string_ptr("", ".empty_str").as_pointer_value();
+        let comma = self.builder.build_global_string_ptr(", ", ".comma_str").as_pointer_value();
+        self.build_counted_loop(len, |this, k| {
+            let first = this.builder.build_int_compare(IntPredicate::EQ, k, this.ctx.i64_type().const_zero(), "first");
+            let sep = this.builder.build_select(first, empty, comma, "sep");
+            this.builder.build_call(print_str, &[sep], "call");
+            let value = this.build_list_nth(list, elem, k)?;
+            this.print_value(pos, value, false)
+        })?;
+        if newline {
+            self.build_println_str(close.to_owned())
+        } else {
+            self.build_print_str(close.to_owned())
+        }
+    }
+
+    /// Builds `[elem for x in xs if cond]` as nested loops that push onto a new list. The element
+    /// type isn't known until `elem` is compiled, so the list is allocated afterwards, in a block that
+    /// runs before the loops.
+    pub(super) fn build_comprehension(&mut self, elem: Located<Expr>, clauses: Vec<Clause>) -> Result<Value<'ctx>, LocatedCompileError> {
+        let alloc_bb = self.ctx.append_basic_block(self.get_fp()?, "comprehension");
+        let start_bb = self.ctx.append_basic_block(self.get_fp()?, "start");
+        self.builder.build_unconditional_branch(alloc_bb);
+
+        self.set_and_move_block(start_bb)?;
+        let mut list = None;
+        self.build_clauses(alloc_bb, &elem, &clauses, &mut list)?;
+        let (list, elem) = list.ok_or_else(|| InternalError::invalid_state("comprehension never built its element"))?;
+        let end_bb = self.get_block()?;
+
+        self.set_and_move_block(alloc_bb)?;
+        self.builder.build_unconditional_branch(start_bb);
+        self.set_and_move_block(end_bb)?;
+        Ok(Value::List(list, elem))
+    }
+
+    fn build_clauses(&mut self, alloc_bb: BasicBlock<'ctx>, elem: &Located<Expr>, clauses: &[Clause],
+            list: &mut Option<(PointerValue<'ctx>, Type)>) -> Result<(), LocatedCompileError> {
+        match clauses.split_first() {
+            Some((Clause::For(id, iter), rest)) =>
+                self.build_for_each(None, id.clone(), iter.clone(), |this| this.build_clauses(alloc_bb, elem, rest, list)),
+            Some((Clause::If(cond), rest)) => {
+                let cond = self.build_cond(cond.clone())?;
+                let then_bb = self.ctx.append_basic_block(self.get_fp()?, "then");
+                let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "cont");
+                self.builder.build_conditional_branch(cond, then_bb, cont_bb);
+
+                self.set_and_move_block(then_bb)?;
+                self.build_clauses(alloc_bb, elem, rest, list)?;
+                self.builder.build_unconditional_branch(cont_bb);
+                self.set_and_move_block(cont_bb)
+            },
+            None => {
+                let value = self.build_expr(elem.clone())?;
+                let ty = value.ty();
+                let push_bb = self.get_block()?;
+                self.set_and_move_block(alloc_bb)?;
+                let new_list = self.build_list_alloc(&ty, self.ctx.i64_type().const_zero())?;
+                self.set_and_move_block(push_bb)?;
+                self.build_list_push(new_list, &ty, value)?;
+                *list = Some((new_list, ty));
+                Ok(())
+            },
+        }
+    }
+}
@@ -0,0 +1,546 @@
+use inkwell::{AddressSpace, FloatPredicate, IntPredicate, types::StructType, values::{FloatValue, IntValue, PointerValue}};
+
+use crate::{analyse::{ComplexValue, Location, Type, Value}, error::{InternalError, LocatedCompileError}, parse::{BinOp, UnOp}};
+
+use super::Compiler;
+
+/// Matrices live on the heap as `{ i64 rows, i64 cols, complex* data }`, with the entries stored one
+/// row after another. Like lists they are passed around by pointer, but every operation builds a new
+/// matrix, so there's no way to tell. They are never freed.
+impl<'ctx> Compiler<'ctx> {
+    pub(super) fn matrix_type(&self) -> StructType<'ctx> {
+        let t_i64 = self.ctx.i64_type();
+        let data_type = self.complex_type().ptr_type(AddressSpace::Generic);
+        self.ctx.struct_type(&[t_i64.into(), t_i64.into(), data_type.into()], false)
+    }
+
+    fn matrix_field(&self, matrix: PointerValue<'ctx>, index: u32, name: &str) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        self.builder.build_struct_gep(matrix, index, name)
+            .map_err(|_| InternalError::invalid_state("malformed matrix"))
+    }
+
+    fn matrix_rows(&self, matrix: PointerValue<'ctx>) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let ptr = self.matrix_field(matrix, 0, "rows_ptr")?;
+        Ok(self.builder.build_load(ptr, "rows").into_int_value())
+    }
+
+    fn matrix_cols(&self, matrix: PointerValue<'ctx>) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let ptr = self.matrix_field(matrix, 1, "cols_ptr")?;
+        Ok(self.builder.build_load(ptr, "cols").into_int_value())
+    }
+
+    /// The number of entries.
+    fn matrix_size(&self, matrix: PointerValue<'ctx>) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let rows = self.matrix_rows(matrix)?;
+        let cols = self.matrix_cols(matrix)?;
+        Ok(self.builder.build_int_mul(rows, cols, "size"))
+    }
+
+    fn matrix_entry_ptr(&self, matrix: PointerValue<'ctx>, k: IntValue<'ctx>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let ptr = self.matrix_field(matrix, 2, "data_ptr")?;
+        let data = self.builder.build_load(ptr, "data").into_pointer_value();
+        // Safety: callers only use indices below the size of the matrix
+        Ok(unsafe { self.builder.build_in_bounds_gep(data, &[k], "entry_ptr") })
+    }
+
+    /// Reads the k-th entry, counting along each row in turn.
+    fn matrix_load(&self, matrix: PointerValue<'ctx>, k: IntValue<'ctx>) -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+        let ptr = self.matrix_entry_ptr(matrix, k)?;
+        self.unpack_complex(self.builder.build_load(ptr, "entry"))
+    }
+
+    /// Writes the k-th entry, counting along each row in turn.
+    fn matrix_store(&self, matrix: PointerValue<'ctx>, k: IntValue<'ctx>, value: ComplexValue<'ctx>) -> Result<(), LocatedCompileError> {
+        let ptr = self.matrix_entry_ptr(matrix, k)?;
+        let value = self.pack(&Value::Complex(value))?;
+        self.builder.build_store(ptr, value);
+        Ok(())
+    }
+
+    /// Where the entry at the given row and column is stored.
+    fn matrix_index(&self, row: IntValue<'ctx>, col: IntValue<'ctx>, cols: IntValue<'ctx>) -> IntValue<'ctx> {
+        let offset = self.builder.build_int_mul(row, cols, "row_offset");
+        self.builder.build_int_add(offset, col, "index")
+    }
+
+    /// Allocates a matrix with the given dimensions, whose entries are uninitialised.
+    fn build_matrix_alloc(&mut self, rows: IntValue<'ctx>, cols: IntValue<'ctx>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let size = self.builder.build_int_mul(rows, cols, "size");
+        let matrix = self.builder.build_malloc(self.matrix_type(), "matrix")
+            .map_err(InternalError::invalid_state)?;
+        let data = self.builder.build_array_malloc(self.complex_type(), size, "matrix_data")
+            .map_err(InternalError::invalid_state)?;
+        self.builder.build_store(self.matrix_field(matrix, 0, "rows_ptr")?, rows);
+        self.builder.build_store(self.matrix_field(matrix, 1, "cols_ptr")?, cols);
+        self.builder.build_store(self.matrix_field(matrix, 2, "data_ptr")?, data);
+        Ok(matrix)
+    }
+
+    /// Builds a matrix out of already-computed rows, which must all have the same length.
+    pub(super) fn build_matrix_from(&mut self, entries: Vec<Vec<ComplexValue<'ctx>>>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let rows = t_i64.const_int(entries.len() as u64, false);
+        let cols = t_i64.const_int(entries.first().map_or(0, Vec::len) as u64, false);
+        let matrix = self.build_matrix_alloc(rows, cols)?;
+        for (k, entry) in entries.into_iter().flatten().enumerate() {
+            self.matrix_store(matrix, t_i64.const_int(k as u64, false), entry)?;
+        }
+        Ok(matrix)
+    }
+
+    /// Arithmetic where at least one side is a matrix.
+    pub(super) fn build_matrix_binop(&mut self, pos: Location, op: BinOp, lpos: Location, lval: Value<'ctx>, rpos: Location, rval: Value<'ctx>)
+            -> Result<Value<'ctx>, LocatedCompileError> {
+        match (op, lval, rval) {
+            (BinOp::Plus | BinOp::Minus, Value::Matrix(lhs), Value::Matrix(rhs)) =>
+                self.build_matrix_sum(pos, op, lhs, rhs).map(Value::Matrix),
+            (BinOp::Times, Value::Matrix(lhs), Value::Matrix(rhs)) =>
+                self.build_matrix_product(pos, lhs, rhs).map(Value::Matrix),
+            (BinOp::Times, Value::Complex(scalar), Value::Matrix(matrix))
+                | (BinOp::Times, Value::Matrix(matrix), Value::Complex(scalar)) =>
+                self.build_matrix_scale(matrix, scalar).map(Value::Matrix),
+            (BinOp::Plus | BinOp::Minus | BinOp::Times, Value::Matrix(_), rval) =>
+                Err(LocatedCompileError::type_mismatch(rpos, &Type::Matrix, &rval.ty())),
+            (BinOp::Plus | BinOp::Minus, lval, _) =>
+                Err(LocatedCompileError::type_mismatch(lpos, &Type::Matrix, &lval.ty())),
+            (BinOp::Times, lval, _) =>
+                Err(LocatedCompileError::type_mismatch(lpos, &Type::Complex, &lval.ty())),
+            _ => Err(LocatedCompileError::unsupported(pos, "this operator is not defined for matrices".to_owned())),
+        }
+    }
+
+    /// `-m` scales by -1, and `m^` is the conjugate transpose.
+    pub(super) fn build_matrix_unop(&mut self, pos: Location, op: UnOp, matrix: PointerValue<'ctx>) -> Result<Value<'ctx>, LocatedCompileError> {
+        match op {
+            UnOp::Negate => {
+                let t_f64 = self.ctx.f64_type();
+                let minus_one = ComplexValue { re: t_f64.const_float(-1.), im: t_f64.const_zero() };
+                self.build_matrix_scale(matrix, minus_one).map(Value::Matrix)
+            },
+            UnOp::Conjugate => self.build_matrix_transpose(matrix, true).map(Value::Matrix),
+            UnOp::Modulus   => Err(LocatedCompileError::unsupported(pos, "matrices have no modulus".to_owned())),
+            UnOp::Factorial => Err(LocatedCompileError::unsupported(pos, "matrices have no factorial".to_owned())),
+        }
+    }
+
+    fn build_matrix_sum(&mut self, pos: Location, op: BinOp, lhs: PointerValue<'ctx>, rhs: PointerValue<'ctx>)
+            -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let rows = self.matrix_rows(lhs)?;
+        let cols = self.matrix_cols(lhs)?;
+        let same_rows = self.builder.build_int_compare(IntPredicate::EQ, rows, self.matrix_rows(rhs)?, "same_rows");
+        let same_cols = self.builder.build_int_compare(IntPredicate::EQ, cols, self.matrix_cols(rhs)?, "same_cols");
+        let same = self.builder.build_and(same_rows, same_cols, "same_dims");
+        self.build_check(same, pos, "matrices have different dimensions")?;
+
+        let res = self.build_matrix_alloc(rows, cols)?;
+        let size = self.builder.build_int_mul(rows, cols, "size");
+        self.build_counted_loop(size, |this, k| {
+            let lval = this.matrix_load(lhs, k)?;
+            let rval = this.matrix_load(rhs, k)?;
+            let entry = if matches!(op, BinOp::Plus) {
+                (this.builder.build_float_add(lval.re, rval.re, "entry_re"),
+                 this.builder.build_float_add(lval.im, rval.im, "entry_im")).into()
+            } else {
+                (this.builder.build_float_sub(lval.re, rval.re, "entry_re"),
+                 this.builder.build_float_sub(lval.im, rval.im, "entry_im")).into()
+            };
+            this.matrix_store(res, k, entry)
+        })?;
+        Ok(res)
+    }
+
+    /// Accumulates every product `lhs[i][t] * rhs[t][j]` into `res[i][j]` in a single flat loop,
+    /// rather than nesting three loops.
+    fn build_matrix_product(&mut self, pos: Location, lhs: PointerValue<'ctx>, rhs: PointerValue<'ctx>)
+            -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let rows = self.matrix_rows(lhs)?;
+        let inner = self.matrix_cols(lhs)?;
+        let cols = self.matrix_cols(rhs)?;
+        let compatible = self.builder.build_int_compare(IntPredicate::EQ, inner, self.matrix_rows(rhs)?, "compatible");
+        self.build_check(compatible, pos, "matrix dimensions do not match for multiplication")?;
+
+        let res = self.build_matrix_alloc(rows, cols)?;
+        let size = self.builder.build_int_mul(rows, cols, "size");
+        let t_f64 = self.ctx.f64_type();
+        let zero = ComplexValue { re: t_f64.const_zero(), im: t_f64.const_zero() };
+        self.build_counted_loop(size, |this, k| this.matrix_store(res, k, zero))?;
+
+        let steps = self.builder.build_int_mul(size, inner, "steps");
+        self.build_counted_loop(steps, |this, k| {
+            let index = this.builder.build_int_signed_div(k, inner, "index");
+            let t = this.builder.build_int_signed_rem(k, inner, "t");
+            let i = this.builder.build_int_signed_div(index, cols, "i");
+            let j = this.builder.build_int_signed_rem(index, cols, "j");
+            let lval = this.matrix_load(lhs, this.matrix_index(i, t, inner))?;
+            let rval = this.matrix_load(rhs, this.matrix_index(t, j, cols))?;
+            let product = this.complex_mul(lval, rval);
+            let acc = this.matrix_load(res, index)?;
+            let acc = (this.builder.build_float_add(acc.re, product.re, "acc_re"),
+                       this.builder.build_float_add(acc.im, product.im, "acc_im")).into();
+            this.matrix_store(res, index, acc)
+        })?;
+        Ok(res)
+    }
+
+    fn build_matrix_scale(&mut self, matrix: PointerValue<'ctx>, scalar: ComplexValue<'ctx>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let rows = self.matrix_rows(matrix)?;
+        let cols = self.matrix_cols(matrix)?;
+        let res = self.build_matrix_alloc(rows, cols)?;
+        let size = self.builder.build_int_mul(rows, cols, "size");
+        self.build_counted_loop(size, |this, k| {
+            let entry = this.matrix_load(matrix, k)?;
+            let entry = this.complex_mul(scalar, entry);
+            this.matrix_store(res, k, entry)
+        })?;
+        Ok(res)
+    }
+
+    /// Swaps rows and columns, conjugating each entry if asked to.
+    pub(super) fn build_matrix_transpose(&mut self, matrix: PointerValue<'ctx>, conjugate: bool) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let rows = self.matrix_rows(matrix)?;
+        let cols = self.matrix_cols(matrix)?;
+        let res = self.build_matrix_alloc(cols, rows)?;
+        let size = self.builder.build_int_mul(rows, cols, "size");
+        self.build_counted_loop(size, |this, k| {
+            let i = this.builder.build_int_signed_div(k, cols, "i");
+            let j = this.builder.build_int_signed_rem(k, cols, "j");
+            let mut entry = this.matrix_load(matrix, k)?;
+            if conjugate {
+                entry = this.complex_conjugate(entry);
+            }
+            this.matrix_store(res, this.matrix_index(j, i, rows), entry)
+        })?;
+        Ok(res)
+    }
+
+    /// Prints the matrix as a list of rows, e.g. `[[1, i], [2, 3]]`.
+    pub(super) fn print_matrix(&mut self, matrix: PointerValue<'ctx>, newline: bool) -> Result<(), LocatedCompileError> {
+        let print_str = self.builtins.print_str();
+        let print_float = self.builtins.print_float();
+        self.move_to_end()?;
+        let first = self.builder.build_global_string_ptr("[[", ".matrix_open_str").as_pointer_value();
+        let row = self.builder.build_global_string_ptr("], [", ".matrix_row_str").as_pointer_value();
+        let comma = self.builder.build_global_string_ptr(", ", ".comma_str").as_pointer_value();
+
+        let cols = self.matrix_cols(matrix)?;
+        let size = self.matrix_size(matrix)?;
+        self.build_counted_loop(size, |this, k| {
+            let zero = this.ctx.i64_type().const_zero();
+            let col = this.builder.build_int_signed_rem(k, cols, "col");
+            let row_start = this.builder.build_int_compare(IntPredicate::EQ, col, zero, "row_start");
+            let is_first = this.builder.build_int_compare(IntPredicate::EQ, k, zero, "first");
+            let sep = this.builder.build_select(row_start, row, comma, "sep");
+            let sep = this.builder.build_select(is_first, first, sep.into_pointer_value(), "sep");
+            this.builder.build_call(print_str, &[sep], "call");
+            let entry = this.matrix_load(matrix, k)?;
+            this.builder.build_call(print_float, &[entry.re.into(), entry.im.into()], "call");
+            Ok(())
+        })?;
+        if newline {
+            self.build_println_str("]]".to_owned())
+        } else {
+            self.build_print_str("]]".to_owned())
+        }
+    }
+
+    /// `det(m)`, which stays exact for Gaussian integer entries as long as the minors fit in 53 bits.
+    pub(super) fn build_matrix_det(&mut self, pos: Location, matrix: PointerValue<'ctx>) -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+        self.check_square(pos, matrix, "`det` needs a square matrix")?;
+        let n = self.matrix_rows(matrix)?;
+        let work = self.build_matrix_copy(matrix)?;
+        let (rank, pivot) = self.build_bareiss(work, n, false)?;
+        let full = self.builder.build_int_compare(IntPredicate::EQ, rank, n, "full");
+        let zero = self.ctx.f64_type().const_zero();
+        Ok(ComplexValue {
+            re: self.builder.build_select(full, pivot.re, zero, "det_re").into_float_value(),
+            im: self.builder.build_select(full, pivot.im, zero, "det_im").into_float_value(),
+        })
+    }
+
+    /// `rank(m)`, the number of pivots left after elimination.
+    pub(super) fn build_matrix_rank(&mut self, matrix: PointerValue<'ctx>) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let cols = self.matrix_cols(matrix)?;
+        let work = self.build_matrix_copy(matrix)?;
+        self.build_bareiss(work, cols, false).map(|(rank, _)| rank)
+    }
+
+    /// `inverse(m)`. Eliminating in `[m | 1]` leaves `[d | d m^-1]`, where `d = det(m)`, and dividing
+    /// by `d` at the end gives a rational for each entry, so the result is a list of rows rather than
+    /// a matrix. The entries of `m` have to be Gaussian integers.
+    pub(super) fn build_matrix_inverse(&mut self, pos: Location, matrix: PointerValue<'ctx>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        self.check_square(pos, matrix, "`inverse` needs a square matrix")?;
+        let t_i64 = self.ctx.i64_type();
+        let t_f64 = self.ctx.f64_type();
+        let n = self.matrix_rows(matrix)?;
+        let width = self.builder.build_int_mul(n, t_i64.const_int(2, false), "width");
+        let work = self.build_matrix_alloc(n, width)?;
+        let size = self.builder.build_int_mul(n, width, "size");
+        self.build_counted_loop(size, |this, k| {
+            let i = this.builder.build_int_signed_div(k, width, "i");
+            let j = this.builder.build_int_signed_rem(k, width, "j");
+            let left = this.builder.build_int_compare(IntPredicate::SLT, j, n, "left");
+            let entry_index = this.builder.build_select(left, this.matrix_index(i, j, n), t_i64.const_zero(), "entry_index").into_int_value();
+            let entry = this.matrix_load(matrix, entry_index)?;
+            let shifted = this.builder.build_int_sub(j, n, "shifted");
+            let diagonal = this.builder.build_int_compare(IntPredicate::EQ, shifted, i, "diagonal");
+            let one = this.builder.build_select(diagonal, t_f64.const_float(1.), t_f64.const_zero(), "one").into_float_value();
+            this.matrix_store(work, k, ComplexValue {
+                re: this.builder.build_select(left, entry.re, one, "entry_re").into_float_value(),
+                im: this.builder.build_select(left, entry.im, t_f64.const_zero(), "entry_im").into_float_value(),
+            })
+        })?;
+
+        let (rank, det) = self.build_bareiss(work, n, true)?;
+        let invertible = self.builder.build_int_compare(IntPredicate::EQ, rank, n, "invertible");
+        self.build_check(invertible, pos, "the matrix is singular")?;
+
+        let row_type = Type::List(Box::new(Type::Rational));
+        let res = self.build_list_alloc(&row_type, n)?;
+        self.build_counted_loop(n, |this, i| {
+            let row = this.build_list_alloc(&Type::Rational, n)?;
+            this.build_list_store(res, i, Value::List(row, Type::Rational))
+        })?;
+        let size = self.builder.build_int_mul(n, n, "size");
+        self.build_counted_loop(size, |this, k| {
+            let i = this.builder.build_int_signed_div(k, n, "i");
+            let j = this.builder.build_int_signed_rem(k, n, "j");
+            let row = match this.build_list_nth(res, &row_type, i)? {
+                Value::List(row, _) => row,
+                _ => return Err(InternalError::invalid_state("a row of the inverse isn't a list")),
+            };
+            let entry = this.matrix_load(work, this.matrix_index(i, this.builder.build_int_add(j, n, "j"), width))?;
+            let entry = this.build_rational_call(pos, entry, det)?;
+            this.build_list_store(row, j, entry)
+        })?;
+        Ok(res)
+    }
+
+    /// `solve(a, b)`, the column `x` with `a * x == b`. Like `inverse`, it eliminates in `[a | b]`,
+    /// which leaves `d x` in the last column, for the last pivot `d`, and gives `x` as a list of
+    /// rationals. It aborts unless there's exactly one solution.
+    pub(super) fn build_matrix_solve(&mut self, pos: Location, a: PointerValue<'ctx>, b: PointerValue<'ctx>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let zero = self.ctx.f64_type().const_zero();
+        let one = t_i64.const_int(1, false);
+        let rows = self.matrix_rows(a)?;
+        let cols = self.matrix_cols(a)?;
+        let same_rows = self.builder.build_int_compare(IntPredicate::EQ, self.matrix_rows(b)?, rows, "same_rows");
+        let column = self.builder.build_int_compare(IntPredicate::EQ, self.matrix_cols(b)?, one, "column");
+        let ok = self.builder.build_and(same_rows, column, "ok");
+        self.build_check(ok, pos, "`solve` needs a column with as many rows as the matrix")?;
+
+        let width = self.builder.build_int_add(cols, one, "width");
+        let work = self.build_matrix_alloc(rows, width)?;
+        let size = self.builder.build_int_mul(rows, width, "size");
+        self.build_counted_loop(size, |this, k| {
+            let i = this.builder.build_int_signed_div(k, width, "i");
+            let j = this.builder.build_int_signed_rem(k, width, "j");
+            let left = this.builder.build_int_compare(IntPredicate::SLT, j, cols, "left");
+            let entry_index = this.builder.build_select(left, this.matrix_index(i, j, cols), t_i64.const_zero(), "entry_index").into_int_value();
+            let entry = this.matrix_load(a, entry_index)?;
+            let rhs = this.matrix_load(b, i)?;
+            this.matrix_store(work, k, ComplexValue {
+                re: this.builder.build_select(left, entry.re, rhs.re, "entry_re").into_float_value(),
+                im: this.builder.build_select(left, entry.im, rhs.im, "entry_im").into_float_value(),
+            })
+        })?;
+        let (rank, det) = self.build_bareiss(work, cols, true)?;
+
+        // Rows without a pivot say 0 == something, which has to be 0
+        let consistent = self.builder.build_alloca(self.ctx.bool_type(), "consistent");
+        self.builder.build_store(consistent, self.ctx.bool_type().const_int(1, false));
+        self.build_counted_loop(rows, |this, i| {
+            let spare = this.builder.build_int_compare(IntPredicate::SGE, i, rank, "spare");
+            let rhs = this.matrix_load(work, this.matrix_index(i, cols, width))?;
+            let nonzero = this.builder.build_float_compare(FloatPredicate::ONE, this.complex_norm(rhs), zero, "nonzero");
+            let bad = this.builder.build_and(spare, nonzero, "bad");
+            let acc = this.builder.build_load(consistent, "acc").into_int_value();
+            let acc = this.builder.build_and(acc, this.builder.build_not(bad, "good"), "acc");
+            this.builder.build_store(consistent, acc);
+            Ok(())
+        })?;
+        let consistent = self.builder.build_load(consistent, "consistent").into_int_value();
+        self.build_check(consistent, pos, "the system has no solution")?;
+        let unique = self.builder.build_int_compare(IntPredicate::EQ, rank, cols, "unique");
+        self.build_check(unique, pos, "the system has infinitely many solutions")?;
+
+        // Every column has a pivot, so the j-th row holds the j-th unknown
+        let res = self.build_list_alloc(&Type::Rational, cols)?;
+        self.build_counted_loop(cols, |this, j| {
+            let entry = this.matrix_load(work, this.matrix_index(j, cols, width))?;
+            let entry = this.build_rational_call(pos, entry, det)?;
+            this.build_list_store(res, j, entry)
+        })?;
+        Ok(res)
+    }
+
+    /// `dot(u, v)`, the Hermitian inner product `sum(u[k] * v[k]^)` of two vectors. A vector is a
+    /// matrix with a single row or a single column, and either works on either side.
+    pub(super) fn build_vector_dot(&mut self, pos: Location, u: PointerValue<'ctx>, v: PointerValue<'ctx>) -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+        let t_f64 = self.ctx.f64_type();
+        let len = self.vector_len(pos, u, "`dot` needs vectors")?;
+        let same = self.builder.build_int_compare(IntPredicate::EQ, len, self.vector_len(pos, v, "`dot` needs vectors")?, "same_len");
+        self.build_check(same, pos, "vectors have different lengths")?;
+        let acc = (self.builder.build_alloca(t_f64, "dot_re"), self.builder.build_alloca(t_f64, "dot_im"));
+        self.store_complex(acc, ComplexValue { re: t_f64.const_zero(), im: t_f64.const_zero() });
+        self.build_counted_loop(len, |this, k| {
+            let product = this.complex_mul(this.matrix_load(u, k)?, this.complex_conjugate(this.matrix_load(v, k)?));
+            let sum = this.load_complex(acc);
+            this.store_complex(acc, ComplexValue {
+                re: this.builder.build_float_add(sum.re, product.re, "sum_re"),
+                im: this.builder.build_float_add(sum.im, product.im, "sum_im"),
+            });
+            Ok(())
+        })?;
+        Ok(self.load_complex(acc))
+    }
+
+    /// `norm2(v)`, which is `dot(v, v)`: the sum of the entries' norms.
+    pub(super) fn build_vector_norm(&mut self, pos: Location, v: PointerValue<'ctx>) -> Result<FloatValue<'ctx>, LocatedCompileError> {
+        Ok(self.build_vector_dot(pos, v, v)?.re)
+    }
+
+    /// `cross(u, v)` for vectors of length 3, with the same shape as `u`. There's no conjugation,
+    /// so it's bilinear like the real one.
+    pub(super) fn build_vector_cross(&mut self, pos: Location, u: PointerValue<'ctx>, v: PointerValue<'ctx>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let three = t_i64.const_int(3, false);
+        for vector in [u, v].iter() {
+            let len = self.vector_len(pos, *vector, "`cross` needs vectors")?;
+            let ok = self.builder.build_int_compare(IntPredicate::EQ, len, three, "three");
+            self.build_check(ok, pos, "`cross` needs vectors of length 3")?;
+        }
+        let mut a = Vec::new();
+        let mut b = Vec::new();
+        for k in 0..3 {
+            a.push(self.matrix_load(u, t_i64.const_int(k, false))?);
+            b.push(self.matrix_load(v, t_i64.const_int(k, false))?);
+        }
+        let res = self.build_matrix_alloc(self.matrix_rows(u)?, self.matrix_cols(u)?)?;
+        for k in 0..3 {
+            let (i, j) = ((k + 1) % 3, (k + 2) % 3);
+            let first = self.complex_mul(a[i], b[j]);
+            let second = self.complex_mul(a[j], b[i]);
+            let entry = (self.builder.build_float_sub(first.re, second.re, "cross_re"),
+                         self.builder.build_float_sub(first.im, second.im, "cross_im")).into();
+            self.matrix_store(res, t_i64.const_int(k as u64, false), entry)?;
+        }
+        Ok(res)
+    }
+
+    /// The number of entries in a vector, which aborts if `matrix` has more than one row and column.
+    fn vector_len(&mut self, pos: Location, matrix: PointerValue<'ctx>, msg: &str) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let one = self.ctx.i64_type().const_int(1, false);
+        let row = self.builder.build_int_compare(IntPredicate::EQ, self.matrix_rows(matrix)?, one, "row");
+        let column = self.builder.build_int_compare(IntPredicate::EQ, self.matrix_cols(matrix)?, one, "column");
+        let ok = self.builder.build_or(row, column, "vector");
+        self.build_check(ok, pos, msg)?;
+        self.matrix_size(matrix)
+    }
+
+    fn check_square(&mut self, pos: Location, matrix: PointerValue<'ctx>, msg: &str) -> Result<(), LocatedCompileError> {
+        let square = self.builder.build_int_compare(IntPredicate::EQ, self.matrix_rows(matrix)?, self.matrix_cols(matrix)?, "square");
+        self.build_check(square, pos, msg)
+    }
+
+    fn build_matrix_copy(&mut self, matrix: PointerValue<'ctx>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let rows = self.matrix_rows(matrix)?;
+        let cols = self.matrix_cols(matrix)?;
+        let res = self.build_matrix_alloc(rows, cols)?;
+        let size = self.builder.build_int_mul(rows, cols, "size");
+        self.build_counted_loop(size, |this, k| this.matrix_store(res, k, this.matrix_load(matrix, k)?))?;
+        Ok(res)
+    }
+
+    /// Fraction-free (Bareiss) elimination in place, looking for pivots in the first `pivot_cols`
+    /// columns. Each step sets `m[i][j] = (p * m[i][j] - m[i][c] * m[r][j]) / prev`, where `p` is the
+    /// new pivot and `prev` the one before, and the division is always exact. Swapping two rows also
+    /// negates one of them, so the determinant never changes and the last pivot is the determinant
+    /// of what was eliminated. Below the pivots only, or above them too if `jordan` is set. Gives the
+    /// number of pivots and the last one.
+    fn build_bareiss(&mut self, work: PointerValue<'ctx>, pivot_cols: IntValue<'ctx>, jordan: bool)
+            -> Result<(IntValue<'ctx>, ComplexValue<'ctx>), LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let t_f64 = self.ctx.f64_type();
+        let zero = t_f64.const_zero();
+        let rows = self.matrix_rows(work)?;
+        let cols = self.matrix_cols(work)?;
+        let rank_ptr = self.builder.build_alloca(t_i64, "rank");
+        self.builder.build_store(rank_ptr, t_i64.const_zero());
+        let pivot_ptr = self.builder.build_alloca(t_i64, "pivot_row");
+        let prev = (self.builder.build_alloca(t_f64, "prev_re"), self.builder.build_alloca(t_f64, "prev_im"));
+        self.store_complex(prev, ComplexValue { re: t_f64.const_float(1.), im: zero });
+
+        self.build_counted_loop(pivot_cols, |this, c| {
+            // The first row from r down with a non-zero entry in column c, or -1
+            let r = this.builder.build_load(rank_ptr, "r").into_int_value();
+            this.builder.build_store(pivot_ptr, t_i64.const_int(-1i64 as u64, true));
+            this.build_counted_loop(rows, |this, i| {
+                let p = this.builder.build_load(pivot_ptr, "p").into_int_value();
+                let below = this.builder.build_int_compare(IntPredicate::SGE, i, r, "below");
+                let unset = this.builder.build_int_compare(IntPredicate::SLT, p, t_i64.const_zero(), "unset");
+                let entry = this.matrix_load(work, this.matrix_index(i, c, cols))?;
+                let nonzero = this.builder.build_float_compare(FloatPredicate::ONE, this.complex_norm(entry), zero, "nonzero");
+                let found = this.builder.build_and(below, unset, "found");
+                let found = this.builder.build_and(found, nonzero, "found");
+                this.builder.build_store(pivot_ptr, this.builder.build_select(found, i, p, "p"));
+                Ok(())
+            })?;
+            let p = this.builder.build_load(pivot_ptr, "p").into_int_value();
+            let found = this.builder.build_int_compare(IntPredicate::SGE, p, t_i64.const_zero(), "found");
+            let elim_bb = this.ctx.append_basic_block(this.get_fp()?, "bareiss_elim");
+            let cont_bb = this.ctx.append_basic_block(this.get_fp()?, "bareiss_next");
+            this.builder.build_conditional_branch(found, elim_bb, cont_bb);
+
+            this.set_and_move_block(elim_bb)?;
+            let swap = this.builder.build_int_compare(IntPredicate::NE, p, r, "swap");
+            this.build_counted_loop(cols, |this, j| {
+                let top = this.matrix_load(work, this.matrix_index(r, j, cols))?;
+                let other = this.matrix_load(work, this.matrix_index(p, j, cols))?;
+                let negated = ComplexValue {
+                    re: this.builder.build_float_neg(other.re, "negated_re"),
+                    im: this.builder.build_float_neg(other.im, "negated_im"),
+                };
+                let moved = ComplexValue {
+                    re: this.builder.build_select(swap, negated.re, other.re, "moved_re").into_float_value(),
+                    im: this.builder.build_select(swap, negated.im, other.im, "moved_im").into_float_value(),
+                };
+                this.matrix_store(work, this.matrix_index(p, j, cols), top)?;
+                this.matrix_store(work, this.matrix_index(r, j, cols), moved)
+            })?;
+            let pivot = this.matrix_load(work, this.matrix_index(r, c, cols))?;
+            let prev_val = this.load_complex(prev);
+            this.build_counted_loop(rows, |this, i| {
+                let skip = if jordan {
+                    this.builder.build_int_compare(IntPredicate::EQ, i, r, "skip")
+                } else {
+                    this.builder.build_int_compare(IntPredicate::SLE, i, r, "skip")
+                };
+                let factor = this.matrix_load(work, this.matrix_index(i, c, cols))?;
+                this.build_counted_loop(cols, |this, j| {
+                    let index = this.matrix_index(i, j, cols);
+                    let entry = this.matrix_load(work, index)?;
+                    let top = this.matrix_load(work, this.matrix_index(r, j, cols))?;
+                    let scaled = this.complex_mul(pivot, entry);
+                    let cancel = this.complex_mul(factor, top);
+                    let diff = ComplexValue {
+                        re: this.builder.build_float_sub(scaled.re, cancel.re, "diff_re"),
+                        im: this.builder.build_float_sub(scaled.im, cancel.im, "diff_im"),
+                    };
+                    let next = this.complex_div(diff, prev_val);
+                    this.matrix_store(work, index, ComplexValue {
+                        re: this.builder.build_select(skip, entry.re, next.re, "entry_re").into_float_value(),
+                        im: this.builder.build_select(skip, entry.im, next.im, "entry_im").into_float_value(),
+                    })
+                })
+            })?;
+            this.store_complex(prev, pivot);
+            this.builder.build_store(rank_ptr, this.builder.build_int_add(r, t_i64.const_int(1, false), "rank"));
+            this.builder.build_unconditional_branch(cont_bb);
+
+            this.set_and_move_block(cont_bb)
+        })?;
+        let rank = self.builder.build_load(rank_ptr, "rank").into_int_value();
+        Ok((rank, self.load_complex(prev)))
+    }
+}
@@ -0,0 +1,76 @@
+use inkwell::{AddressSpace, types::BasicType, values::PointerValue};
+
+use crate::{analyse::{Location, Type, Value}, error::{InternalError, LocatedCompileError}};
+
+use super::{Compiler, MemoCache};
+
+/// A `memo fn` remembers its results in two lists per instance: the arguments it was called with,
+/// as tuples, and what it returned for each. Looking them up is a linear search, which is still far
+/// cheaper than what naive recursion does. The lists live in globals, and are allocated the first
+/// time the function is called.
+impl<'ctx> Compiler<'ctx> {
+    fn memo_list(&self, name: String, elem: &Type) -> PointerValue<'ctx> {
+        let ty = self.list_type(elem).ptr_type(AddressSpace::Generic);
+        let global = self.module.get_global(&name).unwrap_or_else(|| {
+            let global = self.module.add_global(ty, None, &name);
+            global.set_initializer(&ty.const_null());
+            global
+        });
+        global.as_pointer_value()
+    }
+
+    /// Returns the remembered result if there is one. Otherwise, carries on to compute it.
+    pub(super) fn build_memo_lookup(&mut self, pos: Location, instance: &str, args: Vec<Value<'ctx>>, ret: &Type)
+            -> Result<MemoCache<'ctx>, LocatedCompileError> {
+        let key = Value::Tuple(args);
+        let key_type = key.ty();
+        let keys = self.memo_list(format!(".memo.keys.{}", instance), &key_type);
+        let values = self.memo_list(format!(".memo.values.{}", instance), ret);
+
+        let keys_list = self.builder.build_load(keys, "keys").into_pointer_value();
+        let empty = self.builder.build_is_null(keys_list, "no_cache");
+        let alloc_bb = self.ctx.append_basic_block(self.get_fp()?, "alloc_cache");
+        let lookup_bb = self.ctx.append_basic_block(self.get_fp()?, "lookup");
+        self.builder.build_conditional_branch(empty, alloc_bb, lookup_bb);
+
+        self.set_and_move_block(alloc_bb)?;
+        let zero = self.ctx.i64_type().const_zero();
+        let keys_list = self.build_list_alloc(&key_type, zero)?;
+        self.builder.build_store(keys, keys_list);
+        let values_list = self.build_list_alloc(ret, zero)?;
+        self.builder.build_store(values, values_list);
+        self.builder.build_unconditional_branch(lookup_bb);
+
+        self.set_and_move_block(lookup_bb)?;
+        let keys_list = self.builder.build_load(keys, "keys").into_pointer_value();
+        let (present, index) = match self.build_list_find(pos, keys_list, &key_type, key.clone())? {
+            Value::Option(present, index) => match *index {
+                Value::Complex(index) => (present, index.re),
+                _ => return Err(InternalError::invalid_state("`find` did not return a number")),
+            },
+            _ => return Err(InternalError::invalid_state("`find` did not return an optional value")),
+        };
+        let hit_bb = self.ctx.append_basic_block(self.get_fp()?, "memo_hit");
+        let miss_bb = self.ctx.append_basic_block(self.get_fp()?, "memo_miss");
+        self.builder.build_conditional_branch(present, hit_bb, miss_bb);
+
+        self.set_and_move_block(hit_bb)?;
+        let values_list = self.builder.build_load(values, "values").into_pointer_value();
+        let index = self.builder.build_float_to_signed_int(index, self.ctx.i64_type(), "index");
+        let value = self.build_list_nth(values_list, ret, index)?;
+        let value = self.pack(&value)?;
+        self.build_leave()?;
+        self.builder.build_return(Some(&value));
+
+        self.set_and_move_block(miss_bb)?;
+        Ok(MemoCache { keys, values, key })
+    }
+
+    /// Remembers the result, just before it's returned.
+    pub(super) fn build_memo_store(&mut self, memo: &MemoCache<'ctx>, value: &Value<'ctx>) -> Result<(), LocatedCompileError> {
+        let keys_list = self.builder.build_load(memo.keys, "keys").into_pointer_value();
+        self.build_list_push(keys_list, &memo.key.ty(), memo.key.clone())?;
+        let values_list = self.builder.build_load(memo.values, "values").into_pointer_value();
+        self.build_list_push(values_list, &value.ty(), value.clone())
+    }
+}
@@ -0,0 +1,226 @@
+use inkwell::{FloatPredicate, IntPredicate, values::{FloatValue, IntValue}};
+
+use crate::{analyse::{ComplexValue, Location, Value}, error::LocatedCompileError, parse::BinOp};
+
+use super::Compiler;
+
+/// A Gaussian integer as a pair of `i128`s, which is wide enough that multiplying two remainders
+/// and then dividing by the modulus never overflows while the modulus fits in 32 bits, which
+/// `check_modulus` makes sure of.
+type Wide<'ctx> = (IntValue<'ctx>, IntValue<'ctx>);
+
+/// Arithmetic in Z[i]/(m). Remainders are taken the same way as `divmod` does: for a real modulus
+/// and real operands they're in `0..|m|`, and otherwise they come from rounding the quotient to the
+/// nearest Gaussian integer. Inside `mod (m) { ... }`, `+`, `-`, `*` and `**` give remainders like
+/// these, and `==` compares them.
+impl<'ctx> Compiler<'ctx> {
+    /// `powmod(base, exp, m)`, which is `base ** exp` reduced mod `m` by repeated squaring.
+    pub(super) fn build_powmod(&mut self, pos: Location, base: ComplexValue<'ctx>, exp: ComplexValue<'ctx>, modulus: ComplexValue<'ctx>)
+            -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+        self.check_gaussian(pos, base, modulus, "`powmod` needs Gaussian integers")?;
+        let t_f64 = self.ctx.f64_type();
+        let zero = t_f64.const_zero();
+        let floor = self.builtins.floor();
+        self.move_to_end()?;
+        let floored = self.call_float(floor, &[exp.re.into()], "floored")?;
+        let whole = self.builder.build_float_compare(FloatPredicate::OEQ, exp.re, floored, "whole");
+        let real = self.builder.build_float_compare(FloatPredicate::OEQ, exp.im, zero, "real");
+        let non_negative = self.builder.build_float_compare(FloatPredicate::OGE, exp.re, zero, "non_negative");
+        let ok = self.builder.build_and(whole, real, "integer");
+        let ok = self.builder.build_and(ok, non_negative, "natural");
+        self.build_check(ok, pos, "`powmod` needs a non-negative real integer exponent")?;
+        self.check_modulus(pos, modulus, "powmod")?;
+
+        let i64_type = self.ctx.i64_type();
+        let i128_type = self.ctx.i128_type();
+        let base_real = self.builder.build_float_compare(FloatPredicate::OEQ, base.im, zero, "base_real");
+        let modulus_real = self.builder.build_float_compare(FloatPredicate::OEQ, modulus.im, zero, "modulus_real");
+        let real = self.builder.build_and(base_real, modulus_real, "real");
+        let m = self.to_wide(modulus);
+        let one = (i128_type.const_int(1, false), i128_type.const_zero());
+        let one = self.build_mod(one, m, real);
+        let base = self.to_wide(base);
+        let base = self.build_mod(base, m, real);
+        let exp = self.build_real_integer(pos, exp, "`powmod` needs a non-negative real integer exponent")?;
+
+        let entry_bb = self.get_block()?;
+        let test_bb = self.ctx.append_basic_block(self.get_fp()?, "powmod_test");
+        let body_bb = self.ctx.append_basic_block(self.get_fp()?, "powmod_body");
+        let done_bb = self.ctx.append_basic_block(self.get_fp()?, "powmod_done");
+        self.builder.build_unconditional_branch(test_bb);
+
+        self.set_and_move_block(test_bb)?;
+        let phis = [
+            self.builder.build_phi(i128_type, "result_re"),
+            self.builder.build_phi(i128_type, "result_im"),
+            self.builder.build_phi(i128_type, "base_re"),
+            self.builder.build_phi(i128_type, "base_im"),
+            self.builder.build_phi(i64_type, "exp"),
+        ];
+        let part = |k: usize| phis[k].as_basic_value().into_int_value();
+        let (result, square, e) = ((part(0), part(1)), (part(2), part(3)), part(4));
+        let done = self.builder.build_int_compare(IntPredicate::EQ, e, i64_type.const_zero(), "done");
+        self.builder.build_conditional_branch(done, done_bb, body_bb);
+
+        self.set_and_move_block(body_bb)?;
+        let bit = self.builder.build_and(e, i64_type.const_int(1, false), "bit");
+        let odd = self.builder.build_int_compare(IntPredicate::NE, bit, i64_type.const_zero(), "odd");
+        let product = self.wide_mul(result, square);
+        let product = self.build_mod(product, m, real);
+        let next_result = (
+            self.builder.build_select(odd, product.0, result.0, "next_re").into_int_value(),
+            self.builder.build_select(odd, product.1, result.1, "next_im").into_int_value(),
+        );
+        let next_square = self.wide_mul(square, square);
+        let next_square = self.build_mod(next_square, m, real);
+        let next_e = self.builder.build_right_shift(e, i64_type.const_int(1, false), false, "next_exp");
+        self.builder.build_unconditional_branch(test_bb);
+
+        let initial = [one.0, one.1, base.0, base.1, exp];
+        let next = [next_result.0, next_result.1, next_square.0, next_square.1, next_e];
+        for ((phi, initial), next) in phis.iter().zip(initial.iter()).zip(next.iter()) {
+            phi.add_incoming(&[(initial, entry_bb), (next, body_bb)]);
+        }
+
+        self.set_and_move_block(done_bb)?;
+        Ok(ComplexValue { re: self.from_wide(result.0), im: self.from_wide(result.1) })
+    }
+
+    /// Aborts unless the modulus is non-zero and both of its parts fit in 32 bits, so that nothing
+    /// overflows an `i128` while reducing by it.
+    pub(super) fn check_modulus(&mut self, pos: Location, modulus: ComplexValue<'ctx>, name: &str) -> Result<(), LocatedCompileError> {
+        let t_f64 = self.ctx.f64_type();
+        let nonzero = self.builder.build_float_compare(FloatPredicate::ONE, self.complex_norm(modulus), t_f64.const_zero(), "nonzero");
+        self.build_check(nonzero, pos, &format!("`{}` needs a non-zero modulus", name))?;
+        let limit = 2f64.powi(32);
+        let mut fits = self.ctx.bool_type().const_int(1, false);
+        for part in [modulus.re, modulus.im].iter() {
+            let above = self.builder.build_float_compare(FloatPredicate::OGT, *part, t_f64.const_float(-limit), "above");
+            let below = self.builder.build_float_compare(FloatPredicate::OLT, *part, t_f64.const_float(limit), "below");
+            fits = self.builder.build_and(fits, self.builder.build_and(above, below, "part_fits"), "fits");
+        }
+        self.build_check(fits, pos, &format!("`{}` needs a modulus that fits in 32 bits", name))
+    }
+
+    /// `lval op rval` inside a `mod` block, or `None` if there isn't one or `op` isn't arithmetic.
+    pub(super) fn build_mod_binop(&mut self, pos: Location, op: BinOp, lval: ComplexValue<'ctx>, rval: ComplexValue<'ctx>)
+            -> Result<Option<ComplexValue<'ctx>>, LocatedCompileError> {
+        let modulus = match self.moduli.last() {
+            Some(modulus) => *modulus,
+            None => return Ok(None),
+        };
+        let (a, b) = match op {
+            // The operands are reduced first, so that their product can't overflow
+            BinOp::Plus | BinOp::Minus | BinOp::Times => {
+                self.check_gaussian(pos, lval, rval, "arithmetic in a `mod` block needs Gaussian integers")?;
+                let lval = self.reduce(self.to_wide(lval), modulus);
+                let rval = self.reduce(self.to_wide(rval), modulus);
+                (self.to_wide(lval), self.to_wide(rval))
+            },
+            BinOp::Power => return self.build_powmod(pos, lval, rval, modulus).map(Some),
+            BinOp::Divide | BinOp::Remainder | BinOp::Quotient =>
+                return Err(LocatedCompileError::unsupported(pos, "division in a `mod` block".to_owned())),
+            _ => return Ok(None),
+        };
+        let res = match op {
+            BinOp::Plus  => (self.builder.build_int_add(a.0, b.0, "sum_re"), self.builder.build_int_add(a.1, b.1, "sum_im")),
+            BinOp::Minus => (self.builder.build_int_sub(a.0, b.0, "diff_re"), self.builder.build_int_sub(a.1, b.1, "diff_im")),
+            _            => self.wide_mul(a, b),
+        };
+        Ok(Some(self.reduce(res, modulus)))
+    }
+
+    /// `z` reduced mod the innermost `mod` block's modulus.
+    pub(super) fn build_residue(&mut self, pos: Location, z: ComplexValue<'ctx>) -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+        let modulus = match self.moduli.last() {
+            Some(modulus) => *modulus,
+            None => return Ok(z),
+        };
+        self.check_gaussian(pos, z, z, "arithmetic in a `mod` block needs Gaussian integers")?;
+        Ok(self.reduce(self.to_wide(z), modulus))
+    }
+
+    /// The result of a `mod` block, with every number in it reduced, even if it came straight from a
+    /// literal or a variable rather than from arithmetic.
+    pub(super) fn build_residue_value(&mut self, pos: Location, value: Value<'ctx>) -> Result<Value<'ctx>, LocatedCompileError> {
+        match value {
+            Value::Complex(z) => self.build_residue(pos, z).map(Value::Complex),
+            Value::Tuple(fields) => fields.into_iter()
+                .map(|field| self.build_residue_value(pos, field))
+                .collect::<Result<_, _>>()
+                .map(Value::Tuple),
+            value => Ok(value),
+        }
+    }
+
+    fn reduce(&self, x: Wide<'ctx>, modulus: ComplexValue<'ctx>) -> ComplexValue<'ctx> {
+        let x_real = self.builder.build_int_compare(IntPredicate::EQ, x.1, self.ctx.i128_type().const_zero(), "x_real");
+        let modulus_real = self.builder.build_float_compare(FloatPredicate::OEQ, modulus.im, self.ctx.f64_type().const_zero(), "modulus_real");
+        let real = self.builder.build_and(x_real, modulus_real, "real");
+        let res = self.build_mod(x, self.to_wide(modulus), real);
+        ComplexValue { re: self.from_wide(res.0), im: self.from_wide(res.1) }
+    }
+
+    fn to_wide(&self, z: ComplexValue<'ctx>) -> Wide<'ctx> {
+        let i128_type = self.ctx.i128_type();
+        (self.builder.build_float_to_signed_int(z.re, i128_type, "wide_re"),
+         self.builder.build_float_to_signed_int(z.im, i128_type, "wide_im"))
+    }
+
+    fn from_wide(&self, part: IntValue<'ctx>) -> FloatValue<'ctx> {
+        self.builder.build_signed_int_to_float(part, self.ctx.f64_type(), "part")
+    }
+
+    fn wide_mul(&self, (a, b): Wide<'ctx>, (c, d): Wide<'ctx>) -> Wide<'ctx> {
+        let ac = self.builder.build_int_mul(a, c, "ac");
+        let bd = self.builder.build_int_mul(b, d, "bd");
+        let ad = self.builder.build_int_mul(a, d, "ad");
+        let bc = self.builder.build_int_mul(b, c, "bc");
+        (self.builder.build_int_sub(ac, bd, "re"), self.builder.build_int_add(ad, bc, "im"))
+    }
+
+    /// `floor(a / b)` for `b > 0`, where LLVM's division rounds towards zero instead.
+    fn floor_div(&self, a: IntValue<'ctx>, b: IntValue<'ctx>) -> IntValue<'ctx> {
+        let zero = a.get_type().const_zero();
+        let q = self.builder.build_int_signed_div(a, b, "q");
+        let r = self.builder.build_int_signed_rem(a, b, "r");
+        let negative = self.builder.build_int_compare(IntPredicate::SLT, r, zero, "negative");
+        let lower = self.builder.build_int_sub(q, a.get_type().const_int(1, false), "lower");
+        self.builder.build_select(negative, lower, q, "floor_div").into_int_value()
+    }
+
+    /// `x` mod `m`, where `real` says whether both are real.
+    fn build_mod(&self, x: Wide<'ctx>, m: Wide<'ctx>, real: IntValue<'ctx>) -> Wide<'ctx> {
+        let i128_type = self.ctx.i128_type();
+        let zero = i128_type.const_zero();
+        let two = i128_type.const_int(2, false);
+
+        // Real: x - floor(x / |m|) * |m|
+        let negative = self.builder.build_int_compare(IntPredicate::SLT, m.0, zero, "negative");
+        let negated = self.builder.build_int_neg(m.0, "negated");
+        let abs_m = self.builder.build_select(negative, negated, m.0, "abs_m").into_int_value();
+        // A purely imaginary modulus would divide by zero here, even though the result isn't used
+        let abs_m = self.builder.build_select(real, abs_m, i128_type.const_int(1, false), "abs_m").into_int_value();
+        let q_real = self.floor_div(x.0, abs_m);
+        let below = self.builder.build_int_mul(q_real, abs_m, "below");
+        let r_real = self.builder.build_int_sub(x.0, below, "r_real");
+
+        // Otherwise: q is x * conj(m) / |m|^2 rounded to the nearest, i.e. floor((2n + N) / 2N)
+        let conj_m = (m.0, self.builder.build_int_neg(m.1, "conj_im"));
+        let numer = self.wide_mul(x, conj_m);
+        let norm = self.wide_mul(m, conj_m).0;
+        let two_norm = self.builder.build_int_mul(norm, two, "two_norm");
+        let mut q = Vec::new();
+        for part in [numer.0, numer.1].iter() {
+            let doubled = self.builder.build_int_mul(*part, two, "doubled");
+            let shifted = self.builder.build_int_add(doubled, norm, "shifted");
+            q.push(self.floor_div(shifted, two_norm));
+        }
+        let qm = self.wide_mul((q[0], q[1]), m);
+        let r_re = self.builder.build_int_sub(x.0, qm.0, "r_re");
+        let r_im = self.builder.build_int_sub(x.1, qm.1, "r_im");
+
+        (self.builder.build_select(real, r_real, r_re, "mod_re").into_int_value(),
+         self.builder.build_select(real, zero, r_im, "mod_im").into_int_value())
+    }
+}
@@ -0,0 +1,169 @@
+use inkwell::{AddressSpace, FloatPredicate, IntPredicate, values::{FloatValue, IntValue, PointerValue}};
+
+use crate::{analyse::{ComplexValue, Location, Type, Value}, error::{InternalError, LocatedCompileError}};
+
+use super::Compiler;
+
+/// Arithmetic functions of a positive integer `n`. Each is worked out from the prime powers `p ** e`
+/// that make up `n`: `totient`, `sigma` and `mobius` are multiplicative, so they're products over
+/// the prime powers, and the divisors of `n` are the products of a divisor of each.
+impl<'ctx> Compiler<'ctx> {
+    /// `totient(n)`, how many of `1..=n` are coprime to `n`: the product of `(p - 1) * p ** (e - 1)`.
+    pub(super) fn build_totient(&mut self, pos: Location, n: ComplexValue<'ctx>) -> Result<FloatValue<'ctx>, LocatedCompileError> {
+        let one = self.ctx.f64_type().const_float(1.);
+        self.build_multiplicative(pos, n, "totient", |this, p, e| {
+            let e = this.builder.build_float_sub(e, one, "e");
+            let power = this.build_float_power(p, e)?;
+            let p_minus_one = this.builder.build_float_sub(p, one, "p_minus_one");
+            Ok(this.builder.build_float_mul(p_minus_one, power, "totient"))
+        })
+    }
+
+    /// `sigma(n)`, the sum of the divisors of `n`: the product of `1 + p + ... + p ** e`.
+    pub(super) fn build_sigma(&mut self, pos: Location, n: ComplexValue<'ctx>) -> Result<FloatValue<'ctx>, LocatedCompileError> {
+        let one = self.ctx.f64_type().const_float(1.);
+        self.build_multiplicative(pos, n, "sigma", |this, p, e| {
+            let e = this.builder.build_float_add(e, one, "e");
+            let power = this.build_float_power(p, e)?;
+            let numer = this.builder.build_float_sub(power, one, "numer");
+            let denom = this.builder.build_float_sub(p, one, "denom");
+            Ok(this.builder.build_float_div(numer, denom, "sigma"))
+        })
+    }
+
+    /// `mobius(n)`, which is 0 if a square divides `n`, and otherwise -1 to the number of primes.
+    pub(super) fn build_mobius(&mut self, pos: Location, n: ComplexValue<'ctx>) -> Result<FloatValue<'ctx>, LocatedCompileError> {
+        let t_f64 = self.ctx.f64_type();
+        self.build_multiplicative(pos, n, "mobius", |this, _, e| {
+            let square = this.builder.build_float_compare(FloatPredicate::OGT, e, t_f64.const_float(1.), "square");
+            Ok(this.builder.build_select(square, t_f64.const_zero(), t_f64.const_float(-1.), "mobius").into_float_value())
+        })
+    }
+
+    /// `divisors(n)`, in increasing order. For each prime power, the divisors so far are merged with
+    /// their multiples by `p`, `p ** 2`, ..., `p ** e`, each of which is still in order.
+    pub(super) fn build_divisors(&mut self, pos: Location, n: ComplexValue<'ctx>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let t_f64 = self.ctx.f64_type();
+        let powers = self.build_prime_powers(pos, n, "divisors")?;
+        let one = ComplexValue { re: t_f64.const_float(1.), im: t_f64.const_zero() };
+        let first = self.build_list_from(&Type::Complex, vec![Value::Complex(one)])?;
+        let divisors = self.builder.build_alloca(self.list_type(&Type::Complex).ptr_type(AddressSpace::Generic), "divisors");
+        self.builder.build_store(divisors, first);
+        let power = self.builder.build_alloca(t_f64, "power");
+
+        let count = self.list_len(powers)?;
+        self.build_counted_loop(count, |this, k| {
+            let (p, e) = this.prime_power_nth(powers, k)?;
+            let before = this.builder.build_load(divisors, "before").into_pointer_value();
+            this.builder.build_store(power, t_f64.const_float(1.));
+            let e = this.builder.build_float_to_signed_int(e, t_i64, "e");
+            this.build_counted_loop(e, |this, _| {
+                let scale = this.builder.build_load(power, "scale").into_float_value();
+                let scale = this.builder.build_float_mul(scale, p, "scale");
+                this.builder.build_store(power, scale);
+                let len = this.list_len(before)?;
+                let multiples = this.build_list_alloc(&Type::Complex, len)?;
+                this.build_counted_loop(len, |this, j| {
+                    let d = this.divisor_nth(before, j)?;
+                    let d = ComplexValue { re: this.builder.build_float_mul(d, scale, "multiple"), im: t_f64.const_zero() };
+                    this.build_list_store(multiples, j, Value::Complex(d))
+                })?;
+                let so_far = this.builder.build_load(divisors, "so_far").into_pointer_value();
+                let merged = this.build_merge_sorted(so_far, multiples)?;
+                this.builder.build_store(divisors, merged);
+                Ok(())
+            })
+        })?;
+        Ok(self.builder.build_load(divisors, "divisors").into_pointer_value())
+    }
+
+    /// The product of `f(p, e)` over the prime powers `p ** e` that make up `n`.
+    fn build_multiplicative<F>(&mut self, pos: Location, n: ComplexValue<'ctx>, name: &str, mut f: F) -> Result<FloatValue<'ctx>, LocatedCompileError>
+            where F: FnMut(&mut Self, FloatValue<'ctx>, FloatValue<'ctx>) -> Result<FloatValue<'ctx>, LocatedCompileError> {
+        let t_f64 = self.ctx.f64_type();
+        let powers = self.build_prime_powers(pos, n, name)?;
+        let acc = self.builder.build_alloca(t_f64, "acc");
+        self.builder.build_store(acc, t_f64.const_float(1.));
+        let count = self.list_len(powers)?;
+        self.build_counted_loop(count, |this, k| {
+            let (p, e) = this.prime_power_nth(powers, k)?;
+            let factor = f(this, p, e)?;
+            let product = this.builder.build_load(acc, "product").into_float_value();
+            this.builder.build_store(acc, this.builder.build_float_mul(product, factor, "product"));
+            Ok(())
+        })?;
+        let res = self.builder.build_load(acc, "res").into_float_value();
+        // A zero factor after an odd number of -1s would otherwise be -0
+        Ok(self.builder.build_float_add(res, t_f64.const_zero(), "res"))
+    }
+
+    /// Merges two lists of real numbers that are each in increasing order.
+    fn build_merge_sorted(&mut self, lhs: PointerValue<'ctx>, rhs: PointerValue<'ctx>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let t_f64 = self.ctx.f64_type();
+        let zero = t_i64.const_zero();
+        let lhs_len = self.list_len(lhs)?;
+        let rhs_len = self.list_len(rhs)?;
+        let len = self.builder.build_int_add(lhs_len, rhs_len, "len");
+        let res = self.build_list_alloc(&Type::Complex, len)?;
+        let lhs_index = self.builder.build_alloca(t_i64, "lhs_index");
+        let rhs_index = self.builder.build_alloca(t_i64, "rhs_index");
+        self.builder.build_store(lhs_index, zero);
+        self.builder.build_store(rhs_index, zero);
+        self.build_counted_loop(len, |this, k| {
+            let i = this.builder.build_load(lhs_index, "i").into_int_value();
+            let j = this.builder.build_load(rhs_index, "j").into_int_value();
+            let lhs_left = this.builder.build_int_compare(IntPredicate::SLT, i, lhs_len, "lhs_left");
+            let rhs_left = this.builder.build_int_compare(IntPredicate::SLT, j, rhs_len, "rhs_left");
+            // Once a side runs out, its index stays in bounds but its value isn't used
+            let i_read = this.builder.build_select(lhs_left, i, zero, "i_read").into_int_value();
+            let j_read = this.builder.build_select(rhs_left, j, zero, "j_read").into_int_value();
+            let x = this.divisor_nth(lhs, i_read)?;
+            let y = this.divisor_nth(rhs, j_read)?;
+            let smaller = this.builder.build_float_compare(FloatPredicate::OLT, x, y, "smaller");
+            let rhs_done = this.builder.build_not(rhs_left, "rhs_done");
+            let take_lhs = this.builder.build_or(rhs_done, smaller, "take_lhs");
+            let take_lhs = this.builder.build_and(lhs_left, take_lhs, "take_lhs");
+            let value = this.builder.build_select(take_lhs, x, y, "value").into_float_value();
+            this.build_list_store(res, k, Value::Complex(ComplexValue { re: value, im: t_f64.const_zero() }))?;
+            let i_step = this.builder.build_int_z_extend(take_lhs, t_i64, "i_step");
+            let j_step = this.builder.build_int_z_extend(this.builder.build_not(take_lhs, "take_rhs"), t_i64, "j_step");
+            this.builder.build_store(lhs_index, this.builder.build_int_add(i, i_step, "i"));
+            this.builder.build_store(rhs_index, this.builder.build_int_add(j, j_step, "j"));
+            Ok(())
+        })?;
+        Ok(res)
+    }
+
+    /// `p ** e` for a whole number `e >= 0`, by repeated multiplication since `e` is small.
+    fn build_float_power(&mut self, p: FloatValue<'ctx>, e: FloatValue<'ctx>) -> Result<FloatValue<'ctx>, LocatedCompileError> {
+        let t_f64 = self.ctx.f64_type();
+        let acc = self.builder.build_alloca(t_f64, "power");
+        self.builder.build_store(acc, t_f64.const_float(1.));
+        let e = self.builder.build_float_to_signed_int(e, self.ctx.i64_type(), "e");
+        self.build_counted_loop(e, |this, _| {
+            let power = this.builder.build_load(acc, "power").into_float_value();
+            this.builder.build_store(acc, this.builder.build_float_mul(power, p, "power"));
+            Ok(())
+        })?;
+        Ok(self.builder.build_load(acc, "power").into_float_value())
+    }
+
+    fn prime_power_nth(&mut self, powers: PointerValue<'ctx>, k: IntValue<'ctx>) -> Result<(FloatValue<'ctx>, FloatValue<'ctx>), LocatedCompileError> {
+        match self.build_list_nth(powers, &Type::Tuple(vec![Type::Complex, Type::Complex]), k)? {
+            Value::Tuple(fields) => match fields.as_slice() {
+                [Value::Complex(p), Value::Complex(e)] => Ok((p.re, e.re)),
+                _ => Err(InternalError::invalid_state("prime power is not a pair of numbers")),
+            },
+            _ => Err(InternalError::invalid_state("prime power is not a tuple")),
+        }
+    }
+
+    fn divisor_nth(&mut self, list: PointerValue<'ctx>, k: IntValue<'ctx>) -> Result<FloatValue<'ctx>, LocatedCompileError> {
+        match self.build_list_nth(list, &Type::Complex, k)? {
+            Value::Complex(d) => Ok(d.re),
+            _ => Err(InternalError::invalid_state("divisor is not a number")),
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use inkwell::{IntPredicate, basic_block::BasicBlock, types::StructType, values::{BasicValueEnum, IntValue}};
+
+use crate::{analyse::{Located, Location, Type, Value, Variable}, error::{InternalError, LocatedCompileError}, parse::{BinOp, Pattern}};
+
+use super::Compiler;
+
+/// Optional values are stored as `{ i1 present, T value }`. When there's no value, the second field
+/// is zero, but it should never be looked at.
+impl<'ctx> Compiler<'ctx> {
+    pub(super) fn option_type(&self, inner: &Type) -> StructType<'ctx> {
+        self.ctx.struct_type(&[self.ctx.bool_type().into(), self.llvm_type(inner)], false)
+    }
+
+    pub(super) fn pack_option(&self, present: IntValue<'ctx>, inner: &Value<'ctx>) -> Result<BasicValueEnum<'ctx>, LocatedCompileError> {
+        let res = self.option_type(&inner.ty()).get_undef();
+        let res = self.builder.build_insert_value(res, present, 0, "pack_present")
+            .ok_or_else(|| InternalError::invalid_state("failed to pack optional flag"))?
+            .into_struct_value();
+        let res = self.builder.build_insert_value(res, self.pack(inner)?, 1, "pack_inner")
+            .ok_or_else(|| InternalError::invalid_state("failed to pack optional value"))?
+            .into_struct_value();
+        Ok(res.into())
+    }
+
+    pub(super) fn unpack_option(&self, value: BasicValueEnum<'ctx>, inner: &Type) -> Result<Value<'ctx>, LocatedCompileError> {
+        let value = value.into_struct_value();
+        let present = self.builder.build_extract_value(value, 0, "unpack_present")
+            .ok_or_else(|| InternalError::invalid_state("failed to unpack optional flag"))?
+            .into_int_value();
+        let value = self.builder.build_extract_value(value, 1, "unpack_inner")
+            .ok_or_else(|| InternalError::invalid_state("failed to unpack optional value"))?;
+        Ok(Value::Option(present, Box::new(self.unpack(value, inner)?)))
+    }
+
+    pub(super) fn build_some(&self, value: Value<'ctx>) -> Value<'ctx> {
+        Value::Option(self.ctx.bool_type().const_int(1, false), Box::new(value))
+    }
+
+    pub(super) fn build_none(&self, inner: &Type) -> Result<Value<'ctx>, LocatedCompileError> {
+        let zero = self.unpack(self.llvm_type(inner).const_zero(), inner)?;
+        Ok(Value::Option(self.ctx.bool_type().const_zero(), Box::new(zero)))
+    }
+
+    /// Two optional values are equal when neither has a value, or both have equal values. The values
+    /// are only compared if they're both there.
+    pub(super) fn option_equals(&mut self, pos: Location, (lpresent, lval): (IntValue<'ctx>, Value<'ctx>),
+            (rpresent, rval): (IntValue<'ctx>, Value<'ctx>)) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let same = self.builder.build_int_compare(IntPredicate::EQ, lpresent, rpresent, "tmp_present_eq");
+        let both = self.builder.build_and(lpresent, rpresent, "tmp_both_present");
+        let entry_bb = self.get_block()?;
+        let cmp_bb = self.ctx.append_basic_block(self.get_fp()?, "cmp_inner");
+        let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "cont");
+        self.builder.build_conditional_branch(both, cmp_bb, cont_bb);
+
+        self.set_and_move_block(cmp_bb)?;
+        let inner = self.build_equality(pos, BinOp::Equals, lval, rval)?;
+        let inner = self.expect_bool(pos, inner)?;
+        let cmp_bb = self.get_block()?;
+        self.builder.build_unconditional_branch(cont_bb);
+
+        self.set_and_move_block(cont_bb)?;
+        let phi = self.builder.build_phi(self.ctx.bool_type(), "tmp_option_eq");
+        phi.add_incoming(&[(&same, entry_bb), (&inner, cmp_bb)]);
+        Ok(phi.as_basic_value().into_int_value())
+    }
+
+    /// Checks whether the value matches `some(pattern)` or `none`, jumping to `next_bb` if not.
+    /// Otherwise, binds whatever the inner pattern names and returns what it shadowed.
+    pub(super) fn build_option_test(&mut self, present: IntValue<'ctx>, inner: Value<'ctx>, name: Located<String>, mut fields: Vec<Pattern>,
+            next_bb: BasicBlock<'ctx>) -> Result<Vec<(String, Option<Located<Variable<'ctx>>>)>, LocatedCompileError> {
+        let (expected, want_present) = match name.borrow_val().as_str() {
+            "some" => (1, true),
+            "none" => (0, false),
+            _ => return Err(match self.sym.variant(name.borrow_val()) {
+                Some((def, _)) => LocatedCompileError::type_mismatch(name.pos(),
+                    &Type::Enum(def.name.borrow_val().clone()), &Type::Option(Box::new(inner.ty()))),
+                None => LocatedCompileError::unknown_symbol(name),
+            }),
+        };
+        if fields.len() != expected {
+            return Err(LocatedCompileError::arity(name.pos(), name.borrow_val(), expected, fields.len()));
+        }
+
+        let arm_bb = self.ctx.append_basic_block(self.get_fp()?, "arm");
+        let matched = if want_present { present } else { self.builder.build_not(present, "absent") };
+        self.builder.build_conditional_branch(matched, arm_bb, next_bb);
+        self.set_and_move_block(arm_bb)?;
+
+        let field = match fields.pop() {
+            Some(field) => self.constructor_pattern(&inner, field),
+            None => return Ok(Vec::new()),
+        };
+        match field {
+            Pattern::Literal(literal) => {
+                let literal_pos = literal.pos();
+                let literal = self.build_expr(literal)?;
+                let matched = self.build_equality(literal_pos, BinOp::Equals, inner, literal)?;
+                let matched = self.expect_bool(literal_pos, matched)?;
+                let field_bb = self.ctx.append_basic_block(self.get_fp()?, "field_matched");
+                self.builder.build_conditional_branch(matched, field_bb, next_bb);
+                self.set_and_move_block(field_bb)?;
+                Ok(Vec::new())
+            },
+            Pattern::Binding(id) => {
+                let ptr = self.builder.build_alloca(self.llvm_type(&inner.ty()), id.borrow_val());
+                let packed = self.pack(&inner)?;
+                self.builder.build_store(ptr, packed);
+                let id_name = id.borrow_val().clone();
+                let shadowed = self.sym.add_var(id, Variable { ptr, ty: inner.ty(), mutable: false });
+                Ok(vec![(id_name, shadowed)])
+            },
+            Pattern::Wildcard => Ok(Vec::new()),
+            Pattern::Variant(name, fields) => self.build_constructor_test(&inner, name, fields, next_bb),
+        }
+    }
+
+    /// Prints `some(value)` or `none`.
+    pub(super) fn print_option(&mut self, pos: Location, present: IntValue<'ctx>, inner: Value<'ctx>, newline: bool)
+            -> Result<(), LocatedCompileError> {
+        let some_bb = self.ctx.append_basic_block(self.get_fp()?, "print_some");
+        let none_bb = self.ctx.append_basic_block(self.get_fp()?, "print_none");
+        let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "cont");
+        self.builder.build_conditional_branch(present, some_bb, none_bb);
+
+        self.set_and_move_block(some_bb)?;
+        self.build_print_str("some(".to_owned())?;
+        self.print_value(pos, inner, false)?;
+        self.build_print_str(")".to_owned())?;
+        self.builder.build_unconditional_branch(cont_bb);
+
+        self.set_and_move_block(none_bb)?;
+        self.build_print_str("none".to_owned())?;
+        self.builder.build_unconditional_branch(cont_bb);
+
+        self.set_and_move_block(cont_bb)?;
+        if newline {
+            self.build_println_str(String::new())?;
+        }
+        Ok(())
+    }
+}
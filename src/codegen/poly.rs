@@ -0,0 +1,391 @@
+use inkwell::{FloatPredicate, IntPredicate, values::{IntValue, PointerValue}};
+
+use crate::{analyse::{ComplexValue, Location, Type, Value}, error::{InternalError, LocatedCompileError}, parse::{BinOp, UnOp}};
+
+use super::Compiler;
+
+/// Polynomials in one variable, kept as a list of complex coefficients starting from the constant
+/// term. The last coefficient is never zero, so the zero polynomial is the empty list and the degree
+/// is one less than the length. A number can be used wherever a polynomial is expected, as a
+/// constant. Division is exact: each step has to divide a coefficient by the leading coefficient of
+/// the divisor, and that has to give a Gaussian integer, so dividing by e.g. `2x` doesn't work
+/// unless the coefficients are even.
+impl<'ctx> Compiler<'ctx> {
+    /// `poly xs`, which copies the list so that changing `xs` doesn't change the polynomial.
+    pub(super) fn build_poly_from(&mut self, list: PointerValue<'ctx>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let p = self.build_list_copy(list, &Type::Complex)?;
+        self.build_poly_trim(p)?;
+        Ok(p)
+    }
+
+    /// Arithmetic where at least one side is a polynomial.
+    pub(super) fn build_poly_binop(&mut self, pos: Location, op: BinOp, lpos: Location, lval: Value<'ctx>, rpos: Location, rval: Value<'ctx>)
+            -> Result<Value<'ctx>, LocatedCompileError> {
+        if let (BinOp::Power, Value::Poly(base), Value::Complex(exp)) = (op, &lval, &rval) {
+            return self.build_poly_pow(rpos, *base, *exp).map(Value::Poly);
+        }
+        let (lhs, rhs) = match self.promote_poly(lval, rval)? {
+            (Value::Poly(lhs), Value::Poly(rhs)) => (lhs, rhs),
+            (Value::Poly(_), rval) => return Err(LocatedCompileError::type_mismatch(rpos, &Type::Poly, &rval.ty())),
+            (lval, _) => return Err(LocatedCompileError::type_mismatch(lpos, &Type::Poly, &lval.ty())),
+        };
+        match op {
+            BinOp::Plus | BinOp::Minus => self.build_poly_sum(op, lhs, rhs).map(Value::Poly),
+            BinOp::Times => self.build_poly_product(lhs, rhs).map(Value::Poly),
+            BinOp::Divide => {
+                let (q, r) = self.build_poly_divmod(pos, lhs, rhs)?;
+                let exact = self.builder.build_int_compare(IntPredicate::EQ, self.list_len(r)?, self.ctx.i64_type().const_zero(), "exact");
+                self.build_check(exact, pos, "the polynomials don't divide exactly")?;
+                Ok(Value::Poly(q))
+            },
+            BinOp::Quotient => self.build_poly_divmod(pos, lhs, rhs).map(|(q, _)| Value::Poly(q)),
+            BinOp::Remainder => self.build_poly_divmod(pos, lhs, rhs).map(|(_, r)| Value::Poly(r)),
+            _ => Err(LocatedCompileError::unsupported(pos, "this operator is not defined for polynomials".to_owned())),
+        }
+    }
+
+    pub(super) fn build_poly_unop(&mut self, pos: Location, op: UnOp, p: PointerValue<'ctx>) -> Result<Value<'ctx>, LocatedCompileError> {
+        match op {
+            UnOp::Negate => {
+                let zero = self.build_list_alloc(&Type::Complex, self.ctx.i64_type().const_zero())?;
+                self.build_poly_sum(BinOp::Minus, zero, p).map(Value::Poly)
+            },
+            _ => Err(LocatedCompileError::unsupported(pos, "this operator is not defined for polynomials".to_owned())),
+        }
+    }
+
+    /// If one side is a polynomial and the other is a number, turns the number into a constant.
+    pub(super) fn promote_poly(&mut self, lval: Value<'ctx>, rval: Value<'ctx>) -> Result<(Value<'ctx>, Value<'ctx>), LocatedCompileError> {
+        match (lval, rval) {
+            (Value::Complex(lval), rval @ Value::Poly(_)) => Ok((Value::Poly(self.build_poly_constant(lval)?), rval)),
+            (lval @ Value::Poly(_), Value::Complex(rval)) => Ok((lval, Value::Poly(self.build_poly_constant(rval)?))),
+            vals => Ok(vals),
+        }
+    }
+
+    fn build_poly_constant(&mut self, c: ComplexValue<'ctx>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let p = self.build_list_from(&Type::Complex, vec![Value::Complex(c)])?;
+        self.build_poly_trim(p)?;
+        Ok(p)
+    }
+
+    /// `p(z)`, by Horner's method.
+    pub(super) fn build_poly_eval(&mut self, p: PointerValue<'ctx>, z: ComplexValue<'ctx>) -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+        let t_f64 = self.ctx.f64_type();
+        let t_i64 = self.ctx.i64_type();
+        let acc = (self.builder.build_alloca(t_f64, "acc_re"), self.builder.build_alloca(t_f64, "acc_im"));
+        self.store_complex(acc, ComplexValue { re: t_f64.const_zero(), im: t_f64.const_zero() });
+        let len = self.list_len(p)?;
+        self.build_counted_loop(len, |this, k| {
+            let last = this.builder.build_int_sub(len, t_i64.const_int(1, false), "last");
+            let index = this.builder.build_int_sub(last, k, "index");
+            let coeff = this.poly_coeff(p, index)?;
+            let scaled = this.complex_mul(this.load_complex(acc), z);
+            this.store_complex(acc, ComplexValue {
+                re: this.builder.build_float_add(scaled.re, coeff.re, "acc_re"),
+                im: this.builder.build_float_add(scaled.im, coeff.im, "acc_im"),
+            });
+            Ok(())
+        })?;
+        Ok(self.load_complex(acc))
+    }
+
+    /// The degree, which is -1 for the zero polynomial.
+    pub(super) fn build_poly_degree(&self, p: PointerValue<'ctx>) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let len = self.list_len(p)?;
+        Ok(self.builder.build_int_sub(len, self.ctx.i64_type().const_int(1, false), "degree"))
+    }
+
+    /// `roots(p)`, the distinct Gaussian integer roots of `p`. A root divides the lowest non-zero
+    /// coefficient `c` (once any factors of `x` are taken out), so its norm divides `norm(c)`, which
+    /// leaves finitely many candidates to try.
+    pub(super) fn build_poly_roots(&mut self, pos: Location, p: PointerValue<'ctx>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let t_f64 = self.ctx.f64_type();
+        let t_i64 = self.ctx.i64_type();
+        let zero = t_f64.const_zero();
+        let len = self.list_len(p)?;
+        let nonzero = self.builder.build_int_compare(IntPredicate::SGT, len, t_i64.const_zero(), "nonzero");
+        self.build_check(nonzero, pos, "every number is a root of the zero polynomial")?;
+        let roots = self.build_list_alloc(&Type::Complex, t_i64.const_zero())?;
+
+        // The lowest coefficient that isn't zero, which there is since p isn't zero
+        let lowest = self.builder.build_alloca(t_i64, "lowest");
+        self.builder.build_store(lowest, t_i64.const_zero());
+        let test_bb = self.ctx.append_basic_block(self.get_fp()?, "lowest_test");
+        let next_bb = self.ctx.append_basic_block(self.get_fp()?, "lowest_next");
+        let found_bb = self.ctx.append_basic_block(self.get_fp()?, "lowest_found");
+        self.builder.build_unconditional_branch(test_bb);
+
+        self.set_and_move_block(test_bb)?;
+        let m = self.builder.build_load(lowest, "m").into_int_value();
+        let c = self.poly_nth(p, m)?;
+        let is_zero = self.builder.build_float_compare(FloatPredicate::OEQ, self.complex_norm(c), zero, "is_zero");
+        self.builder.build_conditional_branch(is_zero, next_bb, found_bb);
+
+        self.set_and_move_block(next_bb)?;
+        self.builder.build_store(lowest, self.builder.build_int_add(m, t_i64.const_int(1, false), "next"));
+        self.builder.build_unconditional_branch(test_bb);
+
+        self.set_and_move_block(found_bb)?;
+        self.check_gaussian(pos, c, c, "`roots` needs Gaussian integer coefficients")?;
+        let has_zero = self.builder.build_int_compare(IntPredicate::SGT, m, t_i64.const_zero(), "has_zero");
+        let push_zero_bb = self.ctx.append_basic_block(self.get_fp()?, "roots_zero");
+        let search_bb = self.ctx.append_basic_block(self.get_fp()?, "roots_search");
+        self.builder.build_conditional_branch(has_zero, push_zero_bb, search_bb);
+
+        self.set_and_move_block(push_zero_bb)?;
+        self.build_list_push(roots, &Type::Complex, Value::Complex(ComplexValue { re: zero, im: zero }))?;
+        self.builder.build_unconditional_branch(search_bb);
+
+        // Tries every a + bi with -s <= a, b <= s, where s^2 <= norm(c)
+        self.set_and_move_block(search_bb)?;
+        let norm = self.complex_norm(c);
+        let sqrt = self.builtins.sqrt();
+        let floor = self.builtins.floor();
+        self.move_to_end()?;
+        let s = self.call_float(sqrt, &[norm.into()], "s")?;
+        let s = self.call_float(floor, &[s.into()], "s")?;
+        let width = self.builder.build_float_mul(s, t_f64.const_float(2.), "width");
+        let width = self.builder.build_float_add(width, t_f64.const_float(1.), "width");
+        let width = self.builder.build_float_to_signed_int(width, t_i64, "width");
+        self.build_counted_loop(width, |this, j| {
+            let a = this.builder.build_signed_int_to_float(j, t_f64, "a");
+            let a = this.builder.build_float_sub(a, s, "a");
+            this.build_counted_loop(width, |this, k| {
+                let b = this.builder.build_signed_int_to_float(k, t_f64, "b");
+                let b = this.builder.build_float_sub(b, s, "b");
+                let r = ComplexValue { re: a, im: b };
+                let r_norm = this.complex_norm(r);
+                let nonzero = this.builder.build_float_compare(FloatPredicate::ONE, r_norm, zero, "nonzero");
+                // The remainder is only used when r isn't zero
+                let divisor = this.builder.build_select(nonzero, r_norm, t_f64.const_float(1.), "divisor").into_float_value();
+                let rem = this.builder.build_float_rem(norm, divisor, "rem");
+                let divides = this.builder.build_float_compare(FloatPredicate::OEQ, rem, zero, "divides");
+                let candidate = this.builder.build_and(nonzero, divides, "candidate");
+                let try_bb = this.ctx.append_basic_block(this.get_fp()?, "roots_try");
+                let root_bb = this.ctx.append_basic_block(this.get_fp()?, "roots_found");
+                let cont_bb = this.ctx.append_basic_block(this.get_fp()?, "roots_next");
+                this.builder.build_conditional_branch(candidate, try_bb, cont_bb);
+
+                this.set_and_move_block(try_bb)?;
+                let value = this.build_poly_eval(p, r)?;
+                let is_root = this.builder.build_float_compare(FloatPredicate::OEQ, this.complex_norm(value), zero, "is_root");
+                this.builder.build_conditional_branch(is_root, root_bb, cont_bb);
+
+                this.set_and_move_block(root_bb)?;
+                this.build_list_push(roots, &Type::Complex, Value::Complex(r))?;
+                this.builder.build_unconditional_branch(cont_bb);
+
+                this.set_and_move_block(cont_bb)
+            })
+        })?;
+        Ok(roots)
+    }
+
+    /// The coefficient of `x^k`, which is zero past the end.
+    fn poly_coeff(&mut self, p: PointerValue<'ctx>, k: IntValue<'ctx>) -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+        let zero = self.ctx.f64_type().const_zero();
+        let len = self.list_len(p)?;
+        let in_range = self.builder.build_int_compare(IntPredicate::SLT, k, len, "in_range");
+        // Every list has room for a few elements, so reading the first is fine even if it's empty
+        let index = self.builder.build_select(in_range, k, self.ctx.i64_type().const_zero(), "index").into_int_value();
+        let coeff = self.poly_nth(p, index)?;
+        Ok(ComplexValue {
+            re: self.builder.build_select(in_range, coeff.re, zero, "coeff_re").into_float_value(),
+            im: self.builder.build_select(in_range, coeff.im, zero, "coeff_im").into_float_value(),
+        })
+    }
+
+    fn poly_nth(&mut self, p: PointerValue<'ctx>, k: IntValue<'ctx>) -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+        match self.build_list_nth(p, &Type::Complex, k)? {
+            Value::Complex(coeff) => Ok(coeff),
+            _ => Err(InternalError::invalid_state("polynomial coefficient is not a number")),
+        }
+    }
+
+    /// Drops zeroes from the end, so that the last coefficient isn't zero.
+    fn build_poly_trim(&mut self, p: PointerValue<'ctx>) -> Result<(), LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let zero = self.ctx.f64_type().const_zero();
+        let test_bb = self.ctx.append_basic_block(self.get_fp()?, "trim_test");
+        let check_bb = self.ctx.append_basic_block(self.get_fp()?, "trim_check");
+        let pop_bb = self.ctx.append_basic_block(self.get_fp()?, "trim_pop");
+        let done_bb = self.ctx.append_basic_block(self.get_fp()?, "trim_done");
+        self.builder.build_unconditional_branch(test_bb);
+
+        self.set_and_move_block(test_bb)?;
+        let len = self.list_len(p)?;
+        let nonempty = self.builder.build_int_compare(IntPredicate::SGT, len, t_i64.const_zero(), "nonempty");
+        self.builder.build_conditional_branch(nonempty, check_bb, done_bb);
+
+        self.set_and_move_block(check_bb)?;
+        let last = self.builder.build_int_sub(len, t_i64.const_int(1, false), "last");
+        let coeff = self.poly_nth(p, last)?;
+        let is_zero = self.builder.build_float_compare(FloatPredicate::OEQ, self.complex_norm(coeff), zero, "is_zero");
+        self.builder.build_conditional_branch(is_zero, pop_bb, done_bb);
+
+        self.set_and_move_block(pop_bb)?;
+        self.set_list_len(p, last)?;
+        self.builder.build_unconditional_branch(test_bb);
+
+        self.set_and_move_block(done_bb)
+    }
+
+    fn build_poly_zeroes(&mut self, len: IntValue<'ctx>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let zero = self.ctx.f64_type().const_zero();
+        let p = self.build_list_alloc(&Type::Complex, len)?;
+        self.build_counted_loop(len, |this, k| this.build_list_store(p, k, Value::Complex(ComplexValue { re: zero, im: zero })))?;
+        Ok(p)
+    }
+
+    fn build_poly_sum(&mut self, op: BinOp, lhs: PointerValue<'ctx>, rhs: PointerValue<'ctx>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let llen = self.list_len(lhs)?;
+        let rlen = self.list_len(rhs)?;
+        let longer = self.builder.build_int_compare(IntPredicate::SGT, llen, rlen, "longer");
+        let len = self.builder.build_select(longer, llen, rlen, "len").into_int_value();
+        let res = self.build_list_alloc(&Type::Complex, len)?;
+        self.build_counted_loop(len, |this, k| {
+            let a = this.poly_coeff(lhs, k)?;
+            let b = this.poly_coeff(rhs, k)?;
+            let c = match op {
+                BinOp::Plus => ComplexValue {
+                    re: this.builder.build_float_add(a.re, b.re, "sum_re"),
+                    im: this.builder.build_float_add(a.im, b.im, "sum_im"),
+                },
+                _ => ComplexValue {
+                    re: this.builder.build_float_sub(a.re, b.re, "diff_re"),
+                    im: this.builder.build_float_sub(a.im, b.im, "diff_im"),
+                },
+            };
+            this.build_list_store(res, k, Value::Complex(c))
+        })?;
+        self.build_poly_trim(res)?;
+        Ok(res)
+    }
+
+    fn build_poly_product(&mut self, lhs: PointerValue<'ctx>, rhs: PointerValue<'ctx>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let llen = self.list_len(lhs)?;
+        let rlen = self.list_len(rhs)?;
+        // Zero times anything has no coefficients, which the subtraction would make -1
+        let either_zero = self.builder.build_or(
+            self.builder.build_int_compare(IntPredicate::EQ, llen, t_i64.const_zero(), "lhs_zero"),
+            self.builder.build_int_compare(IntPredicate::EQ, rlen, t_i64.const_zero(), "rhs_zero"),
+            "either_zero");
+        let len = self.builder.build_int_add(llen, rlen, "len");
+        let len = self.builder.build_int_sub(len, t_i64.const_int(1, false), "len");
+        let len = self.builder.build_select(either_zero, t_i64.const_zero(), len, "len").into_int_value();
+        let res = self.build_poly_zeroes(len)?;
+        self.build_counted_loop(llen, |this, j| {
+            let a = this.poly_coeff(lhs, j)?;
+            this.build_counted_loop(rlen, |this, k| {
+                let b = this.poly_coeff(rhs, k)?;
+                let index = this.builder.build_int_add(j, k, "index");
+                let c = this.poly_coeff(res, index)?;
+                let ab = this.complex_mul(a, b);
+                this.build_list_store(res, index, Value::Complex(ComplexValue {
+                    re: this.builder.build_float_add(c.re, ab.re, "coeff_re"),
+                    im: this.builder.build_float_add(c.im, ab.im, "coeff_im"),
+                }))
+            })
+        })?;
+        self.build_poly_trim(res)?;
+        Ok(res)
+    }
+
+    /// `p ** n` for a natural number `n`, by multiplying `n` times.
+    fn build_poly_pow(&mut self, pos: Location, base: PointerValue<'ctx>, exp: ComplexValue<'ctx>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let t_f64 = self.ctx.f64_type();
+        let zero = t_f64.const_zero();
+        let floor = self.builtins.floor();
+        self.move_to_end()?;
+        let floored = self.call_float(floor, &[exp.re.into()], "floored")?;
+        let whole = self.builder.build_float_compare(FloatPredicate::OEQ, exp.re, floored, "whole");
+        let real = self.builder.build_float_compare(FloatPredicate::OEQ, exp.im, zero, "real");
+        let non_negative = self.builder.build_float_compare(FloatPredicate::OGE, exp.re, zero, "non_negative");
+        let ok = self.builder.build_and(whole, real, "integer");
+        let ok = self.builder.build_and(ok, non_negative, "natural");
+        self.build_check(ok, pos, "polynomials can only be raised to a non-negative integer power")?;
+
+        let one = self.build_poly_constant(ComplexValue { re: t_f64.const_float(1.), im: zero })?;
+        let res = self.builder.build_alloca(one.get_type(), "pow");
+        self.builder.build_store(res, one);
+        let n = self.builder.build_float_to_signed_int(exp.re, self.ctx.i64_type(), "n");
+        self.build_counted_loop(n, |this, _| {
+            let acc = this.builder.build_load(res, "acc").into_pointer_value();
+            let product = this.build_poly_product(acc, base)?;
+            this.builder.build_store(res, product);
+            Ok(())
+        })?;
+        Ok(self.builder.build_load(res, "pow").into_pointer_value())
+    }
+
+    /// Long division, giving `(q, r)` with `a == q * b + r` and `degree(r) < degree(b)`.
+    fn build_poly_divmod(&mut self, pos: Location, a: PointerValue<'ctx>, b: PointerValue<'ctx>)
+            -> Result<(PointerValue<'ctx>, PointerValue<'ctx>), LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let alen = self.list_len(a)?;
+        let blen = self.list_len(b)?;
+        let nonzero = self.builder.build_int_compare(IntPredicate::SGT, blen, t_i64.const_zero(), "nonzero");
+        self.build_check(nonzero, pos, "division by zero")?;
+
+        let steps = self.builder.build_int_sub(alen, blen, "steps");
+        let steps = self.builder.build_int_add(steps, t_i64.const_int(1, false), "steps");
+        let some = self.builder.build_int_compare(IntPredicate::SGT, steps, t_i64.const_zero(), "some");
+        let steps = self.builder.build_select(some, steps, t_i64.const_zero(), "steps").into_int_value();
+        let q = self.build_poly_zeroes(steps)?;
+        let r = self.build_list_copy(a, &Type::Complex)?;
+        let lead_index = self.builder.build_int_sub(blen, t_i64.const_int(1, false), "lead_index");
+        let lead = self.poly_coeff(b, lead_index)?;
+        let round = self.builtins.round();
+        self.move_to_end()?;
+
+        // Cancels the top coefficient of r each time, from the top down
+        self.build_counted_loop(steps, |this, k| {
+            let last = this.builder.build_int_sub(steps, t_i64.const_int(1, false), "last");
+            let shift = this.builder.build_int_sub(last, k, "shift");
+            let top = this.builder.build_int_add(shift, lead_index, "top");
+            let exact = this.complex_div(this.poly_coeff(r, top)?, lead);
+            let t = ComplexValue {
+                re: this.call_float(round, &[exact.re.into()], "t_re")?,
+                im: this.call_float(round, &[exact.im.into()], "t_im")?,
+            };
+            let whole = this.complex_cmp(FloatPredicate::OEQ, exact, t);
+            this.build_check(whole, pos, "the leading coefficient doesn't divide exactly")?;
+            this.build_list_store(q, shift, Value::Complex(t))?;
+            this.build_counted_loop(blen, |this, j| {
+                let index = this.builder.build_int_add(shift, j, "index");
+                let c = this.poly_coeff(r, index)?;
+                let tb = this.complex_mul(t, this.poly_coeff(b, j)?);
+                this.build_list_store(r, index, Value::Complex(ComplexValue {
+                    re: this.builder.build_float_sub(c.re, tb.re, "coeff_re"),
+                    im: this.builder.build_float_sub(c.im, tb.im, "coeff_im"),
+                }))
+            })
+        })?;
+        self.build_poly_trim(q)?;
+        self.build_poly_trim(r)?;
+        Ok((q, r))
+    }
+
+    /// Trimmed polynomials are equal when they have the same coefficients.
+    pub(super) fn poly_equals(&mut self, lhs: PointerValue<'ctx>, rhs: PointerValue<'ctx>) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let bool_type = self.ctx.bool_type();
+        let llen = self.list_len(lhs)?;
+        let rlen = self.list_len(rhs)?;
+        let res = self.builder.build_alloca(bool_type, "poly_eq");
+        let same_len = self.builder.build_int_compare(IntPredicate::EQ, llen, rlen, "same_len");
+        self.builder.build_store(res, same_len);
+        // With different lengths, this compares against zeroes past the end, but the answer is no anyway
+        self.build_counted_loop(llen, |this, k| {
+            let a = this.poly_coeff(lhs, k)?;
+            let b = this.poly_coeff(rhs, k)?;
+            let same = this.complex_cmp(FloatPredicate::OEQ, a, b);
+            let acc = this.builder.build_load(res, "acc").into_int_value();
+            let acc = this.builder.build_and(acc, same, "acc");
+            this.builder.build_store(res, acc);
+            Ok(())
+        })?;
+        Ok(self.builder.build_load(res, "poly_eq").into_int_value())
+    }
+}
@@ -0,0 +1,119 @@
+use inkwell::FloatPredicate;
+
+use crate::{analyse::{ComplexValue, Location, Type, Value}, error::LocatedCompileError, parse::{BinOp, UnOp}};
+
+use super::Compiler;
+
+/// Quaternions `a + bi + cj + dk`, with `i^2 = j^2 = k^2 = ijk = -1`. They're kept as a pair of
+/// complex numbers `(z, w)` standing for `z + wj`, since `jz` is `z^ j` for any complex `z`. With
+/// whole or half-integer coefficients, they're exact, so the Lipschitz and Hurwitz quaternions are
+/// both covered. Any complex number can be used as a quaternion.
+impl<'ctx> Compiler<'ctx> {
+    /// Arithmetic where at least one side is a quaternion. Multiplication doesn't commute.
+    pub(super) fn build_quaternion_binop(&mut self, pos: Location, op: BinOp, lpos: Location, lval: Value<'ctx>, rpos: Location, rval: Value<'ctx>)
+            -> Result<Value<'ctx>, LocatedCompileError> {
+        let (lval, rval) = match self.promote_quaternion(lval, rval) {
+            (Value::Quaternion(lz, lw), Value::Quaternion(rz, rw)) => ((lz, lw), (rz, rw)),
+            (Value::Quaternion(..), rval) => return Err(LocatedCompileError::type_mismatch(rpos, &Type::Quaternion, &rval.ty())),
+            (lval, _) => return Err(LocatedCompileError::type_mismatch(lpos, &Type::Quaternion, &lval.ty())),
+        };
+        match op {
+            BinOp::Plus | BinOp::Minus => {
+                let (z, w) = if matches!(op, BinOp::Plus) {
+                    (self.complex_add(lval.0, rval.0), self.complex_add(lval.1, rval.1))
+                } else {
+                    (self.complex_sub(lval.0, rval.0), self.complex_sub(lval.1, rval.1))
+                };
+                Ok(Value::Quaternion(z, w))
+            },
+            BinOp::Times => {
+                let (z, w) = self.quaternion_mul(lval, rval);
+                Ok(Value::Quaternion(z, w))
+            },
+            BinOp::Power => {
+                let (z, w) = self.quaternion_pow(rpos, lval, rval)?;
+                Ok(Value::Quaternion(z, w))
+            },
+            _ => Err(LocatedCompileError::unsupported(pos, "this operator is not defined for quaternions".to_owned())),
+        }
+    }
+
+    /// `-q`, and `q^`, which is `a - bi - cj - dk`, i.e. `z^ - wj`.
+    pub(super) fn build_quaternion_unop(&mut self, pos: Location, op: UnOp, z: ComplexValue<'ctx>, w: ComplexValue<'ctx>)
+            -> Result<Value<'ctx>, LocatedCompileError> {
+        let neg = |this: &Self, val: ComplexValue<'ctx>| ComplexValue {
+            re: this.builder.build_float_neg(val.re, "tmp_neg_re"),
+            im: this.builder.build_float_neg(val.im, "tmp_neg_im"),
+        };
+        match op {
+            UnOp::Negate    => Ok(Value::Quaternion(neg(self, z), neg(self, w))),
+            UnOp::Conjugate => Ok(Value::Quaternion(self.complex_conjugate(z), neg(self, w))),
+            UnOp::Modulus   => Err(LocatedCompileError::unsupported(pos, "quaternions have no modulus; use `norm`".to_owned())),
+            UnOp::Factorial => Err(LocatedCompileError::unsupported(pos, "quaternions have no factorial".to_owned())),
+        }
+    }
+
+    /// If one side is a quaternion and the other is a number, turns the number into one too.
+    pub(super) fn promote_quaternion(&self, lval: Value<'ctx>, rval: Value<'ctx>) -> (Value<'ctx>, Value<'ctx>) {
+        let zero = self.ctx.f64_type().const_zero();
+        let zero = ComplexValue { re: zero, im: zero };
+        match (lval, rval) {
+            (Value::Complex(lval), rval @ Value::Quaternion(..)) => (Value::Quaternion(lval, zero), rval),
+            (lval @ Value::Quaternion(..), Value::Complex(rval)) => (lval, Value::Quaternion(rval, zero)),
+            vals => vals,
+        }
+    }
+
+    fn complex_add(&self, lval: ComplexValue<'ctx>, rval: ComplexValue<'ctx>) -> ComplexValue<'ctx> {
+        ComplexValue {
+            re: self.builder.build_float_add(lval.re, rval.re, "tmp_add_re"),
+            im: self.builder.build_float_add(lval.im, rval.im, "tmp_add_im"),
+        }
+    }
+
+    fn complex_sub(&self, lval: ComplexValue<'ctx>, rval: ComplexValue<'ctx>) -> ComplexValue<'ctx> {
+        ComplexValue {
+            re: self.builder.build_float_sub(lval.re, rval.re, "tmp_sub_re"),
+            im: self.builder.build_float_sub(lval.im, rval.im, "tmp_sub_im"),
+        }
+    }
+
+    /// `(z + wj)(u + vj) = (zu - wv^) + (zv + wu^)j`
+    fn quaternion_mul(&self, (z, w): (ComplexValue<'ctx>, ComplexValue<'ctx>), (u, v): (ComplexValue<'ctx>, ComplexValue<'ctx>))
+            -> (ComplexValue<'ctx>, ComplexValue<'ctx>) {
+        let zu = self.complex_mul(z, u);
+        let wv = self.complex_mul(w, self.complex_conjugate(v));
+        let zv = self.complex_mul(z, v);
+        let wu = self.complex_mul(w, self.complex_conjugate(u));
+        (self.complex_sub(zu, wv), self.complex_add(zv, wu))
+    }
+
+    /// `q ** n` for a natural number `n`, by multiplying `n` times.
+    fn quaternion_pow(&mut self, pos: Location, base: (ComplexValue<'ctx>, ComplexValue<'ctx>), exp: ComplexValue<'ctx>)
+            -> Result<(ComplexValue<'ctx>, ComplexValue<'ctx>), LocatedCompileError> {
+        let t_f64 = self.ctx.f64_type();
+        let zero = t_f64.const_zero();
+        let floor = self.builtins.floor();
+        self.move_to_end()?;
+        let floored = self.call_float(floor, &[exp.re.into()], "floored")?;
+        let whole = self.builder.build_float_compare(FloatPredicate::OEQ, exp.re, floored, "whole");
+        let real = self.builder.build_float_compare(FloatPredicate::OEQ, exp.im, zero, "real");
+        let non_negative = self.builder.build_float_compare(FloatPredicate::OGE, exp.re, zero, "non_negative");
+        let ok = self.builder.build_and(whole, real, "integer");
+        let ok = self.builder.build_and(ok, non_negative, "natural");
+        self.build_check(ok, pos, "quaternions can only be raised to a non-negative integer power")?;
+
+        let z = (self.builder.build_alloca(t_f64, "pow_z_re"), self.builder.build_alloca(t_f64, "pow_z_im"));
+        let w = (self.builder.build_alloca(t_f64, "pow_w_re"), self.builder.build_alloca(t_f64, "pow_w_im"));
+        self.store_complex(z, ComplexValue { re: t_f64.const_float(1.), im: zero });
+        self.store_complex(w, ComplexValue { re: zero, im: zero });
+        let n = self.builder.build_float_to_signed_int(exp.re, self.ctx.i64_type(), "n");
+        self.build_counted_loop(n, |this, _| {
+            let (next_z, next_w) = this.quaternion_mul((this.load_complex(z), this.load_complex(w)), base);
+            this.store_complex(z, next_z);
+            this.store_complex(w, next_w);
+            Ok(())
+        })?;
+        Ok((self.load_complex(z), self.load_complex(w)))
+    }
+}
@@ -0,0 +1,117 @@
+use inkwell::{FloatPredicate, IntPredicate, values::{IntValue, PointerValue}};
+
+use crate::{analyse::{ComplexValue, Location}, error::LocatedCompileError};
+
+use super::Compiler;
+
+/// Random numbers from splitmix64, whose whole state is a single `i64` in a global. It starts from
+/// the same seed every run, so a program gives the same numbers each time unless it calls `seed`
+/// with something else.
+impl<'ctx> Compiler<'ctx> {
+    /// `seed(n)`, which restarts the sequence: the same seed always gives the same numbers after it.
+    pub(super) fn build_seed(&mut self, pos: Location, n: ComplexValue<'ctx>) -> Result<(), LocatedCompileError> {
+        let n = self.build_real_integer(pos, n, "`seed` needs a real integer")?;
+        self.builder.build_store(self.rng_state_ptr(), n);
+        Ok(())
+    }
+
+    /// `rand_int(a, b)`, an integer from `a` to `b` inclusive, each as likely as the others.
+    pub(super) fn build_rand_int(&mut self, pos: Location, a: ComplexValue<'ctx>, b: ComplexValue<'ctx>) -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let a = self.build_real_integer(pos, a, "`rand_int` needs real integers")?;
+        let b = self.build_real_integer(pos, b, "`rand_int` needs real integers")?;
+        let ordered = self.builder.build_int_compare(IntPredicate::SLE, a, b, "ordered");
+        self.build_check(ordered, pos, "`rand_int` needs the lower bound first")?;
+        let span = self.builder.build_int_sub(b, a, "span");
+        let span = self.builder.build_int_add(span, t_i64.const_int(1, false), "span");
+        let offset = self.build_rand_below(span)?;
+        let res = self.builder.build_int_add(a, offset, "rand_int");
+        Ok(ComplexValue {
+            re: self.builder.build_signed_int_to_float(res, self.ctx.f64_type(), "rand_int"),
+            im: self.ctx.f64_type().const_zero(),
+        })
+    }
+
+    /// `rand_gaussian(max_norm)`, a Gaussian integer whose norm is at most `max_norm`, each as likely
+    /// as the others. Points in the square around them are tried until one lands inside the circle,
+    /// which takes fewer than two tries on average.
+    pub(super) fn build_rand_gaussian(&mut self, pos: Location, max_norm: ComplexValue<'ctx>) -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let t_f64 = self.ctx.f64_type();
+        let real = self.builder.build_float_compare(FloatPredicate::OEQ, max_norm.im, t_f64.const_zero(), "real");
+        let non_negative = self.builder.build_float_compare(FloatPredicate::OGE, max_norm.re, t_f64.const_zero(), "non_negative");
+        let ok = self.builder.build_and(real, non_negative, "ok");
+        self.build_check(ok, pos, "`rand_gaussian` needs a non-negative real bound")?;
+        let sqrt = self.builtins.sqrt();
+        let floor = self.builtins.floor();
+        self.move_to_end()?;
+        let radius = self.call_float(sqrt, &[max_norm.re.into()], "radius")?;
+        let radius = self.call_float(floor, &[radius.into()], "radius")?;
+        let radius = self.builder.build_float_to_signed_int(radius, t_i64, "radius");
+        let width = self.builder.build_int_mul(radius, t_i64.const_int(2, false), "width");
+        let width = self.builder.build_int_add(width, t_i64.const_int(1, false), "width");
+
+        let try_bb = self.ctx.append_basic_block(self.get_fp()?, "rand_gaussian_try");
+        let done_bb = self.ctx.append_basic_block(self.get_fp()?, "rand_gaussian_done");
+        self.builder.build_unconditional_branch(try_bb);
+
+        self.set_and_move_block(try_bb)?;
+        let mut parts = Vec::new();
+        for name in ["re", "im"].iter() {
+            let part = self.build_rand_below(width)?;
+            let part = self.builder.build_int_sub(part, radius, name);
+            parts.push(self.builder.build_signed_int_to_float(part, t_f64, name));
+        }
+        let z = ComplexValue { re: parts[0], im: parts[1] };
+        let inside = self.builder.build_float_compare(FloatPredicate::OLE, self.complex_norm(z), max_norm.re, "inside");
+        self.builder.build_conditional_branch(inside, done_bb, try_bb);
+
+        self.set_and_move_block(done_bb)?;
+        Ok(z)
+    }
+
+    /// A random integer in `0..bound`, for `bound > 0`. Taking the remainder of a 64-bit number would
+    /// favour small results, so numbers below `2^64 mod bound` are thrown away first.
+    fn build_rand_below(&mut self, bound: IntValue<'ctx>) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let negated = self.builder.build_int_neg(bound, "negated");
+        let threshold = self.builder.build_int_unsigned_rem(negated, bound, "threshold");
+        let draw_bb = self.ctx.append_basic_block(self.get_fp()?, "rand_draw");
+        let done_bb = self.ctx.append_basic_block(self.get_fp()?, "rand_done");
+        self.builder.build_unconditional_branch(draw_bb);
+
+        self.set_and_move_block(draw_bb)?;
+        let x = self.build_next_random();
+        let fair = self.builder.build_int_compare(IntPredicate::UGE, x, threshold, "fair");
+        self.builder.build_conditional_branch(fair, done_bb, draw_bb);
+
+        self.set_and_move_block(done_bb)?;
+        Ok(self.builder.build_int_unsigned_rem(x, bound, "rand_below"))
+    }
+
+    /// Advances the state and mixes it into the next 64 random bits.
+    fn build_next_random(&self) -> IntValue<'ctx> {
+        let t_i64 = self.ctx.i64_type();
+        let ptr = self.rng_state_ptr();
+        let state = self.builder.build_load(ptr, "state").into_int_value();
+        let state = self.builder.build_int_add(state, t_i64.const_int(0x9e37_79b9_7f4a_7c15, false), "state");
+        self.builder.build_store(ptr, state);
+        let mut z = state;
+        for (shift, factor) in [(30, 0xbf58_476d_1ce4_e5b9), (27, 0x94d0_49bb_1331_11eb)].iter() {
+            let shifted = self.builder.build_right_shift(z, t_i64.const_int(*shift, false), false, "shifted");
+            let mixed = self.builder.build_xor(z, shifted, "mixed");
+            z = self.builder.build_int_mul(mixed, t_i64.const_int(*factor, false), "mixed");
+        }
+        let shifted = self.builder.build_right_shift(z, t_i64.const_int(31, false), false, "shifted");
+        self.builder.build_xor(z, shifted, "random")
+    }
+
+    /// The generator's state, shared by all functions.
+    fn rng_state_ptr(&self) -> PointerValue<'ctx> {
+        let global = self.module.get_global(".rng").unwrap_or_else(|| {
+            let global = self.module.add_global(self.ctx.i64_type(), None, ".rng");
+            global.set_initializer(&self.ctx.i64_type().const_zero());
+            global
+        });
+        global.as_pointer_value()
+    }
+}
@@ -0,0 +1,159 @@
+use inkwell::FloatPredicate;
+
+use crate::{analyse::{ComplexValue, Location, Type, Value}, error::LocatedCompileError, parse::{BinOp, UnOp}};
+
+use super::Compiler;
+
+/// Gaussian rationals `p / q`, kept as the pair of Gaussian integers `(p, q)` in lowest terms, with
+/// `q` the canonical associate of its class, so that each rational is written exactly one way. Both
+/// parts have to stay within `2^53` to be exact, and arithmetic that would leave that range aborts.
+/// A Gaussian integer can be used wherever a rational is expected.
+impl<'ctx> Compiler<'ctx> {
+    /// Arithmetic where at least one side is a rational.
+    pub(super) fn build_rational_binop(&mut self, pos: Location, op: BinOp, lpos: Location, lval: Value<'ctx>, rpos: Location, rval: Value<'ctx>)
+            -> Result<Value<'ctx>, LocatedCompileError> {
+        let (a, b) = self.expect_rational(lpos, lval)?;
+        let (c, d) = self.expect_rational(rpos, rval)?;
+        let (num, den) = match op {
+            // a/b ± c/d = (ad ± cb) / bd
+            BinOp::Plus | BinOp::Minus => {
+                let ad = self.exact_mul(pos, a, d)?;
+                let cb = self.exact_mul(pos, c, b)?;
+                let num: ComplexValue = if let BinOp::Plus = op {
+                    (self.builder.build_float_add(ad.re, cb.re, "num_re"), self.builder.build_float_add(ad.im, cb.im, "num_im")).into()
+                } else {
+                    (self.builder.build_float_sub(ad.re, cb.re, "num_re"), self.builder.build_float_sub(ad.im, cb.im, "num_im")).into()
+                };
+                self.check_exact_arithmetic(pos, op, ad, cb, num)?;
+                (num, self.exact_mul(pos, b, d)?)
+            },
+            BinOp::Times => (self.exact_mul(pos, a, c)?, self.exact_mul(pos, b, d)?),
+            BinOp::Divide => (self.exact_mul(pos, a, d)?, self.exact_mul(pos, b, c)?),
+            _ => return Err(LocatedCompileError::unsupported(pos, "this operator is not defined for rationals".to_owned())),
+        };
+        self.build_rational(pos, num, den).map(|(num, den)| Value::Rational(num, den))
+    }
+
+    /// `-r`, and `r^`, the conjugate of both parts.
+    pub(super) fn build_rational_unop(&mut self, pos: Location, op: UnOp, num: ComplexValue<'ctx>, den: ComplexValue<'ctx>)
+            -> Result<Value<'ctx>, LocatedCompileError> {
+        match op {
+            UnOp::Negate    => Ok(Value::Rational((self.builder.build_float_neg(num.re, "tmp_neg_re"),
+                                   self.builder.build_float_neg(num.im, "tmp_neg_im")).into(), den)),
+            // The conjugate of the denominator usually isn't a canonical associate any more
+            UnOp::Conjugate => {
+                let num = self.complex_conjugate(num);
+                let den = self.complex_conjugate(den);
+                self.build_rational(pos, num, den).map(|(num, den)| Value::Rational(num, den))
+            },
+            UnOp::Modulus   => Err(LocatedCompileError::unsupported(pos, "the modulus of a rational usually isn't rational".to_owned())),
+            UnOp::Factorial => Err(LocatedCompileError::unsupported(pos, "rationals have no factorial".to_owned())),
+        }
+    }
+
+    /// `3r`, which has to be a whole number.
+    pub(super) fn build_rational_literal(&mut self, pos: Location, val: f64) -> Result<Value<'ctx>, LocatedCompileError> {
+        if val.fract() != 0. || val > 2f64.powi(53) {
+            return Err(LocatedCompileError::unsupported(pos, "a rational literal has to be a whole number no bigger than 2^53".to_owned()));
+        }
+        let t_f64 = self.ctx.f64_type();
+        let num = ComplexValue { re: t_f64.const_float(val), im: t_f64.const_zero() };
+        let den = ComplexValue { re: t_f64.const_float(1.), im: t_f64.const_zero() };
+        Ok(Value::Rational(num, den))
+    }
+
+    /// `rational(p, q)`, which is `p / q` in lowest terms.
+    pub(super) fn build_rational_call(&mut self, pos: Location, num: ComplexValue<'ctx>, den: ComplexValue<'ctx>)
+            -> Result<Value<'ctx>, LocatedCompileError> {
+        self.check_rational_parts(pos, num, den)?;
+        self.build_rational(pos, num, den).map(|(num, den)| Value::Rational(num, den))
+    }
+
+    /// `exact(z)`, the rational equal to `z`. A double is an integer over a power of two, so this
+    /// doubles `z` until it's a Gaussian integer, and aborts if that takes a denominator beyond `2^53`.
+    pub(super) fn build_exact(&mut self, pos: Location, z: ComplexValue<'ctx>) -> Result<Value<'ctx>, LocatedCompileError> {
+        let t_f64 = self.ctx.f64_type();
+        let two = t_f64.const_float(2.);
+        let num_ptr = (self.builder.build_alloca(t_f64, "num_re"), self.builder.build_alloca(t_f64, "num_im"));
+        let den_ptr = self.builder.build_alloca(t_f64, "den");
+        self.store_complex(num_ptr, z);
+        self.builder.build_store(den_ptr, t_f64.const_float(1.));
+        let steps = self.ctx.i64_type().const_int(53, false);
+        self.build_counted_loop(steps, |this, _| {
+            let num = this.load_complex(num_ptr);
+            let den = this.builder.build_load(den_ptr, "den").into_float_value();
+            let done = this.build_is_exact_integer(num)?;
+            let doubled = ComplexValue {
+                re: this.builder.build_float_mul(num.re, two, "doubled_re"),
+                im: this.builder.build_float_mul(num.im, two, "doubled_im"),
+            };
+            this.store_complex(num_ptr, ComplexValue {
+                re: this.builder.build_select(done, num.re, doubled.re, "num_re").into_float_value(),
+                im: this.builder.build_select(done, num.im, doubled.im, "num_im").into_float_value(),
+            });
+            let den = this.builder.build_select(done, den, this.builder.build_float_mul(den, two, "doubled"), "den");
+            this.builder.build_store(den_ptr, den);
+            Ok(())
+        })?;
+        let num = self.load_complex(num_ptr);
+        let den = ComplexValue { re: self.builder.build_load(den_ptr, "den").into_float_value(), im: t_f64.const_zero() };
+        self.build_rational_call(pos, num, den)
+    }
+
+    /// The numerator and denominator of a rational, or of a Gaussian integer over 1.
+    pub(super) fn expect_rational(&mut self, pos: Location, value: Value<'ctx>) -> Result<(ComplexValue<'ctx>, ComplexValue<'ctx>), LocatedCompileError> {
+        match value {
+            Value::Rational(num, den) => Ok((num, den)),
+            Value::Complex(num) => {
+                let t_f64 = self.ctx.f64_type();
+                let den = ComplexValue { re: t_f64.const_float(1.), im: t_f64.const_zero() };
+                self.check_rational_parts(pos, num, den)?;
+                Ok((num, den))
+            },
+            value => Err(LocatedCompileError::type_mismatch(pos, &Type::Rational, &value.ty())),
+        }
+    }
+
+    /// If one side is a rational and the other is a number, turns the number into a rational too.
+    pub(super) fn promote_rational(&mut self, lpos: Location, lval: Value<'ctx>, rpos: Location, rval: Value<'ctx>)
+            -> Result<(Value<'ctx>, Value<'ctx>), LocatedCompileError> {
+        match (lval, rval) {
+            (lval @ Value::Complex(_), rval @ Value::Rational(..)) | (lval @ Value::Rational(..), rval @ Value::Complex(_)) => {
+                let (a, b) = self.expect_rational(lpos, lval)?;
+                let (c, d) = self.expect_rational(rpos, rval)?;
+                Ok((Value::Rational(a, b), Value::Rational(c, d)))
+            },
+            vals => Ok(vals),
+        }
+    }
+
+    fn check_rational_parts(&mut self, pos: Location, num: ComplexValue<'ctx>, den: ComplexValue<'ctx>) -> Result<(), LocatedCompileError> {
+        let num_exact = self.build_is_exact_integer(num)?;
+        let den_exact = self.build_is_exact_integer(den)?;
+        let exact = self.builder.build_and(num_exact, den_exact, "exact");
+        self.build_check(exact, pos, "a rational needs Gaussian integers no bigger than 2^53")
+    }
+
+    fn exact_mul(&mut self, pos: Location, lval: ComplexValue<'ctx>, rval: ComplexValue<'ctx>) -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+        let res = self.complex_mul(lval, rval);
+        self.check_exact_arithmetic(pos, BinOp::Times, lval, rval, res)?;
+        Ok(res)
+    }
+
+    /// Divides `num` and `den` by their gcd, and then by whichever unit makes `den` a canonical
+    /// associate. Aborts if `den` is zero.
+    pub(super) fn build_rational(&mut self, pos: Location, num: ComplexValue<'ctx>, den: ComplexValue<'ctx>)
+            -> Result<(ComplexValue<'ctx>, ComplexValue<'ctx>), LocatedCompileError> {
+        let zero = self.ctx.f64_type().const_zero();
+        let re_nonzero = self.builder.build_float_compare(FloatPredicate::ONE, den.re, zero, "re_nonzero");
+        let im_nonzero = self.builder.build_float_compare(FloatPredicate::ONE, den.im, zero, "im_nonzero");
+        let nonzero = self.builder.build_or(re_nonzero, im_nonzero, "nonzero");
+        self.build_check(nonzero, pos, "division by zero")?;
+        // The gcd divides both exactly, so the rounding in `divmod` never comes into it
+        let g = self.gcd(pos, num, den)?;
+        let (num, _) = self.complex_divmod(pos, num, g)?;
+        let (den, _) = self.complex_divmod(pos, den, g)?;
+        let quadrants = self.quadrants(den);
+        Ok((self.rotate(num, quadrants), self.rotate(den, quadrants)))
+    }
+}
@@ -0,0 +1,76 @@
+use inkwell::values::{IntValue, PointerValue};
+
+use crate::{analyse::{Location, Type, Value}, error::{InternalError, LocatedCompileError}};
+
+use super::Compiler;
+
+/// A set is a list that never has the same element twice, so it shares the list's representation.
+/// Elements stay in the order they were first added, and checking for one is a linear search.
+impl<'ctx> Compiler<'ctx> {
+    /// Builds a set out of already-computed values, which must all have type `elem`. Duplicates are
+    /// dropped.
+    pub(super) fn build_set_from(&mut self, pos: Location, elem: &Type, values: Vec<Value<'ctx>>) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let set = self.build_list_alloc(elem, self.ctx.i64_type().const_zero())?;
+        for value in values {
+            self.build_set_insert(pos, set, elem, value)?;
+        }
+        Ok(set)
+    }
+
+    pub(super) fn build_set_contains(&mut self, pos: Location, set: PointerValue<'ctx>, elem: &Type, value: Value<'ctx>)
+            -> Result<IntValue<'ctx>, LocatedCompileError> {
+        if value.ty() != *elem {
+            return Err(LocatedCompileError::type_mismatch(pos, elem, &value.ty()));
+        }
+        match self.build_list_find(pos, set, elem, value)? {
+            Value::Option(present, _) => Ok(present),
+            _ => Err(InternalError::invalid_state("`find` did not return an optional value")),
+        }
+    }
+
+    /// Adds the value, unless it's already there.
+    pub(super) fn build_set_insert(&mut self, pos: Location, set: PointerValue<'ctx>, elem: &Type, value: Value<'ctx>)
+            -> Result<(), LocatedCompileError> {
+        let present = self.build_set_contains(pos, set, elem, value.clone())?;
+        let add_bb = self.ctx.append_basic_block(self.get_fp()?, "add");
+        let cont_bb = self.ctx.append_basic_block(self.get_fp()?, "cont");
+        self.builder.build_conditional_branch(present, cont_bb, add_bb);
+
+        self.set_and_move_block(add_bb)?;
+        self.build_list_push(set, elem, value)?;
+        self.builder.build_unconditional_branch(cont_bb);
+        self.set_and_move_block(cont_bb)
+    }
+
+    pub(super) fn build_set_union(&mut self, pos: Location, lhs: PointerValue<'ctx>, rhs: PointerValue<'ctx>, elem: &Type)
+            -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let res = self.build_list_copy(lhs, elem)?;
+        let len = self.list_len(rhs)?;
+        self.build_counted_loop(len, |this, k| {
+            let value = this.build_list_nth(rhs, elem, k)?;
+            this.build_set_insert(pos, res, elem, value)
+        })?;
+        Ok(res)
+    }
+
+    /// The elements of `lhs` that are in `rhs` (for `intersect`), or that aren't (for `diff`).
+    pub(super) fn build_set_filter(&mut self, pos: Location, lhs: PointerValue<'ctx>, rhs: PointerValue<'ctx>, elem: &Type, keep_shared: bool)
+            -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let res = self.build_list_alloc(elem, self.ctx.i64_type().const_zero())?;
+        let len = self.list_len(lhs)?;
+        self.build_counted_loop(len, |this, k| {
+            let value = this.build_list_nth(lhs, elem, k)?;
+            let shared = this.build_set_contains(pos, rhs, elem, value.clone())?;
+            let keep = if keep_shared { shared } else { this.builder.build_not(shared, "not_shared") };
+            let keep_bb = this.ctx.append_basic_block(this.get_fp()?, "keep");
+            let cont_bb = this.ctx.append_basic_block(this.get_fp()?, "cont");
+            this.builder.build_conditional_branch(keep, keep_bb, cont_bb);
+
+            this.set_and_move_block(keep_bb)?;
+            this.build_list_push(res, elem, value)?;
+            this.builder.build_unconditional_branch(cont_bb);
+            this.set_and_move_block(cont_bb)
+        })?;
+        Ok(res)
+    }
+}
@@ -0,0 +1,319 @@
+use inkwell::{FloatPredicate, IntPredicate, values::{IntValue, PointerValue}};
+
+use crate::{analyse::{ComplexValue, Location, Type, Value}, error::{InternalError, LocatedCompileError}};
+
+use super::Compiler;
+
+/// Fast Fourier transforms: `fft` over complex numbers, and the number-theoretic transform `ntt`,
+/// which works mod a prime `p` with an `n`-th root of unity in place of `e^(-2 pi i / n)`. Both are
+/// the iterative radix-2 transform, so the length has to be a power of two: the entries are put in
+/// bit-reversed order, and then each of the `log2(n)` stages combines pairs of entries that are
+/// twice as far apart as in the stage before. The twiddle factors are worked out once up front.
+impl<'ctx> Compiler<'ctx> {
+    /// `fft(xs)`, where entry `k` is `sum(xs[j] * e^(-2 pi i jk / n))`, or `ifft(xs)` for the
+    /// inverse, which uses `e^(2 pi i jk / n)` and divides by `n`.
+    pub(super) fn build_fft(&mut self, pos: Location, xs: PointerValue<'ctx>, inverse: bool) -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let t_f64 = self.ctx.f64_type();
+        let name = if inverse { "ifft" } else { "fft" };
+        let n = self.list_len(xs)?;
+        self.check_power_of_two(pos, n, name)?;
+        let log = self.build_log2(n)?;
+
+        let cos = self.builtins.cos();
+        let sin = self.builtins.sin();
+        self.move_to_end()?;
+        let half = self.builder.build_right_shift(n, self.ctx.i64_type().const_int(1, false), false, "half");
+        let twiddles = self.build_list_alloc(&Type::Complex, half)?;
+        let sign = if inverse { 1. } else { -1. };
+        let step = self.builder.build_signed_int_to_float(n, t_f64, "n");
+        let step = self.builder.build_float_div(t_f64.const_float(sign * std::f64::consts::TAU), step, "step");
+        self.build_counted_loop(half, |this, t| {
+            let theta = this.builder.build_signed_int_to_float(t, t_f64, "t");
+            let theta = this.builder.build_float_mul(step, theta, "theta");
+            let twiddle = ComplexValue {
+                re: this.call_float(cos, &[theta.into()], "twiddle_re")?,
+                im: this.call_float(sin, &[theta.into()], "twiddle_im")?,
+            };
+            this.build_list_store(twiddles, t, Value::Complex(twiddle))
+        })?;
+
+        let res = self.build_list_copy(xs, &Type::Complex)?;
+        self.build_bit_reversal(n, log, |this, i, j| {
+            let x = this.build_list_nth(res, &Type::Complex, i)?;
+            let y = this.build_list_nth(res, &Type::Complex, j)?;
+            this.build_list_store(res, i, y)?;
+            this.build_list_store(res, j, x)
+        })?;
+        self.build_butterflies(n, log, |this, top, bottom, t| {
+            let u = this.transform_nth(res, top)?;
+            let v = this.transform_nth(res, bottom)?;
+            let w = this.transform_nth(twiddles, t)?;
+            let v = this.complex_mul(v, w);
+            let sum = (this.builder.build_float_add(u.re, v.re, "sum_re"), this.builder.build_float_add(u.im, v.im, "sum_im")).into();
+            let diff = (this.builder.build_float_sub(u.re, v.re, "diff_re"), this.builder.build_float_sub(u.im, v.im, "diff_im")).into();
+            this.build_list_store(res, top, Value::Complex(sum))?;
+            this.build_list_store(res, bottom, Value::Complex(diff))
+        })?;
+
+        if inverse {
+            let size = self.builder.build_signed_int_to_float(n, t_f64, "size");
+            self.build_counted_loop(n, |this, k| {
+                let x = this.transform_nth(res, k)?;
+                let x = ComplexValue {
+                    re: this.builder.build_float_div(x.re, size, "scaled_re"),
+                    im: this.builder.build_float_div(x.im, size, "scaled_im"),
+                };
+                this.build_list_store(res, k, Value::Complex(x))
+            })?;
+        }
+        Ok(res)
+    }
+
+    /// `ntt(xs, p)`, where entry `k` is `sum(xs[j] * w ** (jk)) % p`, or `intt(xs, p)` for the inverse.
+    /// The entries have to be integers and `n` has to divide `p - 1`. The root of unity `w` is
+    /// `g ** ((p - 1) / n)` for the smallest `g` that makes it primitive, which for the usual moduli
+    /// like `998244353` is the usual one. The results are in `0..p`.
+    pub(super) fn build_ntt(&mut self, pos: Location, xs: PointerValue<'ctx>, modulus: ComplexValue<'ctx>, inverse: bool)
+            -> Result<PointerValue<'ctx>, LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let t_f64 = self.ctx.f64_type();
+        let zero = t_i64.const_zero();
+        let one = t_i64.const_int(1, false);
+        let name = if inverse { "intt" } else { "ntt" };
+        self.check_gaussian(pos, modulus, modulus, &format!("`{}` needs a prime modulus", name))?;
+        let real = self.builder.build_float_compare(FloatPredicate::OEQ, modulus.im, t_f64.const_zero(), "real");
+        self.build_check(real, pos, &format!("`{}` needs a prime modulus", name))?;
+        let p = self.builder.build_float_to_signed_int(modulus.re, t_i64, "p");
+        let prime = self.build_is_prime(p)?;
+        self.build_check(prime, pos, &format!("`{}` needs a prime modulus", name))?;
+
+        let n = self.list_len(xs)?;
+        self.check_power_of_two(pos, n, name)?;
+        let p_minus_one = self.builder.build_int_sub(p, one, "p_minus_one");
+        let rem = self.builder.build_int_signed_rem(p_minus_one, n, "rem");
+        let divides = self.builder.build_int_compare(IntPredicate::EQ, rem, zero, "divides");
+        self.build_check(divides, pos, &format!("the length passed to `{}` has to divide the modulus minus 1", name))?;
+        let log = self.build_log2(n)?;
+
+        let root = self.build_root_of_unity(n, p)?;
+        let root = if inverse {
+            let n_minus_one = self.builder.build_int_sub(n, one, "n_minus_one");
+            self.build_int_powmod(root, n_minus_one, p)?
+        } else {
+            root
+        };
+        let half = self.builder.build_right_shift(n, one, false, "half");
+        let twiddles = self.builder.build_array_malloc(t_i64, half, "twiddles")
+            .map_err(InternalError::invalid_state)?;
+        let twiddle = self.builder.build_alloca(t_i64, "twiddle");
+        self.builder.build_store(twiddle, one);
+        self.build_counted_loop(half, |this, t| {
+            let w = this.builder.build_load(twiddle, "w").into_int_value();
+            this.builder.build_store(this.transform_ptr(twiddles, t), w);
+            let next = this.build_int_mulmod(w, root, p)?;
+            this.builder.build_store(twiddle, next);
+            Ok(())
+        })?;
+
+        // Every entry is reduced into 0..p first
+        let data = self.builder.build_array_malloc(t_i64, n, "ntt_data")
+            .map_err(InternalError::invalid_state)?;
+        self.build_counted_loop(n, |this, k| {
+            let x = this.transform_nth(xs, k)?;
+            this.check_gaussian(pos, x, x, &format!("`{}` needs integer entries", name))?;
+            let real = this.builder.build_float_compare(FloatPredicate::OEQ, x.im, t_f64.const_zero(), "real");
+            this.build_check(real, pos, &format!("`{}` needs integer entries", name))?;
+            let x = this.builder.build_float_to_signed_int(x.re, t_i64, "x");
+            let x = this.builder.build_int_signed_rem(x, p, "x");
+            let x = this.builder.build_int_add(x, p, "x");
+            let x = this.builder.build_int_signed_rem(x, p, "x");
+            this.builder.build_store(this.transform_ptr(data, k), x);
+            Ok(())
+        })?;
+
+        self.build_bit_reversal(n, log, |this, i, j| {
+            let (i, j) = (this.transform_ptr(data, i), this.transform_ptr(data, j));
+            let x = this.builder.build_load(i, "x");
+            let y = this.builder.build_load(j, "y");
+            this.builder.build_store(i, y);
+            this.builder.build_store(j, x);
+            Ok(())
+        })?;
+        self.build_butterflies(n, log, |this, top, bottom, t| {
+            let (top, bottom) = (this.transform_ptr(data, top), this.transform_ptr(data, bottom));
+            let u = this.builder.build_load(top, "u").into_int_value();
+            let v = this.builder.build_load(bottom, "v").into_int_value();
+            let w = this.builder.build_load(this.transform_ptr(twiddles, t), "w").into_int_value();
+            let v = this.build_int_mulmod(v, w, p)?;
+            let sum = this.builder.build_int_add(u, v, "sum");
+            let sum = this.builder.build_int_signed_rem(sum, p, "sum");
+            let diff = this.builder.build_int_add(u, this.builder.build_int_sub(p, v, "minus_v"), "diff");
+            let diff = this.builder.build_int_signed_rem(diff, p, "diff");
+            this.builder.build_store(top, sum);
+            this.builder.build_store(bottom, diff);
+            Ok(())
+        })?;
+
+        // Dividing by n is multiplying by n ** (p - 2), since p is prime
+        let scale = if inverse {
+            let p_minus_two = self.builder.build_int_sub(p, t_i64.const_int(2, false), "p_minus_two");
+            self.build_int_powmod(n, p_minus_two, p)?
+        } else {
+            one
+        };
+        let res = self.build_list_alloc(&Type::Complex, n)?;
+        self.build_counted_loop(n, |this, k| {
+            let x = this.builder.build_load(this.transform_ptr(data, k), "x").into_int_value();
+            let x = this.build_int_mulmod(x, scale, p)?;
+            let x = ComplexValue {
+                re: this.builder.build_signed_int_to_float(x, t_f64, "x"),
+                im: t_f64.const_zero(),
+            };
+            this.build_list_store(res, k, Value::Complex(x))
+        })?;
+        Ok(res)
+    }
+
+    /// A primitive `n`-th root of unity mod the prime `p`, where `n` is a power of two dividing `p - 1`.
+    /// For `n > 1`, `w = g ** ((p - 1) / n)` is one exactly when `w ** (n / 2)` is `-1`, which it is
+    /// whenever `g` generates the units, so trying `g = 2, 3, ...` finds one.
+    fn build_root_of_unity(&mut self, n: IntValue<'ctx>, p: IntValue<'ctx>) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let one = t_i64.const_int(1, false);
+        let p_minus_one = self.builder.build_int_sub(p, one, "p_minus_one");
+        let exp = self.builder.build_int_signed_div(p_minus_one, n, "exp");
+        let half = self.builder.build_right_shift(n, one, false, "half");
+        let trivial = self.builder.build_int_compare(IntPredicate::EQ, n, one, "trivial");
+        let g_ptr = self.builder.build_alloca(t_i64, "g");
+        let root_ptr = self.builder.build_alloca(t_i64, "root");
+        self.builder.build_store(g_ptr, t_i64.const_int(2, false));
+
+        let search_bb = self.ctx.append_basic_block(self.get_fp()?, "root_search");
+        let done_bb = self.ctx.append_basic_block(self.get_fp()?, "root_done");
+        self.builder.build_unconditional_branch(search_bb);
+
+        self.set_and_move_block(search_bb)?;
+        let g = self.builder.build_load(g_ptr, "g").into_int_value();
+        let w = self.build_int_powmod(g, exp, p)?;
+        self.builder.build_store(root_ptr, w);
+        let check = self.build_int_powmod(w, half, p)?;
+        let minus_one = self.builder.build_int_compare(IntPredicate::EQ, check, p_minus_one, "minus_one");
+        let found = self.builder.build_or(trivial, minus_one, "found");
+        self.builder.build_store(g_ptr, self.builder.build_int_add(g, one, "next_g"));
+        self.builder.build_conditional_branch(found, done_bb, search_bb);
+
+        self.set_and_move_block(done_bb)?;
+        Ok(self.builder.build_load(root_ptr, "root").into_int_value())
+    }
+
+    /// Calls `swap(i, j)` for every `i` whose bit reversal `j` (as a `log`-bit number) is bigger. The
+    /// reversals are filled in from the ones before: `rev(i)` is `rev(i / 2) / 2` with the last bit
+    /// of `i` moved to the top.
+    fn build_bit_reversal<F>(&mut self, n: IntValue<'ctx>, log: IntValue<'ctx>, mut swap: F) -> Result<(), LocatedCompileError>
+            where F: FnMut(&mut Self, IntValue<'ctx>, IntValue<'ctx>) -> Result<(), LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let zero = t_i64.const_zero();
+        let one = t_i64.const_int(1, false);
+        let rev = self.builder.build_array_malloc(t_i64, n, "rev")
+            .map_err(InternalError::invalid_state)?;
+        self.builder.build_store(self.transform_ptr(rev, zero), zero);
+        let rest = self.builder.build_int_sub(n, one, "rest");
+        let top = self.builder.build_int_sub(log, one, "top");
+        self.build_counted_loop(rest, |this, k| {
+            let i = this.builder.build_int_add(k, one, "i");
+            let parent = this.builder.build_right_shift(i, one, false, "parent");
+            let parent = this.builder.build_load(this.transform_ptr(rev, parent), "parent_rev").into_int_value();
+            let shifted = this.builder.build_right_shift(parent, one, false, "shifted");
+            let bit = this.builder.build_and(i, one, "bit");
+            let bit = this.builder.build_left_shift(bit, top, "bit");
+            let j = this.builder.build_or(shifted, bit, "j");
+            this.builder.build_store(this.transform_ptr(rev, i), j);
+
+            let later = this.builder.build_int_compare(IntPredicate::SLT, i, j, "later");
+            let swap_bb = this.ctx.append_basic_block(this.get_fp()?, "reverse_swap");
+            let cont_bb = this.ctx.append_basic_block(this.get_fp()?, "reverse_cont");
+            this.builder.build_conditional_branch(later, swap_bb, cont_bb);
+            this.set_and_move_block(swap_bb)?;
+            swap(this, i, j)?;
+            this.builder.build_unconditional_branch(cont_bb);
+            this.set_and_move_block(cont_bb)
+        })
+    }
+
+    /// Calls `butterfly(top, bottom, t)` for each pair in each stage, where the twiddle factor is the
+    /// `t`-th power of the `n`-th root of unity.
+    fn build_butterflies<F>(&mut self, n: IntValue<'ctx>, log: IntValue<'ctx>, mut butterfly: F) -> Result<(), LocatedCompileError>
+            where F: FnMut(&mut Self, IntValue<'ctx>, IntValue<'ctx>, IntValue<'ctx>) -> Result<(), LocatedCompileError> {
+        let one = self.ctx.i64_type().const_int(1, false);
+        let pairs = self.builder.build_right_shift(n, one, false, "pairs");
+        self.build_counted_loop(log, |this, stage| {
+            let half = this.builder.build_left_shift(one, stage, "half");
+            let len = this.builder.build_left_shift(half, one, "len");
+            let stride = this.builder.build_int_signed_div(n, len, "stride");
+            this.build_counted_loop(pairs, |this, k| {
+                let block = this.builder.build_int_signed_div(k, half, "block");
+                let j = this.builder.build_int_signed_rem(k, half, "j");
+                let top = this.builder.build_int_add(this.builder.build_int_mul(block, len, "start"), j, "top");
+                let bottom = this.builder.build_int_add(top, half, "bottom");
+                let t = this.builder.build_int_mul(j, stride, "t");
+                butterfly(this, top, bottom, t)
+            })
+        })
+    }
+
+    fn check_power_of_two(&mut self, pos: Location, n: IntValue<'ctx>, name: &str) -> Result<(), LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let below = self.builder.build_int_sub(n, t_i64.const_int(1, false), "below");
+        let bits = self.builder.build_and(n, below, "bits");
+        let single = self.builder.build_int_compare(IntPredicate::EQ, bits, t_i64.const_zero(), "single");
+        let nonempty = self.builder.build_int_compare(IntPredicate::SGT, n, t_i64.const_zero(), "nonempty");
+        let ok = self.builder.build_and(single, nonempty, "power_of_two");
+        self.build_check(ok, pos, &format!("`{}` needs a list whose length is a power of two", name))
+    }
+
+    /// `log2(n)` for a power of two, which is how many of `1, 2, 4, ...` are less than `n`.
+    fn build_log2(&mut self, n: IntValue<'ctx>) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let t_i64 = self.ctx.i64_type();
+        let log = self.builder.build_alloca(t_i64, "log");
+        self.builder.build_store(log, t_i64.const_zero());
+        self.build_counted_loop(t_i64.const_int(63, false), |this, k| {
+            let power = this.builder.build_left_shift(t_i64.const_int(1, false), k, "power");
+            let less = this.builder.build_int_compare(IntPredicate::SLT, power, n, "less");
+            let less = this.builder.build_int_z_extend(less, t_i64, "less");
+            let count = this.builder.build_load(log, "count").into_int_value();
+            this.builder.build_store(log, this.builder.build_int_add(count, less, "count"));
+            Ok(())
+        })?;
+        Ok(self.builder.build_load(log, "log").into_int_value())
+    }
+
+    fn build_int_mulmod(&mut self, a: IntValue<'ctx>, b: IntValue<'ctx>, m: IntValue<'ctx>) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let mulmod = self.builtins.mulmod();
+        self.move_to_end()?;
+        Ok(self.builder.build_call(mulmod, &[a.into(), b.into(), m.into()], "mulmod")
+            .try_as_basic_value().left()
+                .ok_or_else(|| InternalError::invalid_state("failed to interpret return value of mulmod"))?
+            .into_int_value())
+    }
+
+    fn build_int_powmod(&mut self, a: IntValue<'ctx>, e: IntValue<'ctx>, m: IntValue<'ctx>) -> Result<IntValue<'ctx>, LocatedCompileError> {
+        let powmod = self.builtins.powmod();
+        self.move_to_end()?;
+        Ok(self.builder.build_call(powmod, &[a.into(), e.into(), m.into()], "powmod")
+            .try_as_basic_value().left()
+                .ok_or_else(|| InternalError::invalid_state("failed to interpret return value of powmod"))?
+            .into_int_value())
+    }
+
+    fn transform_nth(&mut self, list: PointerValue<'ctx>, k: IntValue<'ctx>) -> Result<ComplexValue<'ctx>, LocatedCompileError> {
+        match self.build_list_nth(list, &Type::Complex, k)? {
+            Value::Complex(x) => Ok(x),
+            _ => Err(InternalError::invalid_state("list entry is not a number")),
+        }
+    }
+
+    fn transform_ptr(&self, data: PointerValue<'ctx>, k: IntValue<'ctx>) -> PointerValue<'ctx> {
+        // Safety: every index is less than the length the array was allocated with
+        unsafe { self.builder.build_in_bounds_gep(data, &[k], "ptr") }
+    }
+}
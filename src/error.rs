@@ -2,7 +2,7 @@ use std::{error::Error, fmt};
 
 use inkwell::{execution_engine::FunctionLookupError, support::LLVMString};
 
-use crate::{Span, analyse::{Located, Location, SpanLength}};
+use crate::{Span, analyse::{Located, Location, SpanLength, Type}};
 
 /// From https://iximiuz.com/en/posts/rust-writing-parsers-with-nom/
 #[derive(Debug, PartialEq)]
@@ -102,11 +102,15 @@ impl InternalError {
 
 #[derive(Debug)]
 pub enum CompileError {
+    Arity(String),
+    Duplicate(String),
+    Field(String),
     Immutable(String),
     Internal(InternalError),
     NoMain,
     NotYetImplemented(String),
     NotInsideLoop(String),
+    TypeMismatch(String),
     UnknownSymbol(String),
     Unsupported(String),
 }
@@ -140,10 +144,14 @@ impl fmt::Display for LocatedCompileError {
                 write!(f, "missing `main` function")
             },
             CompileError::NotYetImplemented(msg)
+                | CompileError::Arity(msg)
+                | CompileError::Duplicate(msg)
+                | CompileError::Field(msg)
                 | CompileError::UnknownSymbol(msg)
                 | CompileError::Unsupported(msg)
                 | CompileError::Immutable(msg)
-                | CompileError::NotInsideLoop(msg) => {
+                | CompileError::NotInsideLoop(msg)
+                | CompileError::TypeMismatch(msg) => {
                 write!(f, "{}", msg)
             },
         }
@@ -184,6 +192,10 @@ impl LocatedCompileError {
         }
     }
 
+    pub fn unknown_label(label: Located<String>) -> LocatedCompileError {
+        Self::new(label.pos(), CompileError::NotInsideLoop(format!("there is no enclosing loop labelled `'{}`", label.borrow_val())))
+    }
+
     pub fn not_yet_impl<T: fmt::Display>(pos: Location, meta: T) -> LocatedCompileError {
         Self::new(pos, CompileError::NotYetImplemented(format!("not yet implemented: {}", meta)))
     }
@@ -198,15 +210,82 @@ impl LocatedCompileError {
     pub fn immutable(statement_pos: Location, id: String, decl: Location) -> LocatedCompileError {
         Self::with_secondary(statement_pos,
             CompileError::Immutable(format!("attempted to mutate `{}`", id)),
-            format!("`{}` declared immutable here:", id),
+            format!("`{}` declared immutable here (use `let mut` to allow changes):", id),
             decl)
     }
 
+    pub fn type_mismatch<T: fmt::Display + ?Sized>(pos: Location, expected: &T, found: &Type) -> LocatedCompileError {
+        Self::new(pos, CompileError::TypeMismatch(format!("type mismatch: expected `{}`, found `{}`", expected, found)))
+    }
+
+    pub fn arity(pos: Location, name: &str, expected: usize, found: usize) -> LocatedCompileError {
+        Self::new(pos, CompileError::Arity(
+            format!("`{}` takes {} argument{} but {} were supplied", name, expected, if expected == 1 { "" } else { "s" }, found)))
+    }
+
+    pub fn arity_between(pos: Location, name: &str, min: usize, max: usize, found: usize) -> LocatedCompileError {
+        Self::new(pos, CompileError::Arity(format!("`{}` takes {} to {} arguments but {} were supplied", name, min, max, found)))
+    }
+
+    pub fn arity_at_least(pos: Location, name: &str, min: usize, found: usize) -> LocatedCompileError {
+        Self::new(pos, CompileError::Arity(
+            format!("`{}` takes at least {} argument{} but {} were supplied", name, min, if min == 1 { "" } else { "s" }, found)))
+    }
+
+    pub fn no_param(name: Located<String>, func: &str) -> LocatedCompileError {
+        Self::new(name.pos(), CompileError::Arity(format!("`{}` has no parameter named `{}`", func, name.borrow_val())))
+    }
+
+    pub fn duplicate_arg(name: Located<String>, func: &str) -> LocatedCompileError {
+        Self::new(name.pos(), CompileError::Arity(format!("argument `{}` is supplied more than once in call to `{}`", name.borrow_val(), func)))
+    }
+
+    pub fn missing_arg(pos: Location, func: &str, param: &Located<String>) -> LocatedCompileError {
+        Self::with_secondary(pos,
+            CompileError::Arity(format!("missing argument `{}` in call to `{}`", param.borrow_val(), func)),
+            format!("`{}` declared here:", param.borrow_val()),
+            param.pos())
+    }
+
+    pub fn duplicate(id: Located<String>, prev: Location) -> LocatedCompileError {
+        Self::with_secondary(id.pos(),
+            CompileError::Duplicate(format!("`{}` is defined more than once", id.borrow_val())),
+            format!("`{}` first defined here:", id.borrow_val()),
+            prev)
+    }
+
+    /// Recursive calls are compiled before the function's result, so they have to guess its type.
+    pub fn recursive_result(pos: Location, name: &str, expected: &Type, found: &Type) -> LocatedCompileError {
+        Self::new(pos, CompileError::TypeMismatch(
+            format!("type mismatch: recursive function `{}` must return `{}`, found `{}`", name, expected, found)))
+    }
+
+    pub fn no_field<T: fmt::Display>(field: Located<String>, owner: T) -> LocatedCompileError {
+        Self::new(field.pos(), CompileError::Field(format!("`{}` has no field `{}`", owner, field.borrow_val())))
+    }
+
+    pub fn missing_field(pos: Location, name: &str, field: &Located<String>) -> LocatedCompileError {
+        Self::with_secondary(pos,
+            CompileError::Field(format!("missing field `{}` in `{}`", field.borrow_val(), name)),
+            format!("`{}` declared here:", field.borrow_val()),
+            field.pos())
+    }
+
     pub fn unknown_symbol(id: Located<String>) -> LocatedCompileError {
         Self::new(id.pos(), CompileError::UnknownSymbol(format!("unknown symbol: `{}`", id.val())))
     }
 
+    pub fn undeclared_global(id: Located<String>) -> LocatedCompileError {
+        Self::new(id.pos(), CompileError::UnknownSymbol(
+            format!("`{0}` is a global variable; use `global {0};` to refer to it here", id.borrow_val())))
+    }
+
+    pub fn shadowed_global(id: Located<String>) -> LocatedCompileError {
+        Self::new(id.pos(), CompileError::Duplicate(
+            format!("`{0}` was declared `global`, so `let {0}` would hide it; assign to it instead", id.borrow_val())))
+    }
+
     pub fn no_main() -> LocatedCompileError {
-        Self::new(Location { line: 0, col: 0, len: SpanLength::None }, CompileError::NoMain)
+        Self::new(Location { file: 0, line: 0, col: 0, len: SpanLength::None }, CompileError::NoMain)
     }
 }
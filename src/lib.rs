@@ -8,5 +8,6 @@ pub mod error;
 pub mod parse;
 pub mod util;
 
-pub type Span<'a> = LocatedSpan<&'a str>;
+/// The extra field is the index of the file being parsed, which ends up in each `Location`.
+pub type Span<'a> = LocatedSpan<&'a str, usize>;
 pub type IResult<'a, O> = nom::IResult<Span<'a>, O, ParseError<'a>>;
@@ -1,15 +1,165 @@
-use std::{env, fs};
+use std::{collections::HashSet, env, fs, path::{Path, PathBuf}};
 
-use ccomp::{Span, analyse::SpanLength, codegen, parse::parse_all};
+use ccomp::{Span, analyse::{Location, SpanLength}, codegen, error::ParseError, parse::{Item, parse_all}};
+
+// Terminal colours
+const TERM_RED: &str = "\u{001b}[31;1m";
+const TERM_RESET: &str = "\u{001b}[0m";
+const TERM_WHITE: &str = "\u{001b}[37;1m";
+const TERM_BLUE: &str = "\u{001b}[36;1m";
+
+fn report_parse_error(path: &Path, text: &str, e: nom::Err<ParseError>) {
+    let lines = text.split('\n').collect::<Vec<_>>();
+    match e {
+        nom::Err::Incomplete(_) => panic!("Unexpected error while parsing (`Incomplete`)"),
+        nom::Err::Error(e) | nom::Err::Failure(e) => {
+            println!("{}error{}: in {}, at line {}, column {}:{}",
+                TERM_RED,
+                TERM_WHITE,
+                path.display(),
+                e.line(),
+                e.col(),
+                TERM_RESET);
+            if e.line() < lines.len() {
+                println!("\t{}\n\t{}{}^",
+                         lines[e.line() - 1],
+                         " ".repeat(e.col() - 1),
+                         TERM_RED);
+            }
+            print!("{}", TERM_WHITE);
+            if let Some(msg) = e.msg() {
+                println!("{}", msg);
+            } else {
+                println!("unknown error");
+            }
+            print!("{}", TERM_RESET);
+        }
+    }
+}
+
+/// Shows the line of `text` at `pos`, with the span underlined in `colour`.
+fn show_line(text: &str, pos: Location, colour: &str) {
+    let lines = text.split('\n').collect::<Vec<_>>();
+    if pos.line < lines.len() {
+        let line = lines[pos.line - 1];
+        let trimmed = line.trim_start();
+        let begin_whitespace = line.len() - trimmed.len();
+        let space_count = pos.col - 1 - begin_whitespace;
+        let underline_count = match pos.len {
+            SpanLength::Size(len) => len,
+            SpanLength::ToEnd =>
+                // FIXME: this is a hack to deal with semicolon underlining
+                trimmed.len() - space_count -
+                    if line.ends_with(';') { 1 } else { 0 },
+            SpanLength::None  => 1,
+        };
+        let underline = if underline_count > 1 {
+            "└".to_owned() + &"─".repeat(underline_count - 2) + "┘"
+        } else {
+            "^".to_owned()
+        };
+        print!("\n{:3} |\t{}\n     \t{}{}{}{}",
+               pos.line,
+               trimmed,
+               " ".repeat(space_count),
+               colour,
+               underline,
+               TERM_RESET);
+    }
+}
+
+/// Reports a problem with the `import` at `pos` in `path`, whose text is `text`.
+fn report_import_error(path: &Path, text: &str, pos: Location, msg: &str) {
+    println!("{}error{}: in {}, at line {}, column {}:{}",
+        TERM_RED,
+        TERM_WHITE,
+        path.display(),
+        pos.line,
+        pos.col,
+        TERM_RESET);
+    if let Some(line) = text.split('\n').nth(pos.line - 1) {
+        println!("\t{}\n\t{}{}^", line, " ".repeat(pos.col - 1), TERM_RED);
+    }
+    println!("{}{}{}", TERM_WHITE, msg, TERM_RESET);
+}
+
+/// The namespace an imported file's definitions go in, which is its name without the extension.
+fn namespace(path: &Path) -> Option<String> {
+    let name = path.file_stem()?.to_str()?;
+    let valid = name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if valid { Some(name.to_owned()) } else { None }
+}
+
+/// Parses a file, followed by everything it imports. Each file is only loaded once, so imports can
+/// be circular. Its text is kept in `sources`, and its index there is the `file` in each `Location`
+/// in it. Everything an imported file defines goes in its namespace, which is kept in `namespaces`
+/// at the same index. Returns `None` if there was an error, after reporting it.
+fn load(path: &Path, text: String, loaded: &mut HashSet<PathBuf>, sources: &mut Vec<(PathBuf, String)>,
+        namespaces: &mut Vec<Option<String>>, items: &mut Vec<Item>) -> Option<()> {
+    loaded.insert(path.canonicalize().unwrap_or_else(|_| path.to_owned()));
+    let file = sources.len();
+    let parsed = match parse_all(Span::new_extra(&text, file)) {
+        Ok((_remain, parsed)) => parsed,
+        Err(e) => {
+            report_parse_error(path, &text, e);
+            return None;
+        },
+    };
+    sources.push((path.to_owned(), text));
+    let ns = if file == 0 { None } else { namespace(path) };
+    namespaces.push(ns.clone());
+
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    for item in parsed {
+        match item {
+            Item::Import(import) => {
+                let pos = import.pos();
+                let import = dir.join(import.val());
+                if loaded.contains(&import.canonicalize().unwrap_or_else(|_| import.clone())) {
+                    continue;
+                }
+                let text = match fs::read_to_string(&import) {
+                    Ok(text) => text,
+                    Err(e) => {
+                        report_import_error(path, &sources[file].1, pos, &format!("could not read {}: {}", import.display(), e));
+                        return None;
+                    },
+                };
+                // Files are told apart by name, so two with the same name can't both be imported
+                let msg = match namespace(&import) {
+                    None => Some(format!("{} can't be imported, since its name isn't an identifier", import.display())),
+                    Some(name) if namespaces.contains(&Some(name.clone())) =>
+                        Some(format!("{} can't be imported, since another file called `{}` already was", import.display(), name)),
+                    Some(_) => None,
+                };
+                if let Some(msg) = msg {
+                    report_import_error(path, &sources[file].1, pos, &msg);
+                    return None;
+                }
+                load(&import, text, loaded, sources, namespaces, items)?;
+            },
+            item => items.push(match &ns {
+                Some(ns) => item.qualify(ns),
+                None => item,
+            }),
+        }
+    }
+    Some(())
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        println!("usage: ccc <filename>");
+    // `ccc check [--types] <filename>` compiles without running
+    let checking = args.get(1).map_or(false, |arg| arg == "check");
+    let types = checking && args.get(2).map_or(false, |arg| arg == "--types");
+    let file_arg = 1 + checking as usize + types as usize;
+    if args.len() <= file_arg {
+        println!("usage: ccc <filename>\n       ccc check [--types] <filename>");
         return;
     }
 
-    let filename = args[1].as_str();
+    let filename = args[file_arg].as_str();
 
     let text = fs::read_to_string(filename)
         // Clippy: https://rust-lang.github.io/rust-clippy/master/index.html#expect_fun_call
@@ -20,124 +170,53 @@ fn main() {
     let ext_index = filename.rfind('.').unwrap_or(filename.len());
     let raw_filename = &filename[path_index..ext_index];
 
-    // Store a copy of the source code to make error reporting easier down the line
-    let lines = text.as_str().split('\n').collect::<Vec<_>>();
+    // Keep the source code of every file to make error reporting easier down the line
+    let mut sources = Vec::new();
+    let mut namespaces = Vec::new();
+    let mut parsed = Vec::new();
+    // If we hit a parse error, it has already been reported
+    if load(Path::new(filename), text, &mut HashSet::new(), &mut sources, &mut namespaces, &mut parsed).is_none() {
+        return;
+    }
 
-    // Terminal colours
-    const TERM_RED: &str = "\u{001b}[31;1m";
-    const TERM_RESET: &str = "\u{001b}[0m";
-    const TERM_WHITE: &str = "\u{001b}[37;1m";
-    const TERM_BLUE: &str = "\u{001b}[36;1m";
+    // Otherwise, we can simply export the LLVM and run the code in a JIT environment
+    let ast_dest = format!("out/{}.ast", raw_filename);
+    fs::create_dir_all("out")
+        .expect("Failed to create `out` directory");
+    fs::write(&ast_dest, format!("{:#?}", parsed))
+        .unwrap_or_else(|_| panic!("Failed to write AST output to {}", ast_dest));
     
-    match parse_all(Span::new(&text)) {
-        // In this case, we can simply export the LLVM and run the code in a JIT environment
-        Ok((_remain, parsed)) =>  {
-            let ast_dest = format!("out/{}.ast", raw_filename);
-            fs::create_dir_all("out")
-                .expect("Failed to create `out` directory");
-            fs::write(&ast_dest, format!("{:#?}", parsed))
-                .unwrap_or_else(|_| panic!("Failed to write AST output to {}", ast_dest));
-            
-            let llvm_dest = format!("out/{}.ll", raw_filename);
-            // Check if we had a compiler error, and attempt to explain it
-            if let Err(e) = codegen::run(&llvm_dest, parsed) {
-                print!("{}error{}: {}:{}", TERM_RED, TERM_WHITE, e, TERM_RESET);
-                // Show the position if available
-                if let Some(pos) = e.pos {
-                    // idk how to show the filename nicely
-                    // print!("\n{}:{}:{}", filename, pos.line, pos.col);
-
-                    // If possible, show the offending line of source
-                    if pos.line < lines.len() {
-                        let line = lines[pos.line - 1];
-                        let trimmed = line.trim_start();
-                        let begin_whitespace = line.len() - trimmed.len();
-                        let space_count = pos.col - 1 - begin_whitespace;
-                        let underline_count = match pos.len {
-                            SpanLength::Size(len) => len,
-                            SpanLength::ToEnd =>
-                                // FIXME: this is a hack to deal with semicolon underlining
-                                trimmed.len() - space_count -
-                                    if line.ends_with(';') { 1 } else { 0 },
-                            SpanLength::None  => 1,
-                        };
-                        let underline = if underline_count > 1 {
-                            "└".to_owned() + &"─".repeat(underline_count - 2) + "┘"
-                        } else {
-                            "^".to_owned()
-                        };
-                        print!("\n{:3} |\t{}\n     \t{}{}{}{}",
-                               pos.line,
-                               trimmed,
-                               " ".repeat(space_count),
-                               TERM_RED,
-                               underline,
-                               TERM_RESET);
-                    }
-                }
-                // Show the note if one exists
-                if let Some(msg) = e.secondary_msg {
-                    print!("\n{}note{}: {}", TERM_BLUE, TERM_WHITE, msg);
-                }
-                // If the note points to a location, show the location and line of code
-                if let Some(pos) = e.secondary_pos {
-                    if pos.line < lines.len() {
-                        let line = lines[pos.line - 1];
-                        let trimmed = line.trim_start();
-                        let begin_whitespace = line.len() - trimmed.len();
-                        let space_count = pos.col - 1 - begin_whitespace;
-                        let underline_count = match pos.len {
-                            SpanLength::Size(len) => len,
-                            SpanLength::ToEnd =>
-                                // FIXME: this is a hack to deal with semicolon underlining
-                                trimmed.len() - space_count -
-                                    if line.ends_with(';') { 1 } else { 0 },
-                            SpanLength::None  => 1,
-                        };
-                        let underline = if underline_count > 1 {
-                            "└".to_owned() + &"─".repeat(underline_count - 2) + "┘"
-                        } else {
-                            "^".to_owned()
-                        };
-                        print!("\n{}{:3} |\t{}\n    \t{}{}{}{}",
-                               TERM_RESET,
-                               pos.line,
-                               trimmed,
-                               " ".repeat(space_count),
-                               TERM_BLUE,
-                               underline,
-                               TERM_RESET);
-                    }
-                }
-                print!("\u{001b}[0m\n\n");
+    let llvm_dest = format!("out/{}.ll", raw_filename);
+    let result = if checking {
+        codegen::check(parsed, namespaces, types)
+    } else {
+        codegen::run(&llvm_dest, parsed, namespaces)
+    };
+    if checking && result.is_ok() {
+        println!("{}: no errors found", filename);
+    }
+    // Check if we had a compiler error, and attempt to explain it
+    if let Err(e) = result {
+        print!("{}error{}: {}:{}", TERM_RED, TERM_WHITE, e, TERM_RESET);
+        // Show the position if available, along with the file if it was imported
+        if let Some(pos) = e.pos {
+            if pos.file != 0 {
+                print!("\n     --> {}", sources[pos.file].0.display());
             }
-        },
-        // Otherwise, we hit a parse error, so try to report that
-        Err(e) => {
-            match e {
-                nom::Err::Incomplete(_) => panic!("Unexpected error while parsing (`Incomplete`)"),
-                nom::Err::Error(e) | nom::Err::Failure(e) => {
-                    println!("{}error{}: at line {}, column {}:{}",
-                        TERM_RED,
-                        TERM_WHITE,
-                        e.line(),
-                        e.col(),
-                        TERM_RESET);
-                    if e.line() < lines.len() {
-                        println!("\t{}\n\t{}{}^",
-                                 lines[e.line() - 1],
-                                 " ".repeat(e.col() - 1),
-                                 TERM_RED);
-                    }
-                    print!("{}", TERM_WHITE);
-                    if let Some(msg) = e.msg() {
-                        println!("{}", msg);
-                    } else {
-                        println!("unknown error");
-                    }
-                    print!("{}", TERM_RESET);
-                }
+            show_line(&sources[pos.file].1, pos, TERM_RED);
+        }
+        // Show the note if one exists
+        if let Some(msg) = e.secondary_msg {
+            print!("\n{}note{}: {}", TERM_BLUE, TERM_WHITE, msg);
+        }
+        // If the note points to a location, show the location and line of code
+        if let Some(pos) = e.secondary_pos {
+            print!("{}", TERM_RESET);
+            if pos.file != e.pos.map_or(0, |pos| pos.file) {
+                print!("\n     --> {}", sources[pos.file].0.display());
             }
+            show_line(&sources[pos.file].1, pos, TERM_BLUE);
         }
-    } 
+        print!("\u{001b}[0m\n\n");
+    }
 }
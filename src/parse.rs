@@ -1,6 +1,10 @@
-use nom::{branch::alt, bytes::complete::{tag, take_until}, character::complete::{alpha1, alphanumeric1, char, multispace0, one_of}, combinator::{map, opt, recognize, verify}, multi::{fold_many0, many0, many1}, sequence::{delimited, pair, preceded, separated_pair, terminated}};
+use nom::{branch::alt, bytes::complete::{tag, take_until, take_while}, character::complete::{char, digit1, one_of, satisfy}, combinator::{consumed, map, not, opt, recognize, verify}, multi::{fold_many0, many0, many1, separated_list0, separated_list1}, sequence::{delimited, pair, preceded, separated_pair, terminated, tuple}};
 
-use crate::{IResult, Span, analyse::{Located, Location}, error::ParseError, util::{ComplexNum, string_literal, ws, ws_tag}};
+use nom_locate::position;
+
+use std::fmt;
+
+use crate::{IResult, Span, analyse::{Located, Location, Type}, error::ParseError, util::{ComplexNum, interpolated_text, skip, string_literal, ws, ws_tag}};
 
 /* ----------------------------------------------------------------
     EXPRESSIONS
@@ -15,15 +19,36 @@ const RESERVED_WORDS: &[&str] = &[
     "print",
     "println",
     "while",
+    "for",
+    "in",
+    "step",
     "fn",
     "mut",
     "break",
     "continue",
-    // Below reserved for future use
-    "matrix",
+    "true",
+    "false",
+    "match",
     "return",
+    "struct",
+    "enum",
+    "import",
     "pi",
     "tau",
+    "matrix",
+    "do",
+    "loop",
+    "try",
+    "catch",
+    "assert",
+    "global",
+    "xor",
+    "bitor",
+    "lazy",
+    "yield",
+    "mod",
+    "poly",
+    // Below reserved for future use
     "exp",
 ];
 
@@ -34,9 +59,25 @@ pub enum BinOp {
     Times,
     Divide,
     Remainder,
+    /// `a ~/ b`, the quotient of Euclidean division. (`//` would start a comment.)
+    Quotient,
+    // These need real integers
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
     Power,
     Equals,
     NotEquals,
+    Less,
+    LessEquals,
+    Greater,
+    GreaterEquals,
+    /// `x in xs`, for lists and ranges
+    In,
+    And,
+    Or,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -44,64 +85,360 @@ pub enum UnOp {
     Negate,
     Conjugate,
     Modulus,
+    Factorial,
 }
 
 #[derive(Debug, Clone)]
 pub enum Expr {
     Value(ComplexNum),
+    /// `3w` or `3ω`, a multiple of the cube root of unity `ω`, which makes an Eisenstein integer.
+    Eisenstein(f64),
+    /// `3j` or `3k`, held as the coefficients of `j` and `k`, which makes a quaternion.
+    Quaternion(ComplexNum),
+    /// `3r`, a whole number as a Gaussian rational, so that dividing it gives an exact fraction.
+    Rational(f64),
+    Bool(bool),
+    Str(String),
     Id(Located<String>),
     BinOp(BinOp, Box<(Located<Expr>, Located<Expr>)>),
     UnOp(UnOp, Box<Located<Expr>>),
     IfElse(Box<(Located<Expr>, Located<Expr>, Located<Expr>)>),
+    Range(Box<(Located<Expr>, Located<Expr>, Option<Located<Expr>>)>),
+    /// A call with positional arguments followed by named ones, as in `solve(3, b: 4)`.
+    Call(Located<String>, Vec<Located<Expr>>, Vec<(Located<String>, Located<Expr>)>),
+    List(Vec<Located<Expr>>),
+    /// `[expr for x in xs if cond]`, which can have any number of `for` and `if` clauses after the first `for`.
+    Comprehension(Box<Located<Expr>>, Vec<Clause>),
+    /// `{ key: value, ... }`
+    Dict(Vec<(Located<Expr>, Located<Expr>)>),
+    /// `{a, b, c}`, or `{a,}` for just one element
+    Set(Vec<Located<Expr>>),
+    Index(Box<(Located<Expr>, Located<Expr>)>),
+    Tuple(Vec<Located<Expr>>),
+    Field(Box<Located<Expr>>, usize),
+    Match(Box<Located<Expr>>, Vec<(Pattern, Located<Expr>)>),
+    Lambda(Vec<Located<String>>, Box<Located<Expr>>),
+    /// `lazy expr`, which isn't evaluated until a variable holding it is used.
+    Lazy(Box<Located<Expr>>),
+    /// `poly [1, 0, 1]`, the polynomial with those coefficients, starting from the constant term.
+    Poly(Box<Located<Expr>>),
+    Matrix(Vec<Located<Vec<Located<Expr>>>>),
+    Struct(Located<String>, Vec<(Located<String>, Located<Expr>)>),
+    Member(Box<Located<Expr>>, Located<String>),
+    Block(Vec<Located<Statement>>, Box<Located<Expr>>),
+    /// `a < b <= c`, a chain of two or more comparisons, each of which has to hold.
+    Chain(Box<Located<Expr>>, Vec<(BinOp, Located<Expr>)>),
+    /// `mod (m) { ... }`, a block in which arithmetic is done modulo the Gaussian integer `m`.
+    Mod(Box<Located<Expr>>, Box<Located<Expr>>),
+    Loop(Option<Located<String>>, Vec<Located<Statement>>),
+    /// An expression whose value has to fit the annotation, as in `let x: int = 3`.
+    Annotated(Box<Located<Expr>>, Located<Annotation>),
+}
+
+impl Expr {
+    /// Collects every identifier mentioned in the expression. This includes names that are bound
+    /// inside it, so it's an over-approximation of the free variables.
+    pub fn collect_ids(&self, out: &mut Vec<String>) {
+        match self {
+            Self::Value(_) | Self::Eisenstein(_) | Self::Quaternion(_) | Self::Rational(_) | Self::Bool(_) | Self::Str(_) => {},
+            Self::Id(id) => out.push(id.borrow_val().clone()),
+            Self::BinOp(_, boxed) | Self::Index(boxed) => {
+                boxed.0.borrow_val().collect_ids(out);
+                boxed.1.borrow_val().collect_ids(out);
+            },
+            Self::UnOp(_, expr) | Self::Field(expr, _) | Self::Member(expr, _) | Self::Lambda(_, expr) | Self::Lazy(expr) | Self::Poly(expr) | Self::Annotated(expr, _) =>
+                expr.borrow_val().collect_ids(out),
+            Self::IfElse(boxed) => {
+                boxed.0.borrow_val().collect_ids(out);
+                boxed.1.borrow_val().collect_ids(out);
+                boxed.2.borrow_val().collect_ids(out);
+            },
+            Self::Range(boxed) => {
+                boxed.0.borrow_val().collect_ids(out);
+                boxed.1.borrow_val().collect_ids(out);
+                if let Some(step) = &boxed.2 {
+                    step.borrow_val().collect_ids(out);
+                }
+            },
+            Self::Call(id, args, named) => {
+                out.push(id.borrow_val().clone());
+                for arg in args.iter().chain(named.iter().map(|(_, arg)| arg)) {
+                    arg.borrow_val().collect_ids(out);
+                }
+            },
+            Self::List(elems) | Self::Tuple(elems) | Self::Set(elems) => {
+                for elem in elems {
+                    elem.borrow_val().collect_ids(out);
+                }
+            },
+            Self::Block(body, result) => {
+                for statement in body {
+                    statement.borrow_val().collect_ids(out);
+                }
+                result.borrow_val().collect_ids(out);
+            },
+            Self::Mod(modulus, body) => {
+                modulus.borrow_val().collect_ids(out);
+                body.borrow_val().collect_ids(out);
+            },
+            Self::Chain(first, rest) => {
+                first.borrow_val().collect_ids(out);
+                for (_, expr) in rest {
+                    expr.borrow_val().collect_ids(out);
+                }
+            },
+            Self::Loop(_, body) => {
+                for statement in body {
+                    statement.borrow_val().collect_ids(out);
+                }
+            },
+            Self::Struct(_, fields) => {
+                for (_, expr) in fields {
+                    expr.borrow_val().collect_ids(out);
+                }
+            },
+            Self::Dict(pairs) => {
+                for (key, value) in pairs {
+                    key.borrow_val().collect_ids(out);
+                    value.borrow_val().collect_ids(out);
+                }
+            },
+            Self::Matrix(rows) => {
+                for elem in rows.iter().flat_map(|row| row.borrow_val()) {
+                    elem.borrow_val().collect_ids(out);
+                }
+            },
+            Self::Match(scrutinee, arms) => {
+                scrutinee.borrow_val().collect_ids(out);
+                for (pattern, body) in arms {
+                    pattern.collect_ids(out);
+                    body.borrow_val().collect_ids(out);
+                }
+            },
+            Self::Comprehension(elem, clauses) => {
+                for clause in clauses {
+                    match clause {
+                        Clause::For(id, iter) => {
+                            out.push(id.borrow_val().clone());
+                            iter.borrow_val().collect_ids(out);
+                        },
+                        Clause::If(cond) => cond.borrow_val().collect_ids(out),
+                    }
+                }
+                elem.borrow_val().collect_ids(out);
+            },
+        }
+    }
+}
+
+/// A clause of a list comprehension. Each one is nested inside the clauses before it.
+#[derive(Debug, Clone)]
+pub enum Clause {
+    For(Located<String>, Located<Expr>),
+    If(Located<Expr>),
+}
+
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Literal(Located<Expr>),
+    Binding(Located<String>),
+    Wildcard,
+    // A variant of an enum, such as `Ok(x)`. Variants without fields are parsed as bindings.
+    Variant(Located<String>, Vec<Pattern>),
+}
+
+impl Pattern {
+    /// Collects the identifiers mentioned in literals inside the pattern.
+    pub fn collect_ids(&self, out: &mut Vec<String>) {
+        match self {
+            Self::Literal(literal) => literal.borrow_val().collect_ids(out),
+            Self::Variant(_, fields) => {
+                for field in fields {
+                    field.collect_ids(out);
+                }
+            },
+            Self::Binding(_) | Self::Wildcard => {},
+        }
+    }
 }
 
 fn decimal(input: Span) -> IResult<Span> {
     recognize(many1(terminated(one_of("0123456789"), many0(tag("_")))))(input)
 }
 
+/// A decimal number such as `12` or `1_000.5`. Underscores are ignored.
+fn decimal_number(input: Span) -> IResult<f64> {
+    let (rest, integral) = decimal(input)?;
+    let (rest, fraction) = opt(preceded(tag("."), decimal))(rest)?;
+
+    let val = match fraction {
+        Some(fraction) => format!("{}.{}", integral, fraction),
+        None           => integral.to_string(),
+    };
+    let val = val.replace('_', "").parse::<f64>()
+        .map_err(|_| ParseError::error(input, "failed to parse decimal value".to_owned()))?;
+    Ok((rest, val))
+}
+
+/// An integer in base 16, 8 or 2, such as `0xff`, `0o17` or `0b1010`. Like decimals, these can
+/// contain underscores, and can be arbitrarily long, though they get rounded to the nearest double.
+fn radix_number(input: Span) -> IResult<f64> {
+    let (rest, (prefix, digits)) = pair(
+        alt((tag("0x"), tag("0o"), tag("0b"))),
+        recognize(many1(terminated(one_of("0123456789abcdefABCDEF"), many0(tag("_"))))))(input)?;
+    let radix = match *prefix.fragment() {
+        "0x" => 16,
+        "0o" => 8,
+        _    => 2,
+    };
+    let digits = digits.fragment().chars()
+        .filter(|&c| c != '_')
+        .map(|digit| digit.to_digit(radix))
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| ParseError::fail(input, format!("invalid base {} literal", radix)))?;
+    // Rounding only once, at the end, rather than after every digit
+    let val = radix_to_decimal(&digits, radix).parse::<f64>()
+        .map_err(|_| ParseError::error(input, format!("failed to parse base {} literal", radix)))?;
+    Ok((rest, val))
+}
+
+/// The exact decimal expansion of some digits in the given radix, worked out in base `10^9` limbs
+/// (least significant first) so that it's exact however long the literal is.
+fn radix_to_decimal(digits: &[u32], radix: u32) -> String {
+    const LIMB: u64 = 1_000_000_000;
+    let mut limbs = vec![0u64];
+    for &digit in digits {
+        let mut carry = digit as u64;
+        for limb in limbs.iter_mut() {
+            let x = *limb * radix as u64 + carry;
+            *limb = x % LIMB;
+            carry = x / LIMB;
+        }
+        if carry > 0 {
+            limbs.push(carry);
+        }
+    }
+    let mut res = limbs.pop().unwrap_or(0).to_string();
+    for limb in limbs.iter().rev() {
+        res.push_str(&format!("{:09}", limb));
+    }
+    res
+}
+
+fn real_number(input: Span) -> IResult<f64> {
+    alt((radix_number, decimal_number))(input)
+}
+
 fn real(input: Span) -> IResult<Located<Expr>> {
     let left = Location::from(&input);
-    let (input, integral) = decimal(input)?;
-    let (input, rest) = opt(preceded(tag("."), decimal))(input)?;
+    let (input, val) = real_number(input)?;
+    let right = Location::from(&input);
+    Ok((input, Located::new(Expr::Value(ComplexNum(val, 0.)), left.span_to(right))))
+}
+
+fn imag(input: Span) -> IResult<Located<Expr>> {
+    let left = Location::from(&input);
+    let (input, val) = terminated(opt(real_number), tag("i"))(input)?;
     let right = Location::from(&input);
+    let expr = Expr::Value(ComplexNum(0., val.unwrap_or(1.)));
+    Ok((input, Located::new(expr, left.span_to(right))))
+}
+
+/// The number is needed even for `1w`, since `w` and `ω` on their own are ordinary names.
+fn eisenstein(input: Span) -> IResult<Located<Expr>> {
+    let left = Location::from(&input);
+    let (input, val) = terminated(real_number, terminated(alt((tag("w"), tag("ω"))), not(satisfy(is_identifier_char))))(input)?;
+    let right = Location::from(&input);
+    Ok((input, Located::new(Expr::Eisenstein(val), left.span_to(right))))
+}
 
-    let val = match rest {
-        Some(rest) => integral.to_string() + &rest,
-        None       => integral.to_string(),
+/// As with `w`, `j` and `k` on their own are names, so `1j` and `1k` need the number.
+fn quaternion(input: Span) -> IResult<Located<Expr>> {
+    let left = Location::from(&input);
+    let (input, (val, unit)) = pair(real_number, terminated(alt((tag("j"), tag("k"))), not(satisfy(is_identifier_char))))(input)?;
+    let right = Location::from(&input);
+    let expr = if *unit.fragment() == "j" {
+        Expr::Quaternion(ComplexNum(val, 0.))
+    } else {
+        Expr::Quaternion(ComplexNum(0., val))
     };
+    Ok((input, Located::new(expr, left.span_to(right))))
+}
+
+/// `r` on its own is a name too, so `1r` needs the number.
+fn rational(input: Span) -> IResult<Located<Expr>> {
+    let left = Location::from(&input);
+    let (input, val) = terminated(real_number, terminated(tag("r"), not(satisfy(is_identifier_char))))(input)?;
+    let right = Location::from(&input);
+    Ok((input, Located::new(Expr::Rational(val), left.span_to(right))))
+}
 
-    let expr = val.parse::<f64>().map(|val| {
-        Located::new(Expr::Value(ComplexNum(val, 0.)), left.span_to(right))
-    }).map_err(|_| ParseError::error(input, "failed to parse decimal value".to_owned()))?;
+fn boolean(input: Span) -> IResult<Located<Expr>> {
+    let left = Location::from(&input);
+    let (input, val) = alt((
+        map(tag("true"), |_| true),
+        map(tag("false"), |_| false)))(input)?;
+    let right = Location::from(&input);
+    Ok((input, Located::new(Expr::Bool(val), left.span_to(right))))
+}
 
-    Ok((input, expr))
+/// `pi` or `tau`. Numbers are stored as a pair of doubles, so these are the nearest doubles to the
+/// true values, and arithmetic involving them rounds in the same way as any other non-integer.
+fn constant(input: Span) -> IResult<Located<Expr>> {
+    let left = Location::from(&input);
+    let (input, val) = alt((
+        map(terminated(tag("pi"), not(satisfy(is_identifier_char))), |_| std::f64::consts::PI),
+        map(terminated(tag("tau"), not(satisfy(is_identifier_char))), |_| std::f64::consts::TAU)))(input)?;
+    let right = Location::from(&input);
+    Ok((input, Located::new(Expr::Value(ComplexNum(val, 0.)), left.span_to(right))))
 }
 
-fn imag(input: Span) -> IResult<Located<Expr>> {
+fn string(input: Span) -> IResult<Located<Expr>> {
     let left = Location::from(&input);
-    let (input, res) = terminated(recognize(opt(real)), tag("i"))(input)?;
+    let (input, val) = string_literal(input)?;
     let right = Location::from(&input);
-    
-    let expr = if res.is_empty() {
-        Ok(Expr::Value(ComplexNum(0., 1.)))
-    } else {
-        res.parse::<f64>().map(|val| Expr::Value(ComplexNum(0., val)))
-    }.map_err(|_| ParseError::error(input, "failed to parse decimal value".to_owned()))?;
+    Ok((input, Located::new(Expr::Str(val), left.span_to(right))))
+}
 
-    Ok((input, Located::new(expr, left.span_to(right))))
+fn raw_string(input: Span) -> IResult<Located<Expr>> {
+    if input.fragment().starts_with('r') {
+        string(input)
+    } else {
+        Err(ParseError::error(input, "expecting a raw string".to_owned()))
+    }
 }
 
 fn value(input: Span) -> IResult<Located<Expr>> {
-    alt((imag, real))(input)
+    alt((imag, eisenstein, quaternion, rational, real, boolean, constant, string))(input)
 }
 
+/// Identifiers can use letters from any script, so `α` and `θ'` are allowed as well as `alpha`.
 pub fn identifier(input: Span) -> IResult<Span> {
     verify(recognize(pair(
-        alt((alpha1, tag("_"))),
-        many0(alt((alphanumeric1, tag("_"), tag("'"))))
+        satisfy(|c| c.is_alphabetic() || c == '_'),
+        take_while(is_identifier_char)
     )), |id: &Span| !RESERVED_WORDS.contains(id))(input)
 }
 
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '\''
+}
+
+fn located_identifier(input: Span) -> IResult<Located<String>> {
+    let left = Location::from(&input);
+    let (input, id) = identifier(input)?;
+    let right = Location::from(&input);
+    Ok((input, Located::new(id.to_string(), left.span_to(right))))
+}
+
+/// A name that might be qualified by the file that defines it, such as `geometry.area`.
+fn qualified_identifier(input: Span) -> IResult<Located<String>> {
+    let left = Location::from(&input);
+    let (input, id) = recognize(pair(identifier, opt(pair(tag("."), identifier))))(input)?;
+    let right = Location::from(&input);
+    Ok((input, Located::new(id.to_string(), left.span_to(right))))
+}
+
 fn identifier_expr(input: Span) -> IResult<Located<Expr>> {
     let left = Location::from(&input);
     let (input, id) = identifier(input)?;
@@ -111,6 +448,32 @@ fn identifier_expr(input: Span) -> IResult<Located<Expr>> {
     Ok((input, expr))
 }
 
+fn call(input: Span) -> IResult<Located<Expr>> {
+    let left = Location::from(&input);
+    let (input, id) = qualified_identifier(input)?;
+    let (input, _) = ws_tag("(")(input)?;
+    let args_input = input;
+    let (input, all_args) = separated_list0(tag(","), call_arg)(input)?;
+    let (input, _) = ws_tag(")")(input)?;
+    let right = Location::from(&input);
+
+    let mut args = Vec::new();
+    let mut named = Vec::new();
+    for (name, arg) in all_args {
+        match name {
+            Some(name) => named.push((name, arg)),
+            None if named.is_empty() => args.push(arg),
+            None => return Err(ParseError::fail(args_input, "positional arguments must come before named arguments".to_owned())),
+        }
+    }
+    Ok((input, Located::new(Expr::Call(id, args, named), left.span_to(right))))
+}
+
+/// An argument, optionally named as in `b: 4`.
+fn call_arg(input: Span) -> IResult<(Option<Located<String>>, Located<Expr>)> {
+    pair(opt(terminated(ws(located_identifier), ws_tag(":"))), expression)(input)
+}
+
 fn if_else(input: Span) -> IResult<Located<Expr>> {
     let left = Location::from(&input);
     let (input, ((cond, e_if), e_else)) = preceded(tag("if"),
@@ -124,17 +487,135 @@ fn if_else(input: Span) -> IResult<Located<Expr>> {
     Ok((input, expr))
 }
 
-fn conj(input: Span) -> IResult<Located<Expr>> {
+/// `f >>> g >>> h`, which is sugar for `fn(x) h(g(f(x)))`. Each part must name a function, builtin
+/// or closure.
+fn composition(input: Span) -> IResult<Located<Expr>> {
+    let left = Location::from(&input);
+    let (input, first) = located_identifier(input)?;
+    let (input, rest) = many1(preceded(ws_tag(">>>"), ws(located_identifier)))(input)?;
+    let right = Location::from(&input);
+    let pos = left.span_to(right);
+
+    // Users can't write this name, so it can't clash with anything the functions refer to
+    let param = Located::new("$arg".to_owned(), pos);
+    let mut body = Located::new(Expr::Id(param.clone()), pos);
+    for func in std::iter::once(first).chain(rest) {
+        body = Located::new(Expr::Call(func, vec![body], Vec::new()), pos);
+    }
+    Ok((input, Located::new(Expr::Lambda(vec![param], Box::new(body)), pos)))
+}
+
+/// An anonymous function such as `fn(x) x * x`, which captures the variables it uses.
+fn lambda(input: Span) -> IResult<Located<Expr>> {
+    let left = Location::from(&input);
+    let (input, params) = preceded(
+        tag("fn"),
+        delimited(ws_tag("("), separated_list0(ws_tag(","), ws(located_identifier)), ws_tag(")")))(input)?;
+    let (input, body) = expression(input)?;
+    let right = Location::from(&input);
+    Ok((input, Located::new(Expr::Lambda(params, Box::new(body)), left.span_to(right))))
+}
+
+fn lazy(input: Span) -> IResult<Located<Expr>> {
+    let left = Location::from(&input);
+    let (input, expr) = preceded(keyword("lazy"), expression)(input)?;
+    let right = Location::from(&input);
+    Ok((input, Located::new(Expr::Lazy(Box::new(expr)), left.span_to(right))))
+}
+
+fn poly(input: Span) -> IResult<Located<Expr>> {
+    let left = Location::from(&input);
+    let (input, coeffs) = preceded(keyword("poly"), basic_factor)(input)?;
+    let right = Location::from(&input);
+    Ok((input, Located::new(Expr::Poly(Box::new(coeffs)), left.span_to(right))))
+}
+
+/// A literal such as `-2`, `3i` or `true`.
+fn literal_pattern(input: Span) -> IResult<Located<Expr>> {
+    let left = Location::from(&input);
+    let (input, (minus, lit)) = pair(opt(ws_tag("-")), ws(value))(input)?;
+    let right = Location::from(&input);
+    match minus {
+        Some(_) => Ok((input, Located::new(Expr::UnOp(UnOp::Negate, Box::new(lit)), left.span_to(right)))),
+        None    => Ok((input, lit)),
+    }
+}
+
+fn variant_pattern(input: Span) -> IResult<Pattern> {
+    let (input, name) = ws(qualified_identifier)(input)?;
+    let (input, fields) = delimited(ws_tag("("), separated_list0(ws_tag(","), pattern), ws_tag(")"))(input)?;
+    Ok((input, Pattern::Variant(name, fields)))
+}
+
+fn pattern(input: Span) -> IResult<Pattern> {
+    alt((
+        map(literal_pattern, Pattern::Literal),
+        variant_pattern,
+        map(ws(qualified_identifier), |id| {
+            if id.borrow_val() == "_" {
+                Pattern::Wildcard
+            } else {
+                Pattern::Binding(id)
+            }
+        })))(input)
+}
+
+/// `match z { 0 => a, n => n * 2 }`, where each arm is a pattern and an expression.
+fn match_expr(input: Span) -> IResult<Located<Expr>> {
+    let left = Location::from(&input);
+    let (input, scrutinee) = preceded(tag("match"), expression)(input)?;
+    let (input, arms) = delimited(
+        expect_open_brace,
+        terminated(
+            separated_list1(ws_tag(","), separated_pair(pattern, ws_tag("=>"), expression)),
+            opt(ws_tag(","))),
+        expect_close_brace)(input)?;
+    let right = Location::from(&input);
+    Ok((input, Located::new(Expr::Match(Box::new(scrutinee), arms), left.span_to(right))))
+}
+
+#[derive(Debug, Clone)]
+enum Postfix {
+    Conjugate,
+    Factorial,
+    Index(Located<Expr>),
+    Field(usize),
+    Member(Located<String>),
+}
+
+/// A tuple field such as `.0`. Requiring a digit straight after the dot keeps this apart from `..`
+fn field(input: Span) -> IResult<usize> {
+    let (rest, index) = preceded(tag("."), digit1)(input)?;
+    let index = index.parse::<usize>()
+        .map_err(|_| ParseError::error(input, "invalid tuple field".to_owned()))?;
+    Ok((rest, index))
+}
+
+/// A basic factor followed by any number of postfix operators, e.g. `xs[0]^` or `t.1`
+fn postfix(input: Span) -> IResult<Located<Expr>> {
     let (input, init) = basic_factor(input)?;
     let left = init.pos();
 
-    let (input, elems) = many0(tag("^"))(input)?;
+    let (input, ops) = many0(pair(
+        alt((
+            map(tag("^"), |_| Postfix::Conjugate),
+            // Not to be confused with `!=`
+            map(terminated(tag("!"), not(char('='))), |_| Postfix::Factorial),
+            map(delimited(ws_tag("["), expression, tag("]")), Postfix::Index),
+            map(field, Postfix::Field),
+            map(preceded(tag("."), located_identifier), Postfix::Member))),
+        position))(input)?;
 
-    let mut expr = init;
-    for elem in elems {
-        let right = Location::from(&elem);
-        expr = Located::new(Expr::UnOp(UnOp::Conjugate, Box::new(expr)), left.span_to(right));
-    }
+    let expr = ops.into_iter().fold(init, |expr, (op, end)| {
+        let pos = left.span_to(Location::from(&end));
+        match op {
+            Postfix::Conjugate    => Located::new(Expr::UnOp(UnOp::Conjugate, Box::new(expr)), pos),
+            Postfix::Factorial    => Located::new(Expr::UnOp(UnOp::Factorial, Box::new(expr)), pos),
+            Postfix::Index(index) => Located::new(Expr::Index(Box::new((expr, index))), pos),
+            Postfix::Field(index) => Located::new(Expr::Field(Box::new(expr), index), pos),
+            Postfix::Member(name) => Located::new(Expr::Member(Box::new(expr), name), pos),
+        }
+    });
     Ok((input, expr))
 }
 
@@ -146,24 +627,166 @@ fn modulus(input: Span) -> IResult<Located<Expr>> {
     )(input)
 }
 
+fn list(input: Span) -> IResult<Located<Expr>> {
+    let left = Location::from(&input);
+    let (input, elems) = delimited(tag("["), separated_list0(tag(","), expression), tag("]"))(input)?;
+    let right = Location::from(&input);
+    Ok((input, Located::new(Expr::List(elems), left.span_to(right))))
+}
+
+fn for_clause(input: Span) -> IResult<Clause> {
+    let (input, _) = keyword("for")(input)?;
+    let left = Location::from(&input);
+    let (input, id) = identifier(input)?;
+    let id = Located::new(id.to_string(), left.span_to(Location::from(&input)));
+    let (input, _) = ws_tag("in")(input)?;
+    let (input, iter) = expression(input)?;
+    Ok((input, Clause::For(id, iter)))
+}
+
+/// `[a + b*i for a in 0..n for b in 0..n if a != b]`, which is short for nested loops that build
+/// a list.
+fn comprehension(input: Span) -> IResult<Located<Expr>> {
+    let left = Location::from(&input);
+    let (input, _) = tag("[")(input)?;
+    let (input, elem) = expression(input)?;
+    let (input, first) = for_clause(input)?;
+    let (input, rest) = many0(alt((for_clause, map(preceded(keyword("if"), expression), Clause::If))))(input)?;
+    let (input, _) = tag("]")(input)?;
+    let right = Location::from(&input);
+
+    let mut clauses = vec![first];
+    clauses.extend(rest);
+    Ok((input, Located::new(Expr::Comprehension(Box::new(elem), clauses), left.span_to(right))))
+}
+
+/// `point { re: 1, im: 2 }`. There must be at least one field, so that `while x {}` isn't a record.
+fn struct_lit(input: Span) -> IResult<Located<Expr>> {
+    let left = Location::from(&input);
+    let (input, name) = qualified_identifier(input)?;
+    let (input, fields) = delimited(
+        ws_tag("{"),
+        terminated(
+            separated_list1(ws_tag(","), separated_pair(ws(located_identifier), ws_tag(":"), expression)),
+            opt(ws_tag(","))),
+        tag("}"))(input)?;
+    let right = Location::from(&input);
+    Ok((input, Located::new(Expr::Struct(name, fields), left.span_to(right))))
+}
+
+fn matrix_row(input: Span) -> IResult<Located<Vec<Located<Expr>>>> {
+    let left = Location::from(&input);
+    let (input, elems) = delimited(tag("["), separated_list1(tag(","), expression), tag("]"))(input)?;
+    let right = Location::from(&input);
+    Ok((input, Located::new(elems, left.span_to(right))))
+}
+
+/// `matrix [[a, b], [c, d]]`. The rows are checked to have the same length when compiling.
+fn matrix(input: Span) -> IResult<Located<Expr>> {
+    let left = Location::from(&input);
+    let (input, rows) = preceded(keyword("matrix"),
+        delimited(tag("["), separated_list1(tag(","), ws(matrix_row)), tag("]")))(input)?;
+    let right = Location::from(&input);
+    Ok((input, Located::new(Expr::Matrix(rows), left.span_to(right))))
+}
+
+/// `{ 1 + i: 2, 3: 4i }`. Unlike a block, `{}` is allowed, and is an empty dictionary.
+fn dict(input: Span) -> IResult<Located<Expr>> {
+    let left = Location::from(&input);
+    let (input, pairs) = delimited(
+        ws_tag("{"),
+        terminated(
+            separated_list0(ws_tag(","), separated_pair(expression, ws_tag(":"), expression)),
+            opt(ws_tag(","))),
+        ws_tag("}"))(input)?;
+    let right = Location::from(&input);
+    Ok((input, Located::new(Expr::Dict(pairs), left.span_to(right))))
+}
+
+/// `{1, i, -1, -i}`. Like a tuple, a set with one element needs a trailing comma, since `{a}` is a
+/// block.
+fn set(input: Span) -> IResult<Located<Expr>> {
+    let left = Location::from(&input);
+    let (input, (elems, trailing)) = delimited(
+        ws_tag("{"),
+        pair(separated_list1(ws_tag(","), expression), opt(ws_tag(","))),
+        ws_tag("}"))(input)?;
+    let right = Location::from(&input);
+    if elems.len() == 1 && trailing.is_none() {
+        return Err(ParseError::error(input, "a set with one element needs a trailing comma".to_owned()));
+    }
+    Ok((input, Located::new(Expr::Set(elems), left.span_to(right))))
+}
+
+/// `{ let t = a * b; t + 1 }`: some statements, followed by the value of the block.
+fn block(input: Span) -> IResult<Located<Expr>> {
+    let left = Location::from(&input);
+    let (input, (body, result)) = delimited(tag("{"), pair(many0(statement), expression), ws_tag("}"))(input)?;
+    let right = Location::from(&input);
+    Ok((input, Located::new(Expr::Block(body, Box::new(result)), left.span_to(right))))
+}
+
+/// `mod (5) { a * b + 3 }`: a block whose arithmetic gives residues mod 5.
+fn mod_block(input: Span) -> IResult<Located<Expr>> {
+    let left = Location::from(&input);
+    let (input, (modulus, body)) = preceded(keyword("mod"), pair(delimited(ws_tag("("), expression, ws_tag(")")), ws(block)))(input)?;
+    let right = Location::from(&input);
+    Ok((input, Located::new(Expr::Mod(Box::new(modulus), Box::new(body)), left.span_to(right))))
+}
+
+/// `loop { ... }`, which runs until a `break`. Its value is whatever the `break` gives it.
+fn loop_expr(input: Span) -> IResult<Located<Expr>> {
+    let left = Location::from(&input);
+    let (input, label) = loop_label(input)?;
+    let (input, body) = preceded(keyword("loop"), delimited(tag("{"), many0(statement), ws_tag("}")))(input)?;
+    let right = Location::from(&input);
+    Ok((input, Located::new(Expr::Loop(label, body), left.span_to(right))))
+}
+
+/// `(a)` is just `a`, but `(a, b)` and `(a,)` are tuples.
 fn parens(input: Span) -> IResult<Located<Expr>> {
-    delimited(multispace0, 
-        delimited(tag("("), expression, tag(")")), 
-        multispace0)(input)
+    let left = Location::from(&input);
+    let (input, (mut elems, trailing)) = delimited(tag("("),
+        pair(separated_list1(tag(","), expression), opt(tag(","))),
+        tag(")"))(input)?;
+    let right = Location::from(&input);
+
+    if elems.len() == 1 && trailing.is_none() {
+        Ok((input, elems.remove(0)))
+    } else {
+        Ok((input, Located::new(Expr::Tuple(elems), left.span_to(right))))
+    }
 }
 
-/// Basic factor, used to remove left recursion from conjugation i.e. A -> A^
+/// Basic factor, used to remove left recursion from postfix operators e.g. A -> A^
 fn basic_factor(input: Span) -> IResult<Located<Expr>> {
-    alt((ws(identifier_expr),
+    // A raw string would otherwise start with the identifier `r`
+    alt((ws(raw_string),
+         ws(struct_lit),
+         ws(composition),
+         ws(call),
+         ws(identifier_expr),
          ws(if_else),
+         ws(match_expr),
+         ws(loop_expr),
+         ws(mod_block),
+         ws(lambda),
+         ws(lazy),
+         ws(poly),
          ws(value),
          ws(modulus),
-         parens))(input)
+         ws(matrix),
+         ws(comprehension),
+         ws(list),
+         ws(parens),
+         ws(dict),
+         ws(set),
+         ws(block)))(input)
 }
 
-/// Either a basic factor or a conjugated basic factor
+/// A basic factor, possibly with postfix operators
 fn factor(input: Span) -> IResult<Located<Expr>> {
-    alt((ws(conj), basic_factor))(input)
+    ws(postfix)(input)
 }
 
 /// TODO: This can't parse 2**-2. Could maybe be fixed by adding another negation case?
@@ -202,13 +825,14 @@ fn term(input: Span) -> IResult<Located<Expr>> {
     let pos = init.pos();
 
     fold_many0(
-        pair(alt((char('*'), char('/'), char('%'))), fac),
+        pair(alt((tag("*"), tag("/"), tag("%"), tag("~/"))), fac),
         move || init.clone(),
-        move |acc, (op, val): (char, Located<Expr>)| {
-            let op = match op {
-                '*' => BinOp::Times,
-                '/' => BinOp::Divide,
-                _   => BinOp::Remainder,
+        move |acc, (op, val): (Span, Located<Expr>)| {
+            let op = match *op.fragment() {
+                "*" => BinOp::Times,
+                "/" => BinOp::Divide,
+                "%" => BinOp::Remainder,
+                _   => BinOp::Quotient,
             };
             Located::new(Expr::BinOp(op, Box::new((acc, val))), pos)
         })(input)
@@ -230,12 +854,108 @@ fn expr(input: Span) -> IResult<Located<Expr>> {
         })(input)
 }
 
-fn equality(input: Span) -> IResult<Located<Expr>> {
+/// `<<` and `>>` shift real integers.
+fn shift(input: Span) -> IResult<Located<Expr>> {
     let (input, init) = expr(input)?;
     let pos = init.pos();
 
     fold_many0(
-        pair(alt((tag("=="), tag("!="))), expr),
+        pair(alt((tag("<<"), terminated(tag(">>"), not(char('>'))))), expr),
+        move || init.clone(),
+        move |acc, (op, val): (Span, Located<Expr>)| {
+            let op = if *op.fragment() == "<<" { BinOp::ShiftLeft } else { BinOp::ShiftRight };
+            Located::new(Expr::BinOp(op, Box::new((acc, val))), pos)
+        })(input)
+}
+
+/// `a & b`, but not `a && b`.
+fn bit_and(input: Span) -> IResult<Located<Expr>> {
+    let (input, init) = shift(input)?;
+    let pos = init.pos();
+
+    fold_many0(
+        preceded(terminated(tag("&"), not(char('&'))), shift),
+        move || init.clone(),
+        move |acc, val| Located::new(Expr::BinOp(BinOp::BitAnd, Box::new((acc, val))), pos))(input)
+}
+
+fn bit_xor(input: Span) -> IResult<Located<Expr>> {
+    let (input, init) = bit_and(input)?;
+    let pos = init.pos();
+
+    fold_many0(
+        preceded(keyword("xor"), bit_and),
+        move || init.clone(),
+        move |acc, val| Located::new(Expr::BinOp(BinOp::BitXor, Box::new((acc, val))), pos))(input)
+}
+
+/// Bitwise or is spelled `bitor`, since `|` delimits the modulus.
+fn bit_or(input: Span) -> IResult<Located<Expr>> {
+    let (input, init) = bit_xor(input)?;
+    let pos = init.pos();
+
+    fold_many0(
+        preceded(keyword("bitor"), bit_xor),
+        move || init.clone(),
+        move |acc, val| Located::new(Expr::BinOp(BinOp::BitOr, Box::new((acc, val))), pos))(input)
+}
+
+/// Ordering comparisons. Values are ordered by norm, with ties broken lexicographically by real and
+/// then imaginary part (see `Compiler::complex_less`).
+/// Comparisons can be chained, so `0 <= k < n` means `0 <= k && k < n`, except that `k` is only
+/// evaluated once.
+fn comparison(input: Span) -> IResult<Located<Expr>> {
+    let (input, init) = bit_or(input)?;
+    let (input, mut rest) = many0(pair(
+        map(alt((tag("<="), tag(">="), tag("<"), tag(">"))), |op: Span| match *op.fragment() {
+            "<=" => BinOp::LessEquals,
+            ">=" => BinOp::GreaterEquals,
+            "<"  => BinOp::Less,
+            _    => BinOp::Greater,
+        }),
+        bit_or))(input)?;
+    let pos = init.pos();
+    let expr = match rest.len() {
+        0 => init,
+        1 => {
+            let (op, rhs) = rest.remove(0);
+            Located::new(Expr::BinOp(op, Box::new((init, rhs))), pos)
+        },
+        _ => Located::new(Expr::Chain(Box::new(init), rest), pos),
+    };
+    Ok((input, expr))
+}
+
+/// `x in xs`. A range on the right doesn't need parentheses, so `k in 0..n && ok` works.
+fn membership(input: Span) -> IResult<Located<Expr>> {
+    let (input, elem) = comparison(input)?;
+    let pos = elem.pos();
+    let (input, rest) = opt(preceded(keyword("in"), pair(
+        comparison,
+        opt(pair(
+            preceded(tag(".."), comparison),
+            opt(preceded(ws_tag("step"), comparison)))))))(input)?;
+
+    let (start, range) = match rest {
+        Some(rest) => rest,
+        None       => return Ok((input, elem)),
+    };
+    let container = match range {
+        Some((end, step)) => {
+            let start_pos = start.pos();
+            Located::new(Expr::Range(Box::new((start, end, step))), start_pos)
+        },
+        None => start,
+    };
+    Ok((input, Located::new(Expr::BinOp(BinOp::In, Box::new((elem, container))), pos)))
+}
+
+fn equality(input: Span) -> IResult<Located<Expr>> {
+    let (input, init) = membership(input)?;
+    let pos = init.pos();
+
+    fold_many0(
+        pair(alt((tag("=="), tag("!="))), membership),
         move || init.clone(),
         move |acc, (op, val): (Span, Located<Expr>)| {
             let op = match *op {
@@ -246,8 +966,72 @@ fn equality(input: Span) -> IResult<Located<Expr>> {
         })(input)
 }
 
+fn conjunction(input: Span) -> IResult<Located<Expr>> {
+    let (input, init) = equality(input)?;
+    let pos = init.pos();
+
+    fold_many0(
+        preceded(tag("&&"), equality),
+        move || init.clone(),
+        move |acc, val| Located::new(Expr::BinOp(BinOp::And, Box::new((acc, val))), pos))(input)
+}
+
+fn disjunction(input: Span) -> IResult<Located<Expr>> {
+    let (input, init) = conjunction(input)?;
+    let pos = init.pos();
+
+    fold_many0(
+        preceded(tag("||"), conjunction),
+        move || init.clone(),
+        move |acc, val| Located::new(Expr::BinOp(BinOp::Or, Box::new((acc, val))), pos))(input)
+}
+
+/// `a..b step s`, where the step is optional.
+fn range(input: Span) -> IResult<Located<Expr>> {
+    let (input, start) = disjunction(input)?;
+    let pos = start.pos();
+    let (input, rest) = opt(pair(
+        preceded(tag(".."), disjunction),
+        opt(preceded(ws_tag("step"), disjunction))))(input)?;
+
+    match rest {
+        Some((end, step)) => Ok((input, Located::new(Expr::Range(Box::new((start, end, step))), pos))),
+        None              => Ok((input, start)),
+    }
+}
+
+/// `x |> f |> g(y)`, which is sugar for `g(f(x), y)`: the left side becomes the first argument.
+fn pipeline(input: Span) -> IResult<Located<Expr>> {
+    let (mut input, mut acc) = range(input)?;
+    let pos = acc.pos();
+    loop {
+        let (func_input, _) = match ws_tag("|>")(input) {
+            Ok(res) => res,
+            Err(_) => return Ok((input, acc)),
+        };
+        let (rest, func) = ws(range)(func_input)?;
+        acc = match func.val() {
+            Expr::Id(id) => Located::new(Expr::Call(id, vec![acc], Vec::new()), pos),
+            // `x |> geometry.area`, a function from another file
+            Expr::Member(target, name) => match target.val() {
+                Expr::Id(ns) => {
+                    let id = Located::new(format!("{}.{}", ns.val(), name.borrow_val()), name.pos());
+                    Located::new(Expr::Call(id, vec![acc], Vec::new()), pos)
+                },
+                _ => return Err(ParseError::fail(func_input, "expecting a function name or call after `|>`".to_owned())),
+            },
+            Expr::Call(id, mut args, named) => {
+                args.insert(0, acc);
+                Located::new(Expr::Call(id, args, named), pos)
+            },
+            _ => return Err(ParseError::fail(func_input, "expecting a function name or call after `|>`".to_owned())),
+        };
+        input = rest;
+    }
+}
+
 pub fn expression(input: Span) -> IResult<Located<Expr>> {
-    ws(equality)(input)
+    ws(pipeline)(input)
 }
 /* ----------------------------------------------------------------
     STATEMENTS
@@ -274,15 +1058,68 @@ fn expect_close_brace(input: Span) -> IResult<()> {
     }
 }
 
+/// A piece of an interpolated string: either literal text, or an expression to print.
+#[derive(Debug, Clone)]
+pub enum Segment {
+    Text(String),
+    Expr(Located<Expr>),
+}
+
+fn interpolation(input: Span) -> IResult<Located<Expr>> {
+    let (input, _) = char('{')(input)?;
+    match terminated(expression, tag("}"))(input) {
+        Ok(res) => Ok(res),
+        Err(_) => Err(ParseError::fail(input, "expecting an expression followed by `}`".to_owned())),
+    }
+}
+
+/// A string literal in which `{expr}` stands for the value of `expr`, e.g. `"norm of {z} is {|z|}"`.
+/// Braces can be printed literally with `{{` and `}}`.
+fn interpolated_string(input: Span) -> IResult<Vec<Segment>> {
+    delimited(
+        tag("\""),
+        many0(alt((
+            map(interpolated_text, Segment::Text),
+            map(interpolation, Segment::Expr)))),
+        tag("\""))(input)
+}
+
+/// The left side of a destructuring `let`, such as `(q, r)` or `((a, b), _)`.
+#[derive(Debug, Clone)]
+pub enum Binder {
+    Name(Located<String>),
+    Wildcard,
+    Tuple(Located<Vec<Binder>>),
+}
+
+impl Binder {
+    pub fn collect_ids(&self, out: &mut Vec<String>) {
+        match self {
+            Self::Name(id) => out.push(id.borrow_val().clone()),
+            Self::Wildcard => {},
+            Self::Tuple(binders) => {
+                for binder in binders.borrow_val() {
+                    binder.collect_ids(out);
+                }
+            },
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Statement {
-    PrintLit(String),
-    Print(Located<Expr>),
-    PrintLitLn(String),
-    PrintLn(Located<Expr>),
+    PrintLit(Vec<Segment>),
+    /// `print a, b, c;`, which separates the values with spaces.
+    Print(Vec<Located<Expr>>),
+    PrintLitLn(Vec<Segment>),
+    PrintLn(Vec<Located<Expr>>),
     Let(Located<String>, Located<Expr>),
     LetMut(Located<String>, Located<Expr>),
+    LetTuple(Located<Vec<Binder>>, Located<Expr>),
+    LetMutTuple(Located<Vec<Binder>>, Located<Expr>),
     Assign(Located<String>, Located<Expr>),
+    AssignTuple(Located<Vec<Binder>>, Located<Expr>),
+    AssignIndex(Located<String>, Located<Expr>, Located<Expr>),
     AddAssign(Located<String>, Located<Expr>),
     SubAssign(Located<String>, Located<Expr>),
     MulAssign(Located<String>, Located<Expr>),
@@ -290,59 +1127,223 @@ pub enum Statement {
     ModAssign(Located<String>, Located<Expr>),
     If(Located<Expr>, Vec<Located<Statement>>),
     IfElse(Located<Expr>, Vec<Located<Statement>>, Vec<Located<Statement>>),
-    While(Located<Expr>, Vec<Located<Statement>>),
-    Break,
-    Continue,
+    // Loops have an optional label, such as `'outer`, for `break` and `continue` to refer to
+    While(Option<Located<String>>, Located<Expr>, Vec<Located<Statement>>),
+    /// `do { ... } while cond;`, which runs the body before testing the condition.
+    DoWhile(Option<Located<String>>, Vec<Located<Statement>>, Located<Expr>),
+    For(Option<Located<String>>, Located<String>, Located<Expr>, Vec<Located<Statement>>),
+    /// `break;`, or `break value;` to give a `loop` expression its value.
+    Break(Option<Located<String>>, Option<Located<Expr>>),
+    Continue(Option<Located<String>>),
+    /// `assert cond, "message";`, along with the condition as written.
+    Assert(Located<Expr>, String, Option<String>),
+    /// `try { ... } catch e { ... }`, where `e` is optional and holds the error message.
+    Try(Vec<Located<Statement>>, Option<Located<String>>, Vec<Located<Statement>>),
+    Return(Option<Located<Expr>>),
+    /// `yield expr;`, which hands the next value to the `for` loop iterating over the generator.
+    Yield(Located<Expr>),
+    /// `global x, y;`, which lets the function use variables defined at the top level.
+    Global(Vec<Located<String>>),
+    Expr(Located<Expr>),
+}
+
+impl Statement {
+    /// Collects every identifier mentioned in the statement, like `Expr::collect_ids`.
+    pub fn collect_ids(&self, out: &mut Vec<String>) {
+        let all = |body: &[Located<Statement>], out: &mut Vec<String>| {
+            for statement in body {
+                statement.borrow_val().collect_ids(out);
+            }
+        };
+        match self {
+            Self::PrintLit(segments) | Self::PrintLitLn(segments) => {
+                for segment in segments {
+                    if let Segment::Expr(expr) = segment {
+                        expr.borrow_val().collect_ids(out);
+                    }
+                }
+            },
+            Self::Print(exprs) | Self::PrintLn(exprs) => {
+                for expr in exprs {
+                    expr.borrow_val().collect_ids(out);
+                }
+            },
+            Self::Expr(expr) | Self::Assert(expr, ..) | Self::Yield(expr) =>
+                expr.borrow_val().collect_ids(out),
+            Self::Let(id, expr) | Self::LetMut(id, expr) | Self::Assign(id, expr)
+                | Self::AddAssign(id, expr) | Self::SubAssign(id, expr) | Self::MulAssign(id, expr)
+                | Self::DivAssign(id, expr) | Self::ModAssign(id, expr) => {
+                out.push(id.borrow_val().clone());
+                expr.borrow_val().collect_ids(out);
+            },
+            Self::LetTuple(binders, expr) | Self::LetMutTuple(binders, expr) | Self::AssignTuple(binders, expr) => {
+                Binder::Tuple(binders.clone()).collect_ids(out);
+                expr.borrow_val().collect_ids(out);
+            },
+            Self::AssignIndex(id, index, expr) => {
+                out.push(id.borrow_val().clone());
+                index.borrow_val().collect_ids(out);
+                expr.borrow_val().collect_ids(out);
+            },
+            Self::If(cond, body) | Self::While(_, cond, body) | Self::DoWhile(_, body, cond) => {
+                cond.borrow_val().collect_ids(out);
+                all(body, out);
+            },
+            Self::IfElse(cond, body_if, body_else) => {
+                cond.borrow_val().collect_ids(out);
+                all(body_if, out);
+                all(body_else, out);
+            },
+            Self::Try(body, id, handler) => {
+                all(body, out);
+                if let Some(id) = id {
+                    out.push(id.borrow_val().clone());
+                }
+                all(handler, out);
+            },
+            Self::For(_, id, iter, body) => {
+                out.push(id.borrow_val().clone());
+                iter.borrow_val().collect_ids(out);
+                all(body, out);
+            },
+            Self::Return(expr) | Self::Break(_, expr) => if let Some(expr) = expr {
+                expr.borrow_val().collect_ids(out);
+            },
+            Self::Continue(_) => {},
+            Self::Global(ids) => out.extend(ids.iter().map(|id| id.borrow_val().clone())),
+        }
+    }
+
+    /// Whether this contains `return` with a value.
+    fn returns_value(&self) -> bool {
+        let any = |body: &[Located<Statement>]| body.iter().any(|statement| statement.borrow_val().returns_value());
+        match self {
+            Self::Return(expr) => expr.is_some(),
+            Self::If(_, body) | Self::While(_, _, body) | Self::DoWhile(_, body, _) | Self::For(_, _, _, body) => any(body),
+            Self::IfElse(_, body_if, body_else) | Self::Try(body_if, _, body_else) => any(body_if) || any(body_else),
+            _ => false,
+        }
+    }
+
+    /// Whether this contains `yield`.
+    fn yields(&self) -> bool {
+        let any = |body: &[Located<Statement>]| body.iter().any(|statement| statement.borrow_val().yields());
+        match self {
+            Self::Yield(_) => true,
+            // A `loop` written as a statement
+            Self::Expr(expr) => matches!(expr.borrow_val(), Expr::Loop(_, body) if any(body)),
+            Self::If(_, body) | Self::While(_, _, body) | Self::DoWhile(_, body, _) | Self::For(_, _, _, body) => any(body),
+            Self::IfElse(_, body_if, body_else) | Self::Try(body_if, _, body_else) => any(body_if) || any(body_else),
+            _ => false,
+        }
+    }
 }
 
 fn parse_print(input: Span) -> IResult<Statement> {
     map(
-        delimited(ws_tag("print"), expression, ws_tag(";")),
+        delimited(ws_tag("print"), separated_list1(ws_tag(","), expression), ws_tag(";")),
         Statement::Print
     )(input)
 }
 
 fn parse_print_lit(input: Span) -> IResult<Statement> {
     map(
-        delimited(ws_tag("print"), string_literal, ws_tag(";")),
+        delimited(ws_tag("print"), interpolated_string, ws_tag(";")),
         Statement::PrintLit
     )(input)
 }
 
 fn parse_print_ln(input: Span) -> IResult<Statement> {
     map(
-        delimited(ws_tag("println"), expression, expect_semicolon),
+        delimited(ws_tag("println"), separated_list1(ws_tag(","), expression), expect_semicolon),
         Statement::PrintLn
     )(input)
 }
 
 fn parse_print_lit_ln(input: Span) -> IResult<Statement> {
     map(
-        delimited(ws_tag("println"), string_literal, ws_tag(";")),
+        delimited(ws_tag("println"), interpolated_string, ws_tag(";")),
         Statement::PrintLitLn
     )(input)
 }
 
+fn parse_assert(input: Span) -> IResult<Statement> {
+    map(
+        delimited(
+            keyword("assert"),
+            pair(consumed(expression), opt(preceded(ws_tag(","), ws(string_literal)))),
+            expect_semicolon),
+        |((text, cond), message)| Statement::Assert(cond, text.fragment().trim().to_owned(), message)
+    )(input)
+}
+
 fn parse_let(input: Span) -> IResult<Statement> {
     let (input, _) = ws_tag("let")(input)?;
-    let left = Location::from(&input);
     let (input, (id, expr)) = terminated(
-        separated_pair(identifier, ws_tag("="), expression),
+        separated_pair(annotated_identifier, ws_tag("="), expression),
         ws_tag(";"))(input)?;
-    let statement = Statement::Let(Located::new(id.to_string(), left.span_to(Location::from(&id))), expr);
+    let statement = Statement::Let(id.0, annotate(expr, id.1));
     Ok((input, statement))
 }
 
 fn parse_let_mut(input: Span) -> IResult<Statement> {
     let (input, _) = preceded(ws_tag("let"), ws_tag("mut"))(input)?;
-    let left = Location::from(&input);
     let (input, (id, expr)) = terminated(
-        separated_pair(identifier, ws_tag("="), expression),
+        separated_pair(annotated_identifier, ws_tag("="), expression),
         ws_tag(";"))(input)?;
-    let statement = Statement::LetMut(Located::new(id.to_string(), left.span_to(Location::from(&id))), expr);
+    let statement = Statement::LetMut(id.0, annotate(expr, id.1));
     Ok((input, statement))
 }
 
+/// `x` or `x: int`.
+fn annotated_identifier(input: Span) -> IResult<(Located<String>, Option<Located<Annotation>>)> {
+    pair(located_identifier, opt(preceded(ws_tag(":"), ws(located_annotation))))(input)
+}
+
+/// Wraps the expression so that its value is checked against the annotation, if there is one.
+pub fn annotate(expr: Located<Expr>, annotation: Option<Located<Annotation>>) -> Located<Expr> {
+    match annotation {
+        Some(annotation) => {
+            let pos = expr.pos();
+            Located::new(Expr::Annotated(Box::new(expr), annotation), pos)
+        },
+        None => expr,
+    }
+}
+
+fn binder(input: Span) -> IResult<Binder> {
+    alt((
+        map(binder_tuple, Binder::Tuple),
+        map(ws(located_identifier), |id| if id.borrow_val() == "_" { Binder::Wildcard } else { Binder::Name(id) })
+    ))(input)
+}
+
+/// `(a, b)`, or `(a,)` for a tuple with one field.
+fn binder_tuple(input: Span) -> IResult<Located<Vec<Binder>>> {
+    let (input, _) = skip(input)?;
+    let left = Location::from(&input);
+    let (input, binders) = delimited(tag("("), terminated(separated_list1(tag(","), binder), opt(tag(","))), tag(")"))(input)?;
+    let right = Location::from(&input);
+    let (input, _) = skip(input)?;
+    Ok((input, Located::new(binders, left.span_to(right))))
+}
+
+fn parse_let_tuple(input: Span) -> IResult<Statement> {
+    let (input, _) = ws_tag("let")(input)?;
+    let (input, (binders, expr)) = terminated(
+        separated_pair(binder_tuple, ws_tag("="), expression),
+        expect_semicolon)(input)?;
+    Ok((input, Statement::LetTuple(binders, expr)))
+}
+
+fn parse_let_mut_tuple(input: Span) -> IResult<Statement> {
+    let (input, _) = preceded(ws_tag("let"), ws_tag("mut"))(input)?;
+    let (input, (binders, expr)) = terminated(
+        separated_pair(binder_tuple, ws_tag("="), expression),
+        expect_semicolon)(input)?;
+    Ok((input, Statement::LetMutTuple(binders, expr)))
+}
+
 fn parse_assign(input: Span) -> IResult<Statement> {
     map(
         terminated(separated_pair(identifier, ws_tag("="), expression), expect_semicolon),
@@ -350,6 +1351,47 @@ fn parse_assign(input: Span) -> IResult<Statement> {
     )(input)
 }
 
+fn parse_assign_tuple(input: Span) -> IResult<Statement> {
+    map(
+        terminated(separated_pair(binder_tuple, ws_tag("="), expression), expect_semicolon),
+        |(binders, expr)| Statement::AssignTuple(binders, expr)
+    )(input)
+}
+
+fn parse_assign_index(input: Span) -> IResult<Statement> {
+    map(
+        terminated(
+            separated_pair(
+                pair(identifier, delimited(ws_tag("["), expression, ws_tag("]"))),
+                ws_tag("="),
+                expression),
+            expect_semicolon),
+        |((id, index), expr)| Statement::AssignIndex(Located::new(id.to_string(), Location::from(&id)), index, expr)
+    )(input)
+}
+
+/// `xs[k] += v` and friends, which desugar to `xs[k] = xs[k] + v`. Note that this means the index
+/// is evaluated twice.
+fn parse_compound_assign_index(input: Span) -> IResult<Statement> {
+    let left = Location::from(&input);
+    let (input, id) = identifier(input)?;
+    let id = Located::new(id.to_string(), left.span_to(Location::from(&input)));
+    let (input, index) = delimited(ws_tag("["), expression, ws_tag("]"))(input)?;
+    let target_pos = left.span_to(Location::from(&input));
+    let (input, op) = alt((
+        map(ws_tag("+="), |_| BinOp::Plus),
+        map(ws_tag("-="), |_| BinOp::Minus),
+        map(ws_tag("*="), |_| BinOp::Times),
+        map(ws_tag("/="), |_| BinOp::Divide),
+        map(ws_tag("%="), |_| BinOp::Remainder)))(input)?;
+    let (input, rhs) = terminated(expression, expect_semicolon)(input)?;
+
+    let target = Located::new(Expr::Index(Box::new((Located::new(Expr::Id(id.clone()), id.pos()), index.clone()))), target_pos);
+    let pos = target_pos.span_to(rhs.pos());
+    let value = Located::new(Expr::BinOp(op, Box::new((target, rhs))), pos);
+    Ok((input, Statement::AssignIndex(id, index, value)))
+}
+
 fn parse_add_assign(input: Span) -> IResult<Statement> {
     map(
         terminated(separated_pair(identifier, ws_tag("+="), expression), expect_semicolon),
@@ -385,64 +1427,154 @@ fn parse_mod_assign(input: Span) -> IResult<Statement> {
     )(input)
 }
 
+/// The condition of an `if` statement. If it's followed by `then`, it's an `if` expression instead.
+fn if_cond(input: Span) -> IResult<Located<Expr>> {
+    preceded(ws_tag("if"), terminated(expression, not(ws_tag("then"))))(input)
+}
+
 fn parse_if(input: Span) -> IResult<Statement> {
     map(
-        terminated(separated_pair(preceded(ws_tag("if"), expression), expect_open_brace,
+        terminated(separated_pair(if_cond, expect_open_brace,
         many0(statement)),
         expect_close_brace),
         |(cond, body)| Statement::If(cond, body)
     )(input)
 }
 
+/// `else if ...`, which is sugar for an `else` block holding just the nested `if` statement.
+fn else_if(input: Span) -> IResult<Vec<Located<Statement>>> {
+    let left = Location::from(&input);
+    let (input, nested) = alt((parse_if_else, parse_if))(input)?;
+    let right = Location::from(&input);
+    Ok((input, vec![Located::new(nested, left.span_to(right))]))
+}
+
 fn parse_if_else(input: Span) -> IResult<Statement> {
     map(
         separated_pair(
-            terminated(separated_pair(preceded(ws_tag("if"), expression), expect_open_brace,
+            terminated(separated_pair(if_cond, expect_open_brace,
             many0(statement)),
             expect_close_brace),
-                ws_tag("else"),
-                delimited(ws_tag("{"), many0(statement), ws_tag("}"))),
+                keyword("else"),
+                alt((else_if, delimited(ws_tag("{"), many0(statement), ws_tag("}"))))),
         |((cond, body_if), body_else)| Statement::IfElse(cond, body_if, body_else)
     )(input)
 }
 
+/// A label such as `'outer`, without the quote.
+fn label(input: Span) -> IResult<Located<String>> {
+    preceded(tag("'"), located_identifier)(input)
+}
+
+/// The optional `'outer:` in front of a loop.
+fn loop_label(input: Span) -> IResult<Option<Located<String>>> {
+    opt(terminated(ws(label), tag(":")))(input)
+}
+
 fn parse_while(input: Span) -> IResult<Statement> {
     map(
-        terminated(separated_pair(preceded(ws_tag("while"), expression), expect_open_brace,
+        pair(loop_label, terminated(separated_pair(preceded(ws_tag("while"), expression), expect_open_brace,
         many0(statement)),
-        expect_close_brace),
-        |(cond, body)| Statement::While(cond, body)
+        expect_close_brace)),
+        |(label, (cond, body))| Statement::While(label, cond, body)
+    )(input)
+}
+
+fn parse_do_while(input: Span) -> IResult<Statement> {
+    map(
+        tuple((
+            loop_label,
+            delimited(preceded(keyword("do"), expect_open_brace), many0(statement), expect_close_brace),
+            delimited(keyword("while"), expression, expect_semicolon))),
+        |(label, body, cond)| Statement::DoWhile(label, body, cond)
+    )(input)
+}
+
+fn parse_for(input: Span) -> IResult<Statement> {
+    let (input, label) = loop_label(input)?;
+    let (input, _) = ws_tag("for")(input)?;
+    let left = Location::from(&input);
+    let (input, id) = identifier(input)?;
+    let id = Located::new(id.to_string(), left.span_to(Location::from(&input)));
+    let (input, _) = ws_tag("in")(input)?;
+    let (input, iter) = expression(input)?;
+    let (input, body) = delimited(expect_open_brace, many0(statement), expect_close_brace)(input)?;
+    Ok((input, Statement::For(label, id, iter, body)))
+}
+
+fn parse_try(input: Span) -> IResult<Statement> {
+    map(
+        tuple((
+            delimited(preceded(keyword("try"), expect_open_brace), many0(statement), expect_close_brace),
+            preceded(keyword("catch"), opt(ws(located_identifier))),
+            delimited(expect_open_brace, many0(statement), expect_close_brace))),
+        |(body, id, handler)| Statement::Try(body, id, handler)
     )(input)
 }
 
+/// A keyword, as long as it isn't the start of a longer identifier like `breakpoint`.
+fn keyword<'a>(kw: &'static str) -> impl FnMut(Span<'a>) -> IResult<Span<'a>> {
+    delimited(
+        skip,
+        terminated(tag(kw), not(satisfy(is_identifier_char))),
+        skip)
+}
+
 fn parse_keyword(input: Span) -> IResult<Statement> {
     alt((
-        map(terminated(ws_tag("break"), expect_semicolon), |_| Statement::Break),
-        map(terminated(ws_tag("continue"), expect_semicolon), |_| Statement::Continue),
+        map(delimited(keyword("break"), pair(opt(ws(label)), opt(expression)), expect_semicolon),
+            |(label, value)| Statement::Break(label, value)),
+        map(delimited(keyword("continue"), opt(ws(label)), expect_semicolon), Statement::Continue),
+        map(delimited(keyword("return"), opt(expression), expect_semicolon), Statement::Return),
+        map(delimited(keyword("yield"), expression, expect_semicolon), Statement::Yield),
+        map(delimited(keyword("global"), separated_list1(ws_tag(","), ws(qualified_identifier)), expect_semicolon), Statement::Global),
     ))(input)
 }
 
+/// An expression evaluated for its side effects, e.g. `push(xs, 1);`. A missing semicolon isn't
+/// fatal, since the expression might be the result of a function.
+fn parse_expr_statement(input: Span) -> IResult<Statement> {
+    map(terminated(expression, ws_tag(";")), Statement::Expr)(input)
+}
+
+/// A `loop` used as a statement, which doesn't need a semicolon.
+fn parse_loop(input: Span) -> IResult<Statement> {
+    map(ws(loop_expr), Statement::Expr)(input)
+}
+
 fn statement(input: Span) -> IResult<Located<Statement>> {
     // Throw away comments
     let (input, _) = opt(preceded(tag("--"), take_until("\n")))(input)?;
     let left = Location::from(&input);
 
+    // Grouped, since `alt` only takes so many parsers at once
     let (input, statement) = alt((parse_keyword,
+        parse_assert,
         parse_print_lit_ln,
         parse_print_ln,
         parse_print_lit,
         parse_print,
-        parse_let_mut,
-        parse_let,
-        parse_while,
-        parse_if_else,
-        parse_if,
-        parse_assign,
-        parse_add_assign,
-        parse_sub_assign,
-        parse_mul_assign,
-        parse_div_assign,
-        parse_mod_assign))(input)?;
+        alt((parse_let_mut,
+            parse_let,
+            parse_let_mut_tuple,
+            parse_let_tuple)),
+        alt((parse_while,
+            parse_do_while,
+            parse_for,
+            parse_if_else,
+            parse_if,
+            parse_try)),
+        alt((parse_assign,
+            parse_assign_tuple,
+            parse_assign_index,
+            parse_compound_assign_index,
+            parse_add_assign,
+            parse_sub_assign,
+            parse_mul_assign,
+            parse_div_assign,
+            parse_mod_assign)),
+        parse_expr_statement,
+        parse_loop))(input)?;
     let right = Location::from(&input);
 
     Ok((input, Located::new(statement, left.span_to(right))))
@@ -452,20 +1584,363 @@ fn statement(input: Span) -> IResult<Located<Statement>> {
     FUNCTIONS
    ---------------------------------------------------------------- */
 
+/// A function, such as `fn dist(a, b) { |a - b| }`. The body may end with an expression without
+/// a semicolon, which is the result of the function. Trailing parameters can have defaults, as in
+/// `fn div(a, b = 1)`. The last parameter can instead collect any remaining arguments into a list,
+/// as in `fn max(x, xs...)`; it defaults to the empty list.
 #[derive(Debug)]
 pub struct Func {
-    pub name: String,
-    pub body: Vec<Located<Statement>>
+    pub name: Located<String>,
+    /// A string literal written just before `fn`, which `help` shows
+    pub doc: Option<String>,
+    /// Whether it's a `memo fn`, which remembers its results
+    pub memo: bool,
+    pub params: Vec<Located<String>>,
+    pub annotations: Vec<Option<Located<Annotation>>>,
+    pub defaults: Vec<Option<Located<Expr>>>,
+    pub variadic: bool,
+    /// What the annotation after `->` says the function returns, if there is one
+    pub returns: Option<Located<Annotation>>,
+    pub body: Vec<Located<Statement>>,
+    pub result: Option<Located<Expr>>,
 }
 
-pub fn parse_all(input: Span) -> IResult<Func> {
+fn parse_func(input: Span) -> IResult<Func> {
+    let (input, doc) = opt(ws(string_literal))(input)?;
+    let (input, memo) = opt(keyword("memo"))(input)?;
     let (input, _) = ws_tag("fn")(input)?;
-    let (input, name) = map(identifier, |s| *s)(input)?;
-    let (input, func) = map(
-        preceded(preceded(ws_tag("()"), expect_open_brace),
-            many0(statement)),
-        move |body| Func { name: name.to_owned(), body }
-    )(input)?;
+    let (input, name) = located_identifier(input)?;
+    let (input, _) = ws_tag("(")(input)?;
+    let params_input = input;
+    let (input, all_params) = separated_list0(ws_tag(","), param)(input)?;
+    let (input, _) = ws_tag(")")(input)?;
+    let variadic = all_params.last().map_or(false, |param| param.rest);
+    if all_params.iter().rev().skip(1).any(|param| param.rest) {
+        return Err(ParseError::fail(params_input, "a rest parameter must come last".to_owned()));
+    }
+    let mut params = Vec::new();
+    let mut annotations = Vec::new();
+    let mut defaults = Vec::new();
+    for param in all_params {
+        params.push(param.name);
+        annotations.push(param.annotation);
+        defaults.push(param.default);
+    }
+    if defaults.windows(2).any(|pair| pair[0].is_some() && pair[1].is_none()) {
+        return Err(ParseError::fail(params_input, "parameters with defaults must come last".to_owned()));
+    }
+    let (input, returns) = opt(preceded(ws_tag("->"), ws(located_annotation)))(input)?;
+    let (input, _) = expect_open_brace(input)?;
+    let (input, body) = many0(statement)(input)?;
+    let (input, result) = opt(expression)(input)?;
     let (input, _) = expect_close_brace(input)?;
-    Ok((input, func))
+    Ok((input, Func { name, doc, memo: memo.is_some(), params, annotations, defaults, variadic, returns, body, result }))
+}
+
+struct Param {
+    name: Located<String>,
+    annotation: Option<Located<Annotation>>,
+    default: Option<Located<Expr>>,
+    rest: bool,
+}
+
+/// A parameter, maybe with an annotation, and then either a default or `...` for a rest parameter.
+/// The annotation on a rest parameter describes the list of extra arguments.
+fn param(input: Span) -> IResult<Param> {
+    let (input, (name, annotation)) = ws(annotated_identifier)(input)?;
+    if let Ok((input, _)) = ws_tag("...")(input) {
+        let pos = name.pos();
+        return Ok((input, Param { name, annotation, default: Some(Located::new(Expr::List(Vec::new()), pos)), rest: true }));
+    }
+    let (input, default) = opt(preceded(ws_tag("="), expression))(input)?;
+    Ok((input, Param { name, annotation, default, rest: false }))
+}
+
+impl Func {
+    /// How the parameters and result were declared, such as `(a, b = ..., rest...) -> int`. Defaults
+    /// aren't shown, and parameters holding what a closure captured are skipped.
+    pub fn signature(&self, captured: usize) -> String {
+        let params = self.params.iter().zip(&self.annotations).zip(&self.defaults).enumerate().skip(captured)
+            .map(|(k, ((param, annotation), default))| {
+                let mut param = param.borrow_val().clone();
+                if let Some(annotation) = annotation {
+                    param += &format!(": {}", annotation.borrow_val());
+                }
+                if self.variadic && k + 1 == self.params.len() {
+                    param += "...";
+                } else if default.is_some() {
+                    param += " = ...";
+                }
+                param
+            })
+            .collect::<Vec<_>>();
+        let returns = match &self.returns {
+            Some(annotation) => format!(" -> {}", annotation.borrow_val()),
+            None => String::new(),
+        };
+        format!("({}){}", params.join(", "), returns)
+    }
+
+    /// The number of parameters without defaults, which every call has to supply.
+    pub fn required_params(&self) -> usize {
+        self.defaults.iter().take_while(|default| default.is_none()).count()
+    }
+
+    /// Whether the function produces a value, either as its result or with `return`.
+    pub fn returns_value(&self) -> bool {
+        self.result.is_some() || self.body.iter().any(|statement| statement.borrow_val().returns_value())
+    }
+
+    /// Whether it's a generator, which has `yield` somewhere in its body.
+    pub fn is_generator(&self) -> bool {
+        self.body.iter().any(|statement| statement.borrow_val().yields())
+    }
+}
+
+/* ----------------------------------------------------------------
+    TYPES
+   ---------------------------------------------------------------- */
+
+/// A type written in the source. Every number is complex, so `int` and `real` are only checked when
+/// the program runs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Annotation {
+    Int,
+    Real,
+    Complex,
+    Bool,
+    Str,
+    Range,
+    Matrix,
+    List(Box<Annotation>),
+    Tuple(Vec<Annotation>),
+    Option(Box<Annotation>),
+    // A struct or an enum
+    Named(String),
+}
+
+impl Annotation {
+    /// Whether a value of type `ty` could fit, before checking anything at runtime.
+    pub fn admits(&self, ty: &Type) -> bool {
+        match (self, ty) {
+            (Self::Int | Self::Real | Self::Complex, Type::Complex)
+                | (Self::Bool, Type::Bool)
+                | (Self::Str, Type::Str)
+                | (Self::Range, Type::Range)
+                | (Self::Matrix, Type::Matrix) => true,
+            (Self::List(elem), Type::List(ty)) | (Self::Option(elem), Type::Option(ty)) => elem.admits(ty),
+            (Self::Tuple(elems), Type::Tuple(tys)) =>
+                elems.len() == tys.len() && elems.iter().zip(tys).all(|(elem, ty)| elem.admits(ty)),
+            (Self::Named(name), Type::Struct(ty, _) | Type::Enum(ty)) => name == ty,
+            _ => false,
+        }
+    }
+
+    /// Whether some number inside has to be checked at runtime.
+    pub fn needs_check(&self) -> bool {
+        match self {
+            Self::Int | Self::Real => true,
+            Self::List(elem) | Self::Option(elem) => elem.needs_check(),
+            Self::Tuple(elems) => elems.iter().any(Self::needs_check),
+            _ => false,
+        }
+    }
+
+    /// The type of anything that fits, if that's known without looking up any names.
+    pub fn known_type(&self) -> Option<Type> {
+        match self {
+            Self::Int | Self::Real | Self::Complex => Some(Type::Complex),
+            Self::Bool   => Some(Type::Bool),
+            Self::Str    => Some(Type::Str),
+            Self::Range  => Some(Type::Range),
+            Self::Matrix => Some(Type::Matrix),
+            Self::List(elem) => elem.known_type().map(|elem| Type::List(Box::new(elem))),
+            Self::Tuple(elems) => elems.iter().map(Self::known_type).collect::<Option<Vec<_>>>().map(Type::Tuple),
+            Self::Option(elem) => elem.known_type().map(|elem| Type::Option(Box::new(elem))),
+            Self::Named(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Annotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int     => write!(f, "int"),
+            Self::Real    => write!(f, "real"),
+            Self::Complex => write!(f, "complex"),
+            Self::Bool    => write!(f, "bool"),
+            Self::Str     => write!(f, "str"),
+            Self::Range   => write!(f, "range"),
+            Self::Matrix  => write!(f, "matrix"),
+            Self::List(elem) => write!(f, "[{}]", elem),
+            Self::Tuple(elems) => {
+                write!(f, "(")?;
+                for (k, elem) in elems.iter().enumerate() {
+                    if k > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", elem)?;
+                }
+                if elems.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            },
+            Self::Option(elem) => write!(f, "option({})", elem),
+            Self::Named(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Annotations are written the way types are printed: `int`, `[str]`, `(real, bool)`, `option(int)`.
+fn annotation(input: Span) -> IResult<Annotation> {
+    alt((
+        map(delimited(ws_tag("["), annotation, ws_tag("]")), |elem| Annotation::List(Box::new(elem))),
+        map(preceded(keyword("option"), delimited(ws_tag("("), annotation, ws_tag(")"))), |elem| Annotation::Option(Box::new(elem))),
+        map(delimited(ws_tag("("), separated_list0(ws_tag(","), annotation), pair(opt(ws_tag(",")), ws_tag(")"))),
+            Annotation::Tuple),
+        map(keyword("matrix"), |_| Annotation::Matrix),
+        map(ws(identifier), |name: Span| match *name.fragment() {
+            "int"     => Annotation::Int,
+            "real"    => Annotation::Real,
+            "complex" => Annotation::Complex,
+            "bool"    => Annotation::Bool,
+            "str"     => Annotation::Str,
+            "range"   => Annotation::Range,
+            name      => Annotation::Named(name.to_owned()),
+        }),
+    ))(input)
+}
+
+fn located_annotation(input: Span) -> IResult<Located<Annotation>> {
+    let left = Location::from(&input);
+    let (input, annotation) = annotation(input)?;
+    let right = Location::from(&input);
+    Ok((input, Located::new(annotation, left.span_to(right))))
+}
+
+/// A record type, such as `struct point { re, im }`. Fields don't have declared types: a record's
+/// type also depends on the types of the values it was built from.
+#[derive(Debug)]
+pub struct Struct {
+    pub name: Located<String>,
+    pub fields: Vec<Located<String>>,
+}
+
+fn parse_struct(input: Span) -> IResult<Struct> {
+    let (input, _) = keyword("struct")(input)?;
+    let (input, name) = located_identifier(input)?;
+    let (input, fields) = delimited(
+        ws_tag("{"),
+        terminated(separated_list1(ws_tag(","), ws(located_identifier)), opt(ws_tag(","))),
+        expect_close_brace)(input)?;
+    Ok((input, Struct { name, fields }))
+}
+
+/// A sum type, such as `enum result { Ok(value), Err(code) }`. The fields of a variant hold complex
+/// numbers, and are only named for the reader's benefit.
+#[derive(Debug)]
+pub struct Enum {
+    pub name: Located<String>,
+    pub variants: Vec<(Located<String>, Vec<Located<String>>)>,
+}
+
+impl Enum {
+    /// How many numbers are needed to store any of the variants.
+    pub fn payload_len(&self) -> usize {
+        self.variants.iter().map(|(_, fields)| fields.len()).max().unwrap_or(0)
+    }
+}
+
+fn parse_variant(input: Span) -> IResult<(Located<String>, Vec<Located<String>>)> {
+    let (input, name) = located_identifier(input)?;
+    let (input, fields) = opt(delimited(
+        ws_tag("("),
+        separated_list1(ws_tag(","), ws(located_identifier)),
+        ws_tag(")")))(input)?;
+    Ok((input, (name, fields.unwrap_or_default())))
+}
+
+fn parse_enum(input: Span) -> IResult<Enum> {
+    let (input, _) = keyword("enum")(input)?;
+    let (input, name) = located_identifier(input)?;
+    let (input, variants) = delimited(
+        ws_tag("{"),
+        terminated(separated_list1(ws_tag(","), ws(parse_variant)), opt(ws_tag(","))),
+        expect_close_brace)(input)?;
+    Ok((input, Enum { name, variants }))
+}
+
+/// `import "utils.ccc"`, which brings everything defined in that file into scope as `utils.name`.
+fn parse_import(input: Span) -> IResult<Located<String>> {
+    let (input, _) = keyword("import")(input)?;
+    let left = Location::from(&input);
+    let (input, path) = string_literal(input)?;
+    let right = Location::from(&input);
+    let (input, _) = terminated(skip, opt(ws_tag(";")))(input)?;
+    Ok((input, Located::new(path, left.span_to(right))))
+}
+
+/// A variable defined at the top level, such as `let mut total = 0;`. Functions can only see it
+/// after declaring `global total;`.
+#[derive(Debug)]
+pub struct Global {
+    pub name: Located<String>,
+    pub value: Located<Expr>,
+    pub mutable: bool,
+}
+
+fn parse_global(input: Span) -> IResult<Global> {
+    let (input, statement) = alt((parse_let_mut, parse_let))(input)?;
+    match statement {
+        Statement::LetMut(name, value) => Ok((input, Global { name, value, mutable: true })),
+        Statement::Let(name, value) => Ok((input, Global { name, value, mutable: false })),
+        _ => Err(ParseError::error(input, "expecting `let`".to_owned())),
+    }
+}
+
+/// Anything that can appear at the top level of a program.
+#[derive(Debug)]
+pub enum Item {
+    Func(Func),
+    Struct(Struct),
+    Enum(Enum),
+    Global(Global),
+    // The path of the file, relative to the file importing it
+    Import(Located<String>),
+}
+
+impl Item {
+    /// Puts the name of what this defines, and of any enum variants, in the namespace `ns`, which
+    /// is how the driver keeps imported files apart.
+    pub fn qualify(self, ns: &str) -> Self {
+        let qualify = |name: Located<String>| {
+            let pos = name.pos();
+            Located::new(format!("{}.{}", ns, name.val()), pos)
+        };
+        match self {
+            Item::Func(func) => Item::Func(Func { name: qualify(func.name), ..func }),
+            Item::Struct(def) => Item::Struct(Struct { name: qualify(def.name), ..def }),
+            Item::Enum(def) => Item::Enum(Enum {
+                name: qualify(def.name),
+                variants: def.variants.into_iter().map(|(name, fields)| (qualify(name), fields)).collect(),
+            }),
+            Item::Global(global) => Item::Global(Global { name: qualify(global.name), ..global }),
+            Item::Import(path) => Item::Import(path),
+        }
+    }
+}
+
+pub fn parse_all(input: Span) -> IResult<Vec<Item>> {
+    let (input, items) = many1(alt((
+        map(parse_func, Item::Func),
+        map(parse_struct, Item::Struct),
+        map(parse_enum, Item::Enum),
+        map(parse_import, Item::Import),
+        map(parse_global, Item::Global))))(input)?;
+    if input.fragment().trim().is_empty() {
+        Ok((input, items))
+    } else {
+        Err(ParseError::fail(input, "expecting `fn`, `struct`, `enum`, `import` or `let`".to_owned()))
+    }
 }
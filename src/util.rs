@@ -1,12 +1,12 @@
 /// Taken from https://raw.githubusercontent.com/Geal/nom/master/examples/string.rs
 
-use nom::{branch::alt, bytes::complete::tag, character::complete::multispace0};
+use nom::{branch::alt, bytes::complete::{tag, take_till, take_while}, character::complete::anychar};
 use nom::bytes::streaming::{is_not, take_while_m_n};
 use nom::character::streaming::{char, multispace1};
 use nom::combinator::{map, map_opt, value, verify};
-use nom::multi::fold_many0;
+use nom::multi::{fold_many0, fold_many1, many0};
 use nom::sequence::{delimited, preceded};
-use nom::IResult;
+use nom::{IResult, Slice};
 
 use crate::{Span, error::ParseError};
 
@@ -94,9 +94,61 @@ fn parse_string(input: &str) -> IResult<&str, String> {
     delimited(char('"'), build_string, char('"'))(input)
 }
 
+/// Parse a raw string such as `r"C:\path"`, in which backslashes are just backslashes. Putting
+/// `#`s around it, as in `r#"say "hi""#`, lets it contain quotes.
+fn parse_raw_string(input: &str) -> IResult<&str, String> {
+    let (input, _) = char('r')(input)?;
+    let (input, hashes) = take_while(|c| c == '#')(input)?;
+    let (input, _) = char('"')(input)?;
+    let close = format!("\"{}", hashes);
+    match input.find(&close) {
+        Some(end) => Ok((&input[end + close.len()..], input[..end].to_owned())),
+        None => Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::TakeUntil))),
+    }
+}
+
+/// Parse a non-empty block of text that doesn't include \, ", { or }
+fn parse_interpolated_literal(input: &str) -> IResult<&str, &str> {
+    let not_special = is_not("\"\\{}");
+    verify(not_special, |s: &str| !s.is_empty())(input)
+}
+
+/// Like parse_fragment, but braces need to be doubled to appear literally.
+fn parse_interpolated_fragment(input: &str) -> IResult<&str, StringFragment> {
+    alt((
+        map(parse_interpolated_literal, StringFragment::Literal),
+        map(parse_escaped_char, StringFragment::EscapedChar),
+        value(StringFragment::EscapedChar('{'), tag("{{")),
+        value(StringFragment::EscapedChar('}'), tag("}}")),
+        value(StringFragment::EscapedWS, parse_escaped_whitespace),
+    ))(input)
+}
+
+/// Parse a run of text inside an interpolated string, up to the next `{` or closing quote.
+pub fn interpolated_text(input: Span) -> crate::IResult<String> {
+    let mut build_string = fold_many1(
+        parse_interpolated_fragment,
+        String::new,
+        |mut string, fragment| {
+            match fragment {
+                StringFragment::Literal(s) => string.push_str(s),
+                StringFragment::EscapedChar(c) => string.push(c),
+                StringFragment::EscapedWS => {}
+            }
+            string
+        }
+    );
+
+    match build_string(&input) {
+        Ok((rest, text)) => Ok((input.slice(input.fragment().len() - rest.len()..), text)),
+        Err(_) => Err(nom::Err::Error(ParseError::new(input, "invalid string literal".to_owned())))
+    }
+}
+
 pub fn string_literal(input: Span) -> crate::IResult<String> {
-    match parse_string(&input) {
-        Ok((input, lit)) => Ok((Span::new(input), lit)),
+    match alt((parse_raw_string, parse_string))(&input) {
+        // Slice the original span rather than making a new one, so that positions stay correct
+        Ok((rest, lit)) => Ok((input.slice(input.fragment().len() - rest.len()..), lit)),
         Err(_) => Err(nom::Err::Error(ParseError::new(input, "invalid string literal".to_owned())))
     }
 }
@@ -105,13 +157,44 @@ pub fn string_literal(input: Span) -> crate::IResult<String> {
 #[derive(Debug, Clone, Copy)]
 pub struct ComplexNum(pub f64, pub f64);
 
+/// A `// ...` comment, up to the end of the line.
+fn line_comment(input: Span) -> crate::IResult<()> {
+    map(preceded(tag("//"), take_till(|c| c == '\n')), |_| ())(input)
+}
+
+/// A `/* ... */` comment. These nest, so that commenting out code that has comments in it works.
+fn block_comment(input: Span) -> crate::IResult<()> {
+    let (mut input, _) = tag("/*")(input)?;
+    loop {
+        if let Ok((rest, _)) = tag::<_, _, ParseError>("*/")(input) {
+            return Ok((rest, ()));
+        }
+        input = match block_comment(input) {
+            Ok((rest, _)) => rest,
+            Err(nom::Err::Failure(e)) => return Err(nom::Err::Failure(e)),
+            Err(_) => match anychar::<_, ParseError>(input) {
+                Ok((rest, _)) => rest,
+                Err(_) => return Err(ParseError::fail(input, "unterminated block comment".to_owned())),
+            },
+        };
+    }
+}
+
+/// Skips any amount of whitespace and comments.
+pub fn skip(input: Span) -> crate::IResult<()> {
+    map(many0(alt((
+        map(nom::character::complete::multispace1, |_| ()),
+        line_comment,
+        block_comment))), |_| ())(input)
+}
+
 pub fn ws<'a, F: 'a, O>(f: F) -> impl FnMut(Span<'a>) -> crate::IResult<O>
         where
             F: Fn(Span) -> crate::IResult<O> {
-    delimited(multispace0, f, multispace0)
+    delimited(skip, f, skip)
 }
 
 
 pub fn ws_tag<'a>(s: &'static str) -> impl FnMut(Span<'a>) -> crate::IResult<Span<'a>> {
-    delimited(multispace0, tag(s), multispace0)
+    delimited(skip, tag(s), skip)
 }